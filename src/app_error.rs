@@ -0,0 +1,206 @@
+use crate::config::Config;
+use crate::fetch_budget::RETRY_AFTER_SECS;
+use axum::body::{to_bytes, Body};
+use axum::extract::Request;
+use axum::http::{header, HeaderValue, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use serde_json::{json, Value};
+use std::fmt;
+
+const PROBLEM_JSON: &str = "application/problem+json";
+
+/// Errors returned by the HTTP API, rendered as RFC 9457 `application/problem+json`
+#[derive(Debug, Clone, PartialEq)]
+pub enum AppError {
+    BadRequest(String),
+    Unauthorized(String),
+    Forbidden(String),
+    NotFound(String),
+    Upstream(String),
+    UpstreamTimeout(String),
+    Overloaded(String),
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.detail())
+    }
+}
+
+impl std::error::Error for AppError {}
+
+impl AppError {
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            Self::BadRequest(_) => StatusCode::BAD_REQUEST,
+            Self::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            Self::Forbidden(_) => StatusCode::FORBIDDEN,
+            Self::NotFound(_) => StatusCode::NOT_FOUND,
+            Self::Upstream(_) => StatusCode::BAD_GATEWAY,
+            Self::UpstreamTimeout(_) => StatusCode::GATEWAY_TIMEOUT,
+            Self::Overloaded(_) => StatusCode::SERVICE_UNAVAILABLE,
+        }
+    }
+
+    pub fn detail(&self) -> &str {
+        match self {
+            Self::BadRequest(s)
+            | Self::Unauthorized(s)
+            | Self::Forbidden(s)
+            | Self::NotFound(s)
+            | Self::Upstream(s)
+            | Self::UpstreamTimeout(s)
+            | Self::Overloaded(s) => s,
+        }
+    }
+
+    /// Classifies a failed upstream request
+    pub fn from_reqwest(e: &reqwest::Error) -> Self {
+        if e.is_timeout() {
+            return Self::UpstreamTimeout(e.to_string());
+        }
+        match e.status() {
+            Some(StatusCode::NOT_FOUND) | Some(StatusCode::GONE) => Self::NotFound(e.to_string()),
+            _ => Self::Upstream(e.to_string()),
+        }
+    }
+
+    /// Converts any other error; untyped errors are assumed to come from a failed upstream source
+    pub fn from_error(e: &(dyn std::error::Error + 'static)) -> Self {
+        if let Some(e) = e.downcast_ref::<AppError>() {
+            return e.to_owned();
+        }
+        if let Some(e) = e.downcast_ref::<reqwest::Error>() {
+            return Self::from_reqwest(e);
+        }
+        Self::Upstream(e.to_string())
+    }
+
+    pub fn as_problem(&self) -> Value {
+        let status = self.status_code();
+        json!({
+            "type": "about:blank",
+            "title": status.canonical_reason().unwrap_or_default(),
+            "status": status.as_u16(),
+            "detail": self.detail(),
+        })
+    }
+
+    /// Converts a problem+json body to the original response shape of the route, `{"status":"<detail>"}`,
+    /// or `{"error":"<detail>"}` for `/merge`
+    fn legacy_body(problem: &Value, path: &str) -> Value {
+        let detail = problem["detail"]
+            .as_str()
+            .or_else(|| problem["title"].as_str())
+            .unwrap_or("Error");
+        match path {
+            "/merge" => json!({"error": detail}),
+            _ => json!({"status": detail}),
+        }
+    }
+
+    fn legacy_requested(query: Option<&str>) -> bool {
+        let query_flag = query
+            .unwrap_or_default()
+            .split('&')
+            .any(|kv| kv == "legacy_errors" || kv == "legacy_errors=1");
+        Config::get().server.legacy_errors || query_flag
+    }
+
+    /// Middleware that turns problem+json errors back into HTTP 200 with `{"status":"<detail>"}`,
+    /// for clients that set `?legacy_errors=1`, or for all clients if `server.legacy_errors` is configured.
+    pub async fn legacy_errors(request: Request, next: Next) -> Response {
+        let legacy = Self::legacy_requested(request.uri().query());
+        let path = request.uri().path().to_string();
+        let response = next.run(request).await;
+        let is_problem = response.headers().get(header::CONTENT_TYPE)
+            == Some(&HeaderValue::from_static(PROBLEM_JSON));
+        if !legacy || !is_problem {
+            return response;
+        }
+        let problem: Value = match to_bytes(response.into_body(), usize::MAX).await {
+            Ok(bytes) => serde_json::from_slice(&bytes).unwrap_or_default(),
+            Err(_) => Value::Null,
+        };
+        axum::Json(Self::legacy_body(&problem, &path)).into_response()
+    }
+}
+
+impl From<anyhow::Error> for AppError {
+    fn from(e: anyhow::Error) -> Self {
+        Self::from_error(e.as_ref())
+    }
+}
+
+impl From<Box<dyn std::error::Error>> for AppError {
+    fn from(e: Box<dyn std::error::Error>) -> Self {
+        Self::from_error(e.as_ref())
+    }
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        let body = Body::from(self.as_problem().to_string());
+        let mut response = (
+            self.status_code(),
+            [(header::CONTENT_TYPE, PROBLEM_JSON)],
+            body,
+        )
+            .into_response();
+        if let Self::Overloaded(_) = self {
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, HeaderValue::from(RETRY_AFTER_SECS));
+        }
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+
+    #[test]
+    fn test_from_anyhow() {
+        let e: AppError = anyhow::Error::from(AppError::BadRequest("bad".into())).into();
+        assert_eq!(e, AppError::BadRequest("bad".into()));
+        let e: AppError = anyhow!("parse error").into();
+        assert_eq!(e.status_code(), StatusCode::BAD_GATEWAY);
+        let e: Box<dyn std::error::Error> = AppError::NotFound("No such item".into()).into();
+        assert_eq!(AppError::from(e).status_code(), StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn test_as_problem() {
+        let problem = AppError::NotFound("No such record".into()).as_problem();
+        assert_eq!(problem["status"], json!(404));
+        assert_eq!(problem["title"], json!("Not Found"));
+        assert_eq!(problem["detail"], json!("No such record"));
+        assert_eq!(
+            AppError::legacy_body(&problem, "/item/P227/1"),
+            json!({"status":"No such record"})
+        );
+        assert_eq!(
+            AppError::legacy_body(&problem, "/merge"),
+            json!({"error":"No such record"})
+        );
+    }
+
+    #[test]
+    fn test_overloaded_response() {
+        let response = AppError::Overloaded("busy".into()).into_response();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(
+            response.headers().get(header::RETRY_AFTER),
+            Some(&HeaderValue::from(RETRY_AFTER_SECS))
+        );
+    }
+
+    #[test]
+    fn test_legacy_requested() {
+        assert!(AppError::legacy_requested(Some("foo=bar&legacy_errors=1")));
+        assert!(!AppError::legacy_requested(Some("legacy_errors=0")));
+    }
+}
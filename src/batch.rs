@@ -0,0 +1,697 @@
+use crate::checkpoint::Checkpoint;
+use crate::cli_args::CliArgs;
+use crate::combinator::Combinator;
+use crate::config::Config;
+use crate::edit_log::EditLog;
+use crate::external_id::ExternalId;
+use crate::merge_diff_ext::MergeDiffExt;
+use crate::mixnmatch::MixNMatch;
+use crate::target::Target;
+use crate::utility::Utility;
+use anyhow::{anyhow, Result};
+use futures::stream::{self, StreamExt};
+use indicatif::{ProgressBar, ProgressStyle};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use wikimisc::mediawiki::api::Api;
+use wikimisc::merge_diff::MergeDiff;
+
+const DEFAULT_SUMMARY: &str = "AC2WD";
+const DEFAULT_MAXLAG: u64 = 5;
+const MAX_MAXLAG_RETRIES: usize = 10;
+const PAGEPILE_API: &str = "https://pagepile.toolforge.org/api.php";
+const PETSCAN_API: &str = "https://petscan.wmflabs.org/";
+
+lazy_static! {
+    static ref RE_ITEM: Regex = Regex::new(r"^Q\d+$").expect("Regexp error");
+}
+
+/// Options for a batch run over a list of items
+#[derive(Debug, Clone, PartialEq)]
+pub struct BatchOptions {
+    pub oauth_file: String,
+    pub start: usize,
+    pub delay: Duration,
+    pub summary: String,
+    pub maxlag: u64,
+    pub jobs: usize,
+    pub checkpoint: Option<PathBuf>,
+    pub resume: bool,
+    pub progress: bool,
+}
+
+impl Default for BatchOptions {
+    fn default() -> Self {
+        Self {
+            oauth_file: Config::get().oauth.credentials.to_owned(),
+            start: 0,
+            delay: Duration::from_secs(0),
+            summary: DEFAULT_SUMMARY.to_string(),
+            maxlag: DEFAULT_MAXLAG,
+            jobs: 1,
+            checkpoint: None,
+            resume: false,
+            progress: true,
+        }
+    }
+}
+
+impl BatchOptions {
+    /// `list LIST_FILE [--start-row N] [--oauth FILE] [--delay SECONDS] [--summary TEXT] [--maxlag SECONDS] [--jobs N] [--checkpoint FILE] [--resume]`
+    /// The old `--config FILE.json` still works in place of `--oauth`, and so does `list LIST_FILE START_ROW`
+    /// in place of `--start-row`; the other inputs are options, so they have no positional start row.
+    pub fn from_args(args: &CliArgs) -> Result<Self> {
+        let default = Self::default();
+        let positional_start = match BatchInput::is_option(args) {
+            true => None,
+            false => args.positional(2),
+        };
+        let start = match args.option("start-row").or(positional_start) {
+            Some(s) => s.parse().map_err(|_| anyhow!("Bad start row: '{s}'"))?,
+            None => default.start,
+        };
+        let delay: f64 = args.option_parsed("delay", 0.0).map_err(|e| anyhow!(e))?;
+        Ok(Self {
+            oauth_file: args
+                .option("oauth")
+                .or_else(|| {
+                    args.option("config")
+                        .filter(|path| Config::is_legacy_oauth_path(path))
+                })
+                .unwrap_or(&default.oauth_file)
+                .to_string(),
+            start,
+            delay: Duration::from_secs_f64(delay.max(0.0)),
+            summary: args
+                .option("summary")
+                .unwrap_or(DEFAULT_SUMMARY)
+                .to_string(),
+            maxlag: args
+                .option_parsed("maxlag", default.maxlag)
+                .map_err(|e| anyhow!(e))?,
+            jobs: args
+                .option_parsed("jobs", default.jobs)
+                .map_err(|e| anyhow!(e))?
+                .max(1),
+            checkpoint: args.option("checkpoint").map(PathBuf::from),
+            resume: args.flag("resume"),
+            progress: default.progress,
+        })
+    }
+}
+
+/// Where the items of a batch run come from
+#[derive(Debug, Clone, PartialEq)]
+pub enum BatchInput {
+    File(String),
+    Stdin,
+    Sparql(String),
+    /// The matched entries of a Mix'n'match catalog; with `true`, also automatic matches
+    MixNMatch(usize, bool),
+    PagePile(usize),
+    /// A saved PetScan query, by PSID
+    PetScan(usize),
+}
+
+impl BatchInput {
+    /// True if the input is given by an option, like `--sparql QUERY`, rather than as a file
+    fn is_option(args: &CliArgs) -> bool {
+        ["sparql", "mixnmatch", "pagepile", "petscan"]
+            .iter()
+            .any(|key| args.option(key).is_some())
+    }
+
+    /// `list LIST_FILE`, `list -` (stdin), `list --sparql QUERY`, `list --mixnmatch CATALOG [--automatic]`,
+    /// `list --pagepile ID`, or `list --petscan PSID`
+    pub fn from_args(args: &CliArgs) -> Option<Self> {
+        if let Some(query) = args.option("sparql") {
+            return Some(Self::Sparql(query.to_string()));
+        }
+        if let Some(catalog) = args.option("mixnmatch") {
+            return Some(Self::MixNMatch(
+                catalog.parse().ok()?,
+                args.flag("automatic"),
+            ));
+        }
+        if let Some(id) = args.option("pagepile") {
+            return Some(Self::PagePile(id.parse().ok()?));
+        }
+        if let Some(psid) = args.option("petscan") {
+            return Some(Self::PetScan(psid.parse().ok()?));
+        }
+        match args.positional(1)? {
+            "-" => Some(Self::Stdin),
+            filename => Some(Self::File(filename.to_string())),
+        }
+    }
+
+    /// Identifies the input in a checkpoint, so a checkpoint is not resumed for a different list
+    pub fn source(&self) -> String {
+        match self {
+            Self::File(filename) => filename.to_owned(),
+            Self::Stdin => "-".to_string(),
+            Self::Sparql(query) => format!("sparql:{query}"),
+            Self::MixNMatch(catalog, automatic) => format!("mixnmatch:{catalog}:{automatic}"),
+            Self::PagePile(id) => format!("pagepile:{id}"),
+            Self::PetScan(psid) => format!("petscan:{psid}"),
+        }
+    }
+
+    /// Returns all lines of the input (or SPARQL results), trimmed.
+    /// Mix'n'match entries become lines with the item and the external ID, separated by a tab.
+    pub async fn lines(&self) -> Result<Vec<String>> {
+        let lines = match self {
+            Self::File(filename) => BufReader::new(File::open(filename)?)
+                .lines()
+                .collect::<std::io::Result<Vec<String>>>()?,
+            Self::Stdin => std::io::stdin()
+                .lock()
+                .lines()
+                .collect::<std::io::Result<Vec<String>>>()?,
+            Self::Sparql(query) => {
+                let url = reqwest::Url::parse_with_params(
+                    Target::sparql(),
+                    &[("query", query.as_str()), ("format", "json")],
+                )?;
+                let j: Value = serde_json::from_str(&Utility::get_url(url.as_str()).await?)?;
+                Self::items_from_sparql_results(&j)
+            }
+            Self::MixNMatch(catalog, automatic) => MixNMatch::new(*catalog)
+                .await?
+                .matches(*automatic)
+                .await?
+                .iter()
+                .map(|m| format!("{}\t{}", m.item, m.ext_id))
+                .collect(),
+            Self::PagePile(id) => {
+                let url = reqwest::Url::parse_with_params(
+                    PAGEPILE_API,
+                    &[
+                        ("id", id.to_string().as_str()),
+                        ("action", "get_data"),
+                        ("format", "json"),
+                        ("doit", "1"),
+                    ],
+                )?;
+                let j: Value = serde_json::from_str(&Utility::get_url(url.as_str()).await?)?;
+                Self::items_from_pagepile(&j)?
+            }
+            Self::PetScan(psid) => {
+                let url = reqwest::Url::parse_with_params(
+                    PETSCAN_API,
+                    &[
+                        ("psid", psid.to_string().as_str()),
+                        ("format", "json"),
+                        ("output_compatability", "quick-intersection"),
+                        ("doit", "1"),
+                    ],
+                )?;
+                let j: Value = serde_json::from_str(&Utility::get_url(url.as_str()).await?)?;
+                Self::items_from_petscan(&j)
+            }
+        };
+        Ok(lines.iter().map(|line| line.trim().to_string()).collect())
+    }
+
+    /// Returns the items of a Wikidata PagePile, in order
+    fn items_from_pagepile(j: &Value) -> Result<Vec<String>> {
+        if let Some(wiki) = j["wiki"].as_str().filter(|wiki| *wiki != "wikidatawiki") {
+            return Err(anyhow!("PagePile is for {wiki}, not Wikidata"));
+        }
+        let titles = j["pages"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|page| page.as_str());
+        Ok(Self::unique_items(titles))
+    }
+
+    /// Returns the items of a PetScan result, in order.
+    /// For a query on Wikidata these are the page titles; otherwise the items of the pages, if PetScan was asked for them.
+    fn items_from_petscan(j: &Value) -> Vec<String> {
+        let items = j["pages"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|page| {
+                page["metadata"]["wikidata"]
+                    .as_str()
+                    .or_else(|| page["q"].as_str())
+                    .or_else(|| page["page_title"].as_str())
+            });
+        Self::unique_items(items)
+    }
+
+    fn unique_items<'a>(candidates: impl Iterator<Item = &'a str>) -> Vec<String> {
+        let mut ret: Vec<String> = vec![];
+        for item in candidates.filter(|item| RE_ITEM.is_match(item)) {
+            if !ret.iter().any(|i| i == item) {
+                ret.push(item.to_string());
+            }
+        }
+        ret
+    }
+
+    /// Returns the items in the first result column of a WDQS JSON response, in order
+    fn items_from_sparql_results(j: &Value) -> Vec<String> {
+        let var = match j["head"]["vars"][0].as_str() {
+            Some(var) => var,
+            None => return vec![],
+        };
+        let mut ret: Vec<String> = vec![];
+        for binding in j["results"]["bindings"].as_array().into_iter().flatten() {
+            if let Some(item) = binding[var]["value"]
+                .as_str()
+                .and_then(|uri| uri.strip_prefix(Target::entity_prefix()))
+            {
+                if !ret.iter().any(|i| i == item) {
+                    ret.push(item.to_string());
+                }
+            }
+        }
+        ret
+    }
+}
+
+/// Counts of what happened during a batch run
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BatchReport {
+    pub edited: usize,
+    pub unchanged: usize,
+    pub failed: usize,
+}
+
+impl BatchReport {
+    fn add(&mut self, status: ItemStatus) {
+        match status {
+            ItemStatus::Edited => self.edited += 1,
+            ItemStatus::Unchanged => self.unchanged += 1,
+            ItemStatus::Failed => self.failed += 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ItemStatus {
+    Edited,
+    Unchanged,
+    Failed,
+}
+
+/// The outcome for one item of a batch run, written as one line of JSON
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ItemResult {
+    pub row: usize,
+    pub item: String,
+    pub status: ItemStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub added_statements: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub altered_statements: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Other items the sources link to, see `Combinator::other_linked_items`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub other_linked_items: Vec<String>,
+}
+
+impl ItemResult {
+    pub fn failed(row: usize, item: &str, error: String) -> Self {
+        Self {
+            row,
+            item: item.to_string(),
+            status: ItemStatus::Failed,
+            added_statements: None,
+            altered_statements: None,
+            error: Some(error),
+            other_linked_items: vec![],
+        }
+    }
+}
+
+/// Extends items from a list and writes the changes to Wikidata.
+/// Items are extended concurrently (up to `BatchOptions::jobs`); edits are applied one at a time.
+pub struct Batch {
+    api: Mutex<Api>,
+    options: BatchOptions,
+}
+
+impl Batch {
+    pub async fn new(options: BatchOptions) -> Result<Self> {
+        let api = Self::get_wikidata_api(&options.oauth_file).await?;
+        Ok(Self {
+            api: Mutex::new(api),
+            options,
+        })
+    }
+
+    /// Processes all items from `input`, one QID per line, starting at the configured row.
+    /// Prints the result for each item as newline-delimited JSON; failed items do not stop the run.
+    /// With `--checkpoint`, progress is saved to that file; with `resume`, items that were done in a previous run
+    /// are skipped.
+    pub async fn run(&self, input: &BatchInput) -> Result<BatchReport> {
+        let source = input.source();
+        let mut checkpoint = match &self.options.checkpoint {
+            Some(path) if self.options.resume && path.exists() => {
+                Some(Checkpoint::load(path, &source)?)
+            }
+            Some(path) => Some(Checkpoint::create(path, &source)?),
+            None if self.options.resume => return Err(anyhow!("--resume needs --checkpoint FILE")),
+            None => None,
+        };
+        let lines: Vec<(usize, String)> = input
+            .lines()
+            .await?
+            .into_iter()
+            .enumerate()
+            .skip(self.options.start)
+            .filter(|(index, line)| {
+                let item = Self::parse_line(line).0;
+                !item.is_empty()
+                    && !checkpoint
+                        .as_ref()
+                        .is_some_and(|checkpoint| checkpoint.is_done(*index, item))
+            })
+            .collect();
+        let progress = self.progress_bar(lines.len());
+        let fetches_before = Utility::fetch_count();
+        let mut report = BatchReport::default();
+        let mut results = stream::iter(lines)
+            .map(|(row, line)| async move { self.process_item(row, &line).await })
+            .buffer_unordered(self.options.jobs);
+        while let Some(result) = results.next().await {
+            match &result.error {
+                Some(e) => tracing::warn!("{}: {} failed: {e}", result.row, result.item),
+                None => tracing::info!("{}: {} {:?}", result.row, result.item, result.status),
+            }
+            report.add(result.status);
+            if let Some(checkpoint) = &mut checkpoint {
+                if let Err(e) = checkpoint.record(&result) {
+                    tracing::warn!("Could not save checkpoint: {e}");
+                }
+            }
+            progress.suspend(|| println!("{}", json!(result)));
+            progress.set_message(format!(
+                "{} sources fetched, {} edits, {} failed",
+                Utility::fetch_count() - fetches_before,
+                report.edited,
+                report.failed
+            ));
+            progress.inc(1);
+        }
+        progress.finish();
+        Ok(report)
+    }
+
+    fn progress_bar(&self, len: usize) -> ProgressBar {
+        if !self.options.progress {
+            return ProgressBar::hidden();
+        }
+        let style =
+            ProgressStyle::with_template("{bar:40} {pos}/{len} items [{elapsed_precise}] {msg}")
+                .unwrap_or_else(|_| ProgressStyle::default_bar());
+        ProgressBar::new(len as u64).with_style(style)
+    }
+
+    /// A line is an item, optionally followed by tab-separated external IDs to import as well (like "P227:118523813")
+    fn parse_line(line: &str) -> (&str, Vec<ExternalId>) {
+        let mut parts = line.split('\t').map(|part| part.trim());
+        let item = parts.next().unwrap_or_default();
+        (item, parts.filter_map(ExternalId::from_string).collect())
+    }
+
+    async fn process_item(&self, row: usize, line: &str) -> ItemResult {
+        let (item, extra_ids) = Self::parse_line(line);
+        let mut combinator = Combinator::new();
+        let diff = match combinator.extend_with(item, extra_ids).await {
+            Ok(diff) => diff,
+            Err(e) => return ItemResult::failed(row, item, e.to_string()),
+        };
+        match self.apply_diff(item, &diff, combinator.imported()).await {
+            Ok(edited) => ItemResult {
+                row,
+                item: item.to_string(),
+                status: if edited {
+                    ItemStatus::Edited
+                } else {
+                    ItemStatus::Unchanged
+                },
+                added_statements: Some(diff.added_statements.len()),
+                altered_statements: Some(diff.altered_statements.len()),
+                error: None,
+                other_linked_items: combinator.other_linked_items().to_vec(),
+            },
+            Err(e) => ItemResult::failed(row, item, e.to_string()),
+        }
+    }
+
+    /// Applies the diff via `wbeditentity`, waiting and retrying while the servers are lagged.
+    /// Returns `false` if there was nothing to change.
+    /// Edits are serialized, and followed by the configured delay.
+    /// Successful edits are recorded in the edit log, with the `sources` the diff was built from.
+    pub async fn apply_diff(
+        &self,
+        item: &str,
+        diff: &MergeDiff,
+        sources: &[ExternalId],
+    ) -> Result<bool> {
+        let data = diff.to_wbeditentity_json();
+        if data == json!({}) {
+            return Ok(false);
+        }
+        let summary = Self::edit_summary(&self.options.summary, diff);
+        let mut api = self.api.lock().await;
+        for _ in 0..MAX_MAXLAG_RETRIES {
+            let token = api.get_edit_token().await.map_err(|e| anyhow!("{e}"))?;
+            let params: HashMap<String, String> = [
+                ("action", "wbeditentity"),
+                ("id", item),
+                ("data", &data.to_string()),
+                ("summary", &summary),
+                ("token", &token),
+                ("bot", "1"),
+                ("maxlag", &self.options.maxlag.to_string()),
+            ]
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+            let j = api
+                .post_query_api_json(&params)
+                .await
+                .map_err(|e| anyhow!("{e}"))?;
+            match Self::maxlag_wait(&j) {
+                Some(wait) => {
+                    tracing::warn!("{item}: servers lagged, waiting {}s", wait.as_secs());
+                    tokio::time::sleep(wait).await;
+                }
+                None => {
+                    if let Some(o) = j["error"].as_object() {
+                        return Err(anyhow!("{o:?}"));
+                    }
+                    Self::log_edit(item, &summary, diff, sources, &j);
+                    tokio::time::sleep(self.options.delay).await;
+                    return Ok(true);
+                }
+            }
+        }
+        Err(anyhow!(
+            "Servers still lagged after {MAX_MAXLAG_RETRIES} attempts"
+        ))
+    }
+
+    fn log_edit(item: &str, summary: &str, diff: &MergeDiff, sources: &[ExternalId], j: &Value) {
+        let log = match EditLog::global() {
+            Some(log) => log,
+            None => return,
+        };
+        let record = EditLog::new_record(
+            item,
+            summary,
+            sources.iter().map(|s| s.to_string()).collect(),
+            j["entity"]["lastrevid"].as_u64(),
+            diff.added_statements.len(),
+            diff.altered_statements.len(),
+        );
+        if let Err(e) = log.record(&record) {
+            tracing::warn!("{item}: could not record edit: {e}");
+        }
+    }
+
+    /// Builds an edit summary like "AC2WD: 3 statements added, 1 altered"
+    fn edit_summary(summary: &str, diff: &MergeDiff) -> String {
+        let added = diff.added_statements.len();
+        let altered = diff.altered_statements.len();
+        if added + altered == 0 {
+            return summary.to_string();
+        }
+        format!("{summary}: {added} statements added, {altered} altered")
+    }
+
+    /// Returns the time to wait if the API response is a maxlag error
+    fn maxlag_wait(j: &Value) -> Option<Duration> {
+        if j["error"]["code"].as_str()? != "maxlag" {
+            return None;
+        }
+        let lag = j["error"]["lag"].as_f64().unwrap_or(DEFAULT_MAXLAG as f64);
+        Some(Duration::from_secs_f64(lag.clamp(1.0, 60.0)))
+    }
+
+    /// Reads the `oauth2_token` from a JSON credentials file
+    pub fn read_oauth2_token(path: &str) -> Result<String> {
+        let file = File::open(path).map_err(|e| anyhow!("{path}: {e}"))?;
+        let reader = BufReader::new(file);
+        let j: Value = serde_json::from_reader(reader)?;
+        j["oauth2_token"]
+            .as_str()
+            .map(|token| token.to_string())
+            .ok_or_else(|| anyhow!("No oauth2_token in {path}"))
+    }
+
+    async fn get_wikidata_api(path: &str) -> Result<Api> {
+        let oauth2_token = Self::read_oauth2_token(path)?;
+        let mut api = Api::new(Target::api()).await.map_err(|e| anyhow!("{e}"))?;
+        api.set_oauth2(&oauth2_token);
+        api.set_user_agent(Utility::user_agent());
+        Ok(api)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_maxlag_wait() {
+        let j = json!({"error":{"code":"maxlag","lag":7.5}});
+        assert_eq!(Batch::maxlag_wait(&j), Some(Duration::from_secs_f64(7.5)));
+        let j = json!({"error":{"code":"badtoken"}});
+        assert_eq!(Batch::maxlag_wait(&j), None);
+        assert_eq!(Batch::maxlag_wait(&json!({"success":1})), None);
+    }
+
+    #[test]
+    fn test_options_from_args() {
+        let args: Vec<String> = [
+            "list",
+            "items.txt",
+            "10",
+            "--delay=1.5",
+            "--summary",
+            "test",
+        ]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+        let options = BatchOptions::from_args(&CliArgs::new(&args, &[])).unwrap();
+        assert_eq!(options.start, 10);
+        assert_eq!(options.delay, Duration::from_millis(1500));
+        assert_eq!(options.summary, "test");
+        assert_eq!(options.maxlag, DEFAULT_MAXLAG);
+        assert_eq!(options.oauth_file, "config.json");
+        assert_eq!(options.jobs, 1);
+
+        let args: Vec<String> = ["list", "items.txt", "--config", "bot.json"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let options = BatchOptions::from_args(&CliArgs::new(&args, &[])).unwrap();
+        assert_eq!(options.oauth_file, "bot.json");
+        assert_eq!(options.start, 0);
+
+        let args = |a: &[&str]| {
+            let args = CliArgs::new(&a.iter().map(|s| s.to_string()).collect::<Vec<_>>(), &[]);
+            BatchOptions::from_args(&args).unwrap()
+        };
+        assert_eq!(
+            args(&["list", "--petscan", "123", "--start-row", "5"]).start,
+            5
+        );
+        assert_eq!(args(&["list", "--sparql", "SELECT", "5"]).start, 0);
+        assert_eq!(args(&["list", "-", "--start-row=7"]).start, 7);
+    }
+
+    #[test]
+    fn test_input_from_args() {
+        let args =
+            |a: &[&str]| CliArgs::new(&a.iter().map(|s| s.to_string()).collect::<Vec<_>>(), &[]);
+        assert_eq!(
+            BatchInput::from_args(&args(&["list", "items.txt"])),
+            Some(BatchInput::File("items.txt".to_string()))
+        );
+        assert_eq!(
+            BatchInput::from_args(&args(&["list", "-"])),
+            Some(BatchInput::Stdin)
+        );
+        assert_eq!(
+            BatchInput::from_args(&args(&["list", "--sparql", "SELECT ?q {}"])),
+            Some(BatchInput::Sparql("SELECT ?q {}".to_string()))
+        );
+        assert_eq!(
+            BatchInput::from_args(&args(&["list", "--mixnmatch", "2050"])),
+            Some(BatchInput::MixNMatch(2050, false))
+        );
+        assert_eq!(BatchInput::from_args(&args(&["list"])), None);
+    }
+
+    #[test]
+    fn test_parse_line() {
+        assert_eq!(Batch::parse_line("Q42"), ("Q42", vec![]));
+        assert_eq!(
+            Batch::parse_line("Q1035\tP227:118523813"),
+            ("Q1035", vec![ExternalId::new(227, "118523813")])
+        );
+    }
+
+    #[test]
+    fn test_items_from_sparql_results() {
+        let j = json!({"head":{"vars":["q","name"]},"results":{"bindings":[
+            {"q":{"type":"uri","value":"http://www.wikidata.org/entity/Q42"}},
+            {"q":{"type":"uri","value":"http://www.wikidata.org/entity/Q1035"}},
+            {"q":{"type":"uri","value":"http://www.wikidata.org/entity/Q42"}},
+            {"q":{"type":"literal","value":"foo"}}
+        ]}});
+        assert_eq!(
+            BatchInput::items_from_sparql_results(&j),
+            vec!["Q42", "Q1035"]
+        );
+    }
+
+    #[test]
+    fn test_items_from_pagepile() {
+        let j =
+            json!({"id":12345,"wiki":"wikidatawiki","pages":["Q42","Q1035","Q42","Property:P31"]});
+        assert_eq!(
+            BatchInput::items_from_pagepile(&j).unwrap(),
+            vec!["Q42", "Q1035"]
+        );
+        let j = json!({"id":12346,"wiki":"enwiki","pages":["Douglas Adams"]});
+        assert!(BatchInput::items_from_pagepile(&j).is_err());
+    }
+
+    #[test]
+    fn test_items_from_petscan() {
+        let j = json!({"pages":[
+            {"page_id":138,"page_namespace":0,"page_title":"Q42"},
+            {"page_id":8091,"page_namespace":0,"page_title":"Douglas_Adams","metadata":{"wikidata":"Q42"}},
+            {"page_id":1,"page_namespace":0,"page_title":"Charles_Darwin","metadata":{"wikidata":"Q1035"}}
+        ]});
+        assert_eq!(BatchInput::items_from_petscan(&j), vec!["Q42", "Q1035"]);
+    }
+
+    #[test]
+    fn test_item_result_json() {
+        let result = ItemResult::failed(3, "Q42", "boom".to_string());
+        assert_eq!(
+            json!(result),
+            json!({"row":3,"item":"Q42","status":"failed","error":"boom"})
+        );
+    }
+}
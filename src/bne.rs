@@ -1,20 +1,16 @@
 use crate::external_id::*;
 use crate::external_importer::*;
 use crate::meta_item::*;
+use crate::utility::Utility;
 use anyhow::Result;
 use axum::async_trait;
-use sophia::api::prelude::*;
 use sophia::inmem::graph::FastGraph;
-use sophia::xml;
 
 pub struct BNE {
     id: String,
     graph: FastGraph,
 }
 
-unsafe impl Send for BNE {}
-unsafe impl Sync for BNE {}
-
 #[async_trait]
 impl ExternalImporter for BNE {
     fn my_property(&self) -> usize {
@@ -45,6 +41,17 @@ impl ExternalImporter for BNE {
         self.transform_label_last_first_name(s)
     }
 
+    async fn fetch(&mut self) -> Result<()> {
+        let rdf_url = format!("https://datos.bne.es/resource/{}.rdf", self.id);
+        self.graph = Utility::get_rdf_graph(&rdf_url).await?;
+        Ok(())
+    }
+
+    fn parse(&mut self, text: &str) -> Result<()> {
+        self.graph = Utility::parse_rdf_xml(text)?;
+        Ok(())
+    }
+
     async fn run(&self) -> Result<MetaItem> {
         let mut ret = MetaItem::new();
         self.add_the_usual(&mut ret).await?;
@@ -77,15 +84,17 @@ impl ExternalImporter for BNE {
 }
 
 impl BNE {
-    pub async fn new(id: &str) -> Result<Self> {
-        let rdf_url = format!("https://datos.bne.es/resource/{}.rdf", id);
-        let resp = reqwest::get(&rdf_url).await?.text().await?;
-        let mut graph: FastGraph = FastGraph::new();
-        let _ = xml::parser::parse_str(&resp).add_to_graph(&mut graph)?;
-        Ok(Self {
+    pub fn unfetched(id: &str) -> Self {
+        Self {
             id: id.to_string(),
-            graph,
-        })
+            graph: FastGraph::new(),
+        }
+    }
+
+    pub async fn new(id: &str) -> Result<Self> {
+        let mut ret = Self::unfetched(id);
+        ret.fetch().await?;
+        Ok(ret)
     }
 }
 
@@ -1,13 +1,23 @@
 use crate::external_id::*;
 use crate::external_importer::*;
 use crate::meta_item::*;
+use crate::occupation::Occupation;
 use crate::utility::Utility;
 use anyhow::{anyhow, Result};
 use axum::async_trait;
 use regex::Regex;
-use sophia::api::prelude::*;
 use sophia::inmem::graph::FastGraph;
-use sophia::xml;
+use wikimisc::wikibase::Snak;
+
+const BNF_SPARQL: &str = "https://data.bnf.fr/sparql";
+const DCTERMS_CREATOR: &str = "http://purl.org/dc/terms/creator";
+const DCTERMS_TITLE: &str = "http://purl.org/dc/terms/title";
+/// Notable work suggestions per person; prolific authors have thousands of works
+const MAX_WORKS: usize = 20;
+/// Rights statements of Gallica scans that are in the public domain, lowercase
+const PUBLIC_DOMAIN_RIGHTS: &[&str] = &["domaine public", "public domain"];
+/// Bibliothèque nationale de France, the collection of the Gallica scans
+const BNF_ITEM: &str = "Q193563";
 
 lazy_static! {
     static ref RE_NUMERIC_ID: Regex =
@@ -15,6 +25,21 @@ lazy_static! {
     static ref RE_URL: Regex =
         Regex::new(r#"<meta property="og:url" content="https://data.bnf.fr/\d+/(.+?)/" />"#)
             .expect("Regexp error");
+    static ref RE_LOC_VOCABULARY: Regex =
+        Regex::new(r#"^https?://id\.loc\.gov/vocabulary/(countries|languages|iso639-2)/([a-z]+)$"#)
+            .expect("Regexp error");
+    static ref RE_ARK: Regex =
+        Regex::new(r#"^https?://data\.bnf\.fr/ark:/12148/cb(\d{8,9}[0-9bcdfghjkmnpqrstvwxz])"#)
+            .expect("Regexp error");
+    static ref RE_GALLICA: Regex =
+        Regex::new(r#"^https?://gallica\.bnf\.fr/ark:/12148/([a-z0-9]+)"#).expect("Regexp error");
+    static ref RE_GEONAMES: Regex =
+        Regex::new(r#"^https?://sws\.geonames\.org/(\d+)"#).expect("Regexp error");
+    static ref RE_INSEE_COMMUNE: Regex =
+        Regex::new(r#"^https?://id\.insee\.fr/geo/commune/([0-9AB]{5})$"#).expect("Regexp error");
+    static ref RE_CONCEPT_URI: Regex =
+        Regex::new(r#"^https://data\.bnf\.fr/ark:/12148/cb(\d{8,9}[0-9bcdfghjkmnpqrstvwxz])$"#)
+            .expect("Regexp error");
 }
 
 pub struct BNF {
@@ -22,9 +47,6 @@ pub struct BNF {
     graph: FastGraph,
 }
 
-unsafe impl Send for BNF {}
-unsafe impl Sync for BNF {}
-
 #[async_trait]
 impl ExternalImporter for BNF {
     fn my_property(&self) -> usize {
@@ -35,6 +57,11 @@ impl ExternalImporter for BNF {
         self.id.to_owned()
     }
 
+    /// The concept describing the person carries the ID of the current record, which differs for merged records
+    fn resolve_canonical_id(&self) -> Result<Option<String>> {
+        self.canonical_id_from_subjects("http://xmlns.com/foaf/0.1/focus", &RE_CONCEPT_URI)
+    }
+
     fn my_stated_in(&self) -> &str {
         "Q19938912"
     }
@@ -55,6 +82,34 @@ impl ExternalImporter for BNF {
         self.transform_label_last_first_name(s)
     }
 
+    async fn fetch(&mut self) -> Result<()> {
+        let id = &self.id;
+        if !RE_NUMERIC_ID.is_match(id) {
+            return Err(anyhow!("ID format error for '{id}'"));
+        }
+        let numeric_id = RE_NUMERIC_ID.replace_all(id, "${1}");
+
+        let name = match Self::get_name_for_id(&numeric_id).await? {
+            Some(name) => name,
+            None => return Err(anyhow!("Name retrieval error for '{id}'")),
+        };
+
+        let rdf_url = format!("https://data.bnf.fr/{numeric_id}/{name}/rdf.xml");
+        self.graph = Utility::get_rdf_graph(&rdf_url).await?;
+        if let Some(id) = self.resolve_canonical_id()? {
+            self.id = id;
+        }
+        Ok(())
+    }
+
+    fn parse(&mut self, text: &str) -> Result<()> {
+        self.graph = Utility::parse_rdf_xml(text)?;
+        if let Some(id) = self.resolve_canonical_id()? {
+            self.id = id;
+        }
+        Ok(())
+    }
+
     async fn run(&self) -> Result<MetaItem> {
         let mut ret = MetaItem::new();
         self.add_the_usual(&mut ret).await?;
@@ -90,11 +145,52 @@ impl ExternalImporter for BNF {
             }
         }
 
+        self.add_occupations(&mut ret)?;
+
+        // Nationality and language
+        let countries_languages = [
+            (
+                "http://rdvocab.info/ElementsGr2/countryAssociatedWithThePerson",
+                27,
+            ),
+            ("http://rdvocab.info/ElementsGr2/languageOfThePerson", 1412),
+        ];
+        let mut codes = vec![];
+        for (key, prop) in countries_languages {
+            for s in self.triples_subject_iris(&self.get_id_url(), key)? {
+                codes.push((prop, Self::code_external_id(prop, &s), s));
+            }
+            for s in self.triples_subject_literals(&self.get_id_url(), key)? {
+                codes.push((prop, Self::code_external_id(prop, &s), s));
+            }
+        }
+        let ext_ids: Vec<ExternalId> = codes.iter().filter_map(|c| c.1.to_owned()).collect();
+        let items = ExternalId::get_item_for_external_id_values(&ext_ids).await;
+        for (prop, ext_id, s) in codes {
+            let _ = match ext_id.and_then(|ext_id| items.get(&ext_id)) {
+                Some(item) => ret.add_claim(self.new_statement_item(106, item)),
+                None => ret.add_prop_text(ExternalId::new(prop, &s)),
+            };
+        }
+
+        self.add_depictions(&mut ret)?;
+        self.add_gallica_page(&mut ret)?;
+        for title in self.work_titles().await {
+            let _ = ret.add_prop_text(ExternalId::new(800, &title));
+        }
+
         let born_died_in = [
             ("http://rdvocab.info/ElementsGr2/placeOfBirth", 19),
             ("http://rdvocab.info/ElementsGr2/placeOfDeath", 20),
         ];
         for (key, prop) in born_died_in {
+            let items = self.linked_place_items(key).await?;
+            for item in &items {
+                ret.add_claim(self.new_statement_item(prop, item));
+            }
+            if !items.is_empty() {
+                continue;
+            }
             for s in self.triples_subject_literals(&self.get_id_url(), key)? {
                 ret.add_prop_text(ExternalId::new(prop, &s));
             }
@@ -107,32 +203,236 @@ impl ExternalImporter for BNF {
 }
 
 impl BNF {
+    pub fn unfetched(id: &str) -> Self {
+        Self {
+            id: id.to_string(),
+            graph: FastGraph::new(),
+        }
+    }
+
     pub async fn new(id: &str) -> Result<Self> {
-        if !RE_NUMERIC_ID.is_match(id) {
-            return Err(anyhow!("ID format error for '{id}'"));
+        let mut ret = Self::unfetched(id);
+        ret.fetch().await?;
+        Ok(ret)
+    }
+
+    /// Items for places that are linked as BnF place records, via their GeoNames or INSEE IDs.
+    /// Place records not described in this graph are fetched.
+    async fn linked_place_items(&self, key: &str) -> Result<Vec<String>> {
+        let mut ext_ids = vec![];
+        for url in self.triples_subject_iris(&self.get_id_url(), key)? {
+            let mut matches = self.place_matches(&url)?;
+            if matches.is_empty() {
+                if let Some(place_id) = RE_ARK.captures(&url).map(|c| c[1].to_string()) {
+                    match BNF::new(&place_id).await {
+                        Ok(place) => matches = place.place_matches(&place.get_id_url())?,
+                        Err(e) if Utility::is_overloaded(&e) => return Err(e),
+                        Err(_) => {}
+                    }
+                }
+            }
+            ext_ids.extend(
+                matches
+                    .iter()
+                    .filter_map(|url| Self::place_external_id(url)),
+            );
         }
-        let numeric_id = RE_NUMERIC_ID.replace_all(id, "${1}");
+        let items = ExternalId::get_item_for_external_id_values(&ext_ids).await;
+        let mut ret: Vec<String> = ext_ids
+            .iter()
+            .filter_map(|ext_id| items.get(ext_id).cloned())
+            .collect();
+        ret.sort();
+        ret.dedup();
+        Ok(ret)
+    }
 
-        let name = match Self::get_name_for_id(&numeric_id).await {
-            Some(name) => name,
-            None => return Err(anyhow!("Name retrieval error for '{id}'")),
-        };
+    fn place_matches(&self, url: &str) -> Result<Vec<String>> {
+        let mut ret = self.triples_subject_iris(url, "http://www.w3.org/2002/07/owl#sameAs")?;
+        ret.extend(
+            self.triples_subject_iris(url, "http://www.w3.org/2004/02/skos/core#exactMatch")?,
+        );
+        Ok(ret)
+    }
 
-        let rdf_url = format!("https://data.bnf.fr/{numeric_id}/{name}/rdf.xml");
-        let resp = Utility::get_url(&rdf_url).await?;
+    /// GeoNames (P1566) or INSEE municipality code (P374)
+    fn place_external_id(url: &str) -> Option<ExternalId> {
+        if let Some(captures) = RE_GEONAMES.captures(url) {
+            return Some(ExternalId::new(1566, &captures[1]));
+        }
+        let captures = RE_INSEE_COMMUNE.captures(url)?;
+        Some(ExternalId::new(374, &captures[1]))
+    }
 
-        let mut graph: FastGraph = FastGraph::new();
-        let _ = xml::parser::parse_str(&resp).add_to_graph(&mut graph)?;
-        Ok(Self {
-            id: id.to_string(),
-            graph,
-        })
+    /// Titles of works by this person, as notable work suggestions.
+    /// The works are described in the record itself, or else queried from data.bnf.fr.
+    async fn work_titles(&self) -> Vec<String> {
+        let mut titles = self.work_titles_from_graph().unwrap_or_default();
+        if titles.is_empty() {
+            titles = match self.work_titles_from_sparql().await {
+                Ok(titles) => titles,
+                Err(e) => {
+                    tracing::warn!("Could not query works of BnF {}: {e}", self.id);
+                    vec![]
+                }
+            };
+        }
+        titles.truncate(MAX_WORKS);
+        titles
     }
 
-    async fn get_name_for_id(numeric_id: &str) -> Option<String> {
+    fn work_titles_from_graph(&self) -> Result<Vec<String>> {
+        let mut ret = vec![];
+        for work in self.triples_property_object_iris(DCTERMS_CREATOR, &self.get_id_url())? {
+            ret.extend(self.triples_subject_literals(&work, DCTERMS_TITLE)?);
+        }
+        ret.sort();
+        ret.dedup();
+        Ok(ret)
+    }
+
+    async fn work_titles_from_sparql(&self) -> Result<Vec<String>> {
+        let query = format!(
+            "SELECT DISTINCT ?title WHERE {{ ?work <{DCTERMS_CREATOR}> <{}> ; <{DCTERMS_TITLE}> ?title }} LIMIT {MAX_WORKS}",
+            self.get_id_url()
+        );
+        let url = reqwest::Url::parse_with_params(
+            BNF_SPARQL,
+            &[
+                ("query", query.as_str()),
+                ("format", "application/sparql-results+json"),
+            ],
+        )?;
+        let j: serde_json::Value = serde_json::from_str(&Utility::get_url(url.as_str()).await?)?;
+        Ok(Self::titles_from_sparql_results(&j))
+    }
+
+    fn titles_from_sparql_results(j: &serde_json::Value) -> Vec<String> {
+        let mut ret: Vec<String> = j["results"]["bindings"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|binding| binding["title"]["value"].as_str())
+            .map(|title| title.trim().to_string())
+            .filter(|title| !title.is_empty())
+            .collect();
+        ret.sort();
+        ret.dedup();
+        ret
+    }
+
+    /// The Gallica ID (P4258) of a page about the person, if the record links one
+    fn add_gallica_page(&self, ret: &mut MetaItem) -> Result<()> {
+        let mut urls = self.triples_iris("http://xmlns.com/foaf/0.1/page")?;
+        urls.extend(self.triples_iris("http://www.w3.org/2000/01/rdf-schema#seeAlso")?);
+        for url in urls {
+            if let Some(captures) = RE_GALLICA.captures(&url) {
+                ret.add_claim(self.new_statement_string(4258, &captures[1]));
+            }
+        }
+        Ok(())
+    }
+
+    /// Known occupation terms become P106, whichever element they are in, since the table maps to occupation items.
+    /// Other terms are kept as text, on P106 or P101 (field of work) for fields of activity
+    fn add_occupations(&self, ret: &mut MetaItem) -> Result<()> {
+        let occupations = [
+            (
+                "http://rdvocab.info/ElementsGr2/professionOrOccupation",
+                106,
+            ),
+            (
+                "http://rdvocab.info/ElementsGr2/fieldOfActivityOfThePerson",
+                101,
+            ),
+        ];
+        for (key, prop) in occupations {
+            for s in self.triples_subject_literals(&self.get_id_url(), key)? {
+                for term in Occupation::split_terms(&s) {
+                    let _ = match Occupation::from_french(&term) {
+                        Some(item) => ret.add_claim(self.new_statement_item(prop, item)),
+                        None => ret.add_prop_text(ExternalId::new(prop, &term)),
+                    };
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Portrait scans on Gallica, as Commons compatible image URLs (P4765), if their rights statement says they are public domain
+    fn add_depictions(&self, ret: &mut MetaItem) -> Result<()> {
+        let license = VALID_IMAGE_LICENSES.get("public domain");
+        for url in
+            self.triples_subject_iris(&self.get_id_url(), "http://xmlns.com/foaf/0.1/depiction")?
+        {
+            if !url.starts_with("https://gallica.bnf.fr/")
+                && !url.starts_with("http://gallica.bnf.fr/")
+            {
+                continue;
+            }
+            let mut rights =
+                self.triples_subject_literals(&url, "http://purl.org/dc/elements/1.1/rights")?;
+            rights.extend(self.triples_subject_literals(&url, "http://purl.org/dc/terms/rights")?);
+            let is_public_domain = rights
+                .iter()
+                .any(|r| PUBLIC_DOMAIN_RIGHTS.contains(&r.trim().to_lowercase().as_str()));
+            let license = match license {
+                Some(license) if is_public_domain => license,
+                _ => continue,
+            };
+            let mut statement = self.new_statement_string(4765, &url);
+            statement.add_qualifier_snak(Snak::new_item("P275", license));
+            statement.add_qualifier_snak(Snak::new_item("P195", BNF_ITEM));
+            statement.add_qualifier_snak(Snak::new_url("P2699", &url));
+            // Gallica serves ".highres" images as JPEG
+            let lowercase_url = url.to_lowercase();
+            if [".highres", ".jpg", ".jpeg"]
+                .iter()
+                .any(|suffix| lowercase_url.ends_with(suffix))
+            {
+                statement.add_qualifier_snak(Snak::new_item("P2701", "Q2195"));
+            }
+            ret.add_claim(statement);
+        }
+        Ok(())
+    }
+
+    /// The ID of a country (for P27) or language (for P1412) code, to find its item.
+    /// BnF uses the MARC codes of id.loc.gov (LoC and MARC vocabularies ID, P4801), which are not all ISO codes;
+    /// plain codes are taken as ISO 3166-1 alpha-2 (P297), or ISO 639-1 (P218) and 639-2 (P219).
+    fn code_external_id(property: usize, s: &str) -> Option<ExternalId> {
+        if let Some(captures) = RE_LOC_VOCABULARY.captures(s) {
+            return match &captures[1] {
+                "iso639-2" => Some(ExternalId::new(219, &captures[2])),
+                vocabulary => Some(ExternalId::new(
+                    4801,
+                    &format!("{vocabulary}/{}", &captures[2]),
+                )),
+            };
+        }
+        let s = s.trim();
+        if !s.chars().all(|c| c.is_ascii_alphabetic()) {
+            return None;
+        }
+        match (property, s.len()) {
+            (27, 2) => Some(ExternalId::new(297, &s.to_uppercase())),
+            (1412, 2) => Some(ExternalId::new(218, &s.to_lowercase())),
+            (1412, 3) => Some(ExternalId::new(219, &s.to_lowercase())),
+            _ => None,
+        }
+    }
+
+    async fn get_name_for_id(numeric_id: &str) -> Result<Option<String>> {
         let rdf_url = format!("https://data.bnf.fr/en/{numeric_id}");
-        let resp = Utility::get_url(&rdf_url).await.ok()?;
-        Some(RE_URL.captures(&resp)?.get(1)?.as_str().to_string())
+        let resp = match Utility::get_url(&rdf_url).await {
+            Ok(resp) => resp,
+            Err(e) if Utility::is_overloaded(&e) => return Err(e),
+            Err(_) => return Ok(None),
+        };
+        Ok(RE_URL
+            .captures(&resp)
+            .and_then(|captures| captures.get(1))
+            .map(|name| name.as_str().to_string()))
     }
 }
 
@@ -145,6 +445,114 @@ mod tests {
     const TEST_ID: &str = "11898689q";
     const TEST_ID2: &str = "15585136v";
 
+    #[test]
+    fn test_place_external_id() {
+        assert_eq!(
+            BNF::place_external_id("http://sws.geonames.org/2988507/"),
+            Some(ExternalId::new(1566, "2988507"))
+        );
+        assert_eq!(
+            BNF::place_external_id("http://id.insee.fr/geo/commune/75056"),
+            Some(ExternalId::new(374, "75056"))
+        );
+        assert_eq!(
+            BNF::place_external_id("http://www.wikidata.org/entity/Q90"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_titles_from_sparql_results() {
+        let j = serde_json::json!({"head":{"vars":["title"]},"results":{"bindings":[
+            {"title":{"type":"literal","value":"De l'origine des espèces"}},
+            {"title":{"type":"literal","value":" "}}
+        ]}});
+        assert_eq!(
+            BNF::titles_from_sparql_results(&j),
+            vec!["De l'origine des espèces"]
+        );
+    }
+
+    #[test]
+    fn test_depictions() {
+        let rdf = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#" xmlns:foaf="http://xmlns.com/foaf/0.1/" xmlns:dc="http://purl.org/dc/elements/1.1/">
+  <rdf:Description rdf:about="https://data.bnf.fr/ark:/12148/cb11898689q#about">
+    <foaf:depiction rdf:resource="https://gallica.bnf.fr/ark:/12148/btv1b8451620p.highres"/>
+    <foaf:depiction rdf:resource="https://gallica.bnf.fr/ark:/12148/btv1b0000000x.highres"/>
+    <foaf:depiction rdf:resource="https://gallica.bnf.fr/ark:/12148/btv1b1111111x.highres"/>
+  </rdf:Description>
+  <rdf:Description rdf:about="https://gallica.bnf.fr/ark:/12148/btv1b8451620p.highres">
+    <dc:rights>domaine public</dc:rights>
+  </rdf:Description>
+  <rdf:Description rdf:about="https://gallica.bnf.fr/ark:/12148/btv1b1111111x.highres">
+    <dc:rights>n'est pas dans le domaine public</dc:rights>
+  </rdf:Description>
+</rdf:RDF>"#;
+        let mut bnf = BNF::unfetched(TEST_ID);
+        bnf.parse(rdf).unwrap();
+        let mut mi = MetaItem::new();
+        bnf.add_depictions(&mut mi).unwrap();
+        assert_eq!(mi.item.claims().len(), 1);
+        let statement = &mi.item.claims()[0];
+        assert_eq!(statement.main_snak().property(), "P4765");
+        let has_qualifier = |p: &str| statement.qualifiers().iter().any(|s| s.property() == p);
+        assert!(has_qualifier("P2701"));
+        assert!(has_qualifier("P195"));
+        assert!(!has_qualifier("P2093"));
+    }
+
+    #[test]
+    fn test_occupations() {
+        let rdf = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#" xmlns:rdagroup2elements="http://rdvocab.info/ElementsGr2/">
+  <rdf:Description rdf:about="https://data.bnf.fr/ark:/12148/cb11898689q#about">
+    <rdagroup2elements:professionOrOccupation>Naturaliste. - Alchimiste</rdagroup2elements:professionOrOccupation>
+    <rdagroup2elements:fieldOfActivityOfThePerson>Géologue</rdagroup2elements:fieldOfActivityOfThePerson>
+  </rdf:Description>
+</rdf:RDF>"#;
+        let mut bnf = BNF::unfetched(TEST_ID);
+        bnf.parse(rdf).unwrap();
+        let mut mi = MetaItem::new();
+        bnf.add_occupations(&mut mi).unwrap();
+        let properties: Vec<&str> = mi
+            .item
+            .claims()
+            .iter()
+            .map(|c| c.main_snak().property())
+            .collect();
+        assert_eq!(properties, vec!["P106", "P106"]);
+        // The field of activity "Géologue" is a known occupation
+        assert_eq!(
+            mi.item.claims()[1].main_snak().data_value(),
+            bnf.new_statement_item(106, "Q520549")
+                .main_snak()
+                .data_value()
+        );
+        assert_eq!(mi.prop_text, vec![ExternalId::new(106, "Alchimiste")]);
+    }
+
+    #[test]
+    fn test_code_external_id() {
+        assert_eq!(
+            BNF::code_external_id(27, "http://id.loc.gov/vocabulary/countries/gw"),
+            Some(ExternalId::new(4801, "countries/gw"))
+        );
+        assert_eq!(
+            BNF::code_external_id(1412, "http://id.loc.gov/vocabulary/iso639-2/fre"),
+            Some(ExternalId::new(219, "fre"))
+        );
+        assert_eq!(
+            BNF::code_external_id(27, "FR"),
+            Some(ExternalId::new(297, "FR"))
+        );
+        assert_eq!(
+            BNF::code_external_id(1412, "fr"),
+            Some(ExternalId::new(218, "fr"))
+        );
+        assert_eq!(BNF::code_external_id(27, "France"), None);
+    }
+
     #[tokio::test]
     async fn test_run() {
         let bnf = BNF::new(TEST_ID).await.unwrap();
@@ -163,17 +571,18 @@ mod tests {
             *meta_item.item.labels(),
             vec![LocaleString::new("fr", "Louis Bassal")]
         );
-        assert_eq!(meta_item.prop_text.len(), 2);
+        // Occupations may be in prop_text too
+        let places: Vec<&ExternalId> = meta_item
+            .prop_text
+            .iter()
+            .filter(|ext_id| [19, 20].contains(&ext_id.property()))
+            .collect();
+        assert_eq!(places.len(), 2);
         assert_eq!(
-            meta_item.prop_text[0],
+            *places[0],
             ExternalId::new(19, "Rivesaltes (Pyrénées-Orientales)")
         );
-        assert_eq!(
-            meta_item.prop_text[1],
-            ExternalId::new(20, "Grenoble (Isère)")
-        );
-
-        println!("{:?}", meta_item.prop_text);
+        assert_eq!(*places[1], ExternalId::new(20, "Grenoble (Isère)"));
     }
 
     #[tokio::test]
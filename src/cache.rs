@@ -0,0 +1,237 @@
+use crate::config::Config;
+use crate::external_id::ExternalId;
+use crate::meta_item::MetaItem;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+
+lazy_static! {
+    static ref RESPONSE_CACHE: ResponseCache = ResponseCache::default();
+}
+
+#[derive(Debug)]
+struct CacheEntry {
+    meta_item: MetaItem,
+    inserted: Instant,
+    last_used: u64,
+}
+
+#[derive(Debug, Default)]
+struct CacheEntries {
+    items: HashMap<ExternalId, CacheEntry>,
+    /// IDs by the time they were last used, least recently used first
+    lru: BTreeMap<u64, ExternalId>,
+    /// Increases with every use
+    clock: u64,
+}
+
+impl CacheEntries {
+    fn touch(&mut self, ext_id: &ExternalId) {
+        self.clock += 1;
+        let clock = self.clock;
+        if let Some(entry) = self.items.get_mut(ext_id) {
+            self.lru.remove(&entry.last_used);
+            entry.last_used = clock;
+            self.lru.insert(clock, ext_id.to_owned());
+        }
+    }
+
+    fn remove(&mut self, ext_id: &ExternalId) -> Option<CacheEntry> {
+        let entry = self.items.remove(ext_id)?;
+        self.lru.remove(&entry.last_used);
+        Some(entry)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CacheStats {
+    pub entries: usize,
+    pub max_entries: usize,
+    pub ttl_secs: u64,
+    pub hits: usize,
+    pub misses: usize,
+    pub hit_rate: f64,
+}
+
+/// In-memory cache of parsed external records, keyed by external ID.
+/// Records expire after the TTL; when full, the least recently used one is evicted.
+#[derive(Debug, Clone)]
+pub struct ResponseCache {
+    entries: Arc<Mutex<CacheEntries>>,
+    hits: Arc<AtomicUsize>,
+    misses: Arc<AtomicUsize>,
+    max_entries: usize,
+    /// `None` if records do not expire
+    ttl: Option<Duration>,
+}
+
+impl Default for ResponseCache {
+    fn default() -> Self {
+        let config = &Config::get().cache;
+        let ttl = Some(Duration::from_secs(config.ttl_secs)).filter(|ttl| !ttl.is_zero());
+        Self::with_limits(config.max_entries, ttl)
+    }
+}
+
+impl ResponseCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The cache shared by the server routes and the `Combinator`
+    pub fn global() -> &'static Self {
+        &RESPONSE_CACHE
+    }
+
+    pub fn with_max_entries(max_entries: usize) -> Self {
+        Self::with_limits(max_entries, None)
+    }
+
+    pub fn with_limits(max_entries: usize, ttl: Option<Duration>) -> Self {
+        Self {
+            entries: Arc::new(Mutex::new(CacheEntries::default())),
+            hits: Arc::new(AtomicUsize::new(0)),
+            misses: Arc::new(AtomicUsize::new(0)),
+            max_entries,
+            ttl,
+        }
+    }
+
+    pub async fn get(&self, ext_id: &ExternalId) -> Option<MetaItem> {
+        let mut entries = self.entries.lock().await;
+        let expired = match entries.items.get(ext_id) {
+            Some(entry) => self.ttl.is_some_and(|ttl| entry.inserted.elapsed() > ttl),
+            None => false,
+        };
+        if expired {
+            entries.remove(ext_id);
+        }
+        entries.touch(ext_id);
+        let ret = entries
+            .items
+            .get(ext_id)
+            .map(|entry| entry.meta_item.to_owned());
+        match ret {
+            Some(_) => self.hits.fetch_add(1, Ordering::Relaxed),
+            None => self.misses.fetch_add(1, Ordering::Relaxed),
+        };
+        ret
+    }
+
+    pub async fn insert(&self, ext_id: ExternalId, meta_item: MetaItem) {
+        let mut entries = self.entries.lock().await;
+        entries.remove(&ext_id);
+        let entry = CacheEntry {
+            meta_item,
+            inserted: Instant::now(),
+            last_used: 0,
+        };
+        entries.items.insert(ext_id.to_owned(), entry);
+        entries.touch(&ext_id);
+        while entries.items.len() > self.max_entries {
+            match entries.lru.pop_first() {
+                Some((_, oldest)) => entries.items.remove(&oldest),
+                None => break,
+            };
+        }
+    }
+
+    /// Removes a single record; returns `true` if it was cached
+    pub async fn invalidate(&self, ext_id: &ExternalId) -> bool {
+        self.entries.lock().await.remove(ext_id).is_some()
+    }
+
+    /// Removes all records and resets the statistics; returns the number of removed records
+    pub async fn flush(&self) -> usize {
+        let mut entries = self.entries.lock().await;
+        let ret = entries.items.len();
+        *entries = CacheEntries::default();
+        self.hits.store(0, Ordering::Relaxed);
+        self.misses.store(0, Ordering::Relaxed);
+        ret
+    }
+
+    pub async fn stats(&self) -> CacheStats {
+        let hits = self.hits.load(Ordering::Relaxed);
+        let misses = self.misses.load(Ordering::Relaxed);
+        let hit_rate = match hits + misses {
+            0 => 0.0,
+            total => hits as f64 / total as f64,
+        };
+        CacheStats {
+            entries: self.entries.lock().await.items.len(),
+            max_entries: self.max_entries,
+            ttl_secs: self.ttl.map_or(0, |ttl| ttl.as_secs()),
+            hits,
+            misses,
+            hit_rate,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_cache() {
+        let cache = ResponseCache::new();
+        let ext_id = ExternalId::new(227, "118523813");
+        assert!(cache.get(&ext_id).await.is_none());
+        cache.insert(ext_id.clone(), MetaItem::new()).await;
+        assert!(cache.get(&ext_id).await.is_some());
+        let stats = cache.stats().await;
+        assert_eq!(stats.entries, 1);
+        assert_eq!(stats.hits, 1);
+        assert_eq!(stats.misses, 1);
+        assert_eq!(stats.hit_rate, 0.5);
+        assert!(cache.invalidate(&ext_id).await);
+        assert!(!cache.invalidate(&ext_id).await);
+        cache.insert(ext_id.clone(), MetaItem::new()).await;
+        assert_eq!(cache.flush().await, 1);
+        assert_eq!(cache.stats().await.hits, 0);
+    }
+
+    #[tokio::test]
+    async fn test_cache_eviction() {
+        let cache = ResponseCache::with_max_entries(10);
+        for i in 0..=10 {
+            cache
+                .insert(ExternalId::new(227, &i.to_string()), MetaItem::new())
+                .await;
+        }
+        assert_eq!(cache.stats().await.entries, 10);
+        assert!(cache.get(&ExternalId::new(227, "0")).await.is_none());
+        assert!(cache.get(&ExternalId::new(227, "1")).await.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_cache_lru() {
+        let cache = ResponseCache::with_max_entries(2);
+        let ids: Vec<ExternalId> = (0..3)
+            .map(|i| ExternalId::new(227, &i.to_string()))
+            .collect();
+        cache.insert(ids[0].clone(), MetaItem::new()).await;
+        cache.insert(ids[1].clone(), MetaItem::new()).await;
+        // Using the oldest entry keeps it
+        assert!(cache.get(&ids[0]).await.is_some());
+        cache.insert(ids[2].clone(), MetaItem::new()).await;
+        assert!(cache.get(&ids[0]).await.is_some());
+        assert!(cache.get(&ids[1]).await.is_none());
+        assert!(cache.get(&ids[2]).await.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_cache_ttl() {
+        let cache = ResponseCache::with_limits(10, Some(Duration::from_millis(50)));
+        let ext_id = ExternalId::new(227, "118523813");
+        cache.insert(ext_id.clone(), MetaItem::new()).await;
+        assert!(cache.get(&ext_id).await.is_some());
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(cache.get(&ext_id).await.is_none());
+        assert_eq!(cache.stats().await.entries, 0);
+    }
+}
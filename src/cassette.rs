@@ -0,0 +1,232 @@
+use crate::config::{CassetteMode, Config};
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+lazy_static! {
+    static ref CASSETTE: Option<Cassette> = {
+        let config = &Config::get().cassette;
+        if config.mode == CassetteMode::Off {
+            return None;
+        }
+        match Cassette::open(Path::new(&config.dir), config.mode) {
+            Ok(cassette) => Some(cassette),
+            Err(e) => {
+                tracing::warn!("Could not open cassette {}: {e}", config.dir);
+                None
+            }
+        }
+    };
+}
+
+/// A response as recorded, including error responses
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RecordedResponse {
+    #[serde(default = "RecordedResponse::default_status")]
+    pub status: u16,
+    #[serde(default)]
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl RecordedResponse {
+    /// Recordings made before statuses were recorded only exist for successful requests
+    fn default_status() -> u16 {
+        200
+    }
+
+    /// Headers with a readable value, in order
+    pub fn headers_from(headers: &reqwest::header::HeaderMap) -> Vec<(String, String)> {
+        headers
+            .iter()
+            .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+            .collect()
+    }
+
+    /// Turns the recording back into a response, so callers see the same status and headers as in the live one
+    pub fn into_response(self) -> Result<reqwest::Response> {
+        let mut builder = axum::http::Response::builder().status(self.status);
+        for (name, value) in &self.headers {
+            builder = builder.header(name, value);
+        }
+        Ok(reqwest::Response::from(builder.body(self.body)?))
+    }
+}
+
+/// One recorded request, with its response
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Recording {
+    request: String,
+    #[serde(flatten)]
+    response: RecordedResponse,
+}
+
+/// Records the responses to requests sent via `Utility::get_text`, or serves the recorded responses
+/// instead of sending the requests, so tests run without network access and unaffected by upstream changes.
+/// Requests are told apart by method, URL, `Accept` header, and body.
+///
+/// Not covered: API calls that wikimisc makes with its own client, like loading items via `wbgetentities` in
+/// `MetaItem::from_entity`, and edits. Tests that use those still need the network, even in replay mode.
+pub struct Cassette {
+    dir: PathBuf,
+    mode: CassetteMode,
+}
+
+impl Cassette {
+    pub fn open(dir: &Path, mode: CassetteMode) -> Result<Self> {
+        fs::create_dir_all(dir)?;
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            mode,
+        })
+    }
+
+    /// The cassette configured in `[cassette]`, unless its mode is "off"
+    pub fn global() -> Option<&'static Cassette> {
+        CASSETTE.as_ref()
+    }
+
+    pub fn mode(&self) -> CassetteMode {
+        self.mode
+    }
+
+    /// Describes a request, to find its recording
+    pub fn request_key(request: &reqwest::RequestBuilder) -> Result<String> {
+        let request = request
+            .try_clone()
+            .ok_or_else(|| anyhow!("Streaming requests can not be recorded"))?
+            .build()?;
+        let accept = request
+            .headers()
+            .get(reqwest::header::ACCEPT)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default();
+        let body = request
+            .body()
+            .and_then(|body| body.as_bytes())
+            .map(String::from_utf8_lossy)
+            .unwrap_or_default();
+        Ok(format!(
+            "{} {}\nAccept: {accept}\n\n{body}",
+            request.method(),
+            request.url()
+        ))
+    }
+
+    /// The recorded response; an error if there is none, so a replayed test never reaches the network
+    pub fn load(&self, request: &str) -> Result<RecordedResponse> {
+        fs::read_to_string(self.path(request))
+            .ok()
+            .and_then(|text| serde_json::from_str::<Recording>(&text).ok())
+            // Guards against hash collisions
+            .filter(|recording| recording.request == request)
+            .map(|recording| recording.response)
+            .ok_or_else(|| anyhow!("No recorded response for {}", Self::first_line(request)))
+    }
+
+    pub fn save(&self, request: &str, response: &RecordedResponse) -> Result<()> {
+        let recording = Recording {
+            request: request.to_string(),
+            response: response.to_owned(),
+        };
+        let path = self.path(request);
+        let tmp = path.with_extension(format!("tmp{}", std::process::id()));
+        fs::write(&tmp, serde_json::to_string_pretty(&recording)?)?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    fn path(&self, request: &str) -> PathBuf {
+        let hash: String = Sha256::digest(request.as_bytes())
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect();
+        self.dir.join(format!("{hash}.json"))
+    }
+
+    fn first_line(request: &str) -> &str {
+        request.lines().next().unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The cassette committed with the repository
+    fn committed_cassette() -> Cassette {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("cassettes");
+        Cassette::open(&dir, CassetteMode::Replay).unwrap()
+    }
+
+    fn test_cassette(name: &str) -> Cassette {
+        let dir = std::env::temp_dir().join(format!("auth2wd_test_cassette_{name}"));
+        let _ = fs::remove_dir_all(&dir);
+        Cassette::open(&dir, CassetteMode::Replay).unwrap()
+    }
+
+    #[test]
+    fn test_request_key() {
+        let client = reqwest::Client::new();
+        let request = client
+            .get("https://d-nb.info/gnd/118523813/about/lds.rdf")
+            .header(reqwest::header::ACCEPT, "application/rdf+xml");
+        assert_eq!(
+            Cassette::request_key(&request).unwrap(),
+            "GET https://d-nb.info/gnd/118523813/about/lds.rdf\nAccept: application/rdf+xml\n\n"
+        );
+        let request = client
+            .post("https://query.wikidata.org/sparql")
+            .body("query=1");
+        assert!(Cassette::request_key(&request)
+            .unwrap()
+            .ends_with("\n\nquery=1"));
+    }
+
+    #[test]
+    fn test_save_load() {
+        let cassette = test_cassette("save_load");
+        let request = "GET https://viaf.org/viaf/30701597\nAccept: \n\n";
+        assert_eq!(
+            cassette.load(request).unwrap_err().to_string(),
+            "No recorded response for GET https://viaf.org/viaf/30701597"
+        );
+        let response = RecordedResponse {
+            status: 200,
+            headers: vec![(
+                "content-type".to_string(),
+                "application/rdf+xml".to_string(),
+            )],
+            body: "<rdf/>".to_string(),
+        };
+        cassette.save(request, &response).unwrap();
+        assert_eq!(cassette.load(request).unwrap(), response);
+        assert!(cassette
+            .load("POST https://viaf.org/viaf/30701597\nAccept: \n\n")
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_replay() {
+        let cassette = committed_cassette();
+        let client = reqwest::Client::new();
+        let key =
+            Cassette::request_key(&client.get("https://viaf.org/viaf/30701597/viaf.json")).unwrap();
+        let resp = cassette.load(&key).unwrap().into_response().unwrap();
+        assert_eq!(resp.status(), reqwest::StatusCode::OK);
+        assert_eq!(
+            resp.headers()[reqwest::header::CONTENT_TYPE],
+            "application/json"
+        );
+        let j: serde_json::Value = serde_json::from_str(&resp.text().await.unwrap()).unwrap();
+        assert_eq!(j["viafID"], "30701597");
+
+        let key =
+            Cassette::request_key(&client.get("https://d-nb.info/gnd/1/about/lds.rdf")).unwrap();
+        let resp = cassette.load(&key).unwrap().into_response().unwrap();
+        let e: anyhow::Error = resp.error_for_status().unwrap_err().into();
+        assert!(crate::utility::Utility::is_not_found(&e));
+    }
+}
@@ -0,0 +1,187 @@
+use crate::batch::{ItemResult, ItemStatus};
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CheckpointEntry {
+    pub item: String,
+    pub status: ItemStatus,
+}
+
+/// The first line of a checkpoint file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct CheckpointHeader {
+    source: String,
+}
+
+/// One line per processed row, after the header
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct CheckpointLine {
+    row: usize,
+    #[serde(flatten)]
+    entry: CheckpointEntry,
+}
+
+/// Progress of a batch run, so an interrupted run can be resumed.
+/// The file has a header line, then one JSON line per item, appended as items are done; later lines for the
+/// same row win.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Checkpoint {
+    path: PathBuf,
+    pub source: String,
+    pub last_row: Option<usize>,
+    pub items: BTreeMap<usize, CheckpointEntry>,
+}
+
+impl Checkpoint {
+    pub fn new(path: &Path, source: &str) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            source: source.to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// Starts a new checkpoint file, replacing any existing one. The header is written to a temporary file in the
+    /// same directory first, so a crash never leaves a truncated checkpoint behind.
+    pub fn create(path: &Path, source: &str) -> Result<Self> {
+        let ret = Self::new(path, source);
+        let header = CheckpointHeader {
+            source: source.to_string(),
+        };
+        let tmp = Self::temp_path(path);
+        let mut file = OpenOptions::new().write(true).create_new(true).open(&tmp)?;
+        writeln!(file, "{}", serde_json::to_string(&header)?)?;
+        file.sync_all()?;
+        fs::rename(&tmp, path)?;
+        Ok(ret)
+    }
+
+    /// Loads an existing checkpoint to continue it; it must have been written for the same source.
+    /// A damaged line, like one cut short by a crash, is skipped, and ended so new lines are not appended to it.
+    pub fn load(path: &Path, source: &str) -> Result<Self> {
+        let text = fs::read_to_string(path).map_err(|e| anyhow!("{}: {e}", path.display()))?;
+        let mut lines = text.lines();
+        let header: CheckpointHeader = serde_json::from_str(lines.next().unwrap_or_default())
+            .map_err(|e| anyhow!("{}: {e}", path.display()))?;
+        if header.source != source {
+            return Err(anyhow!(
+                "Checkpoint {} was written for '{}', not '{source}'",
+                path.display(),
+                header.source
+            ));
+        }
+        if !text.ends_with('\n') {
+            OpenOptions::new()
+                .append(true)
+                .open(path)?
+                .write_all(b"\n")?;
+        }
+        let mut ret = Self::new(path, source);
+        for line in lines {
+            match serde_json::from_str::<CheckpointLine>(line) {
+                Ok(line) => ret.insert(line.row, line.entry),
+                Err(e) => tracing::warn!("Skipping damaged line in {}: {e}", path.display()),
+            }
+        }
+        Ok(ret)
+    }
+
+    /// Returns `true` if the row was processed without failure in the previous run
+    pub fn is_done(&self, row: usize, item: &str) -> bool {
+        self.items
+            .get(&row)
+            .is_some_and(|entry| entry.item == item && entry.status != ItemStatus::Failed)
+    }
+
+    /// Records the result, and appends it to the checkpoint file
+    pub fn record(&mut self, result: &ItemResult) -> Result<()> {
+        let line = CheckpointLine {
+            row: result.row,
+            entry: CheckpointEntry {
+                item: result.item.to_owned(),
+                status: result.status,
+            },
+        };
+        let mut file = OpenOptions::new().append(true).open(&self.path)?;
+        writeln!(file, "{}", serde_json::to_string(&line)?)?;
+        self.insert(line.row, line.entry);
+        Ok(())
+    }
+
+    fn insert(&mut self, row: usize, entry: CheckpointEntry) {
+        self.last_row = Some(row);
+        self.items.insert(row, entry);
+    }
+
+    /// A temporary file next to `path` that no other process or run uses
+    fn temp_path(path: &Path) -> PathBuf {
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos())
+            .unwrap_or_default();
+        path.with_file_name(format!(".{name}.{}.{nanos}.tmp", std::process::id()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("auth2wd_test_{name}.checkpoint"))
+    }
+
+    #[test]
+    fn test_record_and_is_done() {
+        let path = test_path("record");
+        let mut checkpoint = Checkpoint::create(&path, "x.txt").unwrap();
+        checkpoint
+            .record(&ItemResult::failed(1, "Q1", "boom".to_string()))
+            .unwrap();
+        let mut ok = ItemResult::failed(2, "Q2", String::new());
+        ok.status = ItemStatus::Unchanged;
+        checkpoint.record(&ok).unwrap();
+        assert!(!checkpoint.is_done(1, "Q1"));
+        assert!(checkpoint.is_done(2, "Q2"));
+        assert!(!checkpoint.is_done(2, "Q3"));
+        assert_eq!(checkpoint.last_row, Some(2));
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let path = test_path("load");
+        let mut checkpoint = Checkpoint::create(&path, "items.txt").unwrap();
+        checkpoint
+            .record(&ItemResult::failed(5, "Q42", String::new()))
+            .unwrap();
+        let mut result = ItemResult::failed(5, "Q42", String::new());
+        result.status = ItemStatus::Edited;
+        checkpoint.record(&result).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 3);
+        let loaded = Checkpoint::load(&path, "items.txt").unwrap();
+        assert_eq!(loaded, checkpoint);
+        assert!(loaded.is_done(5, "Q42"));
+        assert!(Checkpoint::load(&path, "other.txt").is_err());
+
+        // A line cut short by a crash
+        OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(b"{\"row\":6,\"it")
+            .unwrap();
+        assert_eq!(Checkpoint::load(&path, "items.txt").unwrap(), checkpoint);
+        let _ = fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,162 @@
+use std::collections::HashMap;
+use tracing::level_filters::LevelFilter;
+
+/// Command line arguments: positional values, `--key=value` or `--key value` options, and `--flag` switches
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CliArgs {
+    positional: Vec<String>,
+    options: HashMap<String, String>,
+}
+
+impl CliArgs {
+    /// Parses arguments, excluding the program name.
+    /// `flags` are the switches that do not take a value; all other `--key` options consume the next argument,
+    /// unless that is another option.
+    pub fn new(args: &[String], flags: &[&str]) -> Self {
+        let mut ret = Self::default();
+        let mut args = args.iter().peekable();
+        while let Some(arg) = args.next() {
+            match arg.strip_prefix("--") {
+                Some(option) => {
+                    let (key, value) = match option.split_once('=') {
+                        Some((key, value)) => (key, value.to_string()),
+                        None if flags.contains(&option) => (option, String::new()),
+                        None => match args.next_if(|next| !next.starts_with("--")) {
+                            Some(value) => (option, value.to_owned()),
+                            None => (option, String::new()),
+                        },
+                    };
+                    ret.options.insert(key.to_string(), value);
+                }
+                None => ret.positional.push(arg.to_owned()),
+            }
+        }
+        ret
+    }
+
+    /// Removes `-v`/`--verbose` and `-q`/`--quiet` (repeatable, eg `-vv`) from the arguments,
+    /// and returns the log level they select, relative to `default`.
+    pub fn extract_verbosity(argv: &mut Vec<String>, default: LevelFilter) -> LevelFilter {
+        const LEVELS: [LevelFilter; 6] = [
+            LevelFilter::OFF,
+            LevelFilter::ERROR,
+            LevelFilter::WARN,
+            LevelFilter::INFO,
+            LevelFilter::DEBUG,
+            LevelFilter::TRACE,
+        ];
+        let mut level = LEVELS.iter().position(|l| *l == default).unwrap_or(2) as i32;
+        argv.retain(|arg| {
+            let delta = match arg.as_str() {
+                "--verbose" => 1,
+                "--quiet" => -1,
+                s if s.len() > 1 && s.starts_with('-') && s[1..].chars().all(|c| c == 'v') => {
+                    s.len() as i32 - 1
+                }
+                s if s.len() > 1 && s.starts_with('-') && s[1..].chars().all(|c| c == 'q') => {
+                    1 - s.len() as i32
+                }
+                _ => return true,
+            };
+            level += delta;
+            false
+        });
+        LEVELS[level.clamp(0, LEVELS.len() as i32 - 1) as usize]
+    }
+
+    pub fn positional(&self, num: usize) -> Option<&str> {
+        self.positional.get(num).map(|s| s.as_str())
+    }
+
+    pub fn option(&self, key: &str) -> Option<&str> {
+        self.options.get(key).map(|s| s.as_str())
+    }
+
+    /// Parses an option value, using `default` if it is missing
+    pub fn option_parsed<T: std::str::FromStr>(&self, key: &str, default: T) -> Result<T, String> {
+        match self.option(key) {
+            Some(value) => value
+                .parse()
+                .map_err(|_| format!("Bad value for --{key}: '{value}'")),
+            None => Ok(default),
+        }
+    }
+
+    /// Splits a comma-separated option value, eg `--sources P227,P268`
+    pub fn option_list(&self, key: &str) -> Vec<&str> {
+        self.option(key)
+            .map(|v| {
+                v.split(',')
+                    .map(|s| s.trim())
+                    .filter(|s| !s.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    pub fn flag(&self, key: &str) -> bool {
+        self.options.contains_key(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extract_verbosity() {
+        let mut argv: Vec<String> = ["auth2wd", "-vv", "list", "-q", "x.txt", "--verbose"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        assert_eq!(
+            CliArgs::extract_verbosity(&mut argv, LevelFilter::WARN),
+            LevelFilter::DEBUG
+        );
+        assert_eq!(argv, vec!["auth2wd", "list", "x.txt"]);
+        let mut argv = vec!["auth2wd".to_string(), "-qq".to_string()];
+        assert_eq!(
+            CliArgs::extract_verbosity(&mut argv, LevelFilter::INFO),
+            LevelFilter::OFF
+        );
+    }
+
+    #[test]
+    fn test_cli_args() {
+        let args: Vec<String> = [
+            "merge",
+            "Q42",
+            "--format=diff",
+            "--compact",
+            "new.json",
+            "--delay",
+            "3",
+            "--sources",
+            "P227, P268",
+        ]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+        let args = CliArgs::new(&args, &["compact"]);
+        assert_eq!(args.positional(0), Some("merge"));
+        assert_eq!(args.positional(2), Some("new.json"));
+        assert_eq!(args.positional(3), None);
+        assert_eq!(args.option("format"), Some("diff"));
+        assert!(args.flag("compact"));
+        assert!(!args.flag("format2"));
+        assert_eq!(args.option_parsed("delay", 5u64), Ok(3));
+        assert_eq!(args.option_parsed("maxlag", 5u64), Ok(5));
+        assert!(args.option_parsed::<u64>("format", 0).is_err());
+        assert_eq!(args.option_list("sources"), vec!["P227", "P268"]);
+        assert!(args.option_list("exclude").is_empty());
+
+        let args: Vec<String> = ["list", "ids.txt", "--key", "--format", "csv"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let args = CliArgs::new(&args, &[]);
+        assert_eq!(args.option("key"), Some(""));
+        assert_eq!(args.option("format"), Some("csv"));
+        assert_eq!(args.positional(1), Some("ids.txt"));
+    }
+}
@@ -1,15 +1,30 @@
+use crate::app_error::AppError;
+use crate::cache::ResponseCache;
+use crate::config::{Config, MergeStrategy};
 use crate::external_id::*;
 use crate::external_importer::*;
 use crate::meta_item::*;
+use crate::rdf_dataset::SourceGraph;
 use crate::supported_property::SUPPORTED_PROPERTIES;
-use anyhow::{anyhow, Result};
+use crate::target::{Direction, Target};
+use crate::utility::Utility;
+use anyhow::Result;
 use futures::future::join_all;
 use std::collections::HashMap;
 use std::collections::HashSet;
+use wikimisc::merge_diff::MergeDiff;
 
 #[derive(Debug, Clone, Default)]
 pub struct Combinator {
     pub items: HashMap<String, MetaItem>,
+    sources: Option<HashSet<usize>>,
+    exclude: HashSet<usize>,
+    max_depth: Option<usize>,
+    redirects: HashMap<ExternalId, ExternalId>,
+    imported: Vec<ExternalId>,
+    other_linked_items: Vec<String>,
+    keep_graphs: bool,
+    graphs: Vec<SourceGraph>,
 }
 
 impl Combinator {
@@ -17,34 +32,109 @@ impl Combinator {
         Self::default()
     }
 
+    /// Only fetch and follow external IDs for these properties
+    pub fn set_sources(&mut self, properties: &[usize]) {
+        self.sources = Some(properties.iter().cloned().collect());
+    }
+
+    /// Never fetch or follow external IDs for these properties
+    pub fn set_exclude(&mut self, properties: &[usize]) {
+        self.exclude = properties.iter().cloned().collect();
+    }
+
+    /// Limits how many rounds of newly discovered external IDs are followed.
+    /// 0 only imports the initial IDs, 1 also the IDs found on their records, etc.
+    pub fn set_max_depth(&mut self, max_depth: Option<usize>) {
+        self.max_depth = max_depth;
+    }
+
+    /// Requested IDs that the source redirected to another (canonical) ID
+    pub fn redirects(&self) -> &HashMap<ExternalId, ExternalId> {
+        &self.redirects
+    }
+
+    /// The records that were imported, in order; unlike `items`, this is kept when combining
+    pub fn imported(&self) -> &[ExternalId] {
+        &self.imported
+    }
+
+    /// After `extend`, the items other than the extended one that the sources link to, like the WKP member
+    /// of a VIAF cluster; the cluster may conflate several entities
+    pub fn other_linked_items(&self) -> &[String] {
+        &self.other_linked_items
+    }
+
+    /// Keeps the raw RDF graph of every record that is fetched, see `graphs`.
+    /// Records are always downloaded then, as the response cache only holds parsed items.
+    pub fn set_keep_graphs(&mut self, keep_graphs: bool) {
+        self.keep_graphs = keep_graphs;
+    }
+
+    /// The graphs of the imported records, in order, if `set_keep_graphs` was used
+    pub fn graphs(&self) -> &[SourceGraph] {
+        &self.graphs
+    }
+
+    pub fn is_allowed(&self, ext_id: &ExternalId) -> bool {
+        let property = ext_id.property();
+        !self.exclude.contains(&property)
+            && self
+                .sources
+                .as_ref()
+                .map_or(true, |sources| sources.contains(&property))
+    }
+
     pub async fn get_parser_for_property(
         property: &str,
         id: &str,
     ) -> Result<Box<dyn ExternalImporter + Send + Sync>> {
         let property = match ExternalId::prop_numeric(property) {
             Some(property) => property,
-            None => return Err(anyhow!("malformed property: '{property}'")),
+            None => {
+                return Err(
+                    AppError::BadRequest(format!("malformed property: '{property}'")).into(),
+                )
+            }
         };
         let ext_id = ExternalId::new(property, id);
         Self::get_parser_for_ext_id(&ext_id).await
     }
 
-    pub fn get_supported_properties() -> Vec<usize> {
-        SUPPORTED_PROPERTIES
-            .iter()
-            .map(|sp| sp.property())
-            .collect()
+    pub async fn get_parser_for_ext_id(
+        id: &ExternalId,
+    ) -> Result<Box<dyn ExternalImporter + Send + Sync>> {
+        let mut parser = Self::get_unfetched_parser_for_ext_id(id)?;
+        parser.fetch().await?;
+        Ok(parser)
     }
 
-    pub async fn get_parser_for_ext_id(
+    /// Like `get_parser_for_ext_id`, but reads the record from a local copy, eg from a dump
+    pub fn get_parser_for_ext_id_from_text(
+        id: &ExternalId,
+        text: &str,
+    ) -> Result<Box<dyn ExternalImporter + Send + Sync>> {
+        let mut parser = Self::get_unfetched_parser_for_ext_id(id)?;
+        parser.parse(text)?;
+        Ok(parser)
+    }
+
+    /// Like `get_parser_for_ext_id`, but does not download the record yet
+    pub fn get_unfetched_parser_for_ext_id(
         id: &ExternalId,
     ) -> Result<Box<dyn ExternalImporter + Send + Sync>> {
+        if !id.is_well_formed() {
+            return Err(AppError::BadRequest(format!("malformed external ID: '{id}'")).into());
+        }
         match SUPPORTED_PROPERTIES
             .iter()
             .find(|sp| sp.property() == id.property())
         {
-            Some(sp) => sp.generator(id.id()).await,
-            None => Err(anyhow!("unsupported property: '{}'", id.property())),
+            Some(sp) => sp.importer(id.id()),
+            None => Err(AppError::BadRequest(format!(
+                "unsupported property: 'P{}'",
+                id.property()
+            ))
+            .into()),
         }
     }
 
@@ -56,45 +146,95 @@ impl Combinator {
 
     pub async fn import(&mut self, ids: Vec<ExternalId>) -> Result<()> {
         let mut ids_used: HashSet<ExternalId> = HashSet::new();
-        let mut ids = ids.to_owned();
+        let mut ids: Vec<ExternalId> = ids
+            .into_iter()
+            .filter(|id| self.is_allowed(id) && id.is_well_formed())
+            .collect();
+        let mut depth = 0;
         while !ids.is_empty() {
+            let follow = self.max_depth.map_or(true, |max_depth| depth < max_depth);
+            depth += 1;
             ids.sort();
             ids.dedup();
-            let mut futures = vec![];
+            let cache = ResponseCache::global();
+            let mut items = vec![];
+            let mut parsers = vec![];
             for ext_id in &ids {
                 ids_used.insert(ext_id.to_owned());
-                let parser = Self::get_parser_for_ext_id(ext_id);
-                futures.push(parser);
+                // Only download records that were not imported yet
+                if self.items.contains_key(&ext_id.to_string()) {
+                    continue;
+                }
+                // Parsed recently, by a server route or another combinator
+                if !self.keep_graphs {
+                    if let Some(item) = cache.get(ext_id).await {
+                        items.push((ext_id.to_owned(), item));
+                        continue;
+                    }
+                }
+                if let Ok(parser) = Self::get_unfetched_parser_for_ext_id(ext_id) {
+                    parsers.push((ext_id.to_owned(), parser));
+                }
             }
-            let parsers = join_all(futures).await;
-            let parsers: Vec<_> = parsers
+            // Records that fail to load are skipped, unless the fetch budget is exhausted
+            let fetched = join_all(parsers.into_iter().map(|(ext_id, mut parser)| async move {
+                match parser.fetch().await {
+                    Ok(()) => Ok(Some((ext_id, parser))),
+                    Err(e) if Utility::is_overloaded(&e) => Err(e),
+                    Err(_) => Ok(None),
+                }
+            }))
+            .await
+            .into_iter()
+            .collect::<Result<Vec<_>>>()?;
+            let parsers: Vec<_> = fetched
                 .into_iter()
-                .filter_map(|parser| parser.ok())
+                .flatten()
+                .filter_map(|(ext_id, parser)| {
+                    let canonical = ExternalId::new(parser.my_property(), &parser.my_id());
+                    if canonical != ext_id {
+                        ids_used.insert(canonical.to_owned());
+                        self.redirects.insert(ext_id, canonical.to_owned());
+                    }
+                    // A redirect target may have been imported already
+                    (!self.items.contains_key(&canonical.to_string())).then_some(parser)
+                })
                 .collect();
 
             ids.clear();
-            let mut futures = vec![];
-            for parser in &parsers {
-                let key = ExternalId::new(parser.my_property(), &parser.my_id()).to_string();
-                if self.items.contains_key(&key) {
-                    continue;
+            let results = join_all(parsers.iter().map(|parser| parser.run())).await;
+            for (parser, result) in std::iter::zip(parsers, results) {
+                if self.keep_graphs {
+                    self.graphs
+                        .extend(SourceGraph::from_parser(parser.as_ref()));
+                }
+                match result {
+                    Ok(item) => {
+                        let ext_id = ExternalId::new(parser.my_property(), &parser.my_id());
+                        cache.insert(ext_id.to_owned(), item.clone()).await;
+                        items.push((ext_id, item));
+                    }
+                    Err(e) if Utility::is_overloaded(&e) => return Err(e),
+                    Err(_) => {}
                 }
-                futures.push(parser.run());
             }
-            let items = join_all(futures).await;
-            for (parser, item) in std::iter::zip(parsers, items) {
-                let item = match item {
-                    Ok(item) => item,
-                    Err(_) => continue,
-                };
-                let key = ExternalId::new(parser.my_property(), &parser.my_id()).to_string();
+            for (ext_id, item) in items {
+                let key = ext_id.to_string();
                 if self.items.contains_key(&key) {
                     continue;
                 }
                 let external_ids = item.get_external_ids();
                 self.items.insert(key, item);
+                self.imported.push(ext_id);
+                if !follow {
+                    continue;
+                }
                 for external_id in external_ids {
-                    if !ids_used.contains(&external_id) && !ids.contains(&external_id) {
+                    if self.is_allowed(&external_id)
+                        && external_id.is_well_formed()
+                        && !ids_used.contains(&external_id)
+                        && !ids.contains(&external_id)
+                    {
                         ids.push(external_id.to_owned());
                     }
                 }
@@ -103,9 +243,87 @@ impl Combinator {
         Ok(())
     }
 
+    /// Loads a Wikidata item, imports all its supported external IDs, and returns the diff to extend it.
+    pub async fn extend_item(item: &str) -> Result<MergeDiff> {
+        Self::new().extend(item).await
+    }
+
+    /// Like `extend_item`, but honors the source filters of this combinator
+    pub async fn extend(&mut self, item: &str) -> Result<MergeDiff> {
+        self.extend_with(item, vec![]).await
+    }
+
+    /// Like `extend`, but also imports `extra_ids` that are not (yet) on the item, eg from a Mix'n'match catalog.
+    /// Their own ID statements are part of the diff.
+    pub async fn extend_with(
+        &mut self,
+        item: &str,
+        extra_ids: Vec<ExternalId>,
+    ) -> Result<MergeDiff> {
+        let mut base_item = MetaItem::from_entity(item).await.map_err(AppError::from)?;
+        let mut ext_ids: Vec<ExternalId> = base_item
+            .get_external_ids()
+            .iter()
+            .filter(|ext_id| Self::has_parser_for_ext_id(ext_id))
+            .cloned()
+            .collect();
+        for ext_id in extra_ids {
+            if Self::has_parser_for_ext_id(&ext_id) && !ext_ids.contains(&ext_id) {
+                ext_ids.push(ext_id);
+            }
+        }
+        self.import(ext_ids).await?;
+        // IDs already on the item stay as they are
+        let base_ids = base_item.get_external_ids();
+        self.redirects
+            .retain(|old_id, _| !base_ids.contains(old_id));
+        let mut other = match self.combine() {
+            Some(other) => other,
+            None => return Err(AppError::NotFound("No items to combine".to_string()).into()),
+        };
+        let wikidata_item = Target::item(item, Direction::FromTarget);
+        self.other_linked_items = other
+            .linked_items
+            .iter()
+            .filter(|linked| **linked != wikidata_item)
+            .cloned()
+            .collect();
+        if !self.other_linked_items.is_empty() {
+            tracing::warn!(
+                "{item}: the sources link to {}; they may describe another entity",
+                self.other_linked_items.join(", ")
+            );
+        }
+        other.fix_dates();
+        other.fix_images(&base_item);
+        if Config::get().merge.strategy == MergeStrategy::NewPropertiesOnly {
+            other.remove_properties_of(&base_item);
+        }
+        Ok(base_item.merge(&other))
+    }
+
+    /// Merges all imported records into one; records from sources with a higher priority in the configuration are merged first.
+    /// Redirected IDs are added with deprecated rank, next to their canonical IDs.
     pub fn combine(&mut self) -> Option<MetaItem> {
+        let mut ret = self.combine_items()?;
+        let mut old_ids: Vec<&ExternalId> = self.redirects.keys().collect();
+        old_ids.sort();
+        for old_id in old_ids {
+            ret.add_redirected_id(old_id);
+        }
+        Some(ret)
+    }
+
+    fn combine_items(&mut self) -> Option<MetaItem> {
         while self.items.len() > 1 {
-            let keys: Vec<String> = self.items.keys().cloned().collect();
+            let mut keys: Vec<String> = self.items.keys().cloned().collect();
+            keys.sort_by_key(|key| {
+                let property = ExternalId::from_string(key).map(|id| id.property());
+                (
+                    property.map(|p| Config::get().source_priority(p)),
+                    key.to_owned(),
+                )
+            });
             let k1 = &keys[0];
             let k2 = &keys[1];
             let other = self.items.get(k2)?.to_owned();
@@ -115,3 +333,21 @@ impl Combinator {
         self.items.iter().next().map(|(_, v)| v.to_owned())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_allowed() {
+        let gnd = ExternalId::new(227, "118523813");
+        let viaf = ExternalId::new(214, "27063124");
+        let mut combinator = Combinator::new();
+        assert!(combinator.is_allowed(&gnd));
+        combinator.set_sources(&[227, 268]);
+        assert!(combinator.is_allowed(&gnd));
+        assert!(!combinator.is_allowed(&viaf));
+        combinator.set_exclude(&[227]);
+        assert!(!combinator.is_allowed(&gnd));
+    }
+}
@@ -0,0 +1,184 @@
+use crate::meta_item::MetaItem;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use wikimisc::wikibase::EntityTrait;
+
+/// Properties that usually have a single true value; a differing value here is a contradiction
+const FUNCTIONAL_PROPERTIES: &[&str] = &["P19", "P20", "P21", "P569", "P570"];
+
+/// Result of comparing an external record with an existing Wikidata item
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Comparison {
+    pub label_match: bool,
+    pub matches: Vec<Value>,
+    pub additions: Vec<Value>,
+    pub contradictions: Vec<Value>,
+    /// Items other than the compared one that the sources of the record link to;
+    /// the record, or the cluster it came from, may belong to another item
+    pub other_linked_items: Vec<String>,
+}
+
+impl Comparison {
+    /// Compares the statements of `record` (parsed from an external source) with those of `item`.
+    /// `own_property` is the external ID property of the record (eg "P227"); a different value for it on the item counts as a contradiction.
+    pub fn new(item: &MetaItem, record: &MetaItem, own_property: &str) -> Self {
+        let item_json = item.item.to_json();
+        let record_json = record.item.to_json();
+        let mut ret = Self {
+            label_match: Self::has_label_match(item, record),
+            other_linked_items: record
+                .linked_items
+                .iter()
+                .filter(|linked| *linked != item.item.id())
+                .cloned()
+                .collect(),
+            ..Default::default()
+        };
+        let record_claims = match record_json["claims"].as_object() {
+            Some(claims) => claims,
+            None => return ret,
+        };
+        for (property, statements) in record_claims {
+            let existing: Vec<Value> = item_json["claims"][property]
+                .as_array()
+                .map(|a| {
+                    a.iter()
+                        .map(|statement| statement["mainsnak"]["datavalue"].to_owned())
+                        .collect()
+                })
+                .unwrap_or_default();
+            for statement in statements.as_array().into_iter().flatten() {
+                let datavalue = &statement["mainsnak"]["datavalue"];
+                let entry = json!({"property":property,"value":datavalue,"existing":existing});
+                if existing.iter().any(|e| Self::values_match(e, datavalue)) {
+                    ret.matches.push(entry);
+                } else if !existing.is_empty()
+                    && (FUNCTIONAL_PROPERTIES.contains(&property.as_str())
+                        || property == own_property)
+                {
+                    ret.contradictions.push(entry);
+                } else {
+                    ret.additions.push(entry);
+                }
+            }
+        }
+        ret
+    }
+
+    fn has_label_match(item: &MetaItem, record: &MetaItem) -> bool {
+        let names = |mi: &MetaItem| -> HashSet<String> {
+            mi.item
+                .labels()
+                .iter()
+                .chain(mi.item.aliases().iter())
+                .map(|ls| ls.value().to_lowercase())
+                .collect()
+        };
+        !names(item).is_disjoint(&names(record))
+    }
+
+    /// Checks if two data values are the same, allowing for different precision in dates
+    fn values_match(a: &Value, b: &Value) -> bool {
+        if a["type"] != b["type"] {
+            return false;
+        }
+        let (va, vb) = (&a["value"], &b["value"]);
+        match a["type"].as_str() {
+            Some("wikibase-entityid") => va["id"] == vb["id"],
+            Some("monolingualtext") => va["text"] == vb["text"],
+            Some("time") => Self::times_match(va, vb),
+            _ => va == vb,
+        }
+    }
+
+    /// Compares two time values up to the lower of their precisions
+    fn times_match(a: &Value, b: &Value) -> bool {
+        let precision = a["precision"]
+            .as_u64()
+            .unwrap_or(9)
+            .min(b["precision"].as_u64().unwrap_or(9));
+        let len = match precision {
+            11.. => 11, // "+YYYY-MM-DD"
+            10 => 8,    // "+YYYY-MM"
+            _ => 5,     // "+YYYY"
+        };
+        let ta = a["time"].as_str().unwrap_or_default();
+        let tb = b["time"].as_str().unwrap_or_default();
+        ta.get(..len) == tb.get(..len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wikimisc::wikibase::{LocaleString, Snak, Statement};
+
+    fn statement(snak: Snak) -> Statement {
+        Statement::new_normal(snak, vec![], vec![])
+    }
+
+    #[test]
+    fn test_times_match() {
+        let a = json!({"time":"+1809-02-12T00:00:00Z","precision":11});
+        let b = json!({"time":"+1809-00-00T00:00:00Z","precision":9});
+        let c = json!({"time":"+1810-00-00T00:00:00Z","precision":9});
+        assert!(Comparison::times_match(&a, &b));
+        assert!(!Comparison::times_match(&a, &c));
+    }
+
+    #[test]
+    fn test_new() {
+        let mut item = MetaItem::new();
+        item.item
+            .labels_mut()
+            .push(LocaleString::new("en", "Charles Darwin"));
+        item.item
+            .add_claim(statement(Snak::new_item("P21", "Q6581097")));
+        item.item.add_claim(statement(Snak::new_time(
+            "P569",
+            "+1809-00-00T00:00:00Z",
+            9,
+        )));
+
+        let mut record = MetaItem::new();
+        record
+            .item
+            .labels_mut()
+            .push(LocaleString::new("de", "charles darwin"));
+        record
+            .item
+            .add_claim(statement(Snak::new_item("P21", "Q6581072")));
+        record.item.add_claim(statement(Snak::new_time(
+            "P569",
+            "+1809-02-12T00:00:00Z",
+            11,
+        )));
+        record
+            .item
+            .add_claim(statement(Snak::new_item("P27", "Q145")));
+
+        let c = Comparison::new(&item, &record, "P227");
+        assert!(c.label_match);
+        assert_eq!(c.matches.len(), 1); // P569
+        assert_eq!(c.contradictions.len(), 1); // P21
+        assert_eq!(c.additions.len(), 1); // P27
+        assert!(c.other_linked_items.is_empty());
+    }
+
+    #[test]
+    fn test_other_linked_items() {
+        let mut item = MetaItem::new();
+        item.item.set_id("Q1035".to_string());
+        let mut record = MetaItem::new();
+        record.add_linked_item("Q1035");
+        assert!(Comparison::new(&item, &record, "P214")
+            .other_linked_items
+            .is_empty());
+        record.add_linked_item("Q42");
+        assert_eq!(
+            Comparison::new(&item, &record, "P214").other_linked_items,
+            vec!["Q42"]
+        );
+    }
+}
@@ -0,0 +1,617 @@
+use crate::cli_args::CliArgs;
+use crate::id_format::IdFormatRule;
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::{env, fs, sync::OnceLock};
+
+pub const DEFAULT_CONFIG_FILE: &str = "auth2wd.toml";
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ServerConfig {
+    pub address: String,
+    pub port: u16,
+    /// Bearer token for the admin routes; they are disabled if this is not set
+    pub admin_token: Option<String>,
+    /// Directory with template overrides
+    pub template_dir: Option<String>,
+    pub images_dir: String,
+    /// Return errors as HTTP 200 `{"status":"..."}` instead of problem+json
+    pub legacy_errors: bool,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            address: "0.0.0.0".to_string(),
+            port: 8000,
+            admin_token: None,
+            template_dir: None,
+            images_dir: "images".to_string(),
+            legacy_errors: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HttpConfig {
+    /// Sent with every upstream request; should name the tool and a way to contact its operator
+    pub user_agent: String,
+    pub timeout_secs: u64,
+    /// Maximum number of redirects followed per request
+    pub max_redirects: usize,
+    /// How often a fetch is retried after a 429/5xx response or a connection error
+    pub retries: u32,
+    /// Delay before the first retry, doubled for each further one (unless the server sends `Retry-After`)
+    pub retry_delay_ms: u64,
+    /// Larger responses are rejected, so pathological records can not exhaust memory
+    pub max_response_bytes: usize,
+    /// Maximum requests per second, per host (including its subdomains)
+    pub rate_limits: HashMap<String, f64>,
+    /// Maximum number of simultaneous upstream fetches
+    pub max_upstream_fetches: usize,
+    /// Maximum number of upstream fetches waiting for a slot
+    pub max_queued_fetches: usize,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self {
+            user_agent: format!(
+                "auth2wd/{} (https://github.com/magnusmanske/auth2wd)",
+                env!("CARGO_PKG_VERSION")
+            ),
+            timeout_secs: 60,
+            max_redirects: 10,
+            retries: 3,
+            retry_delay_ms: 500,
+            max_response_bytes: 64 * 1024 * 1024,
+            rate_limits: [("eutils.ncbi.nlm.nih.gov", 3.0), ("viaf.org", 5.0)]
+                .into_iter()
+                .map(|(host, per_second)| (host.to_string(), per_second))
+                .collect(),
+            max_upstream_fetches: 32,
+            max_queued_fetches: 256,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CacheConfig {
+    pub max_entries: usize,
+    /// How long a parsed record is kept; 0 keeps it until it is evicted
+    pub ttl_secs: u64,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            max_entries: 1000,
+            ttl_secs: 3600,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SourcesConfig {
+    /// Properties in order of preference (eg `["P227", "P268"]`); earlier sources win when records are combined
+    pub priorities: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OAuthConfig {
+    /// JSON file with an `oauth2_token`, for editing Wikidata
+    pub credentials: String,
+}
+
+impl Default for OAuthConfig {
+    fn default() -> Self {
+        Self {
+            credentials: "config.json".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MergeStrategy {
+    /// Add all new statements and references
+    #[default]
+    All,
+    /// Only add statements for properties the item does not have yet
+    NewPropertiesOnly,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MergeConfig {
+    pub strategy: MergeStrategy,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DiskCacheConfig {
+    /// SQLite file for Wikidata and validity lookups; no persistent cache if not set
+    pub path: Option<String>,
+    pub ttl_secs: u64,
+    /// TTL for lookups that found nothing, and IDs found invalid, which are likely to change sooner
+    pub negative_ttl_secs: u64,
+}
+
+impl Default for DiskCacheConfig {
+    fn default() -> Self {
+        Self {
+            path: None,
+            ttl_secs: 7 * 24 * 3600,
+            negative_ttl_secs: 3600,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CassetteMode {
+    /// Requests go to the network as usual
+    #[default]
+    Off,
+    /// Requests go to the network, and responses are saved
+    Record,
+    /// Saved responses are served; requests without one fail
+    Replay,
+}
+
+/// Recorded HTTP responses, for tests that do not depend on the live sources
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CassetteConfig {
+    pub mode: CassetteMode,
+    pub dir: String,
+}
+
+impl Default for CassetteConfig {
+    fn default() -> Self {
+        Self {
+            mode: CassetteMode::Off,
+            dir: "cassettes".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EditLogConfig {
+    /// SQLite file that records all edits; no log if not set
+    pub path: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HttpCacheConfig {
+    /// Directory for cached source records; no HTTP cache if not set
+    pub dir: Option<String>,
+    pub ttl_secs: u64,
+    /// TTL for specific hosts; 0 disables caching for a host
+    pub host_ttl_secs: HashMap<String, u64>,
+}
+
+impl Default for HttpCacheConfig {
+    fn default() -> Self {
+        Self {
+            dir: None,
+            ttl_secs: 24 * 3600,
+            // Wikidata changes all the time; its lookups are cached in `disk_cache` instead
+            host_ttl_secs: [("www.wikidata.org", 0), ("query.wikidata.org", 0)]
+                .into_iter()
+                .map(|(host, secs)| (host.to_string(), secs))
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ValidationConfig {
+    /// Check discovered external IDs against the format regex of their Wikidata property
+    pub format_constraints: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LookupMode {
+    /// `haswbstatement` via the Wikidata search index
+    #[default]
+    Search,
+    /// The Wikidata Query Service, falling back to search
+    Sparql,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WikidataConfig {
+    /// How to find items that have a given external ID
+    pub lookup: LookupMode,
+}
+
+/// The Wikibase installation that items are loaded from, looked up in, and edited on
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TargetConfig {
+    pub api: String,
+    pub rest_api: String,
+    /// Expected to define the `wd:` and `wdt:` prefixes for its own entities, like the Wikidata Query Service
+    pub sparql: String,
+    pub entity_prefix: String,
+    /// Base URL for links to entity pages
+    pub wiki: String,
+    /// Wikidata property to target property, like "P227" = "P12"; if empty, property IDs are used as they are.
+    /// Otherwise, properties without a mapping are left out.
+    pub properties: HashMap<String, String>,
+    /// Wikidata item to target item, like "Q5" = "Q3"; items without a mapping are used as they are
+    pub items: HashMap<String, String>,
+}
+
+impl Default for TargetConfig {
+    fn default() -> Self {
+        Self {
+            api: "https://www.wikidata.org/w/api.php".to_string(),
+            rest_api: "https://www.wikidata.org/w/rest.php/wikibase/v1".to_string(),
+            sparql: "https://query.wikidata.org/sparql".to_string(),
+            entity_prefix: "http://www.wikidata.org/entity/".to_string(),
+            wiki: "https://www.wikidata.org/wiki".to_string(),
+            properties: HashMap::new(),
+            items: HashMap::new(),
+        }
+    }
+}
+
+/// Settings from `auth2wd.toml`, overridable via `AC2WD_*` environment variables
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub http: HttpConfig,
+    pub cache: CacheConfig,
+    pub disk_cache: DiskCacheConfig,
+    pub edit_log: EditLogConfig,
+    pub http_cache: HttpCacheConfig,
+    pub cassette: CassetteConfig,
+    pub sources: SourcesConfig,
+    pub oauth: OAuthConfig,
+    pub merge: MergeConfig,
+    pub wikidata: WikidataConfig,
+    pub validation: ValidationConfig,
+    pub target: TargetConfig,
+    /// Replaces the built-in ID normalization rule of a property, keyed like "P213"
+    pub id_formats: HashMap<String, IdFormatRule>,
+}
+
+impl Config {
+    /// Loads the configuration and makes it available via `Config::get()`.
+    /// The path is taken from `path`, then `AC2WD_CONFIG`; a missing default file is not an error.
+    /// `server_args` (from the `serve` subcommand) take precedence over both file and environment.
+    /// Fails if the configuration was already used, via `Config::get()`, before this was called.
+    pub fn init(path: Option<&str>, server_args: Option<&CliArgs>) -> Result<&'static Config> {
+        let mut config = Self::load(path)?;
+        if let Some(args) = server_args {
+            config.apply_server_args(args)?;
+        }
+        CONFIG
+            .set(config)
+            .map_err(|_| anyhow!("Configuration was used before it was loaded"))?;
+        Ok(Self::get())
+    }
+
+    /// The global configuration; defaults plus environment overrides if `init` was not called
+    pub fn get() -> &'static Config {
+        CONFIG.get_or_init(|| {
+            let mut config = Self::default();
+            config.apply_overrides(|key| env::var(key).ok());
+            config
+        })
+    }
+
+    fn load(path: Option<&str>) -> Result<Self> {
+        let explicit = path
+            .map(|p| p.to_string())
+            .or_else(|| env::var("AC2WD_CONFIG").ok());
+        let path = explicit.as_deref().unwrap_or(DEFAULT_CONFIG_FILE);
+        let mut config = match fs::read_to_string(path) {
+            Ok(text) => Self::from_toml(&text).map_err(|e| anyhow!("{path}: {e}"))?,
+            Err(_) if explicit.is_none() => Self::default(),
+            Err(e) => return Err(anyhow!("{path}: {e}")),
+        };
+        config.apply_overrides(|key| env::var(key).ok());
+        Ok(config)
+    }
+
+    pub fn from_toml(text: &str) -> Result<Self> {
+        Ok(toml::from_str(text)?)
+    }
+
+    /// Finds `--config FILE` or `--config=FILE` in the command line arguments
+    /// A `.json` file is not a configuration file, but the OAuth credentials that `list --config` used to take
+    pub fn path_from_args(argv: &[String]) -> Option<String> {
+        argv.iter()
+            .enumerate()
+            .find_map(|(num, arg)| match arg.strip_prefix("--config") {
+                Some("") => argv.get(num + 1).cloned(),
+                Some(rest) => rest.strip_prefix('=').map(|s| s.to_string()),
+                None => None,
+            })
+            .filter(|path| !Self::is_legacy_oauth_path(path))
+    }
+
+    /// Before the configuration file, `--config` named the OAuth credentials JSON file
+    pub fn is_legacy_oauth_path(path: &str) -> bool {
+        path.ends_with(".json")
+    }
+
+    /// Parses rate limits like "viaf.org=5,eutils.ncbi.nlm.nih.gov=3"
+    fn parse_rate_limits(s: &str) -> Option<HashMap<String, f64>> {
+        s.split(',')
+            .map(|s| s.trim())
+            .filter(|s| !s.is_empty())
+            .map(|s| {
+                let (host, per_second) = s.split_once('=')?;
+                Some((host.trim().to_string(), per_second.trim().parse().ok()?))
+            })
+            .collect()
+    }
+
+    fn apply_overrides(&mut self, get: impl Fn(&str) -> Option<String>) {
+        let parse = |key: &str| get(key).and_then(|v| v.parse().ok());
+        if let Some(v) = get("AC2WD_ADDRESS") {
+            self.server.address = v;
+        }
+        if let Some(v) = parse("AC2WD_PORT") {
+            self.server.port = v;
+        }
+        if let Some(v) = get("AC2WD_ADMIN_TOKEN") {
+            self.server.admin_token = Some(v);
+        }
+        if let Some(v) = get("AC2WD_TEMPLATE_DIR") {
+            self.server.template_dir = Some(v);
+        }
+        if let Some(v) = get("AC2WD_IMAGES_DIR") {
+            self.server.images_dir = v;
+        }
+        if let Some(v) = get("AC2WD_LEGACY_ERRORS") {
+            self.server.legacy_errors = !v.is_empty() && v != "0";
+        }
+        if let Some(v) = get("AC2WD_USER_AGENT") {
+            self.http.user_agent = v;
+        }
+        if let Some(v) = parse("AC2WD_HTTP_TIMEOUT_SECS") {
+            self.http.timeout_secs = v;
+        }
+        if let Some(v) = parse("AC2WD_HTTP_MAX_REDIRECTS") {
+            self.http.max_redirects = v;
+        }
+        if let Some(v) = parse("AC2WD_HTTP_MAX_RESPONSE_BYTES") {
+            self.http.max_response_bytes = v;
+        }
+        if let Some(v) = parse("AC2WD_HTTP_RETRIES") {
+            self.http.retries = v;
+        }
+        if let Some(v) = parse("AC2WD_HTTP_RETRY_DELAY_MS") {
+            self.http.retry_delay_ms = v;
+        }
+        if let Some(v) = get("AC2WD_RATE_LIMITS") {
+            match Self::parse_rate_limits(&v) {
+                Some(rate_limits) => self.http.rate_limits.extend(rate_limits),
+                None => tracing::warn!("Ignoring malformed AC2WD_RATE_LIMITS: '{v}'"),
+            }
+        }
+        if let Some(v) = parse("AC2WD_MAX_UPSTREAM_FETCHES") {
+            self.http.max_upstream_fetches = v;
+        }
+        if let Some(v) = parse("AC2WD_MAX_QUEUED_FETCHES") {
+            self.http.max_queued_fetches = v;
+        }
+        if let Some(v) = parse("AC2WD_CACHE_MAX_ENTRIES") {
+            self.cache.max_entries = v;
+        }
+        if let Some(v) = parse("AC2WD_CACHE_TTL_SECS") {
+            self.cache.ttl_secs = v;
+        }
+        if let Some(v) = get("AC2WD_DISK_CACHE") {
+            self.disk_cache.path = Some(v);
+        }
+        if let Some(v) = parse("AC2WD_DISK_CACHE_TTL_SECS") {
+            self.disk_cache.ttl_secs = v;
+        }
+        if let Some(v) = parse("AC2WD_DISK_CACHE_NEGATIVE_TTL_SECS") {
+            self.disk_cache.negative_ttl_secs = v;
+        }
+        if let Some(v) = get("AC2WD_EDIT_LOG") {
+            self.edit_log.path = Some(v);
+        }
+        if let Some(v) = get("AC2WD_HTTP_CACHE") {
+            self.http_cache.dir = Some(v);
+        }
+        if let Some(v) = parse("AC2WD_HTTP_CACHE_TTL_SECS") {
+            self.http_cache.ttl_secs = v;
+        }
+        if let Some(v) = get("AC2WD_FORMAT_CONSTRAINTS") {
+            self.validation.format_constraints = !v.is_empty() && v != "0";
+        }
+        if let Some(v) = get("AC2WD_TARGET_API") {
+            self.target.api = v;
+        }
+        if let Some(v) = get("AC2WD_TARGET_REST_API") {
+            self.target.rest_api = v;
+        }
+        if let Some(v) = get("AC2WD_TARGET_SPARQL") {
+            self.target.sparql = v;
+        }
+        if let Some(v) = get("AC2WD_TARGET_ENTITY_PREFIX") {
+            self.target.entity_prefix = v;
+        }
+        if let Some(v) = get("AC2WD_TARGET_WIKI") {
+            self.target.wiki = v;
+        }
+        if let Some(v) = get("AC2WD_CASSETTE_DIR") {
+            self.cassette.dir = v;
+        }
+        match get("AC2WD_CASSETTE").as_deref() {
+            Some("off") => self.cassette.mode = CassetteMode::Off,
+            Some("record") => self.cassette.mode = CassetteMode::Record,
+            Some("replay") => self.cassette.mode = CassetteMode::Replay,
+            _ => {}
+        }
+        match get("AC2WD_WIKIDATA_LOOKUP").as_deref() {
+            Some("search") => self.wikidata.lookup = LookupMode::Search,
+            Some("sparql") => self.wikidata.lookup = LookupMode::Sparql,
+            _ => {}
+        }
+    }
+
+    /// Applies `--address`, `--port`, `--html-dir`, and `--images-dir`
+    fn apply_server_args(&mut self, args: &CliArgs) -> Result<()> {
+        if let Some(v) = args.option("address") {
+            self.server.address = v.to_string();
+        }
+        self.server.port = args
+            .option_parsed("port", self.server.port)
+            .map_err(|e| anyhow!(e))?;
+        if let Some(v) = args.option("html-dir") {
+            self.server.template_dir = Some(v.to_string());
+        }
+        if let Some(v) = args.option("images-dir") {
+            self.server.images_dir = v.to_string();
+        }
+        Ok(())
+    }
+
+    /// The configuration as TOML, with secrets masked, for logging at startup
+    pub fn effective(&self) -> String {
+        let mut config = self.to_owned();
+        if config.server.admin_token.is_some() {
+            config.server.admin_token = Some("***".to_string());
+        }
+        toml::to_string(&config).unwrap_or_else(|e| format!("{e}"))
+    }
+
+    /// The rank of a property in `sources.priorities`; unlisted properties come last
+    pub fn source_priority(&self, property: usize) -> usize {
+        let property = format!("P{property}");
+        self.sources
+            .priorities
+            .iter()
+            .position(|p| p.eq_ignore_ascii_case(&property))
+            .unwrap_or(self.sources.priorities.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_from_toml() {
+        let config = Config::from_toml(
+            r#"
+            [server]
+            port = 8080
+
+            [sources]
+            priorities = ["P227", "P268"]
+
+            [merge]
+            strategy = "new_properties_only"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.server.port, 8080);
+        assert_eq!(config.server.address, "0.0.0.0");
+        assert_eq!(config.cache.max_entries, 1000);
+        assert_eq!(config.merge.strategy, MergeStrategy::NewPropertiesOnly);
+        assert_eq!(config.source_priority(227), 0);
+        assert_eq!(config.source_priority(268), 1);
+        assert_eq!(config.source_priority(214), 2);
+        assert!(Config::from_toml("[server]\nport = \"x\"").is_err());
+    }
+
+    #[test]
+    fn test_apply_overrides() {
+        let env: HashMap<&str, &str> = [
+            ("AC2WD_PORT", "9000"),
+            ("AC2WD_LEGACY_ERRORS", "1"),
+            ("AC2WD_WIKIDATA_LOOKUP", "sparql"),
+            ("AC2WD_CASSETTE", "replay"),
+            ("AC2WD_RATE_LIMITS", "viaf.org=2, lobid.org=10"),
+        ]
+        .into_iter()
+        .collect();
+        let mut config = Config::default();
+        config.apply_overrides(|key| env.get(key).map(|v| v.to_string()));
+        assert_eq!(config.server.port, 9000);
+        assert!(config.server.legacy_errors);
+        assert_eq!(config.wikidata.lookup, LookupMode::Sparql);
+        assert_eq!(config.cassette.mode, CassetteMode::Replay);
+        assert_eq!(config.cassette.dir, "cassettes");
+        assert_eq!(config.http.rate_limits["viaf.org"], 2.0);
+        assert_eq!(config.http.rate_limits["lobid.org"], 10.0);
+        assert_eq!(config.http.rate_limits["eutils.ncbi.nlm.nih.gov"], 3.0);
+        assert_eq!(Config::parse_rate_limits("viaf.org"), None);
+    }
+
+    #[test]
+    fn test_apply_server_args() {
+        let args: Vec<String> = ["serve", "--port", "8080", "--html-dir=tpl"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let mut config = Config::default();
+        config.apply_server_args(&CliArgs::new(&args, &[])).unwrap();
+        assert_eq!(config.server.port, 8080);
+        assert_eq!(config.server.template_dir, Some("tpl".to_string()));
+        assert_eq!(config.server.images_dir, "images");
+        let args = vec!["serve".to_string(), "--port=x".to_string()];
+        assert!(config.apply_server_args(&CliArgs::new(&args, &[])).is_err());
+    }
+
+    #[test]
+    fn test_effective() {
+        let mut config = Config::default();
+        config.server.admin_token = Some("secret".to_string());
+        let text = config.effective();
+        assert!(!text.contains("secret"));
+        assert!(text.contains("port = 8000"));
+    }
+
+    #[test]
+    fn test_path_from_args() {
+        let args = |a: &[&str]| a.iter().map(|s| s.to_string()).collect::<Vec<String>>();
+        assert_eq!(
+            Config::path_from_args(&args(&["auth2wd", "--config", "a.toml"])),
+            Some("a.toml".to_string())
+        );
+        assert_eq!(
+            Config::path_from_args(&args(&["auth2wd", "list", "--config=b.toml"])),
+            Some("b.toml".to_string())
+        );
+        assert_eq!(
+            Config::path_from_args(&args(&["auth2wd", "--configx"])),
+            None
+        );
+        assert_eq!(
+            Config::path_from_args(&args(&[
+                "auth2wd",
+                "list",
+                "x.txt",
+                "--config",
+                "config.json"
+            ])),
+            None
+        );
+    }
+}
@@ -0,0 +1,109 @@
+use crate::combinator::Combinator;
+use crate::external_id::ExternalId;
+use crate::target::{Direction, Target};
+use anyhow::{anyhow, Result};
+use serde_json::{json, Value};
+use std::io::Read;
+
+/// One row of the CSV, for one entity that has no item yet
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CsvRow {
+    /// Row number in the file; the header is row 1
+    pub row: usize,
+    pub ids: Vec<ExternalId>,
+}
+
+/// The JSON for a new item, and the existing items its sources link to; with any of those,
+/// the row is probably a duplicate
+#[derive(Debug, Clone, PartialEq)]
+pub struct NewItem {
+    pub item: Value,
+    pub existing_items: Vec<String>,
+}
+
+/// Reads a CSV with a column per property, like "P227", and an external ID per cell, and builds new items from them.
+/// Columns that are not properties, like a name for reference, are ignored.
+pub struct CsvIngest {}
+
+impl CsvIngest {
+    pub fn read_rows<R: Read>(reader: R) -> Result<Vec<CsvRow>> {
+        let mut reader = csv::ReaderBuilder::new().flexible(true).from_reader(reader);
+        let columns: Vec<Option<usize>> = reader
+            .headers()?
+            .iter()
+            .map(ExternalId::prop_numeric)
+            .collect();
+        if columns.iter().all(|c| c.is_none()) {
+            return Err(anyhow!(
+                "No property columns, like 'P227', in the CSV header"
+            ));
+        }
+        let mut rows = vec![];
+        for (num, record) in reader.records().enumerate() {
+            let record = record?;
+            let ids: Vec<ExternalId> = std::iter::zip(&columns, record.iter())
+                .filter_map(|(property, id)| Some(((*property)?, id.trim())))
+                .filter(|(_, id)| !id.is_empty())
+                .map(|(property, id)| ExternalId::new(property, id))
+                .collect();
+            if !ids.is_empty() {
+                rows.push(CsvRow { row: num + 2, ids });
+            }
+        }
+        Ok(rows)
+    }
+
+    /// Imports the records of the row, and everything they link to, into the JSON for a new item.
+    /// `combinator` carries the source filters; it should not have imported anything yet.
+    pub async fn new_item(row: &CsvRow, mut combinator: Combinator) -> Result<NewItem> {
+        combinator.import(row.ids.to_owned()).await?;
+        let item = combinator
+            .combine()
+            .ok_or_else(|| anyhow!("No records could be imported"))?;
+        let mut j = Target::map_json(&json!(item)["item"], Direction::ToTarget);
+        if let Some(o) = j.as_object_mut() {
+            o.remove("id");
+        }
+        let existing_items = item
+            .linked_items
+            .iter()
+            .map(|linked| Target::item(linked, Direction::ToTarget))
+            .collect();
+        Ok(NewItem {
+            item: j,
+            existing_items,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_rows() {
+        let csv = "name,P227,P214\nGoethe,118540238,24602065\nNobody,,\nSchiller, 118607626 \n";
+        let rows = CsvIngest::read_rows(csv.as_bytes()).unwrap();
+        assert_eq!(
+            rows,
+            vec![
+                CsvRow {
+                    row: 2,
+                    ids: vec![
+                        ExternalId::new(227, "118540238"),
+                        ExternalId::new(214, "24602065")
+                    ],
+                },
+                CsvRow {
+                    row: 4,
+                    ids: vec![ExternalId::new(227, "118607626")],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_read_rows_without_properties() {
+        assert!(CsvIngest::read_rows("name,gnd\nGoethe,118540238\n".as_bytes()).is_err());
+    }
+}
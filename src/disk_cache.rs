@@ -0,0 +1,203 @@
+use crate::config::Config;
+use anyhow::{anyhow, Result};
+use chrono::Utc;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+lazy_static! {
+    static ref DISK_CACHE: Option<DiskCache> = {
+        let config = &Config::get().disk_cache;
+        let path = config.path.as_ref()?;
+        let ttl = Duration::from_secs(config.ttl_secs);
+        let negative_ttl = Duration::from_secs(config.negative_ttl_secs);
+        match DiskCache::open(path, ttl, negative_ttl) {
+            Ok(cache) => Some(cache),
+            Err(e) => {
+                tracing::warn!("Could not open disk cache {path}: {e}");
+                None
+            }
+        }
+    };
+}
+
+/// Optional SQLite-backed cache for results that rarely change, like Wikidata and validity lookups.
+/// Survives restarts, and is shared between server and CLI runs.
+/// Negative results (nothing found, invalid) expire sooner, as they are the ones most likely to change.
+/// SQLite calls block, so they run on the blocking thread pool.
+pub struct DiskCache {
+    conn: Arc<Mutex<Connection>>,
+    ttl: Duration,
+    negative_ttl: Duration,
+}
+
+impl DiskCache {
+    pub fn open(path: &str, ttl: Duration, negative_ttl: Duration) -> Result<Self> {
+        let conn = Connection::open(path)?;
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS `cache` (
+                `namespace` TEXT NOT NULL,
+                `key` TEXT NOT NULL,
+                `value` TEXT NOT NULL,
+                `expires` INTEGER NOT NULL,
+                PRIMARY KEY (`namespace`, `key`)
+            )",
+        )?;
+        conn.execute(
+            "DELETE FROM `cache` WHERE `expires`<=?1",
+            params![Utc::now().timestamp()],
+        )?;
+        Ok(Self {
+            conn: Arc::new(Mutex::new(conn)),
+            ttl,
+            negative_ttl,
+        })
+    }
+
+    /// The cache configured in `disk_cache.path`, if any
+    pub fn global() -> Option<&'static DiskCache> {
+        DISK_CACHE.as_ref()
+    }
+
+    /// Returns the cached value, unless it is missing, expired, or unreadable
+    pub async fn get<T: DeserializeOwned>(&self, namespace: &str, key: &str) -> Option<T> {
+        let (namespace, key) = (namespace.to_string(), key.to_string());
+        let text: String = self
+            .blocking(move |conn| {
+                Ok(conn
+                    .query_row(
+                        "SELECT `value` FROM `cache` WHERE `namespace`=?1 AND `key`=?2 AND `expires`>?3",
+                        params![namespace, key, Utc::now().timestamp()],
+                        |row| row.get(0),
+                    )
+                    .optional()?)
+            })
+            .await
+            .ok()??;
+        serde_json::from_str(&text).ok()
+    }
+
+    /// Stores a value; `negative` results, like an empty lookup, use the shorter TTL
+    pub async fn set<T: Serialize>(
+        &self,
+        namespace: &str,
+        key: &str,
+        value: &T,
+        negative: bool,
+    ) -> Result<()> {
+        let text = serde_json::to_string(value)?;
+        let ttl = if negative {
+            self.negative_ttl
+        } else {
+            self.ttl
+        };
+        let expires = Utc::now().timestamp() + ttl.as_secs() as i64;
+        let (namespace, key) = (namespace.to_string(), key.to_string());
+        self.blocking(move |conn| {
+            conn.execute(
+                "REPLACE INTO `cache` (`namespace`,`key`,`value`,`expires`) VALUES (?1,?2,?3,?4)",
+                params![namespace, key, text, expires],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    pub async fn purge_expired(&self) -> Result<usize> {
+        self.blocking(|conn| {
+            Ok(conn.execute(
+                "DELETE FROM `cache` WHERE `expires`<=?1",
+                params![Utc::now().timestamp()],
+            )?)
+        })
+        .await
+    }
+
+    /// Runs `f` with the connection on the blocking thread pool
+    async fn blocking<T, F>(&self, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Connection) -> Result<T> + Send + 'static,
+    {
+        let conn = self.conn.clone();
+        tokio::task::spawn_blocking(move || {
+            let conn = conn.lock().map_err(|e| anyhow!("{e}"))?;
+            f(&conn)
+        })
+        .await?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_cache() -> DiskCache {
+        DiskCache::open(
+            ":memory:",
+            Duration::from_secs(3600),
+            Duration::from_secs(60),
+        )
+        .unwrap()
+    }
+
+    fn expires(cache: &DiskCache, key: &str) -> i64 {
+        cache
+            .conn
+            .lock()
+            .unwrap()
+            .query_row(
+                "SELECT `expires` FROM `cache` WHERE `key`=?1",
+                params![key],
+                |row| row.get(0),
+            )
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_set_get() {
+        let cache = test_cache();
+        assert_eq!(cache.get::<Vec<String>>("items", "P227:1").await, None);
+        cache
+            .set("items", "P227:1", &vec!["Q1".to_string()], false)
+            .await
+            .unwrap();
+        assert_eq!(
+            cache.get::<Vec<String>>("items", "P227:1").await,
+            Some(vec!["Q1".to_string()])
+        );
+        assert_eq!(cache.get::<Vec<String>>("validity", "P227:1").await, None);
+    }
+
+    #[tokio::test]
+    async fn test_negative_ttl() {
+        let cache = test_cache();
+        let now = Utc::now().timestamp();
+        cache
+            .set("items", "P227:1", &vec!["Q1"], false)
+            .await
+            .unwrap();
+        cache
+            .set("items", "P227:2", &Vec::<String>::new(), true)
+            .await
+            .unwrap();
+        assert!(expires(&cache, "P227:1") >= now + 3600);
+        assert!(expires(&cache, "P227:2") <= now + 61);
+    }
+
+    #[tokio::test]
+    async fn test_expiry() {
+        let cache = test_cache();
+        cache.set("items", "P227:1", &1, false).await.unwrap();
+        cache
+            .conn
+            .lock()
+            .unwrap()
+            .execute("UPDATE `cache` SET `expires`=0", [])
+            .unwrap();
+        assert_eq!(cache.get::<i32>("items", "P227:1").await, None);
+        assert_eq!(cache.purge_expired().await.unwrap(), 1);
+    }
+}
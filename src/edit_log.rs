@@ -0,0 +1,241 @@
+use crate::config::Config;
+use anyhow::{anyhow, Result};
+use chrono::{NaiveDate, Utc};
+use rusqlite::{params, Connection};
+use serde::Serialize;
+use std::sync::Mutex;
+
+lazy_static! {
+    static ref EDIT_LOG: Option<EditLog> = {
+        let path = Config::get().edit_log.path.as_ref()?;
+        match EditLog::open(path) {
+            Ok(log) => Some(log),
+            Err(e) => {
+                tracing::warn!("Could not open edit log {path}: {e}");
+                None
+            }
+        }
+    };
+}
+
+/// One edit made by auth2wd
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct EditRecord {
+    pub item: String,
+    pub summary: String,
+    /// The external IDs whose records went into the edit, like "P227:118523813"
+    pub sources: Vec<String>,
+    pub revision: Option<u64>,
+    /// Unix timestamp
+    pub timestamp: i64,
+    pub added_statements: usize,
+    pub altered_statements: usize,
+}
+
+/// Which edits to return; all fields are optional
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EditFilter {
+    pub item: Option<String>,
+    /// Only edits at or after this Unix timestamp
+    pub since: Option<i64>,
+    pub limit: Option<usize>,
+}
+
+impl EditFilter {
+    /// Parses a `since` value, either a Unix timestamp or a date like "2024-05-01"
+    pub fn parse_since(s: &str) -> Option<i64> {
+        if let Ok(timestamp) = s.parse() {
+            return Some(timestamp);
+        }
+        let date = NaiveDate::parse_from_str(s, "%Y-%m-%d").ok()?;
+        Some(date.and_hms_opt(0, 0, 0)?.and_utc().timestamp())
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct EditStats {
+    pub edits: usize,
+    pub items: usize,
+    pub added_statements: usize,
+    pub altered_statements: usize,
+}
+
+/// Optional SQLite log of all edits, for rollback lists and statistics
+pub struct EditLog {
+    conn: Mutex<Connection>,
+}
+
+impl EditLog {
+    pub fn open(path: &str) -> Result<Self> {
+        let conn = Connection::open(path)?;
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS `edits` (
+                `id` INTEGER PRIMARY KEY AUTOINCREMENT,
+                `item` TEXT NOT NULL,
+                `summary` TEXT NOT NULL,
+                `sources` TEXT NOT NULL,
+                `revision` INTEGER,
+                `timestamp` INTEGER NOT NULL,
+                `added` INTEGER NOT NULL,
+                `altered` INTEGER NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS `edits_item` ON `edits` (`item`);
+            CREATE INDEX IF NOT EXISTS `edits_timestamp` ON `edits` (`timestamp`);",
+        )?;
+        Ok(Self {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    /// The log configured in `edit_log.path`, if any
+    pub fn global() -> Option<&'static EditLog> {
+        EDIT_LOG.as_ref()
+    }
+
+    /// Builds a record for an edit made now
+    pub fn new_record(
+        item: &str,
+        summary: &str,
+        sources: Vec<String>,
+        revision: Option<u64>,
+        added_statements: usize,
+        altered_statements: usize,
+    ) -> EditRecord {
+        EditRecord {
+            item: item.to_string(),
+            summary: summary.to_string(),
+            sources,
+            revision,
+            timestamp: Utc::now().timestamp(),
+            added_statements,
+            altered_statements,
+        }
+    }
+
+    pub fn record(&self, edit: &EditRecord) -> Result<()> {
+        self.conn.lock().map_err(|e| anyhow!("{e}"))?.execute(
+            "INSERT INTO `edits` (`item`,`summary`,`sources`,`revision`,`timestamp`,`added`,`altered`) VALUES (?1,?2,?3,?4,?5,?6,?7)",
+            params![
+                edit.item,
+                edit.summary,
+                serde_json::to_string(&edit.sources)?,
+                edit.revision.map(|r| r as i64),
+                edit.timestamp,
+                edit.added_statements as i64,
+                edit.altered_statements as i64,
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// The matching edits, newest first
+    pub fn edits(&self, filter: &EditFilter) -> Result<Vec<EditRecord>> {
+        let conn = self.conn.lock().map_err(|e| anyhow!("{e}"))?;
+        let mut stmt = conn.prepare(
+            "SELECT `item`,`summary`,`sources`,`revision`,`timestamp`,`added`,`altered` FROM `edits`
+            WHERE (?1 IS NULL OR `item`=?1) AND `timestamp`>=?2
+            ORDER BY `timestamp` DESC, `id` DESC LIMIT ?3",
+        )?;
+        let limit = filter.limit.map_or(-1, |limit| limit as i64);
+        let rows = stmt.query_map(
+            params![filter.item, filter.since.unwrap_or(0), limit],
+            |row| {
+                let sources: String = row.get(2)?;
+                Ok(EditRecord {
+                    item: row.get(0)?,
+                    summary: row.get(1)?,
+                    sources: serde_json::from_str(&sources).unwrap_or_default(),
+                    revision: row.get::<_, Option<i64>>(3)?.map(|r| r as u64),
+                    timestamp: row.get(4)?,
+                    added_statements: row.get::<_, i64>(5)? as usize,
+                    altered_statements: row.get::<_, i64>(6)? as usize,
+                })
+            },
+        )?;
+        Ok(rows.collect::<rusqlite::Result<Vec<_>>>()?)
+    }
+
+    /// Totals for the edits at or after `since`
+    pub fn stats(&self, since: Option<i64>) -> Result<EditStats> {
+        let conn = self.conn.lock().map_err(|e| anyhow!("{e}"))?;
+        let stats = conn.query_row(
+            "SELECT COUNT(*), COUNT(DISTINCT `item`), COALESCE(SUM(`added`),0), COALESCE(SUM(`altered`),0)
+            FROM `edits` WHERE `timestamp`>=?1",
+            params![since.unwrap_or(0)],
+            |row| {
+                Ok(EditStats {
+                    edits: row.get::<_, i64>(0)? as usize,
+                    items: row.get::<_, i64>(1)? as usize,
+                    added_statements: row.get::<_, i64>(2)? as usize,
+                    altered_statements: row.get::<_, i64>(3)? as usize,
+                })
+            },
+        )?;
+        Ok(stats)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(item: &str, revision: u64, timestamp: i64) -> EditRecord {
+        EditRecord {
+            item: item.to_string(),
+            summary: "AC2WD: 2 statements added, 0 altered".to_string(),
+            sources: vec!["P227:118523813".to_string()],
+            revision: Some(revision),
+            timestamp,
+            added_statements: 2,
+            altered_statements: 0,
+        }
+    }
+
+    #[test]
+    fn test_record_and_query() {
+        let log = EditLog::open(":memory:").unwrap();
+        log.record(&record("Q1035", 101, 1000)).unwrap();
+        log.record(&record("Q42", 102, 2000)).unwrap();
+        log.record(&record("Q1035", 103, 3000)).unwrap();
+
+        let all = log.edits(&EditFilter::default()).unwrap();
+        assert_eq!(all.len(), 3);
+        assert_eq!(all[0].revision, Some(103));
+        assert_eq!(all[0].sources, vec!["P227:118523813"]);
+
+        let filter = EditFilter {
+            item: Some("Q1035".to_string()),
+            since: Some(2000),
+            limit: None,
+        };
+        let edits = log.edits(&filter).unwrap();
+        assert_eq!(edits, vec![record("Q1035", 103, 3000)]);
+
+        let filter = EditFilter {
+            limit: Some(1),
+            ..Default::default()
+        };
+        assert_eq!(log.edits(&filter).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_parse_since() {
+        assert_eq!(EditFilter::parse_since("1714521600"), Some(1714521600));
+        assert_eq!(EditFilter::parse_since("2024-05-01"), Some(1714521600));
+        assert_eq!(EditFilter::parse_since("May 2024"), None);
+    }
+
+    #[test]
+    fn test_stats() {
+        let log = EditLog::open(":memory:").unwrap();
+        assert_eq!(log.stats(None).unwrap(), EditStats::default());
+        log.record(&record("Q1035", 101, 1000)).unwrap();
+        log.record(&record("Q1035", 102, 2000)).unwrap();
+        log.record(&record("Q42", 103, 3000)).unwrap();
+        let stats = log.stats(None).unwrap();
+        assert_eq!(stats.edits, 3);
+        assert_eq!(stats.items, 2);
+        assert_eq!(stats.added_statements, 6);
+        assert_eq!(log.stats(Some(2000)).unwrap().edits, 2);
+    }
+}
@@ -0,0 +1,480 @@
+use crate::rdf_export::RdfExport;
+use crate::utility::Utility;
+use anyhow::{anyhow, Result};
+use serde_json::Value;
+use std::str::FromStr;
+
+const CSV_HEADER: &str = "item,property,value,references";
+
+/// Output formats for items and diffs on the command line
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Json,
+    QuickStatements,
+    Csv,
+    Turtle,
+    /// A review table for talk pages
+    Wikitext,
+}
+
+impl FromStr for ExportFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "json" => Ok(Self::Json),
+            "quickstatements" | "qs" => Ok(Self::QuickStatements),
+            "csv" => Ok(Self::Csv),
+            "turtle" | "ttl" => Ok(Self::Turtle),
+            "wikitext" | "wiki" => Ok(Self::Wikitext),
+            other => Err(anyhow!(
+                "Unknown format '{other}', use json, quickstatements, csv, turtle, or wikitext"
+            )),
+        }
+    }
+}
+
+/// Converts Wikibase item or diff JSON into other formats
+pub struct Export {
+    /// The item ID, or `None` for a new item
+    item: Option<String>,
+    statements: Vec<Value>,
+    terms: Vec<(String, String, String)>,
+    json: Value,
+    /// The item the diff applies to, for showing current values
+    current: Value,
+}
+
+impl Export {
+    /// For an item JSON; uses the `id` if there is one
+    pub fn from_item(j: &Value) -> Self {
+        let item = j["id"]
+            .as_str()
+            .filter(|id| *id != "Q0")
+            .map(|id| id.to_string());
+        Self::new(item, j)
+    }
+
+    /// For a diff that is to be applied to `item`
+    pub fn from_diff(item: &str, j: &Value) -> Self {
+        Self::new(Some(item.to_string()), j)
+    }
+
+    fn new(item: Option<String>, j: &Value) -> Self {
+        Self {
+            item,
+            statements: Utility::json_values(&j["claims"]),
+            terms: Self::terms(j),
+            json: j.to_owned(),
+            current: Value::Null,
+        }
+    }
+
+    /// Sets the item JSON the diff applies to, so its current values can be shown next to the proposed ones
+    pub fn with_current(mut self, item: &Value) -> Self {
+        self.current = item.to_owned();
+        self
+    }
+
+    pub fn render(&self, format: ExportFormat) -> Result<String> {
+        Ok(match format {
+            ExportFormat::Json => serde_json::to_string_pretty(&self.json)?,
+            ExportFormat::QuickStatements => self.as_quickstatements(),
+            ExportFormat::Csv => self.as_csv(),
+            ExportFormat::Turtle => self.as_turtle(),
+            ExportFormat::Wikitext => self.as_wikitext(),
+        })
+    }
+
+    fn as_quickstatements(&self) -> String {
+        let mut lines = vec![];
+        let subject = match &self.item {
+            Some(item) => item.to_owned(),
+            None => {
+                lines.push("CREATE".to_string());
+                "LAST".to_string()
+            }
+        };
+        for (term_type, language, value) in &self.terms {
+            let prefix = match term_type.as_str() {
+                "labels" => "L",
+                "descriptions" => "D",
+                _ => "A",
+            };
+            lines.push(format!(
+                "{subject}\t{prefix}{language}\t{}",
+                Self::qs_quote(value)
+            ));
+        }
+        for statement in &self.statements {
+            let snak = &statement["mainsnak"];
+            let value = match Self::qs_value(&snak["datavalue"]) {
+                Some(value) => value,
+                None => continue,
+            };
+            let mut parts = vec![
+                subject.to_owned(),
+                snak["property"].as_str().unwrap_or_default().to_string(),
+                value,
+            ];
+            // QuickStatements only supports a single reference per command
+            let references = Utility::json_values(&statement["references"]);
+            if let Some(reference) = references.first() {
+                for snak in Utility::json_values(&reference["snaks"]) {
+                    if let (Some(property), Some(value)) = (
+                        snak["property"].as_str(),
+                        Self::qs_value(&snak["datavalue"]),
+                    ) {
+                        parts.push(format!("S{}", property.trim_start_matches('P')));
+                        parts.push(value);
+                    }
+                }
+            }
+            lines.push(parts.join("\t"));
+        }
+        lines.join("\n")
+    }
+
+    fn as_csv(&self) -> String {
+        let item = self.item.as_deref().unwrap_or_default();
+        let mut lines = vec![CSV_HEADER.to_string()];
+        for statement in &self.statements {
+            let snak = &statement["mainsnak"];
+            let references: Vec<String> = Utility::json_values(&statement["references"])
+                .iter()
+                .flat_map(|r| Utility::json_values(&r["snaks"]))
+                .filter_map(|snak| {
+                    Some(format!(
+                        "{}={}",
+                        snak["property"].as_str()?,
+                        Self::plain_value(&snak["datavalue"])?
+                    ))
+                })
+                .collect();
+            let fields = [
+                item.to_string(),
+                snak["property"].as_str().unwrap_or_default().to_string(),
+                Self::plain_value(&snak["datavalue"]).unwrap_or_default(),
+                references.join("; "),
+            ];
+            let fields: Vec<String> = fields.iter().map(|f| Self::csv_quote(f)).collect();
+            lines.push(fields.join(","));
+        }
+        lines.join("\n")
+    }
+
+    fn as_turtle(&self) -> String {
+        let mut lines = vec![
+            "@prefix wd: <http://www.wikidata.org/entity/> .".to_string(),
+            "@prefix wdt: <http://www.wikidata.org/prop/direct/> .".to_string(),
+            "@prefix rdfs: <http://www.w3.org/2000/01/rdf-schema#> .".to_string(),
+            "@prefix schema: <http://schema.org/> .".to_string(),
+            "@prefix skos: <http://www.w3.org/2004/02/skos/core#> .".to_string(),
+            "@prefix xsd: <http://www.w3.org/2001/XMLSchema#> .".to_string(),
+            "@prefix geo: <http://www.opengis.net/ont/geosparql#> .".to_string(),
+            String::new(),
+        ];
+        let subject = match &self.item {
+            Some(item) => format!("wd:{item}"),
+            None => "_:item".to_string(),
+        };
+        for (term_type, language, value) in &self.terms {
+            let predicate = match term_type.as_str() {
+                "labels" => "rdfs:label",
+                "descriptions" => "schema:description",
+                _ => "skos:altLabel",
+            };
+            lines.push(format!(
+                "{subject} {predicate} {}@{language} .",
+                Self::turtle_string(value)
+            ));
+        }
+        for statement in &self.statements {
+            let snak = &statement["mainsnak"];
+            if let (Some(property), Some(value)) = (
+                snak["property"].as_str(),
+                Self::turtle_value(&snak["datavalue"]),
+            ) {
+                lines.push(format!("{subject} wdt:{property} {value} ."));
+            }
+        }
+        lines.join("\n")
+    }
+
+    /// A table of current and proposed values, for review on a talk or WikiProject page
+    fn as_wikitext(&self) -> String {
+        let mut lines = vec![];
+        if let Some(item) = &self.item {
+            lines.push(format!("Proposed changes for {{{{Q|{item}}}}}"));
+            lines.push(String::new());
+        }
+        lines.push(r#"{| class="wikitable sortable""#.to_string());
+        lines.push("! Property !! Current value !! Proposed value !! References".to_string());
+        for (term_type, language, value) in &self.terms {
+            // Aliases are lists, labels and descriptions single terms
+            let current = match &self.current[term_type][language] {
+                Value::Array(a) => a.to_owned(),
+                Value::Null => vec![],
+                v => vec![v.to_owned()],
+            };
+            let current: Vec<String> = current
+                .iter()
+                .filter_map(|v| v["value"].as_str())
+                .map(Self::wikitext_escape)
+                .collect();
+            lines.push("|-".to_string());
+            lines.push(format!(
+                "| {term_type} ({language}) || {} || {} ||",
+                Self::wikitext_list(&current),
+                Self::wikitext_escape(value)
+            ));
+        }
+        for statement in &self.statements {
+            let snak = &statement["mainsnak"];
+            let property = snak["property"].as_str().unwrap_or_default();
+            let current: Vec<String> = Utility::json_values(&self.current["claims"][property])
+                .iter()
+                .filter_map(|s| Self::wikitext_value(&s["mainsnak"]["datavalue"]))
+                .collect();
+            let proposed = Self::wikitext_value(&snak["datavalue"]).unwrap_or_default();
+            let references: Vec<String> = Utility::json_values(&statement["references"])
+                .iter()
+                .map(|r| {
+                    Utility::json_values(&r["snaks"])
+                        .iter()
+                        .filter_map(|snak| {
+                            Some(format!(
+                                "{{{{P|{}}}}}: {}",
+                                snak["property"].as_str()?.trim_start_matches('P'),
+                                Self::wikitext_value(&snak["datavalue"])?
+                            ))
+                        })
+                        .collect::<Vec<String>>()
+                        .join("; ")
+                })
+                .collect();
+            lines.push("|-".to_string());
+            lines.push(format!(
+                "| {{{{P|{}}}}} || {} || {proposed} || {}",
+                property.trim_start_matches('P'),
+                Self::wikitext_list(&current),
+                references.join("<br/>")
+            ));
+        }
+        lines.push("|}".to_string());
+        lines.join("\n")
+    }
+
+    fn wikitext_value(datavalue: &Value) -> Option<String> {
+        let value = &datavalue["value"];
+        Some(match datavalue["type"].as_str()? {
+            "wikibase-entityid" => format!("{{{{Q|{}}}}}", value["id"].as_str()?),
+            _ => Self::wikitext_escape(&Self::plain_value(datavalue)?),
+        })
+    }
+
+    /// Current values, one per line; a dash if there are none
+    fn wikitext_list(values: &[String]) -> String {
+        match values.is_empty() {
+            true => "—".to_string(),
+            false => values.join("<br/>"),
+        }
+    }
+
+    /// Keeps markup in values (like `|` or `[[`) from breaking the table
+    fn wikitext_escape(s: &str) -> String {
+        format!("<nowiki>{}</nowiki>", s.replace("</nowiki>", ""))
+    }
+
+    fn qs_value(datavalue: &Value) -> Option<String> {
+        let value = &datavalue["value"];
+        Some(match datavalue["type"].as_str()? {
+            "wikibase-entityid" => value["id"].as_str()?.to_string(),
+            "string" => Self::qs_quote(value.as_str()?),
+            "time" => format!(
+                "{}/{}",
+                value["time"].as_str()?,
+                value["precision"].as_u64()?
+            ),
+            "monolingualtext" => format!(
+                "{}:{}",
+                value["language"].as_str()?,
+                Self::qs_quote(value["text"].as_str()?)
+            ),
+            "quantity" => {
+                let amount = value["amount"].as_str()?.to_string();
+                match value["unit"].as_str()?.rsplit_once("/entity/Q") {
+                    Some((_, unit)) => format!("{amount}U{unit}"),
+                    None => amount,
+                }
+            }
+            "globecoordinate" => format!(
+                "@{}/{}",
+                value["latitude"].as_f64()?,
+                value["longitude"].as_f64()?
+            ),
+            _ => return None,
+        })
+    }
+
+    fn plain_value(datavalue: &Value) -> Option<String> {
+        let value = &datavalue["value"];
+        Some(match datavalue["type"].as_str()? {
+            "wikibase-entityid" => value["id"].as_str()?.to_string(),
+            "string" => value.as_str()?.to_string(),
+            "time" => value["time"].as_str()?.to_string(),
+            "monolingualtext" => value["text"].as_str()?.to_string(),
+            "quantity" => value["amount"].as_str()?.to_string(),
+            "globecoordinate" => format!(
+                "{},{}",
+                value["latitude"].as_f64()?,
+                value["longitude"].as_f64()?
+            ),
+            _ => value.to_string(),
+        })
+    }
+
+    fn turtle_value(datavalue: &Value) -> Option<String> {
+        let value = &datavalue["value"];
+        Some(match datavalue["type"].as_str()? {
+            "wikibase-entityid" => format!("wd:{}", value["id"].as_str()?),
+            "string" => Self::turtle_string(value.as_str()?),
+            "time" => format!(
+                "\"{}\"^^xsd:dateTime",
+                RdfExport::date_time(value["time"].as_str()?)
+            ),
+            "monolingualtext" => format!(
+                "{}@{}",
+                Self::turtle_string(value["text"].as_str()?),
+                value["language"].as_str()?
+            ),
+            "quantity" => format!(
+                "\"{}\"^^xsd:decimal",
+                value["amount"].as_str()?.trim_start_matches('+')
+            ),
+            "globecoordinate" => format!(
+                "\"Point({} {})\"^^geo:wktLiteral",
+                value["longitude"].as_f64()?,
+                value["latitude"].as_f64()?
+            ),
+            _ => return None,
+        })
+    }
+
+    fn qs_quote(s: &str) -> String {
+        format!("\"{}\"", s.replace('"', "\\\""))
+    }
+
+    fn turtle_string(s: &str) -> String {
+        format!(
+            "\"{}\"",
+            s.replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\n', "\\n")
+        )
+    }
+
+    fn csv_quote(s: &str) -> String {
+        if s.contains([',', '"', '\n']) {
+            format!("\"{}\"", s.replace('"', "\"\""))
+        } else {
+            s.to_string()
+        }
+    }
+
+    /// Returns (type, language, value) for all labels, descriptions, and aliases
+    fn terms(j: &Value) -> Vec<(String, String, String)> {
+        let mut ret = vec![];
+        for key in ["labels", "descriptions", "aliases"] {
+            for v in Utility::json_values(&j[key]) {
+                if let (Some(language), Some(value)) = (v["language"].as_str(), v["value"].as_str())
+                {
+                    ret.push((key.to_string(), language.to_string(), value.to_string()));
+                }
+            }
+        }
+        ret
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn test_item() -> Value {
+        json!({
+            "labels":{"en":{"language":"en","value":"Charles Darwin"}},
+            "claims":{
+                "P31":[{"mainsnak":{"snaktype":"value","property":"P31","datavalue":{"type":"wikibase-entityid","value":{"entity-type":"item","id":"Q5"}}}}],
+                "P569":[{"mainsnak":{"snaktype":"value","property":"P569","datavalue":{"type":"time","value":{"time":"+1809-02-12T00:00:00Z","precision":11}}},
+                    "references":[{"snaks":{"P227":[{"property":"P227","datavalue":{"type":"string","value":"118523813"}}]}}]}]
+            }
+        })
+    }
+
+    #[test]
+    fn test_format_from_str() {
+        assert_eq!(
+            ExportFormat::from_str("QS").unwrap(),
+            ExportFormat::QuickStatements
+        );
+        assert!(ExportFormat::from_str("xml").is_err());
+    }
+
+    #[test]
+    fn test_quickstatements() {
+        let qs = Export::from_item(&test_item())
+            .render(ExportFormat::QuickStatements)
+            .unwrap();
+        assert_eq!(
+            qs,
+            "CREATE\nLAST\tLen\t\"Charles Darwin\"\nLAST\tP31\tQ5\nLAST\tP569\t+1809-02-12T00:00:00Z/11\tS227\t\"118523813\""
+        );
+        assert_eq!(Export::qs_quote("\"Boz\""), "\"\\\"Boz\\\"\"");
+    }
+
+    #[test]
+    fn test_csv() {
+        let csv = Export::from_diff("Q1035", &test_item())
+            .render(ExportFormat::Csv)
+            .unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines[0], CSV_HEADER);
+        assert_eq!(lines[1], "Q1035,P31,Q5,");
+        assert_eq!(lines[2], "Q1035,P569,+1809-02-12T00:00:00Z,P227=118523813");
+        assert_eq!(Export::csv_quote("a,\"b\""), "\"a,\"\"b\"\"\"");
+    }
+
+    #[test]
+    fn test_wikitext() {
+        let current = json!({"claims":{"P31":[{"mainsnak":{"snaktype":"value","property":"P31","datavalue":{"type":"wikibase-entityid","value":{"id":"Q5"}}}}]}});
+        let wikitext = Export::from_diff("Q1035", &test_item())
+            .with_current(&current)
+            .render(ExportFormat::Wikitext)
+            .unwrap();
+        let lines: Vec<&str> = wikitext.lines().collect();
+        assert_eq!(lines[0], "Proposed changes for {{Q|Q1035}}");
+        assert!(lines.contains(&"| labels (en) || — || <nowiki>Charles Darwin</nowiki> ||"));
+        assert!(lines.contains(&"| {{P|31}} || {{Q|Q5}} || {{Q|Q5}} || "));
+        assert!(lines.contains(
+            &"| {{P|569}} || — || <nowiki>+1809-02-12T00:00:00Z</nowiki> || {{P|227}}: <nowiki>118523813</nowiki>"
+        ));
+        assert_eq!(lines.last(), Some(&"|}"));
+    }
+
+    #[test]
+    fn test_turtle() {
+        let ttl = Export::from_diff("Q1035", &test_item())
+            .render(ExportFormat::Turtle)
+            .unwrap();
+        assert!(ttl.contains("wd:Q1035 rdfs:label \"Charles Darwin\"@en ."));
+        assert!(ttl.contains("wd:Q1035 wdt:P31 wd:Q5 ."));
+        assert!(ttl.contains("wd:Q1035 wdt:P569 \"1809-02-12T00:00:00Z\"^^xsd:dateTime ."));
+        let year = json!({"type":"time","value":{"time":"+1809-00-00T00:00:00Z","precision":9}});
+        assert_eq!(
+            Export::turtle_value(&year),
+            Some("\"1809-01-01T00:00:00Z\"^^xsd:dateTime".to_string())
+        );
+    }
+}
@@ -1,18 +1,43 @@
+use crate::disk_cache::DiskCache;
+use crate::id_format::{CheckDigit, IdFormat};
+use crate::utility::Utility;
+use crate::wikidata_lookup::{find_items_for_values, lookup, LookupQuery};
 use anyhow::Result;
+use futures::stream::{self, StreamExt};
 use regex::Regex;
 use serde::{Deserialize, Serialize};
 use std::{collections::HashMap, fmt, sync::Arc};
 use tokio::sync::Mutex;
 use wikimisc::wikibase::*;
 
+const DISK_CACHE_ITEMS: &str = "items_for_external_id";
+const DISK_CACHE_VALIDITY: &str = "validity";
+const DISK_CACHE_ITEM_FOR_STRING: &str = "item_for_string";
+const MAX_CONCURRENT_VALIDITY_CHECKS: usize = 5;
+
 lazy_static! {
     static ref RE_PROPERTY_NUMERIC: Regex =
         Regex::new(r#"^\s*[Pp](\d+)\s*$"#).expect("Regexp error");
     static ref RE_FROM_STRING: Regex = Regex::new(r#"^[Pp](\d+):(.+)$"#).expect("Regexp error");
-    static ref EXTERNAL_IDS_OK_CACHE: Arc<Mutex<HashMap<ExternalId, bool>>> =
+    static ref RE_ISNI: Regex = Regex::new(r#"^\d{15}[\dX]$"#).expect("Regexp error");
+    static ref RE_ORCID: Regex =
+        Regex::new(r#"^\d{4}-\d{4}-\d{4}-\d{3}[\dX]$"#).expect("Regexp error");
+    static ref RE_VIAF: Regex =
+        Regex::new(r#"^[1-9]\d(\d{0,7}|\d{17,20})$"#).expect("Regexp error");
+    static ref EXTERNAL_IDS_OK_CACHE: Arc<Mutex<HashMap<ExternalId, IdValidity>>> =
         Arc::new(Mutex::new(HashMap::new()));
 }
 
+/// Whether an external ID is still current at its source
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum IdValidity {
+    Valid,
+    /// Deleted, abandoned, or deprecated without a successor
+    Invalid,
+    /// Merged into, or superseded by, another ID
+    Replaced(ExternalId),
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, Hash)]
 pub struct ExternalId {
     property: usize,
@@ -26,18 +51,40 @@ impl fmt::Display for ExternalId {
 }
 
 impl ExternalId {
+    /// Creates an external ID from either the source or the Wikidata form of the ID
     pub fn new(property: usize, id: &str) -> Self {
-        let id = Self::fix_property_value(property, id);
+        let id = IdFormat::to_source_format(property, id);
         Self { property, id }
     }
 
-    fn fix_property_value(property: usize, id: &str) -> String {
-        match property {
-            213 => id.replace(' ', ""), // P213 (ISNI) has no spaces
-            _ => id.to_string(),
+    /// The ID as it should be stated on Wikidata, eg with spaces for ISNI
+    pub fn wikidata_value(&self) -> String {
+        IdFormat::to_wikidata_format(self.property, &self.id)
+    }
+
+    /// Checks length, format, and (for ISNI and ORCID) the check digit.
+    /// IDs of properties without known rules are assumed to be well-formed if they are not empty.
+    pub fn is_well_formed(&self) -> bool {
+        match self.property {
+            213 => RE_ISNI.is_match(&self.id) && Self::iso7064_mod_11_2_is_valid(&self.id),
+            214 => RE_VIAF.is_match(&self.id),
+            496 => {
+                RE_ORCID.is_match(&self.id)
+                    && Self::iso7064_mod_11_2_is_valid(&self.id.replace('-', ""))
+            }
+            _ => !self.id.trim().is_empty(),
         }
     }
 
+    /// Validates the last character of `digits` as ISO 7064 MOD 11-2 check character, as used by ISNI and ORCID
+    fn iso7064_mod_11_2_is_valid(digits: &str) -> bool {
+        let (body, check) = match digits.char_indices().last() {
+            Some((pos, check)) => (&digits[..pos], check),
+            None => return false,
+        };
+        body.chars().all(|c| c.is_ascii_digit()) && CheckDigit::Iso7064Mod112.compute(body) == check
+    }
+
     pub fn from_string(s: &str) -> Option<Self> {
         let captures = RE_FROM_STRING.captures(s)?;
         let property = Self::prop_numeric(captures.get(1)?.as_str())?;
@@ -62,30 +109,108 @@ impl ExternalId {
             Value::StringValue(id) => id,
             _ => return None,
         };
-        // TODO change value eg P213(ISNI) from Wikidata format to external format
         Some(Self::new(prop_numeric, id))
     }
 
-    pub async fn search_wikidata_single_item(query: &str) -> Option<String> {
-        // TODO urlencode query?
-        let url = format!("https://www.wikidata.org/w/api.php?action=query&list=search&srnamespace=0&format=json&srsearch={}",&query);
-        let text = reqwest::get(url).await.ok()?.text().await.ok()?;
-        // let text = ureq::get(&url).call().ok()?.into_string().ok()?;
-        let j: serde_json::Value = serde_json::from_str(&text).ok()?;
-        if j["query"]["searchinfo"]["totalhits"].as_i64()? == 1 {
-            return Some(j["query"]["search"][0]["title"].as_str()?.to_string());
+    pub async fn get_item_for_external_id_value(&self) -> Option<String> {
+        match self.get_items_for_external_id_value().await {
+            Ok(items) if items.len() == 1 => items.into_iter().next(),
+            Ok(_) => None,
+            Err(e) => {
+                tracing::warn!("Wikidata lookup for {self} failed: {e}");
+                None
+            }
         }
-        None
     }
 
-    pub async fn get_item_for_external_id_value(&self) -> Option<String> {
-        let query = format!("haswbstatement:\"P{}={}\"", self.property, self.id);
-        Self::search_wikidata_single_item(&query).await
+    /// Returns all Wikidata items that have this external ID
+    pub async fn get_items_for_external_id_value(&self) -> Result<Vec<String>> {
+        let key = self.to_string();
+        if let Some(items) = Self::disk_cache_get(DISK_CACHE_ITEMS, &key).await {
+            return Ok(items);
+        }
+        let items = lookup()
+            .find_items(&LookupQuery::new(self.property, &self.wikidata_value()))
+            .await?;
+        Self::disk_cache_set(DISK_CACHE_ITEMS, &key, &items, items.is_empty()).await;
+        Ok(items)
+    }
+
+    /// Like `get_item_for_external_id_value`, but for many IDs at once.
+    /// IDs without exactly one item (or whose lookup failed) are missing from the result.
+    pub async fn get_item_for_external_id_values(
+        ids: &[ExternalId],
+    ) -> HashMap<ExternalId, String> {
+        Self::get_items_for_external_id_values(ids)
+            .await
+            .into_iter()
+            .filter(|(_, items)| items.len() == 1)
+            .filter_map(|(id, items)| Some((id, items.into_iter().next()?)))
+            .collect()
+    }
+
+    /// Like `get_items_for_external_id_value`, but batches the lookups of IDs that are not cached
+    pub async fn get_items_for_external_id_values(
+        ids: &[ExternalId],
+    ) -> HashMap<ExternalId, Vec<String>> {
+        let mut ret = HashMap::new();
+        let mut missing: HashMap<usize, Vec<&ExternalId>> = HashMap::new();
+        for id in ids {
+            match Self::disk_cache_get(DISK_CACHE_ITEMS, &id.to_string()).await {
+                Some(items) => {
+                    ret.insert(id.to_owned(), items);
+                }
+                None => missing.entry(id.property).or_default().push(id),
+            }
+        }
+        for (property, ids) in missing {
+            let values: Vec<String> = ids.iter().map(|id| id.wikidata_value()).collect();
+            let mut found = find_items_for_values(property, &values).await;
+            for (id, value) in ids.into_iter().zip(values) {
+                let items = match found.remove(&value) {
+                    Some(items) => items,
+                    None => continue, // Lookup failed
+                };
+                Self::disk_cache_set(DISK_CACHE_ITEMS, &id.to_string(), &items, items.is_empty())
+                    .await;
+                ret.insert(id.to_owned(), items);
+            }
+        }
+        ret
     }
 
+    /// The single item with this statement and `s` as a label or alias, like a language (P31:Q34770) called "German"
     pub async fn get_item_for_string_external_id_value(&self, s: &str) -> Option<String> {
-        let query = format!("{s} haswbstatement:\"P{}={}\"", self.property, &self.id);
-        Self::search_wikidata_single_item(&query).await
+        let key = format!("{self}:{s}");
+        if let Some(item) = Self::disk_cache_get(DISK_CACHE_ITEM_FOR_STRING, &key).await {
+            return item;
+        }
+        let item = lookup()
+            .find_single_item(&LookupQuery::new(self.property, &self.wikidata_value()).with_text(s))
+            .await;
+        Self::disk_cache_set(DISK_CACHE_ITEM_FOR_STRING, &key, &item, item.is_none()).await;
+        item
+    }
+
+    async fn disk_cache_get<T: serde::de::DeserializeOwned>(
+        namespace: &str,
+        key: &str,
+    ) -> Option<T> {
+        DiskCache::global()?.get(namespace, key).await
+    }
+
+    /// Failures to write are only logged; the cache is an optimization
+    async fn disk_cache_set<T: serde::Serialize>(
+        namespace: &str,
+        key: &str,
+        value: &T,
+        negative: bool,
+    ) {
+        if let Some(cache) = DiskCache::global() {
+            if let Err(e) = cache.set(namespace, key, value, negative).await {
+                tracing::warn!("Could not write to disk cache: {e}");
+            }
+        }
     }
 
     pub fn do_not_use_external_url(url: &str) -> bool {
@@ -96,26 +221,113 @@ impl ExternalId {
 
     /// Checks some properties (eg GND) if the external ID is valid (eg not deprecated)
     pub async fn check_if_valid(&self) -> Result<bool> {
-        if let Some(is_ok) = EXTERNAL_IDS_OK_CACHE.lock().await.get(self) {
-            return Ok(*is_ok);
+        Ok(self.check_validity().await? == IdValidity::Valid)
+    }
+
+    /// Runs `check_validity` for several IDs concurrently. IDs whose check failed (eg because the source
+    /// could not be reached) are logged and left out, as their validity is unknown.
+    pub async fn check_validity_all(ids: &[ExternalId]) -> HashMap<ExternalId, IdValidity> {
+        let mut ids = ids.to_vec();
+        ids.sort();
+        ids.dedup();
+        stream::iter(ids)
+            .map(|id| async move {
+                match id.check_validity().await {
+                    Ok(validity) => Some((id, validity)),
+                    Err(e) => {
+                        tracing::warn!("Could not check validity of {id}: {e}");
+                        None
+                    }
+                }
+            })
+            .buffer_unordered(MAX_CONCURRENT_VALIDITY_CHECKS)
+            .filter_map(|result| async move { result })
+            .collect()
+            .await
+    }
+
+    /// Checks some properties (GND, VIAF, LoC) if the external ID is still current at the source.
+    /// IDs of other properties are always considered valid.
+    pub async fn check_validity(&self) -> Result<IdValidity> {
+        if let Some(validity) = EXTERNAL_IDS_OK_CACHE.lock().await.get(self) {
+            return Ok(validity.to_owned());
         }
-        let mut ret = true;
-        let mut was_checked = false;
-        if self.property == 227 {
-            // GND
-            was_checked = true;
-            let url = format!("https://d-nb.info/gnd/{}/about/lds.rdf", self.id);
-            let resp = reqwest::get(&url).await?.text().await?;
-            let check = format!("rdf:about=\"https://d-nb.info/gnd/{}\">", self.id);
-            ret = resp.contains(&check);
+        if let Some(validity) =
+            Self::disk_cache_get::<IdValidity>(DISK_CACHE_VALIDITY, &self.to_string()).await
+        {
+            EXTERNAL_IDS_OK_CACHE
+                .lock()
+                .await
+                .insert(self.clone(), validity.to_owned());
+            return Ok(validity);
         }
-        if was_checked {
+        let ret = match self.property {
+            227 => {
+                // GND
+                let url = format!(
+                    "https://d-nb.info/gnd/{}/about/lds.rdf",
+                    Utility::encode_path_segment(&self.id)
+                );
+                let resp = match Utility::get_url(&url).await {
+                    Ok(resp) => resp,
+                    Err(e) if Utility::is_not_found(&e) => String::new(),
+                    Err(e) => return Err(e),
+                };
+                let check = format!("rdf:about=\"https://d-nb.info/gnd/{}\">", self.id);
+                if resp.contains(&check) {
+                    IdValidity::Valid
+                } else {
+                    IdValidity::Invalid
+                }
+            }
+            214 => {
+                // VIAF
+                let url = format!(
+                    "https://viaf.org/viaf/{}/viaf.json",
+                    Utility::encode_path_segment(&self.id)
+                );
+                match Utility::get_url(&url).await {
+                    Ok(text) => Self::viaf_validity(&serde_json::from_str(&text)?),
+                    Err(e) if Utility::is_not_found(&e) => IdValidity::Invalid,
+                    Err(e) => return Err(e),
+                }
+            }
+            244 => {
+                // LoC
+                match crate::loc::LOC::fetch_rdf(&self.id).await {
+                    Ok(rdf) => crate::loc::LOC::validity(&self.id, &rdf)?,
+                    Err(e) if Utility::is_not_found(&e) => IdValidity::Invalid,
+                    Err(e) => return Err(e),
+                }
+            }
             // No need to store the result if no check was run
-            EXTERNAL_IDS_OK_CACHE.lock().await.insert(self.clone(), ret);
-        }
+            _ => return Ok(IdValidity::Valid),
+        };
+        EXTERNAL_IDS_OK_CACHE
+            .lock()
+            .await
+            .insert(self.clone(), ret.to_owned());
+        let negative = ret == IdValidity::Invalid;
+        Self::disk_cache_set(DISK_CACHE_VALIDITY, &self.to_string(), &ret, negative).await;
         Ok(ret)
     }
 
+    /// Redirected VIAF clusters point to the cluster they were merged into; abandoned ones are "scavenged"
+    fn viaf_validity(j: &serde_json::Value) -> IdValidity {
+        let redirect = &j["redirect"]["directto"];
+        let target = redirect
+            .as_str()
+            .map(|s| s.to_string())
+            .or_else(|| redirect.as_u64().map(|n| n.to_string()));
+        if let Some(target) = target {
+            return IdValidity::Replaced(Self::new(214, &target));
+        }
+        if j.get("scavenged").is_some() || j.get("abandoned").is_some() {
+            return IdValidity::Invalid;
+        }
+        IdValidity::Valid
+    }
+
     pub fn property(&self) -> usize {
         self.property
     }
@@ -140,6 +352,37 @@ mod tests {
     fn test_isni() {
         let ext_id = ExternalId::new(213, "0000 0001 2184 9233");
         assert_eq!(ext_id.id, "0000000121849233");
+        assert_eq!(ext_id.wikidata_value(), "0000 0001 2184 9233");
+    }
+
+    #[test]
+    fn test_is_well_formed() {
+        assert!(ExternalId::new(213, "0000 0001 2103 2683").is_well_formed());
+        assert!(ExternalId::new(213, "000000012146438X").is_well_formed());
+        assert!(!ExternalId::new(213, "0000000121032684").is_well_formed());
+        assert!(!ExternalId::new(213, "00000001210326").is_well_formed());
+        assert!(ExternalId::new(496, "0000-0002-1825-0097").is_well_formed());
+        assert!(!ExternalId::new(496, "0000-0002-1825-0098").is_well_formed());
+        assert!(!ExternalId::new(496, "0000000218250097").is_well_formed());
+        assert!(ExternalId::new(214, "27063124").is_well_formed());
+        assert!(!ExternalId::new(214, "027063124").is_well_formed());
+        assert!(!ExternalId::new(214, "2706312412345").is_well_formed());
+        assert!(!ExternalId::new(214, "2").is_well_formed());
+        assert!(ExternalId::new(227, "118523813").is_well_formed());
+        assert!(!ExternalId::new(227, " ").is_well_formed());
+    }
+
+    #[test]
+    fn test_viaf_validity() {
+        let j = serde_json::json!({"redirect":{"directto":"27063124"}});
+        assert_eq!(
+            ExternalId::viaf_validity(&j),
+            IdValidity::Replaced(ExternalId::new(214, "27063124"))
+        );
+        let j = serde_json::json!({"scavenged":{"viafID":"123"}});
+        assert_eq!(ExternalId::viaf_validity(&j), IdValidity::Invalid);
+        let j = serde_json::json!({"viafID":"27063124"});
+        assert_eq!(ExternalId::viaf_validity(&j), IdValidity::Valid);
     }
 
     #[test]
@@ -214,6 +457,18 @@ mod tests {
         assert_eq!(None, ExternalId::from_external_id_claim(&statement));
     }
 
+    #[tokio::test]
+    async fn test_check_validity_all() {
+        let ids = vec![
+            ExternalId::new(2, "a"),
+            ExternalId::new(3, "b"),
+            ExternalId::new(2, "a"),
+        ];
+        let validities = ExternalId::check_validity_all(&ids).await;
+        assert_eq!(validities.len(), 2);
+        assert!(validities.values().all(|v| *v == IdValidity::Valid));
+    }
+
     #[tokio::test]
     async fn test_get_item_for_external_id() {
         // Test OK
@@ -243,4 +498,15 @@ mod tests {
 
         // TODOO multiple items
     }
+
+    #[tokio::test]
+    async fn test_get_item_for_external_id_values() {
+        let ids = vec![
+            ExternalId::new(214, "30701597"),
+            ExternalId::new(214, "3070159777777"),
+        ];
+        let items = ExternalId::get_item_for_external_id_values(&ids).await;
+        assert_eq!(items.get(&ids[0]), Some(&"Q13520818".to_string()));
+        assert_eq!(items.get(&ids[1]), None);
+    }
 }
@@ -1,5 +1,8 @@
 use crate::external_id::*;
+use crate::format_constraint::FormatConstraint;
+use crate::fuzzy_date::FuzzyDate;
 use crate::meta_item::*;
+use crate::wikidata_lookup::{lookup, LookupCandidate, LookupQuery};
 use anyhow::Result;
 use axum::async_trait;
 use chrono::prelude::*;
@@ -27,9 +30,10 @@ lazy_static! {
             (Regex::new(r"^https?://d-nb.info/gnd/(1[012]?\d{7}[0-9X]|[47]\d{6}-\d|[1-9]\d{0,7}-[0-9X]|3\d{7}[0-9X])$").unwrap(),"${1}".to_string(),227),
             (Regex::new(r"^https?://id.loc.gov/authorities/names/(gf|n|nb|nr|no|ns|sh|sj)([4-9][0-9]|00|20[0-2][0-9])([0-9]{6})$").unwrap(),"${1}${2}${3}".to_string(),244),
             (Regex::new(r"^https?://id.loc.gov/rwo/agents/(gf|n|nb|nr|no|ns|sh|sj)([4-9][0-9]|00|20[0-2][0-9])([0-9]{6})(\.html)?$").unwrap(),"${1}${2}${3}".to_string(),244),
+            (Regex::new(r"^https?://id.loc.gov/authorities/subjects/(sh\d{8,10})(\.html)?$").unwrap(),"${1}".to_string(),244),
             (Regex::new(r"^https?://vocab.getty.edu/ulan/(\d+).*$").unwrap(),"${1}".to_string(),245),
             (Regex::new(r"^https?://www.getty.edu/vow/ULANFullDisplay\?find=&role=&nation=&subjectid=(\d+)$").unwrap(),"${1}".to_string(),245),
-            (Regex::new(r"^https?://viaf.org/processed/JPG|(\d+)$").unwrap(),"${1}".to_string(),245),
+            (Regex::new(r"^https?://(?:www\.)?viaf\.org/processed/JPG%7C(\d+)$").unwrap(),"${1}".to_string(),245),
             (Regex::new(r"^https?://data.bnf.fr/(\d{8,9}).*$").unwrap(),"${1}".to_string(),268),
             (Regex::new(r"^https?://data.bnf.fr/ark:/12148/cb(\d{8,9}[0-9bcdfghjkmnpqrstvwxz]).*$").unwrap(),"${1}".to_string(),268),
             (Regex::new(r"^https?://idref.fr/(\d{8}[\dX]).*$").unwrap(),"${1}".to_string(),269),
@@ -42,16 +46,21 @@ lazy_static! {
             (Regex::new(r"^https?://datos.bne.es/resource/(.+?)$").unwrap(),"${1}".to_string(),950),
             (Regex::new(r"^https?://data.bibsys.no/data/notrbib/authorityentry/x([1-9]\d*)$").unwrap(),"${1}".to_string(),1015),
             (Regex::new(r"^https?://authority.bibsys.no/authority/rest/authorities/html/([1-9]\d*)$").unwrap(),"${1}".to_string(),1015),
+            (Regex::new(r"^https?://vocab.getty.edu/aat/(\d+)$").unwrap(),"${1}".to_string(),1014),
             (Regex::new(r"^https?://www.scopus.com/authid/detail.uri\?authorId=([1-9]\d{9,10}).*$").unwrap(),"${1}".to_string(),1153),
+            (Regex::new(r"^https?://(?:www\.|sws\.)?geonames\.org/(\d+)(?:/.*)?$").unwrap(),"${1}".to_string(),1566),
+            (Regex::new(r"^https?://vocab.getty.edu/tgn/(\d+)(-place)?$").unwrap(),"${1}".to_string(),1667),
             (Regex::new(r"^https?://data.cerl.org/thesaurus/(c(?:af|nc|ni|nl|np)0\d{7})$").unwrap(),"${1}".to_string(),1871),
             (Regex::new(r"^https?://data.cerl.org/thesaurus/(.*)$").unwrap(),"${1}".to_string(),1871),
             (Regex::new(r"^https?://thesaurus.cerl.org/record/(c(?:af|nc|ni|nl|np)0\d{7})$").unwrap(),"${1}".to_string(),1871),
+            (Regex::new(r"^https?://id.worldcat.org/fast/([1-9]\d*)/?$").unwrap(),"${1}".to_string(),2163),
             (Regex::new(r"^https?://authority\.bibsys\.no/authority/rest/authorities/html/([1-9]\d*).*$").unwrap(),"${1}".to_string(),1015),
             (Regex::new(r"^https?://(?:www\.)?viaf\.org/processed/BIBSYS%7C([1-9]\d*)$").unwrap(),"${1}".to_string(),1015),
             (Regex::new(r"^https?://authority.bibsys.no/authority/rest/authorities/html/(\d+).*$").unwrap(),"${1}".to_string(),1015),
             (Regex::new(r"^https?://entities.oclc.org/worldcat/entity/([^.]+)$").unwrap(),"${1}".to_string(),10832),
             (Regex::new(r"^https?://entities.oclc.org/worldcat/entity/([^.]+).html$").unwrap(),"${1}".to_string(),10832),
             (Regex::new(r"^https?://entities.oclc.org/worldcat/entity/([^.]+).jsonld$").unwrap(),"${1}".to_string(),10832),
+            (Regex::new(r"^https?://id.oclc.org/worldcat/entity/([^./]+)$").unwrap(),"${1}".to_string(),10832),
         ]
     };
 
@@ -146,6 +155,7 @@ lazy_static! {
             ("cc-by", "Q6905323"),
             ("http://creativecommons.org/licenses/by/4.0/","Q20007257"),
             ("http://creativecommons.org/licenses/by-sa/4.0/","Q18199165"),
+            ("public domain", "Q19652"),
         ]
             .into_iter()
             .collect();
@@ -164,6 +174,14 @@ lazy_static! {
     .collect();
 }
 
+/// The single ID of `ids`, if there is exactly one and it differs from `my_id`
+pub fn canonical_id(ids: Vec<String>, my_id: &str) -> Option<String> {
+    match ids.as_slice() {
+        [id] if id != my_id => Some(id.to_owned()),
+        _ => None,
+    }
+}
+
 #[async_trait]
 pub trait ExternalImporter {
     // These methods need to be implemented by the importer
@@ -172,8 +190,27 @@ pub trait ExternalImporter {
     fn my_property(&self) -> usize;
     fn my_id(&self) -> String;
     fn my_stated_in(&self) -> &str;
+    /// Downloads the source record. Constructing an importer is cheap, so the caller can decide if it is needed at all.
+    async fn fetch(&mut self) -> Result<()>;
+    /// Reads the record from text in the format `fetch` downloads, eg from a dump, instead of downloading it
+    fn parse(&mut self, text: &str) -> Result<()>;
     async fn run(&self) -> Result<MetaItem>;
 
+    /// The importer, with the record read from `text` instead of downloaded; eg for records from a dump, or in tests
+    fn with_record(mut self, text: &str) -> Result<Self>
+    where
+        Self: Sized,
+    {
+        self.parse(text)?;
+        Ok(self)
+    }
+
+    /// The ID the source now uses for the record, if it differs from the requested one (after a redirect or merge).
+    /// Importers call this in their constructor, and use the canonical ID from then on.
+    fn resolve_canonical_id(&self) -> Result<Option<String>> {
+        Ok(None)
+    }
+
     fn graph(&self) -> &FastGraph {
         lazy_static! {
             static ref DUMMY_GRAPH: FastGraph = FastGraph::new();
@@ -250,6 +287,28 @@ pub trait ExternalImporter {
         Ok(ret)
     }
 
+    /// Like `triples_subject_literals`, with the language tag of each literal, if any
+    fn triples_subject_literals_with_language(
+        &self,
+        id_url: &str,
+        p: &str,
+    ) -> Result<Vec<(String, Option<String>)>> {
+        let mut ret = vec![];
+        let iri_id = Iri::new(id_url)?;
+        let iri_p = Iri::new(p)?;
+        self.graph()
+            .triples_matching([&iri_id], [&iri_p], Any)
+            .for_each_triple(|t| {
+                if let Some(literal) = t.o().lexical_form() {
+                    let language = t.o().language_tag().map(|tag| tag.as_str().to_lowercase());
+                    ret.push((literal.to_string(), language));
+                }
+            })?;
+        ret.sort();
+        ret.dedup();
+        Ok(ret)
+    }
+
     fn triples_literals(&self, p: &str) -> Result<Vec<String>> {
         self.triples_subject_literals(&self.get_id_url(), p)
     }
@@ -272,6 +331,24 @@ pub trait ExternalImporter {
         Ok(ret)
     }
 
+    /// Returns the first capture group of `re`, if the subject IRIs of property `p` yield exactly one that differs from `my_id()`
+    fn canonical_id_from_subjects(&self, p: &str, re: &Regex) -> Result<Option<String>> {
+        let mut ids = vec![];
+        let iri_p = Iri::new(p)?;
+        self.graph()
+            .triples_matching(Any, [&iri_p], Any)
+            .for_each_triple(|t| {
+                if let Some(iri) = t.s().iri() {
+                    if let Some(captures) = re.captures(iri.as_str()) {
+                        ids.push(captures[1].to_string());
+                    }
+                }
+            })?;
+        ids.sort();
+        ids.dedup();
+        Ok(canonical_id(ids, &self.my_id()))
+    }
+
     fn triples_property_literals(&self, p: &str) -> Result<Vec<String>> {
         let mut ret = vec![];
         let iri_p = Iri::new(p)?;
@@ -328,6 +405,11 @@ pub trait ExternalImporter {
         ])]
     }
 
+    /// A statement for an external ID, in the form Wikidata uses
+    fn new_statement_external_id(&self, ext_id: &ExternalId) -> Statement {
+        self.new_statement_string(ext_id.property(), &ext_id.wikidata_value())
+    }
+
     fn new_statement_string(&self, property: usize, s: &str) -> Statement {
         Statement::new(
             "statement",
@@ -425,6 +507,49 @@ pub trait ExternalImporter {
         )
     }
 
+    /// A date statement, with the qualifiers of an approximate date or a range
+    fn new_statement_fuzzy_date(&self, property: usize, date: &FuzzyDate) -> Statement {
+        let mut statement = Statement::new(
+            "statement",
+            StatementRank::Normal,
+            date.main_snak(property),
+            vec![],
+            self.get_ref(),
+        );
+        date.add_qualifiers(&mut statement);
+        statement
+    }
+
+    fn new_statement_coordinate(
+        &self,
+        property: usize,
+        latitude: f64,
+        longitude: f64,
+        precision: f64,
+    ) -> Statement {
+        Statement::new(
+            "statement",
+            StatementRank::Normal,
+            Snak::new(
+                SnakDataType::GlobeCoordinate,
+                format!("P{}", property),
+                SnakType::Value,
+                Some(DataValue::new(
+                    DataValueType::GlobeCoordinate,
+                    Value::Coordinate(Coordinate::new(
+                        None,
+                        "http://www.wikidata.org/entity/Q2".to_string(),
+                        latitude,
+                        longitude,
+                        Some(precision),
+                    )),
+                )),
+            ),
+            vec![],
+            self.get_ref(),
+        )
+    }
+
     async fn add_same_as(&self, ret: &mut MetaItem) -> Result<()> {
         let iris = [
             "http://www.w3.org/2002/07/owl#sameAs",
@@ -434,22 +559,45 @@ pub trait ExternalImporter {
             "http://schema.org/sameAs",
             "http://www.loc.gov/mads/rdf/v1#identifiesRWO",
         ];
+        let mut urls = vec![];
         for iri in iris {
-            for url in self.triples_iris(iri)? {
-                if ExternalId::do_not_use_external_url(&url) {
+            urls.append(&mut self.triples_iris(iri)?);
+        }
+        self.add_same_as_urls(ret, urls).await
+    }
+
+    /// Adds the external IDs in URLs of the same entity elsewhere, if they are valid;
+    /// URLs that are not an external ID are added as "described at URL".
+    async fn add_same_as_urls(&self, ret: &mut MetaItem, urls: Vec<String>) -> Result<()> {
+        let mut ext_ids = vec![];
+        for url in urls {
+            if ExternalId::do_not_use_external_url(&url) {
+                continue;
+            }
+            let extid = match self.url2external_id(&url) {
+                Some(extid) => extid,
+                None => {
+                    let _ = ret.add_claim(self.new_statement_url(973, &url));
                     continue;
                 }
-                let _ = match self.url2external_id(&url) {
-                    Some(extid) => {
-                        if extid.check_if_valid().await? {
-                            ret.add_claim(self.new_statement_string(extid.property(), extid.id()))
-                        } else {
-                            None
-                        }
-                    }
-                    None => ret.add_claim(self.new_statement_url(973, &url)),
-                };
+            };
+            if !extid.is_well_formed() || !FormatConstraint::is_valid(&extid).await {
+                continue;
             }
+            ext_ids.push(extid);
+        }
+        let validities = ExternalId::check_validity_all(&ext_ids).await;
+        for extid in ext_ids {
+            let _ = match validities.get(&extid) {
+                // Unknown if the check failed; the link is kept, rather than failing the whole import
+                Some(IdValidity::Valid) | None => {
+                    ret.add_claim(self.new_statement_external_id(&extid))
+                }
+                Some(IdValidity::Replaced(current)) => {
+                    ret.add_claim(self.new_statement_external_id(current))
+                }
+                Some(IdValidity::Invalid) => None,
+            };
         }
         Ok(())
     }
@@ -506,20 +654,28 @@ pub trait ExternalImporter {
         }
     }
 
-    fn add_label_aliases(&self, ret: &mut MetaItem) -> Result<()> {
-        let language = self.primary_language();
+    /// The language for a label or alias with the given language tag; by default, all are in the primary language
+    fn label_language(&self, _language_tag: Option<&str>) -> String {
+        self.primary_language()
+    }
 
+    fn add_label_aliases(&self, ret: &mut MetaItem) -> Result<()> {
         let urls = [
             "http://schema.org/name",
             "http://xmlns.com/foaf/0.1/name",
             "https://datos.bne.es/def/P5012",
             "https://d-nb.info/standards/elementset/gnd#preferredNameForThePerson",
             "https://d-nb.info/standards/elementset/gnd#variantNameForThePerson",
+            "https://d-nb.info/standards/elementset/gnd#preferredNameForTheCorporateBody",
+            "https://d-nb.info/standards/elementset/gnd#variantNameForTheCorporateBody",
+            "https://d-nb.info/standards/elementset/gnd#preferredNameForThePlaceOrGeographicName",
+            "https://d-nb.info/standards/elementset/gnd#variantNameForThePlaceOrGeographicName",
             "http://schema.org/alternateName",
             "http://www.w3.org/2000/01/rdf-schema#label",
         ];
         for url in urls {
-            for s in self.triples_literals(url)? {
+            for (s, tag) in self.triples_subject_literals_with_language(&self.get_id_url(), url)? {
+                let language = self.label_language(tag.as_deref());
                 let s = self.transform_label(&s);
                 let s = self.limit_string_length(&s);
                 match ret.item.label_in_locale(&language) {
@@ -569,21 +725,49 @@ pub trait ExternalImporter {
     ) -> Result<bool> {
         let mut found = false;
         for s in self.triples_literals(p_iri)? {
-            let query = format!("{s} haswbstatement:P31={p31}");
-            // TODO check all returned items for label/alias instead of just returning item if a single one was found
-            match ExternalId::search_wikidata_single_item(&query).await {
-                Some(item) => {
-                    ret.add_claim(self.new_statement_item(prop, &item));
+            let query = LookupQuery::new(31, p31).with_text(&s);
+            let candidates = match lookup().find_candidates(&query).await {
+                Ok(candidates) => candidates,
+                Err(e) => {
+                    tracing::warn!("Lookup for '{s}' failed: {e}");
+                    vec![]
+                }
+            };
+            match LookupCandidate::pick(&candidates, &s) {
+                Some(candidate) => {
+                    ret.add_claim(self.new_statement_item(prop, &candidate.item));
                     found = true;
                 }
                 None => {
-                    let _ = ret.add_prop_text(ExternalId::new(prop, &s));
+                    let ext_id = ExternalId::new(prop, &s);
+                    if candidates.len() > 1 {
+                        let items = candidates.into_iter().map(|c| c.item).collect();
+                        ret.add_prop_text_candidates(ext_id.to_owned(), items);
+                    }
+                    let _ = ret.add_prop_text(ext_id);
                 }
             }
         }
         Ok(found)
     }
 
+    /// Links to other records, as (property, URL or label, ID of the linked record). Looks up the items for all IDs
+    /// at once; links without one are kept as text.
+    async fn add_related(
+        &self,
+        ret: &mut MetaItem,
+        related: Vec<(usize, String, Option<ExternalId>)>,
+    ) {
+        let ext_ids: Vec<ExternalId> = related.iter().filter_map(|r| r.2.to_owned()).collect();
+        let items = ExternalId::get_item_for_external_id_values(&ext_ids).await;
+        for (property, url, ext_id) in related {
+            let _ = match ext_id.and_then(|id| items.get(&id)) {
+                Some(item) => ret.add_claim(self.new_statement_item(property, item)),
+                None => ret.add_prop_text(ExternalId::new(property, &url)),
+            };
+        }
+    }
+
     fn lowercase_first_letter(&self, input: &str) -> String {
         let mut chars = input.chars();
         match chars.next() {
@@ -734,6 +918,38 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_url2external_id_viaf_processed() {
+        let t = crate::viaf::VIAF::unfetched("312603351"); // Any ID will do
+        assert_eq!(
+            t.url2external_id("http://viaf.org/processed/JPG%7C500115493"),
+            Some(ExternalId::new(245, "500115493"))
+        );
+        assert_eq!(
+            t.url2external_id("https://www.viaf.org/processed/JPG%7C500115493"),
+            Some(ExternalId::new(245, "500115493"))
+        );
+        // The old pattern matched the prefix alone, and any URL ending in digits
+        assert_eq!(
+            t.url2external_id("http://viaf.org/processed/JPGX%7C500115493"),
+            None
+        );
+        assert_eq!(
+            t.url2external_id("http://www.wikidata.org/entity/Q42"),
+            None
+        );
+        assert_eq!(t.url2external_id("https://example.org/record/12345"), None);
+    }
+
+    #[test]
+    fn test_canonical_id() {
+        let ids = |ids: &[&str]| ids.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        assert_eq!(canonical_id(ids(&["2"]), "1"), Some("2".to_string()));
+        assert_eq!(canonical_id(ids(&["1"]), "1"), None);
+        assert_eq!(canonical_id(ids(&["1", "2"]), "1"), None);
+        assert_eq!(canonical_id(vec![], "1"), None);
+    }
+
     #[tokio::test]
     async fn test_lowercase_first_letter() {
         let t = crate::viaf::VIAF::new("312603351").await.unwrap(); // Any ID will do
@@ -0,0 +1,90 @@
+use crate::app_error::AppError;
+use crate::config::Config;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+const QUEUE_TIMEOUT_SECS: u64 = 30;
+pub const RETRY_AFTER_SECS: u64 = 10;
+
+lazy_static! {
+    static ref FETCH_BUDGET: FetchBudget = FetchBudget::new(
+        Config::get().http.max_upstream_fetches,
+        Config::get().http.max_queued_fetches,
+    );
+}
+
+/// Limits the number of simultaneous upstream HTTP fetches across all requests.
+/// Fetches beyond the limit wait in a bounded queue; once that is full, they fail with `AppError::Overloaded`.
+#[derive(Debug, Clone)]
+pub struct FetchBudget {
+    semaphore: Arc<Semaphore>,
+    waiting: Arc<AtomicUsize>,
+    max_queued: usize,
+}
+
+impl FetchBudget {
+    pub fn new(max_fetches: usize, max_queued: usize) -> Self {
+        Self {
+            semaphore: Arc::new(Semaphore::new(max_fetches.max(1))),
+            waiting: Arc::new(AtomicUsize::new(0)),
+            max_queued,
+        }
+    }
+
+    /// The server-wide budget, configured via `http.max_upstream_fetches` and `http.max_queued_fetches`
+    pub fn global() -> &'static Self {
+        &FETCH_BUDGET
+    }
+
+    /// Waits for a fetch slot; the slot is released when the permit is dropped
+    pub async fn acquire(&self) -> Result<OwnedSemaphorePermit, AppError> {
+        if let Ok(permit) = self.semaphore.clone().try_acquire_owned() {
+            return Ok(permit);
+        }
+        if self.waiting.fetch_add(1, Ordering::SeqCst) >= self.max_queued {
+            self.waiting.fetch_sub(1, Ordering::SeqCst);
+            return Err(Self::overloaded());
+        }
+        let result = tokio::time::timeout(
+            Duration::from_secs(QUEUE_TIMEOUT_SECS),
+            self.semaphore.clone().acquire_owned(),
+        )
+        .await;
+        self.waiting.fetch_sub(1, Ordering::SeqCst);
+        match result {
+            Ok(Ok(permit)) => Ok(permit),
+            _ => Err(Self::overloaded()),
+        }
+    }
+
+    /// True if all fetch slots are taken and the queue is full
+    pub fn is_saturated(&self) -> bool {
+        self.semaphore.available_permits() == 0
+            && self.waiting.load(Ordering::SeqCst) >= self.max_queued
+    }
+
+    pub fn overloaded() -> AppError {
+        AppError::Overloaded("Too many upstream requests, please try again later".to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_acquire() {
+        let budget = FetchBudget::new(1, 0);
+        let permit = budget.acquire().await.unwrap();
+        assert!(budget.is_saturated());
+        assert_eq!(
+            budget.acquire().await.unwrap_err(),
+            FetchBudget::overloaded()
+        );
+        drop(permit);
+        assert!(!budget.is_saturated());
+        assert!(budget.acquire().await.is_ok());
+    }
+}
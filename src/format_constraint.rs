@@ -0,0 +1,107 @@
+use crate::config::Config;
+use crate::external_id::ExternalId;
+use crate::target::{Direction, Target};
+use crate::utility::Utility;
+use anyhow::Result;
+use regex::Regex;
+use serde_json::Value;
+use std::collections::HashMap;
+use tokio::sync::Mutex;
+
+const FORMAT_REGEX_PROPERTY: &str = "P1793";
+
+lazy_static! {
+    /// Format regexes per property; `None` if the property has none. Failed loads are not kept, so they are retried.
+    static ref FORMAT_REGEXES: Mutex<HashMap<usize, Option<Regex>>> = Mutex::new(HashMap::new());
+}
+
+/// Checks external IDs against the "format as a regular expression" (P1793) of their Wikidata property
+pub struct FormatConstraint {}
+
+impl FormatConstraint {
+    /// Returns `false` only if checks are enabled via `validation.format_constraints`,
+    /// and the property has a format regex that the ID does not match.
+    pub async fn is_valid(ext_id: &ExternalId) -> bool {
+        if !Config::get().validation.format_constraints {
+            return true;
+        }
+        match Self::regex_for_property(ext_id.property()).await {
+            Some(re) => re.is_match(&ext_id.wikidata_value()),
+            None => true,
+        }
+    }
+
+    /// The lock is not held while loading, so a slow fetch does not hold up checks for other properties;
+    /// concurrent loads of the same property share one fetch in `Utility::get_url`.
+    async fn regex_for_property(property: usize) -> Option<Regex> {
+        if let Some(re) = FORMAT_REGEXES.lock().await.get(&property) {
+            return re.to_owned();
+        }
+        match Self::load_regex(property).await {
+            Ok(re) => {
+                FORMAT_REGEXES.lock().await.insert(property, re.to_owned());
+                re
+            }
+            Err(e) => {
+                tracing::warn!("Could not load format constraint for P{property}: {e}");
+                None
+            }
+        }
+    }
+
+    async fn load_regex(property: usize) -> Result<Option<Regex>> {
+        let to_target = |p: &str| Target::property(p, Direction::ToTarget);
+        let (property, format_property) = match (
+            to_target(&format!("P{property}")),
+            to_target(FORMAT_REGEX_PROPERTY),
+        ) {
+            (Some(property), Some(format_property)) => (property, format_property),
+            _ => return Ok(None),
+        };
+        let url = format!(
+            "{}?action=wbgetclaims&entity={property}&property={format_property}&format=json",
+            Target::api()
+        );
+        let j: Value = serde_json::from_str(&Utility::get_url(&url).await?)?;
+        Ok(Self::regex_from_claims(&j, &format_property))
+    }
+
+    /// Uses the first format regex of preferred or normal rank, anchored to match the whole ID
+    fn regex_from_claims(j: &Value, format_property: &str) -> Option<Regex> {
+        let claims = j["claims"][format_property].as_array()?;
+        let mut claims: Vec<&Value> = claims
+            .iter()
+            .filter(|c| c["rank"].as_str() != Some("deprecated"))
+            .collect();
+        claims.sort_by_key(|c| c["rank"].as_str() != Some("preferred"));
+        let pattern = claims
+            .first()?
+            .get("mainsnak")?
+            .get("datavalue")?
+            .get("value")?
+            .as_str()?;
+        Regex::new(&format!("^(?:{pattern})$")).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_regex_from_claims() {
+        let j = json!({"claims":{"P1793":[
+            {"rank":"deprecated","mainsnak":{"datavalue":{"value":".*"}}},
+            {"rank":"normal","mainsnak":{"datavalue":{"value":"\\d{4} \\d{4} \\d{4} \\d{3}[\\dX]"}}}
+        ]}});
+        let re = FormatConstraint::regex_from_claims(&j, FORMAT_REGEX_PROPERTY).unwrap();
+        assert!(re.is_match("0000 0001 2184 9233"));
+        assert!(!re.is_match("0000000121849233"));
+        assert!(!re.is_match("x0000 0001 2184 9233"));
+        assert!(
+            FormatConstraint::regex_from_claims(&json!({"claims":{}}), FORMAT_REGEX_PROPERTY)
+                .is_none()
+        );
+    }
+}
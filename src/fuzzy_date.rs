@@ -0,0 +1,233 @@
+use regex::Regex;
+use wikimisc::wikibase::{
+    DataValue, DataValueType, Snak, SnakDataType, SnakType, Statement, TimeValue,
+};
+
+lazy_static! {
+    static ref RE_CIRCA: Regex =
+        Regex::new(r"(?i)^(ca\.?|um|circa|approximately|approx\.)\s+").expect("Regexp error");
+    static ref RE_UNCERTAIN: Regex = Regex::new(r"^(.+?)\s*[?~]$").expect("Regexp error");
+    /// A year before the common era, like "-0500" or "-50"; "-1550" is an open range instead
+    static ref RE_BCE_YEAR: Regex = Regex::new(r"^-(\d{1,3}|0\d{3})$").expect("Regexp error");
+    static ref RE_YEAR_RANGE: Regex =
+        Regex::new(r"^(\d{3,4})?\s*(?:-|/|\bor\b)\s*(\d{3,4})?$").expect("Regexp error");
+    /// Two dates, each a year or a full date, with an optional qualifier like "ca.", and either may be missing
+    static ref RE_LIFE_SPAN: Regex = Regex::new(
+        r"^([^\d-]*\d{3,4}(?:-\d{2}){0,2}\??)?\s*-\s*([^\d-]*\d{3,4}(?:-\d{2}){0,2}\??)?$"
+    )
+    .expect("Regexp error");
+}
+
+/// A date that may be approximate, or a range, like "1749-08-28", "ca. 1500", "[um 1500]", "1500?",
+/// "1500-1550", "1500 or 1501", or "-0500"
+#[derive(Debug, Clone, PartialEq)]
+pub struct FuzzyDate {
+    /// The time and precision of the main value. For a range, this is the decade or century that covers both
+    /// bounds; `None` (an unknown value) if there is none, or if the range is open.
+    pub value: Option<(String, u64)>,
+    pub circa: bool,
+    /// Bounds of a range; either may be open
+    pub earliest: Option<(String, u64)>,
+    pub latest: Option<(String, u64)>,
+}
+
+impl FuzzyDate {
+    pub fn parse(s: &str) -> Option<Self> {
+        let s = s.trim();
+        let s = s
+            .strip_prefix('[')
+            .and_then(|s| s.strip_suffix(']'))
+            .unwrap_or(s)
+            .trim();
+        let mut circa = RE_CIRCA.is_match(s);
+        let s = RE_CIRCA.replace(s, "").to_string();
+        let s = match RE_UNCERTAIN.captures(&s) {
+            Some(captures) => {
+                circa = true;
+                captures[1].to_string()
+            }
+            None => s,
+        };
+        if let Some(captures) = RE_BCE_YEAR.captures(&s) {
+            let year: u32 = captures[1].parse().ok()?;
+            return Some(Self {
+                value: Some((format!("-{year:04}-00-00T00:00:00Z"), 9)),
+                circa,
+                earliest: None,
+                latest: None,
+            });
+        }
+        let year = |m: Option<regex::Match>| Self::parse_simple(m?.as_str());
+        if let Some(captures) = RE_YEAR_RANGE.captures(&s) {
+            let earliest = year(captures.get(1));
+            let latest = year(captures.get(2));
+            let value = match (&earliest, &latest) {
+                (Some(earliest), Some(latest)) => Self::covering(earliest, latest),
+                (None, None) => return None,
+                _ => None,
+            };
+            return Some(Self {
+                value,
+                circa,
+                earliest,
+                latest,
+            });
+        }
+        Some(Self {
+            value: Some(Self::parse_simple(&s)?),
+            circa,
+            earliest: None,
+            latest: None,
+        })
+    }
+
+    /// The precision of the main value; 0 if it is unknown, so it ranks below any known date
+    pub fn precision(&self) -> u64 {
+        self.value.as_ref().map_or(0, |(_, precision)| *precision)
+    }
+
+    /// The year, decade, or century that contains both bounds; these are (common era) years
+    fn covering(earliest: &(String, u64), latest: &(String, u64)) -> Option<(String, u64)> {
+        let year = |(time, _): &(String, u64)| -> Option<i64> {
+            time.trim_start_matches('+').split('-').next()?.parse().ok()
+        };
+        let (from, to) = (year(earliest)?, year(latest)?);
+        let time = |year: i64| format!("+{year:04}-00-00T00:00:00Z");
+        if from == to {
+            Some((time(from), 9))
+        } else if from.div_euclid(10) == to.div_euclid(10) {
+            Some((time(from.div_euclid(10) * 10), 8))
+        } else if (from - 1).div_euclid(100) == (to - 1).div_euclid(100) {
+            // Centuries run from 1501 to 1600
+            Some((time((from - 1).div_euclid(100) * 100 + 1), 7))
+        } else {
+            None
+        }
+    }
+
+    /// The start and end of a life span like "1809-1882", "1950-", or "1809-02-12-1882-04-19".
+    /// Unlike `parse`, the two dates are separate events, not the bounds of one uncertain date.
+    pub fn parse_life_span(s: &str) -> (Option<Self>, Option<Self>) {
+        let s = s.trim().trim_end_matches(['.', ',']);
+        match RE_LIFE_SPAN.captures(s) {
+            Some(captures) => (
+                captures.get(1).and_then(|m| Self::parse(m.as_str())),
+                captures.get(2).and_then(|m| Self::parse(m.as_str())),
+            ),
+            None => (None, None),
+        }
+    }
+
+    fn parse_simple(s: &str) -> Option<(String, u64)> {
+        let date = wikimisc::date::Date::from_str(s)?;
+        Some((date.time().to_string(), date.precision()))
+    }
+
+    fn time_snak(property: &str, (time, precision): &(String, u64)) -> Snak {
+        Snak::new(
+            SnakDataType::Time,
+            property,
+            SnakType::Value,
+            Some(DataValue::new(
+                DataValueType::Time,
+                wikimisc::wikibase::Value::Time(TimeValue::new(
+                    0,
+                    0,
+                    "http://www.wikidata.org/entity/Q1985727",
+                    *precision,
+                    time,
+                    0,
+                )),
+            )),
+        )
+    }
+
+    /// The main snak for `property`; an unknown value if the main value is not known
+    pub fn main_snak(&self, property: usize) -> Snak {
+        let property = format!("P{property}");
+        match &self.value {
+            Some(value) => Self::time_snak(&property, value),
+            None => Snak::new(SnakDataType::Time, &property, SnakType::UnknownValue, None),
+        }
+    }
+
+    /// Adds "circa" (P1480), earliest date (P1319), and latest date (P1326) qualifiers
+    pub fn add_qualifiers(&self, statement: &mut Statement) {
+        if self.circa {
+            statement.add_qualifier_snak(Snak::new_item("P1480", "Q5727902"));
+        }
+        if let Some(earliest) = &self.earliest {
+            statement.add_qualifier_snak(Self::time_snak("P1319", earliest));
+        }
+        if let Some(latest) = &self.latest {
+            statement.add_qualifier_snak(Self::time_snak("P1326", latest));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let date = FuzzyDate::parse("ca. 1500").unwrap();
+        assert!(date.circa);
+        assert_eq!(date.precision(), 9);
+        assert!(FuzzyDate::parse("[um 1500]").unwrap().circa);
+        assert!(FuzzyDate::parse("approximately 1500").unwrap().circa);
+        assert!(FuzzyDate::parse("1500?").unwrap().circa);
+        assert!(!FuzzyDate::parse("1749-08-28").unwrap().circa);
+        assert_eq!(FuzzyDate::parse("1749-08-28").unwrap().precision(), 11);
+        assert_eq!(FuzzyDate::parse("unbekannt"), None);
+    }
+
+    #[test]
+    fn test_parse_range() {
+        // 1500 is the last year of the 15th century, so no century covers both
+        let range = FuzzyDate::parse("1500-1550").unwrap();
+        assert_eq!(range.value, None);
+        assert_eq!(range.precision(), 0);
+        assert_eq!(range.earliest.unwrap().0, "+1500-00-00T00:00:00Z");
+        assert!(range.latest.is_some());
+        let range = FuzzyDate::parse("1501-1550").unwrap();
+        assert_eq!(range.value, Some(("+1501-00-00T00:00:00Z".to_string(), 7)));
+        let range = FuzzyDate::parse("1500 or 1501").unwrap();
+        assert_eq!(range.value, Some(("+1500-00-00T00:00:00Z".to_string(), 8)));
+        assert!(FuzzyDate::parse("1500/1501").unwrap().latest.is_some());
+    }
+
+    #[test]
+    fn test_parse_open_range() {
+        let open = FuzzyDate::parse("-1550").unwrap();
+        assert_eq!(open.value, None);
+        assert!(open.earliest.is_none());
+        assert_eq!(open.latest.unwrap().1, 9);
+        let open = FuzzyDate::parse("1500-").unwrap();
+        assert_eq!(open.value, None);
+        assert!(open.latest.is_none());
+    }
+
+    #[test]
+    fn test_parse_bce() {
+        let date = FuzzyDate::parse("-0500").unwrap();
+        assert_eq!(date.value, Some(("-0500-00-00T00:00:00Z".to_string(), 9)));
+        assert_eq!(date.latest, None);
+        let date = FuzzyDate::parse("ca. -50").unwrap();
+        assert!(date.circa);
+        assert_eq!(date.value, Some(("-0050-00-00T00:00:00Z".to_string(), 9)));
+    }
+
+    #[test]
+    fn test_parse_life_span() {
+        let (birth, death) = FuzzyDate::parse_life_span("1950-");
+        assert!(birth.is_some() && death.is_none());
+        assert_eq!(FuzzyDate::parse_life_span("1809-02-12"), (None, None));
+        let (birth, death) = FuzzyDate::parse_life_span("1809-02-12-1882-04-19");
+        assert_eq!(birth.unwrap().precision(), 11);
+        assert_eq!(death.unwrap().precision(), 11);
+        let (birth, death) = FuzzyDate::parse_life_span("ca. 1500-1550.");
+        assert!(birth.unwrap().circa);
+        assert_eq!(death.unwrap().precision(), 9);
+    }
+}
@@ -1,6 +1,7 @@
 use crate::external_importer::*;
 use crate::meta_item::*;
-use crate::ExternalId;
+use crate::utility::Utility;
+use crate::wikidata_lookup::{lookup, LookupQuery};
 use anyhow::Result;
 use axum::async_trait;
 use serde_json::Value;
@@ -14,9 +15,6 @@ pub struct GBIFtaxon {
     json: Value,
 }
 
-unsafe impl Send for GBIFtaxon {}
-unsafe impl Sync for GBIFtaxon {}
-
 #[async_trait]
 impl ExternalImporter for GBIFtaxon {
     fn my_property(&self) -> usize {
@@ -35,6 +33,17 @@ impl ExternalImporter for GBIFtaxon {
         self.id.to_owned()
     }
 
+    async fn fetch(&mut self) -> Result<()> {
+        let url = format!("https://api.gbif.org/v1/species/{}", self.id);
+        let resp = Utility::get_url(&url).await?;
+        self.parse(&resp)
+    }
+
+    fn parse(&mut self, text: &str) -> Result<()> {
+        self.json = serde_json::from_str(text)?;
+        Ok(())
+    }
+
     async fn run(&self) -> Result<MetaItem> {
         let mut ret = MetaItem::new();
         self.add_own_id(&mut ret)?;
@@ -43,30 +52,31 @@ impl ExternalImporter for GBIFtaxon {
         let _ = self.add_taxon_name_and_labels(&mut ret);
         let _ = self.add_common_name(&mut ret);
         let _ = self.add_taxon_rank(&mut ret);
-        let _ = self.add_commons_compatible_image(&mut ret).await;
+        self.add_commons_compatible_image(&mut ret).await?;
         ret.cleanup();
         Ok(ret)
     }
 }
 
 impl GBIFtaxon {
-    pub async fn new(id: &str) -> Result<Self> {
-        let url = format!("https://api.gbif.org/v1/species/{id}");
-        let resp = reqwest::get(&url).await?.text().await?;
-        let json = serde_json::from_str(&resp)?;
-        Ok(Self {
+    pub fn unfetched(id: &str) -> Self {
+        Self {
             id: id.to_string(),
-            json,
-        })
+            json: Value::Null,
+        }
+    }
+
+    pub async fn new(id: &str) -> Result<Self> {
+        let mut ret = Self::unfetched(id);
+        ret.fetch().await?;
+        Ok(ret)
     }
 
     async fn add_parent_taxon(&self, ret: &mut MetaItem) -> Option<()> {
         let parent_id = self.json.get("parentKey")?.as_i64()?;
-        let query = format!(
-            "haswbstatement:P{}={parent_id} haswbstatement:P31=Q16521",
-            self.my_property()
-        );
-        let item = ExternalId::search_wikidata_single_item(&query).await?;
+        let query = LookupQuery::new(self.my_property(), &parent_id.to_string())
+            .with_statement(31, "Q16521");
+        let item = lookup().find_single_item(&query).await?;
         ret.add_claim(self.new_statement_item(171, &item));
         Some(())
     }
@@ -104,18 +114,23 @@ impl GBIFtaxon {
         Some(())
     }
 
-    async fn add_commons_compatible_image(&self, ret: &mut MetaItem) -> Option<()> {
+    /// Images are optional; only an overloaded fetch budget is an error
+    async fn add_commons_compatible_image(&self, ret: &mut MetaItem) -> Result<()> {
         let url = format!(
             "https://api.gbif.org/v1/occurrence/search?limit=20&media_type=stillImage&taxon_key={}",
             self.id
         );
-        let resp = reqwest::get(&url).await.ok()?.text().await.ok()?;
-        let json: Value = serde_json::from_str(&resp).ok()?;
-        let results = json.get("results")?.as_array()?;
+        let resp = match Utility::get_url(&url).await {
+            Ok(resp) => resp,
+            Err(e) if Utility::is_overloaded(&e) => return Err(e),
+            Err(_) => return Ok(()),
+        };
+        let json: Value = serde_json::from_str(&resp).unwrap_or_default();
+        let results = json["results"].as_array().into_iter().flatten();
         for result in results {
             let _ = self.add_commons_compatible_image_from_photo(ret, result);
         }
-        Some(())
+        Ok(())
     }
 
     fn add_commons_compatible_image_from_photo(
@@ -1,29 +1,136 @@
 use crate::external_id::*;
 use crate::external_importer::*;
+use crate::fuzzy_date::FuzzyDate;
 use crate::meta_item::*;
+use crate::utility::Utility;
 use anyhow::Result;
 use axum::async_trait;
 use regex::Regex;
 use sophia::api::prelude::*;
 use sophia::inmem::graph::FastGraph;
-use sophia::xml;
-use wikimisc::wikibase::{Snak, StatementRank};
+use std::collections::HashMap;
+use wikimisc::wikibase::{EntityTrait, LocaleString, Snak, StatementRank};
 
 lazy_static! {
     static ref RE_COUNTRY: Regex =
         Regex::new(r"^https?://d-nb.info/standards/vocab/gnd/geographic-area-code#XA-(.+)$")
             .expect("Regexp error");
+    static ref RE_WKT_POINT: Regex =
+        Regex::new(r"(?i)^\s*point\s*\(\s*([+-]?\d+(?:\.(\d+))?)\s+([+-]?\d+(?:\.\d+)?)\s*\)\s*$")
+            .expect("Regexp error");
+}
+
+/// Academic degrees (P512), as GND gives them
+const ACADEMIC_DEGREES: &[(&str, &str)] = &[
+    ("dr.", "Q849697"),
+    ("dr. phil.", "Q752297"),
+    ("ph. d.", "Q752297"),
+    ("phd", "Q752297"),
+    ("dr. med.", "Q913404"),
+    ("m.d.", "Q913404"),
+    ("dr. habil.", "Q677678"),
+    ("b.a.", "Q1765120"),
+    ("m.a.", "Q2091008"),
+];
+
+/// Titles of nobility (P97), as GND gives them
+const NOBLE_TITLES: &[(&str, &str)] = &[
+    ("freiherr", "Q28989"),
+    ("freifrau", "Q28989"),
+    ("baron", "Q28989"),
+    ("graf", "Q3519259"),
+    ("gräfin", "Q3519259"),
+    ("herzog", "Q166382"),
+    ("herzogin", "Q166382"),
+];
+
+const GND_AFFILIATION: &str = "https://d-nb.info/standards/elementset/gnd#affiliation";
+
+/// Relations to corporate bodies. The generic `gnd#affiliation` also covers universities attended, and the like,
+/// so it is only used as affiliation (P1416), and only if the record has no more specific relation to that body.
+const AFFILIATIONS: &[(&str, usize)] = &[
+    (
+        "https://d-nb.info/standards/elementset/agrelon#hasEmployer",
+        108,
+    ),
+    (
+        "https://d-nb.info/standards/elementset/agrelon#isMemberOf",
+        463,
+    ),
+    (GND_AFFILIATION, 1416),
+];
+
+const GND_PUBLICATION: &str = "https://d-nb.info/standards/elementset/gnd#publication";
+const GND_PSEUDONYM: &str = "https://d-nb.info/standards/elementset/gnd#pseudonym";
+const GND_VARIANT_NAME_ENTITY: &str =
+    "https://d-nb.info/standards/elementset/gnd#variantNameEntityForThePerson";
+const GND_NS: &str = "https://d-nb.info/standards/elementset/gnd#";
+const RDF_TYPE: &str = "http://www.w3.org/1999/02/22-rdf-syntax-ns#type";
+
+/// GND record types that are corporate bodies, and their P31
+const CORPORATE_BODY_TYPES: &[(&str, &str)] = &[
+    (
+        "https://d-nb.info/standards/elementset/gnd#CorporateBody",
+        "Q43229",
+    ),
+    (
+        "https://d-nb.info/standards/elementset/gnd#Company",
+        "Q4830453",
+    ),
+    (
+        "https://d-nb.info/standards/elementset/gnd#MusicalCorporateBody",
+        "Q2088357",
+    ),
+    (
+        "https://d-nb.info/standards/elementset/gnd#OrganOfCorporateBody",
+        "Q43229",
+    ),
+    (
+        "https://d-nb.info/standards/elementset/gnd#ReligiousCorporateBody",
+        "Q1530022",
+    ),
+];
+
+/// GND record types that are places, and their P31.
+/// Territorial corporate bodies are corporate bodies as well, but are imported as places.
+const PLACE_TYPES: &[(&str, &str)] = &[
+    (
+        "https://d-nb.info/standards/elementset/gnd#TerritorialCorporateBodyOrAdministrativeUnit",
+        "Q56061",
+    ),
+    (
+        "https://d-nb.info/standards/elementset/gnd#AdministrativeUnit",
+        "Q56061",
+    ),
+    (
+        "https://d-nb.info/standards/elementset/gnd#Country",
+        "Q6256",
+    ),
+    (
+        "https://d-nb.info/standards/elementset/gnd#BuildingOrMemorial",
+        "Q41176",
+    ),
+    (
+        "https://d-nb.info/standards/elementset/gnd#PlaceOrGeographicName",
+        "Q2221906",
+    ),
+];
+
+/// What a GND record describes; the statements to import depend on it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum GndKind {
+    Person,
+    CorporateBody,
+    Place,
 }
 
 #[derive(Clone)]
 pub struct GND {
     id: String,
     graph: FastGraph,
+    kind: GndKind,
 }
 
-unsafe impl Send for GND {}
-unsafe impl Sync for GND {}
-
 #[async_trait]
 impl ExternalImporter for GND {
     fn my_property(&self) -> usize {
@@ -34,6 +141,14 @@ impl ExternalImporter for GND {
         self.id.to_owned()
     }
 
+    /// A redirected record carries the identifier of the record it was merged into
+    fn resolve_canonical_id(&self) -> Result<Option<String>> {
+        let ids = self.triples_property_literals(
+            "https://d-nb.info/standards/elementset/gnd#gndIdentifier",
+        )?;
+        Ok(canonical_id(ids, &self.id))
+    }
+
     fn my_stated_in(&self) -> &str {
         "Q36578"
     }
@@ -50,8 +165,20 @@ impl ExternalImporter for GND {
         format!("https://d-nb.info/gnd/{}", self.id)
     }
 
+    /// Variant names are often tagged with their language, like "Gete, Iogann Vol'fgang@ru"
+    fn label_language(&self, language_tag: Option<&str>) -> String {
+        match language_tag {
+            Some(tag) if !tag.is_empty() => tag.to_string(),
+            _ => self.primary_language(),
+        }
+    }
+
+    /// Only names of persons are "last name, first name"; those of corporate bodies often have a comma too
     fn transform_label(&self, s: &str) -> String {
-        self.transform_label_last_first_name(s)
+        match self.kind {
+            GndKind::Person => self.transform_label_last_first_name(s),
+            _ => s.to_string(),
+        }
     }
 
     fn add_own_id(&self, ret: &mut MetaItem) -> Result<()> {
@@ -67,49 +194,130 @@ impl ExternalImporter for GND {
         Ok(())
     }
 
+    async fn add_instance_of(&self, ret: &mut MetaItem) -> Result<()> {
+        for url in self.triples_iris(RDF_TYPE)? {
+            let p31 = PLACE_TYPES
+                .iter()
+                .chain(CORPORATE_BODY_TYPES)
+                .find(|(gnd_type, _)| *gnd_type == url)
+                .map(|(_, item)| *item);
+            let _ = match (url.as_str(), p31) {
+                ("https://d-nb.info/standards/elementset/gnd#DifferentiatedPerson", _) => {
+                    ret.add_claim(self.new_statement_item(31, "Q5"))
+                }
+                (_, Some(item)) => ret.add_claim(self.new_statement_item(31, item)),
+                (url, None) => ret.add_prop_text(ExternalId::new(31, url)),
+            };
+        }
+        Ok(())
+    }
+
+    async fn fetch(&mut self) -> Result<()> {
+        let rdf_url = format!("https://d-nb.info/gnd/{}/about/lds.rdf", self.id);
+        self.graph = Utility::get_rdf_graph(&rdf_url).await?;
+        if let Some(id) = self.resolve_canonical_id()? {
+            self.id = id;
+        }
+        self.kind = self.detect_kind()?;
+        Ok(())
+    }
+
+    fn parse(&mut self, text: &str) -> Result<()> {
+        self.graph = Utility::parse_rdf_xml(text)?;
+        if let Some(id) = self.resolve_canonical_id()? {
+            self.id = id;
+        }
+        self.kind = self.detect_kind()?;
+        Ok(())
+    }
+
     async fn run(&self) -> Result<MetaItem> {
         let mut ret = MetaItem::new();
         self.add_the_usual(&mut ret).await?;
+        match self.kind {
+            GndKind::Person => self.add_person(&mut ret).await?,
+            GndKind::CorporateBody => self.add_corporate_body(&mut ret).await?,
+            GndKind::Place => self.add_place(&mut ret).await?,
+        }
+        self.try_rescue_prop_text(&mut ret).await?;
+        ret.cleanup();
+        Ok(ret)
+    }
+}
 
-        // Nationality
-        for url in self.triples_subject_iris(
-            &self.get_id_url(),
-            "https://d-nb.info/standards/elementset/gnd#geographicAreaCode",
-        )? {
-            let country_code = RE_COUNTRY.replace(&url, "${1}");
-            if country_code != url {
-                let ext_id = ExternalId::new(297, &country_code);
-                let _ = match ext_id.get_item_for_external_id_value().await {
-                    Some(item) => ret.add_claim(self.new_statement_item(27, &item)),
-                    None => ret.add_prop_text(ext_id),
-                };
-            }
+impl GND {
+    pub fn unfetched(id: &str) -> Self {
+        Self {
+            id: id.to_string(),
+            graph: FastGraph::new(),
+            kind: GndKind::Person,
         }
+    }
+
+    pub async fn new(id: &str) -> Result<Self> {
+        let mut ret = Self::unfetched(id);
+        ret.fetch().await?;
+        Ok(ret)
+    }
+
+    fn detect_kind(&self) -> Result<GndKind> {
+        let types = self.triples_subject_iris(&self.get_id_url(), RDF_TYPE)?;
+        let has_type = |table: &[(&str, &str)]| {
+            types
+                .iter()
+                .any(|t| table.iter().any(|(gnd_type, _)| gnd_type == t))
+        };
+        Ok(if has_type(PLACE_TYPES) {
+            GndKind::Place
+        } else if has_type(CORPORATE_BODY_TYPES) {
+            GndKind::CorporateBody
+        } else {
+            GndKind::Person
+        })
+    }
+
+    async fn add_person(&self, ret: &mut MetaItem) -> Result<()> {
+        self.add_countries(ret, 27).await?;
 
         // Born/died
-        let birth_death = [
+        self.add_dates(
+            ret,
+            &[
+                (
+                    "https://d-nb.info/standards/elementset/gnd#dateOfBirth",
+                    569,
+                ),
+                (
+                    "https://d-nb.info/standards/elementset/gnd#dateOfDeath",
+                    570,
+                ),
+            ],
+        )?;
+
+        // Degrees and titles
+        let titles = [
             (
-                "https://d-nb.info/standards/elementset/gnd#dateOfBirth",
-                569,
+                "https://d-nb.info/standards/elementset/gnd#academicDegree",
+                512,
+                ACADEMIC_DEGREES,
             ),
             (
-                "https://d-nb.info/standards/elementset/gnd#dateOfDeath",
-                570,
+                "https://d-nb.info/standards/elementset/gnd#titleOfNobilityAsLiteral",
+                97,
+                NOBLE_TITLES,
             ),
         ];
-        for bd in birth_death {
-            for s in self.triples_subject_literals(&self.get_id_url(), bd.0)? {
-                let _ = match ret.parse_date(&s) {
-                    Some((time, precision)) => {
-                        ret.add_claim(self.new_statement_time(bd.1, &time, precision))
-                    }
-                    None => ret.add_prop_text(ExternalId::new(bd.1, &s)),
+        for (predicate, property, table) in titles {
+            for s in self.triples_subject_literals(&self.get_id_url(), predicate)? {
+                let _ = match Self::lookup_title(table, &s) {
+                    Some(item) => ret.add_claim(self.new_statement_item(property, item)),
+                    None => ret.add_prop_text(ExternalId::new(property, &s)),
                 };
             }
         }
 
-        // Places
-        let key_prop = [
+        // Places, relatives, and occupations
+        let mut related = self.related(&[
             (
                 "https://d-nb.info/standards/elementset/gnd#placeOfBirth",
                 19,
@@ -134,62 +342,314 @@ impl ExternalImporter for GND {
                 "https://d-nb.info/standards/elementset/gnd#placeOfActivity",
                 937,
             ),
-            // TODO parent
-        ];
-        for kp in key_prop {
-            for url in self.triples_subject_iris(&self.get_id_url(), kp.0)? {
-                if let Some(gnd_id) = url.split('/').last() {
-                    if let Some(item) = ExternalId::new(227, gnd_id)
-                        .get_item_for_external_id_value()
-                        .await
-                    {
-                        ret.add_claim(self.new_statement_item(kp.1, &item));
-                    } else {
-                        let _ = ret.add_prop_text(ExternalId::new(kp.1, &url));
+            // Notable works that have a GND record
+            (GND_PUBLICATION, 800),
+        ])?;
+        related.extend(self.affiliations()?);
+        related.extend(self.parents().await?);
+        self.add_related(ret, related).await;
+        self.add_publication_titles(ret)?;
+        self.add_pseudonyms(ret)?;
+        Ok(())
+    }
+
+    /// Pseudonyms (P742) are the variant name structures that are marked as such, and names given directly as
+    /// pseudonym. Other variant names are only spellings, and stay aliases.
+    fn add_pseudonyms(&self, ret: &mut MetaItem) -> Result<()> {
+        let mut names: Vec<String> = self
+            .variant_name_entities()?
+            .into_iter()
+            .filter(|(_, additions)| {
+                additions
+                    .iter()
+                    .any(|addition| addition.to_lowercase().starts_with("pseud"))
+            })
+            .map(|(name, _)| name)
+            .collect();
+        names.extend(self.triples_subject_literals(&self.get_id_url(), GND_PSEUDONYM)?);
+        names.sort();
+        names.dedup();
+        for name in names {
+            let name = self.limit_string_length(&self.transform_label(&name));
+            ret.add_claim(self.new_statement_string(742, &name));
+            let alias = LocaleString::new(&self.primary_language(), &name);
+            let is_label = ret.item.label_in_locale(alias.language()) == Some(name.as_str());
+            if !is_label && !ret.item.aliases().contains(&alias) {
+                ret.item.aliases_mut().push(alias);
+            }
+        }
+        Ok(())
+    }
+
+    /// The `variantNameEntityForThePerson` structures of the record, as ("surname, forename prefix", name additions);
+    /// a structure with a `personalName` uses that instead
+    fn variant_name_entities(&self) -> Result<Vec<(String, Vec<String>)>> {
+        let iri_id = Iri::new(self.get_id_url())?;
+        let iri_p = Iri::new(GND_VARIANT_NAME_ENTITY)?;
+        let mut nodes = vec![];
+        self.graph
+            .triples_matching([&iri_id], [&iri_p], Any)
+            .for_each_triple(|t| nodes.extend(Self::node_key(t.o())))?;
+        let mut parts: HashMap<String, HashMap<String, Vec<String>>> = HashMap::new();
+        self.graph.triples().for_each_triple(|t| {
+            let key = match Self::node_key(t.s()) {
+                Some(key) if nodes.contains(&key) => key,
+                _ => return,
+            };
+            let part = t
+                .p()
+                .iri()
+                .and_then(|p| p.as_str().strip_prefix(GND_NS).map(|part| part.to_string()));
+            if let (Some(part), Some(literal)) = (part, t.o().lexical_form()) {
+                parts
+                    .entry(key)
+                    .or_default()
+                    .entry(part)
+                    .or_default()
+                    .push(literal.to_string());
+            }
+        })?;
+        let mut ret = vec![];
+        for parts in parts.values() {
+            let get = |part: &str| parts.get(part).and_then(|values| values.first());
+            let name = match (get("personalName"), get("surname"), get("forename")) {
+                (Some(name), _, _) => name.to_owned(),
+                (None, Some(surname), forename) => {
+                    let given: Vec<&str> = [forename, get("prefix")]
+                        .into_iter()
+                        .flatten()
+                        .map(|s| s.as_str())
+                        .collect();
+                    match given.is_empty() {
+                        true => surname.to_owned(),
+                        false => format!("{surname}, {}", given.join(" ")),
                     }
-                } else {
-                    let _ = ret.add_prop_text(ExternalId::new(kp.1, &url));
                 }
-            }
+                (None, None, Some(forename)) => forename.to_owned(),
+                (None, None, None) => continue,
+            };
+            let additions = parts.get("nameAddition").cloned().unwrap_or_default();
+            ret.push((name, additions));
         }
+        ret.sort();
+        Ok(ret)
+    }
 
-        self.try_rescue_prop_text(&mut ret).await?;
-        ret.cleanup();
+    fn node_key<T: Term>(term: T) -> Option<String> {
+        match (term.bnode_id(), term.iri()) {
+            (Some(bnode), _) => Some(format!("_:{}", bnode.as_str())),
+            (None, Some(iri)) => Some(iri.as_str().to_string()),
+            _ => None,
+        }
+    }
+
+    /// Parents, as father (P22) or mother (P25) by the gender on their own record, or as parent (P8810)
+    async fn parents(&self) -> Result<Vec<(usize, String, Option<ExternalId>)>> {
+        let mut ret = vec![];
+        for url in self.triples_subject_iris(
+            &self.get_id_url(),
+            "https://d-nb.info/standards/elementset/agrelon#hasParent",
+        )? {
+            let gnd_id = url.split('/').last().map(|id| ExternalId::new(227, id));
+            let parent = match &gnd_id {
+                Some(gnd_id) => match GND::new(gnd_id.id()).await {
+                    Ok(parent) => Some(parent),
+                    Err(e) if Utility::is_overloaded(&e) => return Err(e),
+                    Err(_) => None,
+                },
+                None => None,
+            };
+            let property = match parent {
+                Some(parent) => parent.parent_property()?,
+                None => 8810,
+            };
+            ret.push((property, url, gnd_id));
+        }
         Ok(ret)
     }
-}
 
-impl GND {
-    /// Changes internal ID in case of redirect
-    fn fix_own_id(&mut self) -> Result<()> {
-        let ids = self.triples_property_literals(
-            "https://d-nb.info/standards/elementset/gnd#gndIdentifier",
+    /// The property for this person as a parent
+    fn parent_property(&self) -> Result<usize> {
+        let genders = self.triples_subject_iris(
+            &self.get_id_url(),
+            "https://d-nb.info/standards/elementset/gnd#gender",
         )?;
-        if ids.len() == 1 {
-            self.id = ids[0].to_owned();
+        Ok(match genders.as_slice() {
+            [gender] if gender == "https://d-nb.info/standards/vocab/gnd/gender#male" => 22,
+            [gender] if gender == "https://d-nb.info/standards/vocab/gnd/gender#female" => 25,
+            _ => 8810,
+        })
+    }
+
+    /// Most publications are only given by title; those are suggestions for notable works (P800)
+    fn add_publication_titles(&self, ret: &mut MetaItem) -> Result<()> {
+        for title in self.triples_subject_literals(&self.get_id_url(), GND_PUBLICATION)? {
+            let title = self.limit_string_length(&title);
+            if !title.is_empty() {
+                let _ = ret.add_prop_text(ExternalId::new(800, &title));
+            }
         }
         Ok(())
     }
 
-    pub async fn new(id: &str) -> Result<Self> {
-        let rdf_url = format!("https://d-nb.info/gnd/{}/about/lds.rdf", id);
-        let resp = reqwest::get(&rdf_url).await?.text().await?;
-        let mut graph: FastGraph = FastGraph::new();
-        let _ = xml::parser::parse_str(&resp).add_to_graph(&mut graph)?;
-        let mut ret = Self {
-            id: id.to_string(),
-            graph,
-        };
-        ret.fix_own_id()?;
+    async fn add_corporate_body(&self, ret: &mut MetaItem) -> Result<()> {
+        self.add_countries(ret, 17).await?;
+        self.add_dates(
+            ret,
+            &[
+                (
+                    "https://d-nb.info/standards/elementset/gnd#dateOfEstablishment",
+                    571,
+                ),
+                (
+                    "https://d-nb.info/standards/elementset/gnd#dateOfTermination",
+                    576,
+                ),
+            ],
+        )?;
+        let related = self.related(&[
+            (
+                "https://d-nb.info/standards/elementset/gnd#placeOfBusiness",
+                159,
+            ),
+            (
+                "https://d-nb.info/standards/elementset/gnd#precedingCorporateBody",
+                1365,
+            ),
+            (
+                "https://d-nb.info/standards/elementset/gnd#succeedingCorporateBody",
+                1366,
+            ),
+        ])?;
+        self.add_related(ret, related).await;
+        Ok(())
+    }
+
+    async fn add_place(&self, ret: &mut MetaItem) -> Result<()> {
+        self.add_countries(ret, 17).await?;
+        for wkt in self.triples_literals("http://www.opengis.net/ont/geosparql#asWKT")? {
+            if let Some((latitude, longitude, precision)) = Self::parse_wkt_point(&wkt) {
+                ret.add_claim(self.new_statement_coordinate(625, latitude, longitude, precision));
+            }
+        }
+        // The places this one is part of; only candidates, as GND does not say if they are administrative
+        let related = self.related(&[
+            (
+                "https://d-nb.info/standards/elementset/gnd#hierarchicalSuperiorOfPlaceOrGeographicName",
+                131,
+            ),
+            (
+                "https://d-nb.info/standards/elementset/gnd#hierarchicalSuperior",
+                131,
+            ),
+        ])?;
+        self.add_related(ret, related).await;
+        Ok(())
+    }
+
+    /// Latitude, longitude, and precision of a WKT point like "Point ( +012.373333 +051.340000 )".
+    /// WKT has the longitude first.
+    fn parse_wkt_point(wkt: &str) -> Option<(f64, f64, f64)> {
+        let captures = RE_WKT_POINT.captures(wkt)?;
+        let longitude: f64 = captures.get(1)?.as_str().parse().ok()?;
+        let latitude: f64 = captures.get(3)?.as_str().parse().ok()?;
+        let decimals = captures.get(2).map_or(0, |d| d.as_str().len()).min(6);
+        Some((latitude, longitude, 1.0 / 10f64.powi(decimals as i32)))
+    }
+
+    /// Countries from the geographic area codes, as `property`
+    async fn add_countries(&self, ret: &mut MetaItem, property: usize) -> Result<()> {
+        let mut countries = vec![];
+        for url in self.triples_subject_iris(
+            &self.get_id_url(),
+            "https://d-nb.info/standards/elementset/gnd#geographicAreaCode",
+        )? {
+            let country_code = RE_COUNTRY.replace(&url, "${1}");
+            if country_code != url {
+                countries.push(ExternalId::new(297, &country_code));
+            }
+        }
+        let items = ExternalId::get_item_for_external_id_values(&countries).await;
+        for ext_id in countries {
+            let _ = match items.get(&ext_id) {
+                Some(item) => ret.add_claim(self.new_statement_item(property, item)),
+                None => ret.add_prop_text(ext_id),
+            };
+        }
+        Ok(())
+    }
+
+    /// Only the most precise dates are used, as GND often gives the year as well as the full date
+    fn add_dates(&self, ret: &mut MetaItem, dates: &[(&str, usize)]) -> Result<()> {
+        for (predicate, property) in dates {
+            let literals = self.triples_subject_literals(&self.get_id_url(), predicate)?;
+            let parsed: Vec<(String, Option<FuzzyDate>)> = literals
+                .into_iter()
+                .map(|s| {
+                    let date = FuzzyDate::parse(&s);
+                    (s, date)
+                })
+                .collect();
+            let best_precision = parsed
+                .iter()
+                .filter_map(|(_, date)| date.as_ref().map(|d| d.precision()))
+                .max();
+            for (s, date) in parsed {
+                let _ = match date {
+                    Some(date) if Some(date.precision()) == best_precision => {
+                        ret.add_claim(self.new_statement_fuzzy_date(*property, &date))
+                    }
+                    Some(_) => None,
+                    None => ret.add_prop_text(ExternalId::new(*property, &s)),
+                };
+            }
+        }
+        Ok(())
+    }
+
+    /// Links to other GND records, as (property, URL, GND ID)
+    fn related(
+        &self,
+        key_prop: &[(&str, usize)],
+    ) -> Result<Vec<(usize, String, Option<ExternalId>)>> {
+        let mut ret = vec![];
+        for (predicate, property) in key_prop {
+            for url in self.triples_subject_iris(&self.get_id_url(), predicate)? {
+                let gnd_id = url.split('/').last().map(|id| ExternalId::new(227, id));
+                ret.push((*property, url, gnd_id));
+            }
+        }
+        Ok(ret)
+    }
+
+    fn lookup_title(table: &[(&str, &'static str)], s: &str) -> Option<&'static str> {
+        let s = s.trim().to_lowercase();
+        table
+            .iter()
+            .find(|(title, _)| *title == s)
+            .map(|(_, item)| *item)
+    }
+
+    /// Employers, memberships, and other affiliations, as (property, URL, GND ID)
+    fn affiliations(&self) -> Result<Vec<(usize, String, Option<ExternalId>)>> {
+        let mut ret = vec![];
+        let mut seen = vec![];
+        for (predicate, property) in AFFILIATIONS {
+            for url in self.triples_subject_iris(&self.get_id_url(), predicate)? {
+                if *predicate == GND_AFFILIATION && seen.contains(&url) {
+                    continue;
+                }
+                seen.push(url.to_owned());
+                let gnd_id = url.split('/').last().map(|id| ExternalId::new(227, id));
+                ret.push((*property, url, gnd_id));
+            }
+        }
         Ok(ret)
     }
 
     fn is_undifferentiated_person(&self) -> Result<bool> {
         Ok(self
-            .triples_subject_iris(
-                &self.get_id_url(),
-                "http://www.w3.org/1999/02/22-rdf-syntax-ns#type",
-            )?
+            .triples_subject_iris(&self.get_id_url(), RDF_TYPE)?
             .iter()
             .any(|x| x == "https://d-nb.info/standards/elementset/gnd#UndifferentiatedPerson"))
     }
@@ -197,8 +657,6 @@ impl GND {
 
 #[cfg(test)]
 mod tests {
-    use wikimisc::wikibase::{EntityTrait, LocaleString};
-
     use super::*;
 
     const TEST_ID: &str = "132539691";
@@ -208,6 +666,209 @@ mod tests {
         assert!(GND::new(TEST_ID).await.is_ok());
     }
 
+    #[test]
+    fn test_parse() {
+        // A record merged into another one, as found in a dump
+        let rdf = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#" xmlns:gndo="https://d-nb.info/standards/elementset/gnd#">
+  <rdf:Description rdf:about="https://d-nb.info/gnd/132539691">
+    <gndo:gndIdentifier>118523813</gndo:gndIdentifier>
+  </rdf:Description>
+</rdf:RDF>"#;
+        let mut gnd = GND::unfetched(TEST_ID);
+        gnd.parse(rdf).unwrap();
+        assert_eq!(gnd.my_id(), "118523813");
+        assert!(GND::unfetched(TEST_ID).parse("not RDF").is_err());
+    }
+
+    #[test]
+    fn test_affiliations() {
+        let rdf = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#" xmlns:gndo="https://d-nb.info/standards/elementset/gnd#" xmlns:agrelon="https://d-nb.info/standards/elementset/agrelon#">
+  <rdf:Description rdf:about="https://d-nb.info/gnd/132539691">
+    <gndo:affiliation rdf:resource="https://d-nb.info/gnd/2024631-7"/>
+    <gndo:affiliation rdf:resource="https://d-nb.info/gnd/1030553-6"/>
+    <agrelon:isMemberOf rdf:resource="https://d-nb.info/gnd/1030553-6"/>
+  </rdf:Description>
+</rdf:RDF>"#;
+        let mut gnd = GND::unfetched(TEST_ID);
+        gnd.parse(rdf).unwrap();
+        let affiliations: Vec<(usize, String)> = gnd
+            .affiliations()
+            .unwrap()
+            .into_iter()
+            .map(|(property, _, gnd_id)| (property, gnd_id.unwrap().id().to_string()))
+            .collect();
+        assert_eq!(
+            affiliations,
+            vec![
+                (463, "1030553-6".to_string()),
+                (1416, "2024631-7".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_corporate_body() {
+        let rdf = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#" xmlns:gndo="https://d-nb.info/standards/elementset/gnd#">
+  <rdf:Description rdf:about="https://d-nb.info/gnd/2024631-7">
+    <rdf:type rdf:resource="https://d-nb.info/standards/elementset/gnd#CorporateBody"/>
+    <gndo:preferredNameForTheCorporateBody>Universität Leipzig, Institut für Informatik</gndo:preferredNameForTheCorporateBody>
+  </rdf:Description>
+</rdf:RDF>"#;
+        let mut gnd = GND::unfetched("2024631-7");
+        gnd.parse(rdf).unwrap();
+        assert_eq!(gnd.kind, GndKind::CorporateBody);
+        assert_eq!(
+            gnd.transform_label("Universität Leipzig, Institut für Informatik"),
+            "Universität Leipzig, Institut für Informatik"
+        );
+    }
+
+    #[test]
+    fn test_place() {
+        let rdf = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#" xmlns:gndo="https://d-nb.info/standards/elementset/gnd#">
+  <rdf:Description rdf:about="https://d-nb.info/gnd/4035206-7">
+    <rdf:type rdf:resource="https://d-nb.info/standards/elementset/gnd#TerritorialCorporateBodyOrAdministrativeUnit"/>
+  </rdf:Description>
+</rdf:RDF>"#;
+        let mut gnd = GND::unfetched("4035206-7");
+        gnd.parse(rdf).unwrap();
+        assert_eq!(gnd.kind, GndKind::Place);
+    }
+
+    #[test]
+    fn test_parse_wkt_point() {
+        assert_eq!(
+            GND::parse_wkt_point("Point ( +012.373333 +051.340000 )"),
+            Some((51.34, 12.373333, 0.000001))
+        );
+        assert_eq!(
+            GND::parse_wkt_point("POINT(13 52)"),
+            Some((52.0, 13.0, 1.0))
+        );
+        assert_eq!(GND::parse_wkt_point("Polygon ((1 2, 3 4))"), None);
+    }
+
+    #[test]
+    fn test_publication_titles() {
+        let rdf = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#" xmlns:gndo="https://d-nb.info/standards/elementset/gnd#">
+  <rdf:Description rdf:about="https://d-nb.info/gnd/132539691">
+    <gndo:publication>Die Leiden des jungen Werthers</gndo:publication>
+  </rdf:Description>
+</rdf:RDF>"#;
+        let mut gnd = GND::unfetched(TEST_ID);
+        gnd.parse(rdf).unwrap();
+        let mut mi = MetaItem::new();
+        gnd.add_publication_titles(&mut mi).unwrap();
+        assert_eq!(
+            mi.prop_text,
+            vec![ExternalId::new(800, "Die Leiden des jungen Werthers")]
+        );
+    }
+
+    #[test]
+    fn test_parent_property() {
+        let rdf = |gender: &str| {
+            format!(
+                r#"<?xml version="1.0" encoding="UTF-8"?>
+<rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#" xmlns:gndo="https://d-nb.info/standards/elementset/gnd#">
+  <rdf:Description rdf:about="https://d-nb.info/gnd/132539691">{gender}</rdf:Description>
+</rdf:RDF>"#
+            )
+        };
+        let parent_property = |gender: &str| {
+            let mut gnd = GND::unfetched(TEST_ID);
+            gnd.parse(&rdf(gender)).unwrap();
+            gnd.parent_property().unwrap()
+        };
+        assert_eq!(
+            parent_property(
+                r#"<gndo:gender rdf:resource="https://d-nb.info/standards/vocab/gnd/gender#female"/>"#
+            ),
+            25
+        );
+        assert_eq!(
+            parent_property(
+                r#"<gndo:gender rdf:resource="https://d-nb.info/standards/vocab/gnd/gender#male"/>"#
+            ),
+            22
+        );
+        assert_eq!(parent_property(""), 8810);
+    }
+
+    #[test]
+    fn test_variant_name_languages() {
+        let rdf = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#" xmlns:gndo="https://d-nb.info/standards/elementset/gnd#">
+  <rdf:Description rdf:about="https://d-nb.info/gnd/132539691">
+    <gndo:preferredNameForThePerson>Manske, Magnus</gndo:preferredNameForThePerson>
+    <gndo:variantNameForThePerson xml:lang="ru">Манске, Магнус</gndo:variantNameForThePerson>
+  </rdf:Description>
+</rdf:RDF>"#;
+        let mut gnd = GND::unfetched(TEST_ID);
+        gnd.parse(rdf).unwrap();
+        let mut mi = MetaItem::new();
+        gnd.add_label_aliases(&mut mi).unwrap();
+        assert_eq!(
+            *mi.item.labels(),
+            vec![
+                LocaleString::new("de", "Magnus Manske"),
+                LocaleString::new("ru", "Магнус Манске")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_pseudonyms() {
+        let rdf = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#" xmlns:gndo="https://d-nb.info/standards/elementset/gnd#">
+  <rdf:Description rdf:about="https://d-nb.info/gnd/132539691">
+    <gndo:preferredNameForThePerson>Manske, Magnus</gndo:preferredNameForThePerson>
+    <gndo:variantNameEntityForThePerson rdf:parseType="Resource">
+      <gndo:forename>M.</gndo:forename>
+      <gndo:surname>Manske</gndo:surname>
+    </gndo:variantNameEntityForThePerson>
+    <gndo:variantNameEntityForThePerson rdf:parseType="Resource">
+      <gndo:personalName>Magnus</gndo:personalName>
+      <gndo:nameAddition>Pseudonym</gndo:nameAddition>
+    </gndo:variantNameEntityForThePerson>
+  </rdf:Description>
+</rdf:RDF>"#;
+        let mut gnd = GND::unfetched(TEST_ID);
+        gnd.parse(rdf).unwrap();
+        assert_eq!(
+            gnd.variant_name_entities().unwrap(),
+            vec![
+                ("Magnus".to_string(), vec!["Pseudonym".to_string()]),
+                ("Manske, M.".to_string(), vec![]),
+            ]
+        );
+        let mut mi = MetaItem::new();
+        gnd.add_pseudonyms(&mut mi).unwrap();
+        assert_eq!(mi.item.claims().len(), 1);
+        assert_eq!(*mi.item.aliases(), vec![LocaleString::new("de", "Magnus")]);
+    }
+
+    #[test]
+    fn test_lookup_title() {
+        assert_eq!(
+            GND::lookup_title(ACADEMIC_DEGREES, "Dr. phil."),
+            Some("Q752297")
+        );
+        assert_eq!(
+            GND::lookup_title(NOBLE_TITLES, " Freiherr "),
+            Some("Q28989")
+        );
+        assert_eq!(
+            GND::lookup_title(ACADEMIC_DEGREES, "Dr. rer. nat. Dr. med."),
+            None
+        );
+    }
+
     #[tokio::test]
     async fn test_my_property() {
         let gnd = GND::new(TEST_ID).await.unwrap();
@@ -0,0 +1,308 @@
+use crate::config::Config;
+use anyhow::Result;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+lazy_static! {
+    static ref HTTP_CACHE: Option<HttpCache> = {
+        let config = &Config::get().http_cache;
+        let dir = config.dir.as_ref()?;
+        let host_ttl = config
+            .host_ttl_secs
+            .iter()
+            .map(|(host, secs)| (host.to_owned(), Duration::from_secs(*secs)))
+            .collect();
+        match HttpCache::open(
+            Path::new(dir),
+            Duration::from_secs(config.ttl_secs),
+            host_ttl,
+        ) {
+            Ok(cache) => Some(cache),
+            Err(e) => {
+                tracing::warn!("Could not open HTTP cache {dir}: {e}");
+                None
+            }
+        }
+    };
+}
+
+/// Where a cached response body is stored, and when it was fetched
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct CacheEntry {
+    url: String,
+    object: String,
+    fetched: i64,
+    #[serde(default)]
+    validators: Validators,
+}
+
+/// `ETag` and `Last-Modified` of a response, to ask the server whether a cached copy is still current
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Validators {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+impl Validators {
+    pub fn from_headers(headers: &reqwest::header::HeaderMap) -> Self {
+        let get = |name| {
+            headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(|value| value.to_string())
+        };
+        Self {
+            etag: get(reqwest::header::ETAG),
+            last_modified: get(reqwest::header::LAST_MODIFIED),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.etag.is_none() && self.last_modified.is_none()
+    }
+
+    /// Adds `If-None-Match` and `If-Modified-Since`, so the server can answer 304 Not Modified
+    pub fn apply(&self, mut request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        if let Some(etag) = &self.etag {
+            request = request.header(reqwest::header::IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = &self.last_modified {
+            request = request.header(reqwest::header::IF_MODIFIED_SINCE, last_modified);
+        }
+        request
+    }
+}
+
+/// An expired cache entry that can be revalidated with the server
+#[derive(Debug, Clone, PartialEq)]
+pub struct StaleResponse {
+    pub body: String,
+    pub validators: Validators,
+}
+
+/// Optional on-disk cache for `Utility::get_url` responses, so repeated parses of the same record
+/// do not download it again. Bodies are stored by the hash of their content (in `objects/`),
+/// and each URL points to one of them (in `urls/`).
+pub struct HttpCache {
+    dir: PathBuf,
+    ttl: Duration,
+    host_ttl: HashMap<String, Duration>,
+}
+
+impl HttpCache {
+    pub fn open(dir: &Path, ttl: Duration, host_ttl: HashMap<String, Duration>) -> Result<Self> {
+        fs::create_dir_all(dir.join("objects"))?;
+        fs::create_dir_all(dir.join("urls"))?;
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            ttl,
+            host_ttl,
+        })
+    }
+
+    /// The cache configured in `http_cache.dir`, if any
+    pub fn global() -> Option<&'static HttpCache> {
+        HTTP_CACHE.as_ref()
+    }
+
+    /// How long responses from the host of `url` are kept; 0 means they are not cached
+    pub fn ttl_for(&self, url: &str) -> Duration {
+        reqwest::Url::parse(url)
+            .ok()
+            .and_then(|url| {
+                url.host_str()
+                    .and_then(|host| self.host_ttl.get(host).cloned())
+            })
+            .unwrap_or(self.ttl)
+    }
+
+    /// The cached body, unless it is missing or expired
+    pub fn get(&self, url: &str) -> Option<String> {
+        let ttl = self.ttl_for(url);
+        if ttl.is_zero() {
+            return None;
+        }
+        let entry = self.read_entry(url)?;
+        if entry.fetched < Utc::now().timestamp() - ttl.as_secs() as i64 {
+            return None;
+        }
+        fs::read_to_string(self.object_path(&entry.object)).ok()
+    }
+
+    /// An expired entry, if the server sent validators for it
+    pub fn get_stale(&self, url: &str) -> Option<StaleResponse> {
+        if self.ttl_for(url).is_zero() {
+            return None;
+        }
+        let entry = self.read_entry(url)?;
+        if entry.validators.is_empty() {
+            return None;
+        }
+        Some(StaleResponse {
+            body: fs::read_to_string(self.object_path(&entry.object)).ok()?,
+            validators: entry.validators,
+        })
+    }
+
+    pub fn set(&self, url: &str, body: &str, validators: Validators) -> Result<()> {
+        if self.ttl_for(url).is_zero() {
+            return Ok(());
+        }
+        let object = Self::hash(body);
+        let object_path = self.object_path(&object);
+        if !object_path.exists() {
+            Self::write_atomic(&object_path, body)?;
+        }
+        self.write_entry(&CacheEntry {
+            url: url.to_string(),
+            object,
+            fetched: Utc::now().timestamp(),
+            validators,
+        })
+    }
+
+    /// Marks an entry as current again, after the server answered 304 Not Modified
+    pub fn refresh(&self, url: &str) -> Result<()> {
+        if let Some(mut entry) = self.read_entry(url) {
+            entry.fetched = Utc::now().timestamp();
+            self.write_entry(&entry)?;
+        }
+        Ok(())
+    }
+
+    fn write_entry(&self, entry: &CacheEntry) -> Result<()> {
+        Self::write_atomic(&self.entry_path(&entry.url), &serde_json::to_string(entry)?)
+    }
+
+    fn read_entry(&self, url: &str) -> Option<CacheEntry> {
+        let text = fs::read_to_string(self.entry_path(url)).ok()?;
+        let entry: CacheEntry = serde_json::from_str(&text).ok()?;
+        // Guards against hash collisions
+        (entry.url == url).then_some(entry)
+    }
+
+    fn entry_path(&self, url: &str) -> PathBuf {
+        self.dir
+            .join("urls")
+            .join(format!("{}.json", Self::hash(url)))
+    }
+
+    fn object_path(&self, object: &str) -> PathBuf {
+        self.dir.join("objects").join(object)
+    }
+
+    fn hash(data: &str) -> String {
+        Sha256::digest(data.as_bytes())
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect()
+    }
+
+    /// Writes to a temporary file first, so concurrent readers never see partial content
+    fn write_atomic(path: &Path, text: &str) -> Result<()> {
+        let tmp = path.with_extension(format!("tmp{}", std::process::id()));
+        fs::write(&tmp, text)?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_cache(name: &str, host_ttl: &[(&str, u64)]) -> HttpCache {
+        let dir = std::env::temp_dir().join(format!("auth2wd_test_http_cache_{name}"));
+        let _ = fs::remove_dir_all(&dir);
+        let host_ttl = host_ttl
+            .iter()
+            .map(|(host, secs)| (host.to_string(), Duration::from_secs(*secs)))
+            .collect();
+        HttpCache::open(&dir, Duration::from_secs(3600), host_ttl).unwrap()
+    }
+
+    #[test]
+    fn test_set_get() {
+        let cache = test_cache("set_get", &[]);
+        let url = "https://d-nb.info/gnd/118523813/about/lds.rdf";
+        assert_eq!(cache.get(url), None);
+        cache.set(url, "<rdf/>", Validators::default()).unwrap();
+        assert_eq!(cache.get(url), Some("<rdf/>".to_string()));
+        assert_eq!(cache.get("https://d-nb.info/gnd/1/about/lds.rdf"), None);
+    }
+
+    #[test]
+    fn test_content_addressed() {
+        let cache = test_cache("content_addressed", &[]);
+        cache
+            .set("https://example.org/a", "same", Validators::default())
+            .unwrap();
+        cache
+            .set("https://example.org/b", "same", Validators::default())
+            .unwrap();
+        let objects = fs::read_dir(cache.dir.join("objects")).unwrap().count();
+        assert_eq!(objects, 1);
+        assert_eq!(cache.get("https://example.org/b"), Some("same".to_string()));
+    }
+
+    #[test]
+    fn test_stale() {
+        let cache = test_cache("stale", &[]);
+        let validators = Validators {
+            etag: Some("\"abc\"".to_string()),
+            last_modified: None,
+        };
+        cache
+            .set("https://example.org/plain", "plain", Validators::default())
+            .unwrap();
+        cache
+            .set("https://example.org/etag", "etag", validators.to_owned())
+            .unwrap();
+        assert_eq!(cache.get_stale("https://example.org/plain"), None);
+        let stale = cache.get_stale("https://example.org/etag").unwrap();
+        assert_eq!(stale.body, "etag");
+        assert_eq!(stale.validators, validators);
+
+        let mut entry = cache.read_entry("https://example.org/etag").unwrap();
+        entry.fetched = 0;
+        cache.write_entry(&entry).unwrap();
+        assert_eq!(cache.get("https://example.org/etag"), None);
+        cache.refresh("https://example.org/etag").unwrap();
+        assert_eq!(
+            cache.get("https://example.org/etag"),
+            Some("etag".to_string())
+        );
+    }
+
+    #[test]
+    fn test_validators() {
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert(reqwest::header::ETAG, "W/\"1\"".parse().unwrap());
+        let validators = Validators::from_headers(&headers);
+        assert_eq!(validators.etag.as_deref(), Some("W/\"1\""));
+        assert_eq!(validators.last_modified, None);
+        let request = validators
+            .apply(reqwest::Client::new().get("https://example.org"))
+            .build()
+            .unwrap();
+        assert_eq!(request.headers()[reqwest::header::IF_NONE_MATCH], "W/\"1\"");
+    }
+
+    #[test]
+    fn test_host_ttl() {
+        let cache = test_cache("host_ttl", &[("www.wikidata.org", 0)]);
+        let url = "https://www.wikidata.org/w/api.php?action=query";
+        assert_eq!(cache.ttl_for(url), Duration::ZERO);
+        assert_eq!(
+            cache.ttl_for("https://viaf.org/viaf/1"),
+            Duration::from_secs(3600)
+        );
+        cache.set(url, "{}", Validators::default()).unwrap();
+        assert_eq!(cache.get(url), None);
+    }
+}
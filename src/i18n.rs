@@ -0,0 +1,170 @@
+use std::collections::HashMap;
+
+pub const DEFAULT_LANGUAGE: &str = "en";
+
+lazy_static! {
+    /// Message catalogs, one per interface language, embedded at compile time
+    static ref CATALOGS: HashMap<&'static str, HashMap<String, String>> = {
+        vec![
+            ("en", include_str!("../i18n/en.json")),
+            ("de", include_str!("../i18n/de.json")),
+            ("fr", include_str!("../i18n/fr.json")),
+            ("es", include_str!("../i18n/es.json")),
+            ("nl", include_str!("../i18n/nl.json")),
+            ("sv", include_str!("../i18n/sv.json")),
+            ("no", include_str!("../i18n/no.json")),
+            ("ja", include_str!("../i18n/ja.json")),
+            ("pl", include_str!("../i18n/pl.json")),
+        ]
+        .into_iter()
+        .map(|(language, json)| {
+            let messages: HashMap<String, String> =
+                serde_json::from_str(json).expect("Malformed i18n catalog");
+            (language, messages)
+        })
+        .collect()
+    };
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct I18n {
+    language: String,
+}
+
+impl Default for I18n {
+    fn default() -> Self {
+        Self::new(DEFAULT_LANGUAGE)
+    }
+}
+
+impl I18n {
+    /// Creates a new instance for the given language, falling back to the default language if it is not supported.
+    pub fn new(language: &str) -> Self {
+        let language = Self::normalize_language(language).unwrap_or(DEFAULT_LANGUAGE.to_string());
+        Self { language }
+    }
+
+    /// Picks the interface language from an explicit `uselang` parameter, or from the `Accept-Language` header.
+    pub fn from_request(uselang: Option<&str>, accept_language: Option<&str>) -> Self {
+        if let Some(language) = uselang.and_then(Self::normalize_language) {
+            return Self { language };
+        }
+        let language = accept_language
+            .and_then(Self::parse_accept_language)
+            .unwrap_or(DEFAULT_LANGUAGE.to_string());
+        Self { language }
+    }
+
+    pub fn language(&self) -> &str {
+        &self.language
+    }
+
+    pub fn supported_languages() -> Vec<&'static str> {
+        let mut ret: Vec<&'static str> = CATALOGS.keys().cloned().collect();
+        ret.sort();
+        ret
+    }
+
+    /// Returns the message for `key`, falling back to English, then to the key itself.
+    pub fn message(&self, key: &str) -> String {
+        CATALOGS
+            .get(self.language.as_str())
+            .and_then(|messages| messages.get(key))
+            .or_else(|| CATALOGS.get(DEFAULT_LANGUAGE)?.get(key))
+            .cloned()
+            .unwrap_or(key.to_string())
+    }
+
+    /// Returns the message for `key`, with `$1`, `$2` etc. replaced by `params`.
+    pub fn message_with_params(&self, key: &str, params: &[&str]) -> String {
+        params
+            .iter()
+            .enumerate()
+            .rev() // Replace $10 before $1
+            .fold(self.message(key), |msg, (num, param)| {
+                msg.replace(&format!("${}", num + 1), param)
+            })
+    }
+
+    /// Maps a language code (eg "de-AT", "nb") to a supported catalog language.
+    fn normalize_language(language: &str) -> Option<String> {
+        let language = language.trim().to_lowercase();
+        let language = language.split(['-', '_']).next()?;
+        let language = match language {
+            "nb" | "nn" => "no",
+            other => other,
+        };
+        if CATALOGS.contains_key(language) {
+            Some(language.to_string())
+        } else {
+            None
+        }
+    }
+
+    /// Returns the supported language with the highest quality value from an `Accept-Language` header.
+    fn parse_accept_language(header: &str) -> Option<String> {
+        let mut candidates: Vec<(f32, String)> = header
+            .split(',')
+            .filter_map(|part| {
+                let mut parts = part.split(';');
+                let language = Self::normalize_language(parts.next()?)?;
+                let quality = parts
+                    .filter_map(|p| p.trim().strip_prefix("q="))
+                    .filter_map(|q| q.parse::<f32>().ok())
+                    .next()
+                    .unwrap_or(1.0);
+                Some((quality, language))
+            })
+            .filter(|(quality, _)| *quality > 0.0)
+            .collect();
+        // Stable sort, so equal quality values keep header order
+        candidates.sort_by(|a, b| b.0.total_cmp(&a.0));
+        candidates.into_iter().map(|(_, language)| language).next()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_catalogs_complete() {
+        let en = CATALOGS.get(DEFAULT_LANGUAGE).unwrap();
+        for (language, messages) in CATALOGS.iter() {
+            for key in en.keys() {
+                assert!(messages.contains_key(key), "{language} is missing {key}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_new() {
+        assert_eq!(I18n::new("de").language(), "de");
+        assert_eq!(I18n::new("de-AT").language(), "de");
+        assert_eq!(I18n::new("nb").language(), "no");
+        assert_eq!(I18n::new("xx").language(), "en");
+    }
+
+    #[test]
+    fn test_from_request() {
+        assert_eq!(I18n::from_request(Some("fr"), Some("de")).language(), "fr");
+        assert_eq!(I18n::from_request(Some("xx"), Some("de")).language(), "de");
+        assert_eq!(
+            I18n::from_request(None, Some("xx-YY,pl;q=0.5,sv;q=0.8")).language(),
+            "sv"
+        );
+        assert_eq!(I18n::from_request(None, Some("ja;q=0")).language(), "en");
+        assert_eq!(I18n::from_request(None, None).language(), "en");
+    }
+
+    #[test]
+    fn test_message() {
+        let i18n = I18n::new("de");
+        assert_eq!(i18n.message("nav-help"), "Hilfe");
+        assert_eq!(i18n.message("no-such-key"), "no-such-key");
+        assert_eq!(
+            i18n.message_with_params("source-example", &["A", "B"]),
+            "„A“ aus B"
+        );
+    }
+}
@@ -0,0 +1,289 @@
+use crate::config::Config;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+lazy_static! {
+    static ref DEFAULT_RULES: HashMap<usize, IdFormatRule> = {
+        let mut ret = HashMap::new();
+        // ISNI: "0000000121849233" <=> "0000 0001 2184 9233"
+        ret.insert(
+            213,
+            IdFormatRule {
+                strip: " ".to_string(),
+                strip_prefixes: vec!["https://isni.org/isni/".to_string(), "http://isni.org/isni/".to_string()],
+                case: CaseRule::Upper,
+                check_digit: Some(CheckDigit::Iso7064Mod112),
+                group_size: Some(4),
+                ..Default::default()
+            },
+        );
+        // ORCID: "0000-0002-1825-0097"
+        ret.insert(
+            496,
+            IdFormatRule {
+                strip: " ".to_string(),
+                strip_prefixes: vec!["https://orcid.org/".to_string(), "http://orcid.org/".to_string()],
+                case: CaseRule::Upper,
+                check_digit: Some(CheckDigit::Iso7064Mod112),
+                ..Default::default()
+            },
+        );
+        // NUKAT: "n2003044843" (or "n+2003044843" in URLs) <=> "n 2003044843"
+        ret.insert(
+            1207,
+            IdFormatRule {
+                strip: " +".to_string(),
+                case: CaseRule::Lower,
+                prefix_separator: Some(" ".to_string()),
+                ..Default::default()
+            },
+        );
+        // BAV: "495_12345" <=> "495/12345"
+        ret.insert(
+            8034,
+            IdFormatRule {
+                source_separator: "_".to_string(),
+                wikidata_separator: "/".to_string(),
+                ..Default::default()
+            },
+        );
+        ret
+    };
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CaseRule {
+    #[default]
+    Preserve,
+    Upper,
+    Lower,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckDigit {
+    /// ISO 7064 MOD 11-2, as used by ISNI and ORCID
+    Iso7064Mod112,
+}
+
+impl CheckDigit {
+    /// The check character for `body`, ignoring anything that is not a digit (like dashes)
+    pub fn compute(&self, body: &str) -> char {
+        match self {
+            Self::Iso7064Mod112 => {
+                let total = body
+                    .chars()
+                    .filter_map(|c| c.to_digit(10))
+                    .fold(0, |total, digit| ((total + digit) * 2) % 11);
+                match (12 - total % 11) % 11 {
+                    10 => 'X',
+                    digit => char::from_digit(digit, 10).unwrap_or('X'),
+                }
+            }
+        }
+    }
+
+    /// Recomputes the check character; if it matches the given one (ignoring case), the computed one is used.
+    /// IDs with a wrong check character are left alone, so validation can reject them.
+    fn normalize(&self, id: &str) -> String {
+        let (body, check) = match id.char_indices().last() {
+            Some((pos, check)) => (&id[..pos], check),
+            None => return id.to_string(),
+        };
+        let expected = self.compute(body);
+        if expected.eq_ignore_ascii_case(&check) {
+            format!("{body}{expected}")
+        } else {
+            id.to_string()
+        }
+    }
+}
+
+/// How IDs of a property are normalized, and how their source and Wikidata forms differ.
+/// The defaults can be replaced per property in the `[id_formats]` section of the configuration.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct IdFormatRule {
+    /// Characters that are removed from the ID
+    pub strip: String,
+    /// Prefixes (like the URL of the ID) that are removed
+    pub strip_prefixes: Vec<String>,
+    pub case: CaseRule,
+    pub check_digit: Option<CheckDigit>,
+    /// Replaced by `wikidata_separator` in the Wikidata form
+    pub source_separator: String,
+    pub wikidata_separator: String,
+    /// The Wikidata form groups the characters in blocks of this size, separated by spaces
+    pub group_size: Option<usize>,
+    /// The Wikidata form separates leading letters from the rest with this
+    pub prefix_separator: Option<String>,
+}
+
+impl IdFormatRule {
+    pub fn to_source_format(&self, id: &str) -> String {
+        let mut id = id.trim();
+        if let Some(rest) = self
+            .strip_prefixes
+            .iter()
+            .find_map(|prefix| id.strip_prefix(prefix.as_str()))
+        {
+            id = rest;
+        }
+        let mut id: String = id.chars().filter(|c| !self.strip.contains(*c)).collect();
+        if let Some(separator) = self.prefix_separator.as_deref().filter(|s| !s.is_empty()) {
+            if let Some(pos) = id.find(|c: char| !c.is_alphabetic()) {
+                if id[pos..].starts_with(separator) {
+                    id.replace_range(pos..pos + separator.len(), "");
+                }
+            }
+        }
+        if !self.source_separator.is_empty() && !self.wikidata_separator.is_empty() {
+            id = id.replace(&self.wikidata_separator, &self.source_separator);
+        }
+        id = match self.case {
+            CaseRule::Preserve => id,
+            CaseRule::Upper => id.to_uppercase(),
+            CaseRule::Lower => id.to_lowercase(),
+        };
+        match &self.check_digit {
+            Some(check_digit) => check_digit.normalize(&id),
+            None => id,
+        }
+    }
+
+    pub fn to_wikidata_format(&self, id: &str) -> String {
+        let mut id = self.to_source_format(id);
+        if !self.source_separator.is_empty() && !self.wikidata_separator.is_empty() {
+            id = id.replace(&self.source_separator, &self.wikidata_separator);
+        }
+        if let Some(size) = self.group_size.filter(|size| *size > 0) {
+            let chars: Vec<char> = id.chars().collect();
+            id = chars
+                .chunks(size)
+                .map(|chunk| chunk.iter().collect::<String>())
+                .collect::<Vec<String>>()
+                .join(" ");
+        }
+        if let Some(separator) = &self.prefix_separator {
+            if let Some(pos) = id.find(|c: char| !c.is_alphabetic()).filter(|pos| *pos > 0) {
+                id = format!("{}{separator}{}", &id[..pos], &id[pos..]);
+            }
+        }
+        id
+    }
+}
+
+/// Converts external IDs between the form used by the source (in URLs and records) and the form Wikidata uses.
+/// `ExternalId` always holds the source form.
+pub struct IdFormat {}
+
+impl IdFormat {
+    /// The rule from the configuration, or the built-in one
+    pub fn rule(property: usize) -> Option<&'static IdFormatRule> {
+        Config::get()
+            .id_formats
+            .get(&format!("P{property}"))
+            .or_else(|| DEFAULT_RULES.get(&property))
+    }
+
+    pub fn to_source_format(property: usize, id: &str) -> String {
+        match Self::rule(property) {
+            Some(rule) => rule.to_source_format(id),
+            None => id.to_string(),
+        }
+    }
+
+    pub fn to_wikidata_format(property: usize, id: &str) -> String {
+        match Self::rule(property) {
+            Some(rule) => rule.to_wikidata_format(id),
+            None => id.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Source and Wikidata forms convert into each other
+    fn assert_round_trip(property: usize, source: &str, wikidata: &str) {
+        assert_eq!(IdFormat::to_source_format(property, wikidata), source);
+        assert_eq!(IdFormat::to_wikidata_format(property, source), wikidata);
+        assert_eq!(IdFormat::to_source_format(property, source), source);
+        assert_eq!(IdFormat::to_wikidata_format(property, wikidata), wikidata);
+    }
+
+    #[test]
+    fn test_isni() {
+        assert_round_trip(213, "0000000121849233", "0000 0001 2184 9233");
+        assert_round_trip(213, "0000000121032683", "0000 0001 2103 2683");
+        assert_eq!(
+            IdFormat::to_source_format(213, "https://isni.org/isni/0000000121032683"),
+            "0000000121032683"
+        );
+    }
+
+    #[test]
+    fn test_orcid() {
+        assert_round_trip(496, "0000-0002-1694-233X", "0000-0002-1694-233X");
+        assert_eq!(
+            IdFormat::to_source_format(496, "https://orcid.org/0000-0002-1694-233x"),
+            "0000-0002-1694-233X"
+        );
+    }
+
+    #[test]
+    fn test_nukat() {
+        assert_round_trip(1207, "n2003044843", "n 2003044843");
+        assert_eq!(
+            IdFormat::to_source_format(1207, "n+2003044843"),
+            "n2003044843"
+        );
+        assert_eq!(
+            IdFormat::to_wikidata_format(1207, "2003044843"),
+            "2003044843"
+        );
+    }
+
+    #[test]
+    fn test_bav() {
+        assert_round_trip(8034, "495_12345", "495/12345");
+    }
+
+    #[test]
+    fn test_other() {
+        assert_round_trip(227, "118523813", "118523813");
+    }
+
+    #[test]
+    fn test_check_digit() {
+        let check_digit = CheckDigit::Iso7064Mod112;
+        assert_eq!(check_digit.compute("000000012103268"), '3');
+        assert_eq!(check_digit.compute("0000-0002-1694-233"), 'X');
+        // A wrong check character is not "fixed"
+        assert_eq!(
+            check_digit.normalize("0000000121032684"),
+            "0000000121032684"
+        );
+        assert_eq!(
+            check_digit.normalize("0000-0002-1694-233x"),
+            "0000-0002-1694-233X"
+        );
+    }
+
+    #[test]
+    fn test_rule_from_toml() {
+        let rule: IdFormatRule = toml::from_str(
+            r#"
+            strip = "-"
+            case = "upper"
+            source_separator = "_"
+            wikidata_separator = "/"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(rule.to_source_format("ab-1/2"), "AB1_2");
+        assert_eq!(rule.to_wikidata_format("ab-1_2"), "AB1/2");
+    }
+}
@@ -1,20 +1,106 @@
 use crate::external_id::*;
 use crate::external_importer::*;
 use crate::meta_item::*;
+use crate::occupation::Occupation;
+use crate::utility::Utility;
 use anyhow::Result;
 use axum::async_trait;
-use sophia::api::prelude::*;
+use regex::Regex;
 use sophia::inmem::graph::FastGraph;
-use sophia::xml;
+use std::collections::HashMap;
+
+lazy_static! {
+    static ref RE_PERSON_URI: Regex =
+        Regex::new(r"^https?://www\.idref\.fr/(\d{8}[\dX])/id$").expect("Regexp error");
+    /// The page of the person on theses.fr; its ID is the IdRef ID
+    static ref RE_THESES_PERSON: Regex = Regex::new(
+        r"^https?://(?:www\.)?theses\.fr/(?:api/v1/personnes/)?(\d{8}[\dX])(?:/id)?$"
+    )
+    .expect("Regexp error");
+}
+
+/// Titles of works that become notable work (P800) suggestions
+const MAX_WORKS: usize = 10;
+/// Links from documents to their authors
+const AUTHOR_ROLES: &[&str] = &[
+    "http://id.loc.gov/vocabulary/relators/aut",
+    "http://purl.org/dc/terms/creator",
+];
+const TITLES: &[&str] = &[
+    "http://purl.org/dc/terms/title",
+    "http://purl.org/dc/elements/1.1/title",
+];
+
+/// Fields with occupations of the person, as text or as a linked concept
+const OCCUPATIONS: &[&str] = &[
+    "http://rdvocab.info/ElementsGr2/professionOrOccupation",
+    "http://rdaregistry.info/Elements/a/P50104",
+];
+const BIOGRAPHICAL_NOTE: &str = "http://rdvocab.info/ElementsGr2/biographicalInformation";
+const CONCEPT_LABELS: &[&str] = &[
+    "http://www.w3.org/2004/02/skos/core#prefLabel",
+    "http://www.w3.org/2000/01/rdf-schema#label",
+];
+
+const RDF_TYPE: &str = "http://www.w3.org/1999/02/22-rdf-syntax-ns#type";
+const OWL_SAME_AS: &str = "http://www.w3.org/2002/07/owl#sameAs";
+
+/// Classes of the record, and what it is about
+const KINDS: &[(&str, IdRefKind)] = &[
+    ("http://xmlns.com/foaf/0.1/Person", IdRefKind::Person),
+    (
+        "http://xmlns.com/foaf/0.1/Organization",
+        IdRefKind::Organization,
+    ),
+    (
+        "http://www.w3.org/ns/org#Organization",
+        IdRefKind::Organization,
+    ),
+    (
+        "http://purl.org/NET/c4dm/event.owl#Event",
+        IdRefKind::Conference,
+    ),
+    ("http://schema.org/Event", IdRefKind::Conference),
+];
+
+/// Fields of organizations and conferences, and their properties; places are kept as text
+const ORGANIZATION_DATES: &[(&str, usize)] = &[
+    ("http://schema.org/foundingDate", 571),
+    ("http://schema.org/dissolutionDate", 576),
+];
+const ORGANIZATION_PLACES: &[(&str, usize)] = &[("http://schema.org/location", 131)];
+const CONFERENCE_DATES: &[(&str, usize)] = &[
+    ("http://purl.org/dc/terms/date", 585),
+    ("http://purl.org/NET/c4dm/event.owl#time", 585),
+];
+const CONFERENCE_PLACES: &[(&str, usize)] = &[
+    ("http://purl.org/NET/c4dm/event.owl#place", 276),
+    ("http://schema.org/location", 276),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum IdRefKind {
+    Person,
+    Organization,
+    Conference,
+}
+
+impl IdRefKind {
+    fn instance_of(&self) -> &'static str {
+        match self {
+            Self::Person => "Q5",
+            Self::Organization => "Q43229",
+            Self::Conference => "Q2020153",
+        }
+    }
+}
 
 pub struct IdRef {
     id: String,
     graph: FastGraph,
+    kind: Option<IdRefKind>,
 }
 
-unsafe impl Send for IdRef {}
-unsafe impl Sync for IdRef {}
-
 #[async_trait]
 impl ExternalImporter for IdRef {
     fn my_property(&self) -> usize {
@@ -25,6 +111,14 @@ impl ExternalImporter for IdRef {
         self.id.to_owned()
     }
 
+    /// Merged records are returned under the ID of the surviving record
+    fn resolve_canonical_id(&self) -> Result<Option<String>> {
+        self.canonical_id_from_subjects(
+            "http://www.w3.org/1999/02/22-rdf-syntax-ns#type",
+            &RE_PERSON_URI,
+        )
+    }
+
     fn my_stated_in(&self) -> &str {
         "Q47757534"
     }
@@ -41,10 +135,101 @@ impl ExternalImporter for IdRef {
         format!("http://www.idref.fr/{}/{}", self.id, key)
     }
 
+    async fn fetch(&mut self) -> Result<()> {
+        let rdf_url = format!("https://www.idref.fr/{}.rdf", self.id);
+        self.graph = Utility::get_rdf_graph(&rdf_url).await?;
+        if let Some(id) = self.resolve_canonical_id()? {
+            self.id = id;
+        }
+        self.kind = self.detect_kind()?;
+        Ok(())
+    }
+
+    fn parse(&mut self, text: &str) -> Result<()> {
+        self.graph = Utility::parse_rdf_xml(text)?;
+        if let Some(id) = self.resolve_canonical_id()? {
+            self.id = id;
+        }
+        self.kind = self.detect_kind()?;
+        Ok(())
+    }
+
+    async fn add_instance_of(&self, ret: &mut MetaItem) -> Result<()> {
+        if let Some(kind) = self.kind {
+            ret.add_claim(self.new_statement_item(31, kind.instance_of()));
+        }
+        Ok(())
+    }
+
     async fn run(&self) -> Result<MetaItem> {
         let mut ret = MetaItem::new();
         self.add_the_usual(&mut ret).await?;
+        match self.kind {
+            Some(IdRefKind::Organization) => {
+                self.add_dates_places(&mut ret, ORGANIZATION_DATES, ORGANIZATION_PLACES)?;
+            }
+            Some(IdRefKind::Conference) => {
+                self.add_dates_places(&mut ret, CONFERENCE_DATES, CONFERENCE_PLACES)?;
+            }
+            _ => self.add_person(&mut ret).await?,
+        }
+        self.add_works(&mut ret)?;
+
+        self.try_rescue_prop_text(&mut ret).await?;
+        ret.cleanup();
+        Ok(ret)
+    }
+}
+
+impl IdRef {
+    pub fn unfetched(id: &str) -> Self {
+        Self {
+            id: id.to_string(),
+            graph: FastGraph::new(),
+            kind: None,
+        }
+    }
+
+    pub async fn new(id: &str) -> Result<Self> {
+        let mut ret = Self::unfetched(id);
+        ret.fetch().await?;
+        Ok(ret)
+    }
+
+    fn detect_kind(&self) -> Result<Option<IdRefKind>> {
+        let types = self.triples_iris(RDF_TYPE)?;
+        Ok(KINDS
+            .iter()
+            .find(|(class, _)| types.iter().any(|t| t == class))
+            .map(|(_, kind)| *kind))
+    }
+
+    /// Dates, and places as text for try_rescue_prop_text
+    fn add_dates_places(
+        &self,
+        ret: &mut MetaItem,
+        dates: &[(&str, usize)],
+        places: &[(&str, usize)],
+    ) -> Result<()> {
+        for (predicate, property) in dates {
+            for s in self.triples_literals(predicate)? {
+                let _ = match ret.parse_date(&s) {
+                    Some((time, precision)) => {
+                        ret.add_claim(self.new_statement_time(*property, &time, precision))
+                    }
+                    None => ret.add_prop_text(ExternalId::new(*property, &s)),
+                };
+            }
+        }
+        for (predicate, property) in places {
+            for s in self.triples_literals(predicate)? {
+                let _ = ret.add_prop_text(ExternalId::new(*property, &s));
+            }
+        }
+        Ok(())
+    }
 
+    async fn add_person(&self, ret: &mut MetaItem) -> Result<()> {
         for url in self.triples_iris("http://dbpedia.org/ontology/citizenship")? {
             match self.url2external_id(&url) {
                 Some(extid) => {
@@ -74,22 +259,90 @@ impl ExternalImporter for IdRef {
             }
         }
 
-        self.try_rescue_prop_text(&mut ret).await?;
-        ret.cleanup();
+        self.add_occupations(ret)?;
+        for id in self.theses_ids()? {
+            ret.add_claim(self.new_statement_external_id(&ExternalId::new(4285, &id)));
+        }
+        Ok(())
+    }
+
+    /// theses.fr person IDs (P4285) from the `owl:sameAs` links of the person; other subjects in the record, like
+    /// co-authors or thesis supervisors, have their own links
+    fn theses_ids(&self) -> Result<Vec<String>> {
+        Ok(self
+            .triples_subject_iris(&self.get_id_url(), OWL_SAME_AS)?
+            .iter()
+            .filter_map(|url| RE_THESES_PERSON.captures(url))
+            .map(|captures| captures[1].to_string())
+            .collect())
+    }
+
+    /// Titles of the documents the person is an author of, with how many documents have them, most frequent first
+    fn work_titles(&self) -> Result<Vec<(String, usize)>> {
+        let mut documents = vec![];
+        for role in AUTHOR_ROLES {
+            documents.extend(self.triples_property_object_iris(role, &self.get_id_url())?);
+        }
+        documents.sort();
+        documents.dedup();
+        let mut counts: HashMap<String, usize> = HashMap::new();
+        for document in documents {
+            let title = TITLES
+                .iter()
+                .filter_map(|predicate| self.triples_subject_literals(&document, predicate).ok())
+                .flatten()
+                .next();
+            if let Some(title) = title {
+                let title = self.limit_string_length(title.trim_end_matches([' ', '/', ':', '.']));
+                *counts.entry(title).or_default() += 1;
+            }
+        }
+        let mut ret: Vec<(String, usize)> = counts.into_iter().collect();
+        ret.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
         Ok(ret)
     }
-}
 
-impl IdRef {
-    pub async fn new(id: &str) -> Result<Self> {
-        let rdf_url = format!("https://www.idref.fr/{}.rdf", id);
-        let resp = reqwest::get(&rdf_url).await?.text().await?;
-        let mut graph: FastGraph = FastGraph::new();
-        let _ = xml::parser::parse_str(&resp).add_to_graph(&mut graph)?;
-        Ok(Self {
-            id: id.to_string(),
-            graph,
-        })
+    fn add_works(&self, ret: &mut MetaItem) -> Result<()> {
+        for (title, count) in self.work_titles()?.into_iter().take(MAX_WORKS) {
+            ret.add_prop_text_suggestion(ExternalId::new(800, &title), count);
+        }
+        Ok(())
+    }
+
+    /// Occupation terms, like "Naturaliste. - Géologue", and the labels of linked occupation concepts
+    fn occupation_terms(&self) -> Result<Vec<String>> {
+        let mut ret = vec![];
+        for predicate in OCCUPATIONS {
+            ret.extend(self.triples_literals(predicate)?);
+            for url in self.triples_iris(predicate)? {
+                for label_predicate in CONCEPT_LABELS {
+                    ret.extend(self.triples_subject_literals(&url, label_predicate)?);
+                }
+            }
+        }
+        Ok(ret
+            .iter()
+            .flat_map(|s| Occupation::split_terms(s))
+            .collect())
+    }
+
+    /// Occupations from the occupation fields; the biographical note only adds terms that are known occupations,
+    /// since it is free text
+    fn add_occupations(&self, ret: &mut MetaItem) -> Result<()> {
+        for term in self.occupation_terms()? {
+            let _ = match Occupation::from_french(&term) {
+                Some(item) => ret.add_claim(self.new_statement_item(106, item)),
+                None => ret.add_prop_text(ExternalId::new(106, &term)),
+            };
+        }
+        for note in self.triples_literals(BIOGRAPHICAL_NOTE)? {
+            for term in Occupation::split_terms(&note) {
+                if let Some(item) = Occupation::from_french(&term) {
+                    ret.add_claim(self.new_statement_item(106, item));
+                }
+            }
+        }
+        Ok(())
     }
 }
 
@@ -101,11 +354,134 @@ mod tests {
 
     const TEST_ID: &str = "026812304";
 
+    const TEST_RECORD: &str = r#"<?xml version="1.0"?>
+<rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#"
+    xmlns:foaf="http://xmlns.com/foaf/0.1/"
+    xmlns:skos="http://www.w3.org/2004/02/skos/core#"
+    xmlns:rdaGr2="http://rdvocab.info/ElementsGr2/"
+    xmlns:dcterms="http://purl.org/dc/terms/"
+    xmlns:owl="http://www.w3.org/2002/07/owl#"
+    xmlns:marcrel="http://id.loc.gov/vocabulary/relators/">
+  <skos:Concept rdf:about="http://www.idref.fr/026812304">
+    <foaf:focus rdf:resource="http://www.idref.fr/026812304/id"/>
+  </skos:Concept>
+  <foaf:Person rdf:about="http://www.idref.fr/026812304/id">
+    <foaf:name>Darwin, Charles</foaf:name>
+    <owl:sameAs rdf:resource="http://www.theses.fr/026812304"/>
+    <rdaGr2:professionOrOccupation>Naturaliste. - Explorateur</rdaGr2:professionOrOccupation>
+    <rdaGr2:professionOrOccupation rdf:resource="http://www.idref.fr/027375366/id"/>
+    <rdaGr2:biographicalInformation>Naturaliste. - Géologue. - Auteur de l'Origine des espèces</rdaGr2:biographicalInformation>
+  </foaf:Person>
+  <rdf:Description rdf:about="http://www.sudoc.fr/000000001/id">
+    <dcterms:title>L'origine des espèces /</dcterms:title>
+    <marcrel:aut rdf:resource="http://www.idref.fr/026812304/id"/>
+  </rdf:Description>
+  <rdf:Description rdf:about="http://www.sudoc.fr/000000002/id">
+    <dcterms:title>L'origine des espèces</dcterms:title>
+    <dcterms:creator rdf:resource="http://www.idref.fr/026812304/id"/>
+  </rdf:Description>
+  <rdf:Description rdf:about="http://www.sudoc.fr/000000003/id">
+    <dcterms:title>Voyage d'un naturaliste autour du monde</dcterms:title>
+    <marcrel:aut rdf:resource="http://www.idref.fr/026812304/id"/>
+  </rdf:Description>
+  <rdf:Description rdf:about="http://www.idref.fr/027375366/id">
+    <skos:prefLabel>Géologue</skos:prefLabel>
+  </rdf:Description>
+  <foaf:Person rdf:about="http://www.idref.fr/027000001/id">
+    <owl:sameAs rdf:resource="http://www.theses.fr/027000001"/>
+  </foaf:Person>
+  <rdf:Description rdf:about="http://www.sudoc.fr/000000004/id">
+    <dcterms:relation rdf:resource="http://www.theses.fr/027000002"/>
+  </rdf:Description>
+</rdf:RDF>"#;
+
     #[tokio::test]
     async fn test_new() {
         assert!(IdRef::new(TEST_ID).await.is_ok());
     }
 
+    #[test]
+    fn test_conference() {
+        let idref = IdRef::unfetched("026387565")
+            .with_record(
+                r#"<?xml version="1.0"?>
+<rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#"
+    xmlns:event="http://purl.org/NET/c4dm/event.owl#"
+    xmlns:dcterms="http://purl.org/dc/terms/">
+  <event:Event rdf:about="http://www.idref.fr/026387565/id">
+    <dcterms:date>1968</dcterms:date>
+    <event:place>Grenoble</event:place>
+  </event:Event>
+</rdf:RDF>"#,
+            )
+            .unwrap();
+        assert_eq!(idref.kind, Some(IdRefKind::Conference));
+        let mut mi = MetaItem::new();
+        idref
+            .add_dates_places(&mut mi, CONFERENCE_DATES, CONFERENCE_PLACES)
+            .unwrap();
+        assert_eq!(mi.item.claims()[0].main_snak().property(), "P585");
+        assert_eq!(mi.prop_text, vec![ExternalId::new(276, "Grenoble")]);
+        assert_eq!(
+            IdRef::unfetched(TEST_ID)
+                .with_record(TEST_RECORD)
+                .unwrap()
+                .kind,
+            Some(IdRefKind::Person)
+        );
+    }
+
+    #[test]
+    fn test_theses_ids() {
+        assert_eq!(
+            IdRef::unfetched(TEST_ID)
+                .with_record(TEST_RECORD)
+                .unwrap()
+                .theses_ids()
+                .unwrap(),
+            vec![TEST_ID]
+        );
+    }
+
+    #[test]
+    fn test_work_titles() {
+        assert_eq!(
+            IdRef::unfetched(TEST_ID)
+                .with_record(TEST_RECORD)
+                .unwrap()
+                .work_titles()
+                .unwrap(),
+            vec![
+                ("L'origine des espèces".to_string(), 2),
+                ("Voyage d'un naturaliste autour du monde".to_string(), 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_occupation_terms() {
+        assert_eq!(
+            IdRef::unfetched(TEST_ID)
+                .with_record(TEST_RECORD)
+                .unwrap()
+                .occupation_terms()
+                .unwrap(),
+            vec!["Naturaliste", "Explorateur", "Géologue"]
+        );
+    }
+
+    #[test]
+    fn test_add_occupations() {
+        let mut mi = MetaItem::new();
+        IdRef::unfetched(TEST_ID)
+            .with_record(TEST_RECORD)
+            .unwrap()
+            .add_occupations(&mut mi)
+            .unwrap();
+        assert_eq!(mi.item.claims().len(), 2);
+        assert_eq!(mi.prop_text, vec![ExternalId::new(106, "Explorateur")]);
+    }
+
     #[tokio::test]
     async fn test_my_property() {
         let idref = IdRef::new(TEST_ID).await.unwrap();
@@ -1,6 +1,7 @@
 use crate::external_importer::*;
 use crate::meta_item::*;
-use crate::ExternalId;
+use crate::utility::Utility;
+use crate::wikidata_lookup::{lookup, LookupQuery};
 use anyhow::{anyhow, Result};
 use axum::async_trait;
 use regex::Regex;
@@ -22,9 +23,6 @@ pub struct INaturalist {
     json: Value,
 }
 
-unsafe impl Send for INaturalist {}
-unsafe impl Sync for INaturalist {}
-
 #[async_trait]
 impl ExternalImporter for INaturalist {
     fn my_property(&self) -> usize {
@@ -43,6 +41,18 @@ impl ExternalImporter for INaturalist {
         self.id.to_owned()
     }
 
+    async fn fetch(&mut self) -> Result<()> {
+        let url = format!("https://www.inaturalist.org/taxa/{}", self.id);
+        let resp = Utility::get_url(&url).await?;
+        self.parse(&resp)
+    }
+
+    /// The HTML page of the taxon, which embeds its JSON
+    fn parse(&mut self, text: &str) -> Result<()> {
+        self.json = Self::parse_html(text).ok_or(anyhow!("No JSON found"))?;
+        Ok(())
+    }
+
     async fn run(&self) -> Result<MetaItem> {
         let mut ret = MetaItem::new();
         self.add_own_id(&mut ret)?;
@@ -59,14 +69,17 @@ impl ExternalImporter for INaturalist {
 }
 
 impl INaturalist {
-    pub async fn new(id: &str) -> Result<Self> {
-        let url = format!("https://www.inaturalist.org/taxa/{id}");
-        let resp = reqwest::get(&url).await?.text().await?;
-        let j = Self::parse_html(&resp).ok_or(anyhow!("No JSON found"))?;
-        Ok(Self {
+    pub fn unfetched(id: &str) -> Self {
+        Self {
             id: id.to_string(),
-            json: j,
-        })
+            json: Value::Null,
+        }
+    }
+
+    pub async fn new(id: &str) -> Result<Self> {
+        let mut ret = Self::unfetched(id);
+        ret.fetch().await?;
+        Ok(ret)
     }
 
     fn parse_html(s: &str) -> Option<Value> {
@@ -81,8 +94,8 @@ impl INaturalist {
 
     async fn add_parent_taxon(&self, ret: &mut MetaItem) -> Option<()> {
         let parent_id = self.json.get("parent_id")?.as_u64()?;
-        let query = format!("haswbstatement:P3151={parent_id} haswbstatement:P31=Q16521");
-        let item = ExternalId::search_wikidata_single_item(&query).await?;
+        let query = LookupQuery::new(3151, &parent_id.to_string()).with_statement(31, "Q16521");
+        let item = lookup().find_single_item(&query).await?;
         ret.add_claim(self.new_statement_item(171, &item));
         Some(())
     }
@@ -0,0 +1,545 @@
+use crate::external_id::*;
+use crate::external_importer::*;
+use crate::fuzzy_date::FuzzyDate;
+use crate::meta_item::*;
+use crate::utility::Utility;
+use anyhow::{anyhow, Result};
+use axum::async_trait;
+use quickxml_to_serde::{xml_string_to_json, NullValue};
+use serde_json::Value;
+use std::collections::HashMap;
+use wikimisc::wikibase::{EntityTrait, LocaleString};
+
+const SRU_URL: &str = "https://isni.oclc.org/sru/";
+
+/// Creation titles that become notable work (P800) suggestions
+const MAX_WORKS: usize = 10;
+
+/// Codes of the sources that contributed to an ISNI record, and their properties
+const SOURCES: &[(&str, usize)] = &[
+    ("VIAF", 214),
+    ("DNB", 227),
+    ("LC", 244),
+    ("BNF", 268),
+    ("SUDOC", 269),
+    ("ORCID", 496),
+    ("NKC", 691),
+    ("SELIBR", 906),
+    ("BNE", 950),
+    ("NTA", 1006),
+    ("NUKAT", 1207),
+];
+
+/// Creation roles, as MARC relator codes, and their occupations (P106)
+const CREATION_ROLES: &[(&str, &str)] = &[
+    ("art", "Q483501"),
+    ("aut", "Q36180"),
+    ("cmp", "Q36834"),
+    ("drt", "Q2526255"),
+    ("edt", "Q1607826"),
+    ("ill", "Q644687"),
+    ("pht", "Q33231"),
+    ("trl", "Q333634"),
+];
+
+/// Organisation types, as ISNI gives them, and their items; other organisations are just organizations
+const ORGANISATION_TYPES: &[(&str, &str)] = &[
+    ("Academic", "Q3918"),
+    ("Library", "Q7075"),
+    ("Museum", "Q33506"),
+    ("Music Group/Band", "Q215380"),
+    ("Publisher", "Q2085381"),
+    ("Record Label", "Q18127"),
+];
+
+/// What an ISNI is assigned to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum IsniKind {
+    Person,
+    Organisation,
+}
+
+impl IsniKind {
+    fn detect(json: &Value) -> Self {
+        match json["identity"].get("organisation") {
+            Some(_) => Self::Organisation,
+            None => Self::Person,
+        }
+    }
+
+    /// Properties for the start and end of the dates given with the names
+    fn date_properties(&self) -> (usize, usize) {
+        match self {
+            Self::Person => (569, 570),
+            Self::Organisation => (571, 576),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct ISNI {
+    id: String,
+    /// The `ISNIMetadata` of the record
+    json: Value,
+    kind: IsniKind,
+}
+
+#[async_trait]
+impl ExternalImporter for ISNI {
+    fn my_property(&self) -> usize {
+        213
+    }
+    fn my_stated_in(&self) -> &str {
+        "Q423048"
+    }
+    fn primary_language(&self) -> String {
+        "en".to_string()
+    }
+    fn get_key_url(&self, _key: &str) -> String {
+        format!("https://isni.org/isni/{}", self.id)
+    }
+    fn my_id(&self) -> String {
+        self.id.to_owned()
+    }
+
+    /// With the spaces Wikidata uses
+    fn add_own_id(&self, ret: &mut MetaItem) -> Result<()> {
+        ret.add_claim(self.new_statement_external_id(&ExternalId::new(213, &self.id)));
+        Ok(())
+    }
+
+    async fn fetch(&mut self) -> Result<()> {
+        let query = format!("pica.isn = \"{}\"", self.id);
+        let url = reqwest::Url::parse_with_params(
+            SRU_URL,
+            &[
+                ("operation", "searchRetrieve"),
+                ("recordSchema", "isni-b"),
+                ("query", &query),
+            ],
+        )?;
+        let resp = Utility::get_url(url.as_str()).await?;
+        self.parse(&resp)
+    }
+
+    fn parse(&mut self, text: &str) -> Result<()> {
+        // Source identifiers can have leading zeros
+        let conf =
+            quickxml_to_serde::Config::new_with_custom_values(true, "@", "#text", NullValue::Null);
+        let json = xml_string_to_json(text.to_owned(), &conf)?;
+        self.json = Self::find(&json, "ISNIMetadata")
+            .ok_or_else(|| anyhow!("No ISNI record for {}", self.id))?
+            .to_owned();
+        self.kind = IsniKind::detect(&self.json);
+        Ok(())
+    }
+
+    fn transform_label(&self, s: &str) -> String {
+        self.transform_label_last_first_name(s)
+    }
+
+    async fn add_instance_of(&self, ret: &mut MetaItem) -> Result<()> {
+        ret.add_claim(self.new_statement_item(31, self.instance_of()));
+        Ok(())
+    }
+
+    async fn run(&self) -> Result<MetaItem> {
+        let mut ret = MetaItem::new();
+        self.add_own_id(&mut ret)?;
+        self.add_instance_of(&mut ret).await?;
+        match self.kind {
+            IsniKind::Person => {
+                let person = &self.json["identity"]["personOrFiction"];
+                self.add_person_names(&mut ret, person);
+                self.add_dates(&mut ret, Self::personal_names(person));
+                self.add_creation_roles(&mut ret, person);
+            }
+            IsniKind::Organisation => {
+                self.add_organisation_names(&mut ret);
+                self.add_dates(&mut ret, self.organisation_names());
+            }
+        }
+        self.add_works(&mut ret);
+        self.add_sources(&mut ret);
+        self.try_rescue_prop_text(&mut ret).await?;
+        ret.cleanup();
+        Ok(ret)
+    }
+}
+
+impl ISNI {
+    pub fn unfetched(id: &str) -> Self {
+        Self {
+            id: id.to_string(),
+            json: Value::Null,
+            kind: IsniKind::Person,
+        }
+    }
+
+    pub async fn new(id: &str) -> Result<Self> {
+        let mut ret = Self::unfetched(id);
+        ret.fetch().await?;
+        Ok(ret)
+    }
+
+    /// P31 for the record
+    fn instance_of(&self) -> &'static str {
+        match self.kind {
+            IsniKind::Person => "Q5",
+            IsniKind::Organisation => {
+                let organisation_type =
+                    Self::text(&self.json["identity"]["organisation"]["organisationType"]);
+                ORGANISATION_TYPES
+                    .iter()
+                    .find(|(name, _)| Some(*name) == organisation_type.as_deref())
+                    .map_or("Q43229", |(_, item)| *item)
+            }
+        }
+    }
+
+    /// The first value of a key anywhere in `j`; the SRU response wraps the record in namespaced elements
+    fn find<'a>(j: &'a Value, key: &str) -> Option<&'a Value> {
+        match j {
+            Value::Object(o) => o
+                .iter()
+                .find(|(k, _)| k.as_str() == key || k.ends_with(&format!(":{key}")))
+                .map(|(_, v)| v)
+                .or_else(|| o.values().find_map(|v| Self::find(v, key))),
+            Value::Array(a) => a.iter().find_map(|v| Self::find(v, key)),
+            _ => None,
+        }
+    }
+
+    /// Elements that occur once are objects, those that repeat are arrays
+    fn as_list(j: &Value) -> Vec<&Value> {
+        match j {
+            Value::Array(values) => values.iter().collect(),
+            Value::Null => vec![],
+            v => vec![v],
+        }
+    }
+
+    fn text(j: &Value) -> Option<String> {
+        let s = match j {
+            Value::String(s) => s.to_owned(),
+            Value::Number(n) => n.to_string(),
+            Value::Object(_) => return Self::text(&j["#text"]),
+            _ => return None,
+        };
+        let s = s.trim();
+        (!s.is_empty()).then(|| s.to_string())
+    }
+
+    /// Values in order of how many names give them, most frequent first
+    fn by_frequency(values: impl Iterator<Item = String>) -> Vec<String> {
+        Self::counted(values)
+            .into_iter()
+            .map(|(value, _)| value)
+            .collect()
+    }
+
+    /// Distinct values, with how often they occur, most frequent first
+    fn counted(values: impl Iterator<Item = String>) -> Vec<(String, usize)> {
+        let mut counts: HashMap<String, usize> = HashMap::new();
+        for value in values {
+            *counts.entry(value).or_default() += 1;
+        }
+        let mut ret: Vec<(String, usize)> = counts.into_iter().collect();
+        ret.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        ret
+    }
+
+    fn personal_names(person: &Value) -> Vec<&Value> {
+        ["personalName", "personalNameVariant"]
+            .iter()
+            .flat_map(|key| Self::as_list(&person[key]))
+            .collect()
+    }
+
+    /// The most frequent name becomes the label, the others aliases
+    fn add_names(&self, ret: &mut MetaItem, names: Vec<String>) {
+        let language = self.primary_language();
+        let mut names = names.into_iter();
+        if let Some(label) = names.next() {
+            ret.item
+                .labels_mut()
+                .push(LocaleString::new(&language, &label));
+        }
+        for alias in names {
+            ret.item
+                .aliases_mut()
+                .push(LocaleString::new(&language, &alias));
+        }
+    }
+
+    fn add_person_names(&self, ret: &mut MetaItem, person: &Value) {
+        let names = Self::personal_names(person)
+            .into_iter()
+            .filter_map(|name| self.person_name(name));
+        self.add_names(ret, Self::by_frequency(names));
+    }
+
+    /// "Forename Surname"; sources sometimes put the whole inverted name into the surname
+    fn person_name(&self, name: &Value) -> Option<String> {
+        let part = |key: &str| {
+            Self::text(&name[key])
+                .map(|s| s.trim_end_matches([',', ';', '/', ' ']).to_string())
+                .filter(|s| !s.is_empty())
+        };
+        let surname = part("surname")?;
+        let name = match part("forename") {
+            Some(forename) => format!("{surname}, {forename}"),
+            None => surname,
+        };
+        Some(self.transform_label(&name))
+    }
+
+    fn organisation_names(&self) -> Vec<&Value> {
+        let organisation = &self.json["identity"]["organisation"];
+        ["organisationName", "organisationNameVariant"]
+            .iter()
+            .flat_map(|key| Self::as_list(&organisation[key]))
+            .collect()
+    }
+
+    /// Names of organisations are not inverted, and subdivisions follow the main name
+    fn add_organisation_names(&self, ret: &mut MetaItem) {
+        let names = self.organisation_names().into_iter().filter_map(|name| {
+            let main = Self::text(&name["mainName"])?;
+            let subdivisions: Vec<String> = Self::as_list(&name["subdivisionName"])
+                .into_iter()
+                .filter_map(Self::text)
+                .collect();
+            Some(
+                std::iter::once(main)
+                    .chain(subdivisions)
+                    .collect::<Vec<_>>()
+                    .join(". "),
+            )
+        });
+        self.add_names(ret, Self::by_frequency(names));
+    }
+
+    /// The dates the most names give, like "1809-1882"; life dates for persons, and the founding and
+    /// dissolution for organisations
+    fn add_dates(&self, ret: &mut MetaItem, names: Vec<&Value>) {
+        let dates = names
+            .into_iter()
+            .filter_map(|name| Self::text(&name["dates"]));
+        let dates = match Self::by_frequency(dates).into_iter().next() {
+            Some(dates) => dates,
+            None => return,
+        };
+        let (start, end) = FuzzyDate::parse_life_span(&dates);
+        let (start_property, end_property) = self.kind.date_properties();
+        for (property, date) in [(start_property, start), (end_property, end)] {
+            if let Some(date) = date {
+                ret.add_claim(self.new_statement_fuzzy_date(property, &date));
+            }
+        }
+    }
+
+    fn add_creation_roles(&self, ret: &mut MetaItem, person: &Value) {
+        let roles: Vec<String> = Self::as_list(&person["creationRole"])
+            .into_iter()
+            .filter_map(Self::text)
+            .collect();
+        for role in Self::by_frequency(roles.into_iter()) {
+            let _ = match CREATION_ROLES.iter().find(|(code, _)| *code == role) {
+                Some((_, item)) => ret.add_claim(self.new_statement_item(106, item)),
+                None => ret.add_prop_text(ExternalId::new(106, &role)),
+            };
+        }
+    }
+
+    /// The identity part of the record: the person, or the organisation
+    fn identity(&self) -> &Value {
+        match self.kind {
+            IsniKind::Person => &self.json["identity"]["personOrFiction"],
+            IsniKind::Organisation => &self.json["identity"]["organisation"],
+        }
+    }
+
+    /// Titles of the works the record was assigned for; several sources often give the same title
+    fn creation_titles(&self) -> Vec<(String, usize)> {
+        let titles = Self::as_list(&self.identity()["titleOfWork"])
+            .into_iter()
+            .filter_map(|work| Self::text(&work["title"]))
+            .map(|title| title.trim_end_matches([' ', '/', ':', '.']).to_string())
+            .filter(|title| !title.is_empty());
+        Self::counted(titles)
+    }
+
+    fn add_works(&self, ret: &mut MetaItem) {
+        for (title, count) in self.creation_titles().into_iter().take(MAX_WORKS) {
+            ret.add_prop_text_suggestion(ExternalId::new(800, &title), count);
+        }
+    }
+
+    /// The identifiers of the records the ISNI was assigned from
+    fn source_ids(&self) -> Vec<ExternalId> {
+        let mut ret: Vec<ExternalId> = Self::as_list(&self.json["sources"])
+            .into_iter()
+            .filter_map(|source| {
+                let code = Self::text(&source["codeOfSource"])?;
+                let property = SOURCES.iter().find(|(c, _)| *c == code)?.1;
+                let id: String = Self::text(&source["sourceIdentifier"])?
+                    .chars()
+                    .filter(|c| !c.is_whitespace())
+                    .collect();
+                let id = id.strip_prefix("ark:/12148/cb").unwrap_or(&id);
+                Some(ExternalId::new(property, id))
+            })
+            .filter(|ext_id| ext_id.is_well_formed())
+            .collect();
+        ret.sort();
+        ret.dedup();
+        ret
+    }
+
+    fn add_sources(&self, ret: &mut MetaItem) {
+        for ext_id in self.source_ids() {
+            ret.add_claim(self.new_statement_external_id(&ext_id));
+        }
+        for source in Self::as_list(&self.json["sources"]) {
+            if Self::text(&source["codeOfSource"]).as_deref() == Some("WKP") {
+                if let Some(item) = Self::text(&source["sourceIdentifier"]) {
+                    ret.add_linked_item(&item);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEST_ID: &str = "000000012146438X";
+
+    const TEST_RECORD: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<srw:searchRetrieveResponse xmlns:srw="http://www.loc.gov/zing/srw/">
+  <srw:records><srw:record><srw:recordData>
+    <responseRecord><ISNIAssigned>
+      <isniUnformatted>000000012146438X</isniUnformatted>
+      <ISNIMetadata>
+        <identity><personOrFiction>
+          <personalName><surname>Darwin</surname><forename>Charles</forename><dates>1809-1882</dates></personalName>
+          <personalName><surname>Darwin</surname><forename>Charles</forename><dates>1809-1882</dates></personalName>
+          <personalName><surname>Darwin</surname><forename>Charles Robert</forename></personalName>
+          <creationRole>aut</creationRole>
+          <creationRole>xyz</creationRole>
+          <titleOfWork><title>On the origin of species /</title></titleOfWork>
+          <titleOfWork><title>The descent of man</title></titleOfWork>
+          <titleOfWork><title>On the origin of species</title></titleOfWork>
+        </personOrFiction></identity>
+        <sources><codeOfSource>VIAF</codeOfSource><sourceIdentifier>27063124</sourceIdentifier></sources>
+        <sources><codeOfSource>LC</codeOfSource><sourceIdentifier>n  78095637</sourceIdentifier></sources>
+        <sources><codeOfSource>NTA</codeOfSource><sourceIdentifier>068364229</sourceIdentifier></sources>
+        <sources><codeOfSource>WKP</codeOfSource><sourceIdentifier>Q1035</sourceIdentifier></sources>
+      </ISNIMetadata>
+    </ISNIAssigned></responseRecord>
+  </srw:recordData></srw:record></srw:records>
+</srw:searchRetrieveResponse>"#;
+
+    #[tokio::test]
+    async fn test_new() {
+        assert!(ISNI::new(TEST_ID).await.is_ok());
+    }
+
+    #[test]
+    fn test_source_ids() {
+        let mut isni = ISNI::unfetched(TEST_ID);
+        isni.parse(TEST_RECORD).unwrap();
+        assert_eq!(
+            isni.source_ids(),
+            vec![
+                ExternalId::new(214, "27063124"),
+                ExternalId::new(244, "n78095637"),
+                ExternalId::new(1006, "068364229"),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_run() {
+        let mut isni = ISNI::unfetched(TEST_ID);
+        isni.parse(TEST_RECORD).unwrap();
+        let mi = isni.run().await.unwrap();
+        assert_eq!(
+            *mi.item.labels(),
+            vec![LocaleString::new("en", "Charles Darwin")]
+        );
+        assert_eq!(
+            *mi.item.aliases(),
+            vec![LocaleString::new("en", "Charles Robert Darwin")]
+        );
+        let properties: Vec<&str> = mi
+            .item
+            .claims()
+            .iter()
+            .map(|c| c.main_snak().property())
+            .collect();
+        assert_eq!(
+            properties,
+            vec!["P213", "P31", "P569", "P570", "P106", "P214", "P244", "P1006"]
+        );
+        assert_eq!(mi.prop_text, vec![ExternalId::new(106, "xyz")]);
+        assert_eq!(mi.linked_items, vec!["Q1035"]);
+        let works: Vec<(&str, usize)> = mi
+            .prop_text_suggestions
+            .iter()
+            .map(|s| (s.ext_id.id(), s.count))
+            .collect();
+        assert_eq!(
+            works,
+            vec![("On the origin of species", 2), ("The descent of man", 1)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_run_organisation() {
+        let record = r#"<responseRecord><ISNIAssigned><ISNIMetadata>
+          <identity><organisation>
+            <organisationType>Music Group/Band</organisationType>
+            <organisationName><mainName>Beatles</mainName><dates>1960-1970</dates></organisationName>
+            <organisationNameVariant><mainName>The Beatles</mainName></organisationNameVariant>
+          </organisation></identity>
+        </ISNIMetadata></ISNIAssigned></responseRecord>"#;
+        let mut isni = ISNI::unfetched("0000000121707484");
+        isni.parse(record).unwrap();
+        assert_eq!(isni.kind, IsniKind::Organisation);
+        assert_eq!(isni.instance_of(), "Q215380");
+        let mi = isni.run().await.unwrap();
+        let properties: Vec<&str> = mi
+            .item
+            .claims()
+            .iter()
+            .map(|c| c.main_snak().property())
+            .collect();
+        assert_eq!(properties, vec!["P213", "P31", "P571", "P576"]);
+        assert_eq!(*mi.item.labels(), vec![LocaleString::new("en", "Beatles")]);
+    }
+
+    #[test]
+    fn test_person_name() {
+        let isni = ISNI::unfetched(TEST_ID);
+        let name = |xml: &str| {
+            let conf = quickxml_to_serde::Config::new_with_defaults();
+            let json = xml_string_to_json(xml.to_string(), &conf).unwrap();
+            isni.person_name(&json["personalName"])
+        };
+        assert_eq!(
+            name("<personalName><surname>Darwin,</surname><forename>Charles</forename></personalName>"),
+            Some("Charles Darwin".to_string())
+        );
+        assert_eq!(
+            name("<personalName><surname>Darwin, Charles</surname></personalName>"),
+            Some("Charles Darwin".to_string())
+        );
+        assert_eq!(
+            name("<personalName><forename>Charles</forename></personalName>"),
+            None
+        );
+    }
+}
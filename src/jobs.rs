@@ -0,0 +1,426 @@
+use crate::app_error::AppError;
+use crate::combinator::Combinator;
+use crate::merge_diff_ext::MergeDiffExt;
+use crate::utility::Utility;
+use anyhow::{anyhow, Result};
+use chrono::prelude::*;
+use regex::Regex;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{Mutex, Semaphore};
+use wikimisc::merge_diff::MergeDiff;
+
+/// Maximum number of finished jobs to keep for status queries
+const MAX_FINISHED_JOBS: usize = 10000;
+/// Maximum number of jobs that run at the same time
+const MAX_RUNNING_JOBS: usize = 4;
+/// Maximum number of unfinished jobs; further submissions are rejected until some have finished
+const MAX_PENDING_JOBS: usize = 100;
+const WEBHOOK_TIMEOUT_SECS: u64 = 30;
+
+lazy_static! {
+    static ref RE_ITEM: Regex = Regex::new(r"^Q[1-9]\d*$").expect("Regexp error");
+    /// Callbacks do not follow redirects, so a public URL can not redirect to an internal address
+    static ref CALLBACK_CLIENT: reqwest::Client = reqwest::ClientBuilder::new()
+        .timeout(Duration::from_secs(WEBHOOK_TIMEOUT_SECS))
+        .redirect(reqwest::redirect::Policy::none())
+        .user_agent(Utility::user_agent())
+        .build()
+        .expect("Could not build HTTP client");
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Done,
+    Failed,
+}
+
+impl JobStatus {
+    pub fn is_finished(&self) -> bool {
+        matches!(self, Self::Done | Self::Failed)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Job {
+    pub id: usize,
+    pub item: String,
+    pub status: JobStatus,
+    pub callback: Option<String>,
+    pub created: String,
+    pub finished: Option<String>,
+    pub error: Option<String>,
+    pub result: Option<Value>,
+}
+
+impl Job {
+    fn new(id: usize, item: &str, callback: Option<String>) -> Self {
+        Self {
+            id,
+            item: item.to_string(),
+            status: JobStatus::Queued,
+            callback,
+            created: Utc::now().to_rfc3339(),
+            finished: None,
+            error: None,
+            result: None,
+        }
+    }
+
+    /// The payload that is POSTed to the callback URL
+    pub fn summary(&self) -> Value {
+        let count = |key: &str| match &self.result {
+            Some(result) => result[key].as_u64().unwrap_or(0),
+            None => 0,
+        };
+        json!({
+            "job": self.id,
+            "item": self.item,
+            "status": self.status,
+            "error": self.error,
+            "added_statements": count("added_statements"),
+            "altered_statements": count("altered_statements"),
+            "diff": self.result.as_ref().map(|r| r["diff"].to_owned()),
+        })
+    }
+}
+
+/// In-memory queue for background `extend` jobs
+#[derive(Debug, Clone)]
+pub struct JobQueue {
+    jobs: Arc<Mutex<HashMap<usize, Job>>>,
+    next_id: Arc<AtomicUsize>,
+    running: Arc<Semaphore>,
+    max_pending: usize,
+}
+
+impl Default for JobQueue {
+    fn default() -> Self {
+        Self::with_limits(MAX_RUNNING_JOBS, MAX_PENDING_JOBS)
+    }
+}
+
+impl JobQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn with_limits(max_running: usize, max_pending: usize) -> Self {
+        Self {
+            jobs: Arc::new(Mutex::new(HashMap::new())),
+            next_id: Arc::new(AtomicUsize::new(0)),
+            running: Arc::new(Semaphore::new(max_running.max(1))),
+            max_pending,
+        }
+    }
+
+    /// Creates a new job for `item` and runs it in the background, once a slot is free.
+    /// If `callback` is given, the job summary will be POSTed there once the job has finished.
+    pub async fn submit(&self, item: &str, callback: Option<String>) -> Result<Job, AppError> {
+        if !RE_ITEM.is_match(item) {
+            return Err(AppError::BadRequest(format!("Not an item: '{item}'")));
+        }
+        if let Some(url) = &callback {
+            Self::check_callback_url(url)
+                .await
+                .map_err(|e| AppError::BadRequest(e.to_string()))?;
+        }
+        let job = {
+            let mut jobs = self.jobs.lock().await;
+            let pending = jobs
+                .values()
+                .filter(|job| !job.status.is_finished())
+                .count();
+            if pending >= self.max_pending {
+                return Err(AppError::Overloaded(
+                    "Too many jobs, please try again later".to_string(),
+                ));
+            }
+            let id = self.next_id.fetch_add(1, Ordering::SeqCst) + 1;
+            let job = Job::new(id, item, callback);
+            jobs.insert(id, job.clone());
+            job
+        };
+        self.prune().await;
+
+        let queue = self.clone();
+        let id = job.id;
+        tokio::spawn(async move {
+            let _permit = match queue.running.clone().acquire_owned().await {
+                Ok(permit) => permit,
+                Err(_) => return,
+            };
+            queue.run(id).await;
+        });
+        Ok(job)
+    }
+
+    pub async fn get(&self, id: usize) -> Option<Job> {
+        self.jobs.lock().await.get(&id).cloned()
+    }
+
+    /// Returns all jobs that have not finished yet, oldest first
+    pub async fn in_flight(&self) -> Vec<Job> {
+        let mut ret: Vec<Job> = self
+            .jobs
+            .lock()
+            .await
+            .values()
+            .filter(|job| !job.status.is_finished())
+            .cloned()
+            .collect();
+        ret.sort_by_key(|job| job.id);
+        ret
+    }
+
+    async fn run(&self, id: usize) {
+        let item = match self.set_status(id, JobStatus::Running).await {
+            Some(job) => job.item,
+            None => return,
+        };
+        let result = Combinator::extend_item(&item).await;
+        let job = {
+            let mut jobs = self.jobs.lock().await;
+            let job = match jobs.get_mut(&id) {
+                Some(job) => job,
+                None => return,
+            };
+            match result {
+                Ok(diff) => {
+                    job.status = JobStatus::Done;
+                    job.result = Some(Self::result(&diff, diff.to_target_json()));
+                }
+                Err(e) => {
+                    job.status = JobStatus::Failed;
+                    job.error = Some(e.to_string());
+                }
+            }
+            job.finished = Some(Utc::now().to_rfc3339());
+            job.to_owned()
+        };
+        if let Some(url) = &job.callback {
+            if let Err(e) = Self::notify(url, &job).await {
+                tracing::warn!("Webhook for job {} to {url} failed: {e}", job.id);
+            }
+        }
+    }
+
+    /// The result of a finished job; `target_diff` is the diff with the IDs of the target, as `/extend` returns it
+    fn result(diff: &MergeDiff, target_diff: Value) -> Value {
+        json!({
+            "added_statements": diff.added_statements.len(),
+            "altered_statements": diff.altered_statements.len(),
+            "diff": target_diff,
+        })
+    }
+
+    async fn set_status(&self, id: usize, status: JobStatus) -> Option<Job> {
+        let mut jobs = self.jobs.lock().await;
+        let job = jobs.get_mut(&id)?;
+        job.status = status;
+        Some(job.to_owned())
+    }
+
+    async fn notify(url: &str, job: &Job) -> Result<()> {
+        // Checked again, as the host may resolve differently by now
+        Self::check_callback_url(url).await?;
+        CALLBACK_CLIENT
+            .post(url)
+            .json(&job.summary())
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+
+    /// Callbacks must be HTTP(S), to a host that only resolves to public addresses
+    async fn check_callback_url(url: &str) -> Result<()> {
+        let parsed = reqwest::Url::parse(url).map_err(|e| anyhow!("Bad callback URL: {e}"))?;
+        match parsed.scheme() {
+            "http" | "https" => {}
+            scheme => return Err(anyhow!("Unsupported callback URL scheme: '{scheme}'")),
+        }
+        let host = parsed
+            .host_str()
+            .ok_or_else(|| anyhow!("Callback URL has no host"))?;
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        let port = parsed.port_or_known_default().unwrap_or(80);
+        let addresses: Vec<IpAddr> = tokio::net::lookup_host((host, port))
+            .await
+            .map_err(|e| anyhow!("Can not resolve callback host '{host}': {e}"))?
+            .map(|address| address.ip())
+            .collect();
+        if addresses.is_empty() {
+            return Err(anyhow!("Can not resolve callback host '{host}'"));
+        }
+        match addresses.into_iter().find(|ip| !Self::is_public(ip)) {
+            Some(ip) => Err(anyhow!("Callback host '{host}' is not public ({ip})")),
+            None => Ok(()),
+        }
+    }
+
+    fn is_public(ip: &IpAddr) -> bool {
+        match ip {
+            IpAddr::V4(ip) => Self::is_public_v4(ip),
+            IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+                Some(ip) => Self::is_public_v4(&ip),
+                None => Self::is_public_v6(ip),
+            },
+        }
+    }
+
+    fn is_public_v4(ip: &Ipv4Addr) -> bool {
+        let [a, b, ..] = ip.octets();
+        !(ip.is_private()
+            || ip.is_loopback()
+            || ip.is_link_local()
+            || ip.is_unspecified()
+            || ip.is_broadcast()
+            || ip.is_multicast()
+            || a == 0
+            || (a == 100 && (b & 0xc0) == 64) // Carrier-grade NAT, 100.64.0.0/10
+            || a >= 240)
+    }
+
+    fn is_public_v6(ip: &Ipv6Addr) -> bool {
+        let first = ip.segments()[0];
+        !(ip.is_loopback()
+            || ip.is_unspecified()
+            || ip.is_multicast()
+            || (first & 0xfe00) == 0xfc00 // Unique local, fc00::/7
+            || (first & 0xffc0) == 0xfe80) // Link-local, fe80::/10
+    }
+
+    /// Removes the oldest finished jobs once there are too many
+    async fn prune(&self) {
+        let mut jobs = self.jobs.lock().await;
+        let mut finished: Vec<usize> = jobs
+            .values()
+            .filter(|job| job.status.is_finished())
+            .map(|job| job.id)
+            .collect();
+        if finished.len() <= MAX_FINISHED_JOBS {
+            return;
+        }
+        finished.sort();
+        let remove = finished.len() - MAX_FINISHED_JOBS;
+        for id in finished.into_iter().take(remove) {
+            jobs.remove(&id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::meta_item::MetaItem;
+    use crate::target::{Direction, Target};
+    use wikimisc::wikibase::{EntityTrait, ItemEntity, Snak, Statement};
+
+    #[test]
+    fn test_result_mapped_target() {
+        let mut base = MetaItem::new();
+        base.item = ItemEntity::new_from_json(&json!({
+            "type": "item", "id": "Q1035", "labels": {}, "descriptions": {}, "aliases": {}, "sitelinks": {}, "claims": {}
+        }))
+        .unwrap();
+        let mut record = MetaItem::new();
+        record.item.add_claim(Statement::new_normal(
+            Snak::new_item("P31", "Q5"),
+            vec![],
+            vec![],
+        ));
+        record.item.add_claim(Statement::new_normal(
+            Snak::new_external_id("P214", "27063124"),
+            vec![],
+            vec![],
+        ));
+        let diff = base.merge(&record);
+        let target =
+            Config::from_toml("[target]\nproperties = { P31 = \"P1\" }\nitems = { Q5 = \"Q3\" }")
+                .unwrap()
+                .target;
+        let target_diff = Target::map_json_for(&target, &json!(diff), Direction::ToTarget);
+        let result = JobQueue::result(&diff, target_diff);
+        assert_eq!(result["added_statements"], 2);
+        let diff_json = result["diff"].to_string();
+        assert!(diff_json.contains(r#""property":"P1""#));
+        assert!(diff_json.contains(r#""id":"Q3""#));
+        assert!(!diff_json.contains("P31"));
+        // Not on the target
+        assert!(!diff_json.contains("P214"));
+    }
+
+    #[tokio::test]
+    async fn test_check_callback_url() {
+        assert!(JobQueue::check_callback_url("https://93.184.215.14/hook")
+            .await
+            .is_ok());
+        assert!(JobQueue::check_callback_url("ftp://example.org/hook")
+            .await
+            .is_err());
+        assert!(JobQueue::check_callback_url("not a url").await.is_err());
+        for url in [
+            "http://localhost:8000/hook",
+            "http://127.0.0.1/hook",
+            "http://169.254.169.254/latest/meta-data",
+            "http://10.1.2.3/hook",
+            "http://192.168.0.1/hook",
+            "http://[::1]/hook",
+            "http://[fd00::1]/hook",
+            "http://[::ffff:127.0.0.1]/hook",
+        ] {
+            assert!(JobQueue::check_callback_url(url).await.is_err(), "{url}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_submit_bad_item() {
+        let queue = JobQueue::new();
+        assert_eq!(
+            queue.submit("Q42; DROP", None).await.unwrap_err(),
+            AppError::BadRequest("Not an item: 'Q42; DROP'".to_string())
+        );
+        assert!(queue.submit("P31", None).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_submit_overloaded() {
+        let queue = JobQueue::with_limits(1, 0);
+        assert!(matches!(
+            queue.submit("Q42", None).await,
+            Err(AppError::Overloaded(_))
+        ));
+    }
+
+    #[test]
+    fn test_summary() {
+        let mut job = Job::new(7, "Q42", None);
+        job.status = JobStatus::Done;
+        job.result = Some(json!({"added_statements":3,"altered_statements":1,"diff":{}}));
+        let summary = job.summary();
+        assert_eq!(summary["job"], json!(7));
+        assert_eq!(summary["status"], json!("done"));
+        assert_eq!(summary["added_statements"], json!(3));
+        assert_eq!(summary["altered_statements"], json!(1));
+    }
+
+    #[tokio::test]
+    async fn test_submit_bad_callback() {
+        let queue = JobQueue::new();
+        assert!(queue
+            .submit("Q42", Some("gopher://example.org".to_string()))
+            .await
+            .is_err());
+        assert!(queue.in_flight().await.is_empty());
+    }
+}
@@ -1,20 +1,52 @@
+use crate::external_id::{ExternalId, IdValidity};
 use crate::external_importer::*;
+use crate::fuzzy_date::FuzzyDate;
 use crate::meta_item::*;
+use crate::utility::Utility;
+use crate::wikidata_lookup::{lookup, LookupCandidate, LookupQuery};
 use anyhow::Result;
 use axum::async_trait;
+use regex::Regex;
 use sophia::api::prelude::*;
 use sophia::inmem::graph::FastGraph;
-use sophia::xml;
+use std::collections::HashMap;
+
+const MADS_BIRTH_DATE: &str = "http://www.loc.gov/mads/rdf/v1#birthDate";
+const MADS_DEATH_DATE: &str = "http://www.loc.gov/mads/rdf/v1#deathDate";
+const MADS_OCCUPATION: &str = "http://www.loc.gov/mads/rdf/v1#occupation";
+const MADS_FIELD_OF_ACTIVITY: &str = "http://www.loc.gov/mads/rdf/v1#fieldOfActivity";
+const MADS_BIRTH_PLACE: &str = "http://www.loc.gov/mads/rdf/v1#birthPlace";
+const MADS_ASSOCIATED_LOCALE: &str = "http://www.loc.gov/mads/rdf/v1#associatedLocale";
+const MADS_AUTHORITATIVE_LABEL: &str = "http://www.loc.gov/mads/rdf/v1#authoritativeLabel";
+const RDFS_LABEL: &str = "http://www.w3.org/2000/01/rdf-schema#label";
+const MADS_DEPRECATED_AUTHORITY: &str = "http://www.loc.gov/mads/rdf/v1#DeprecatedAuthority";
+const MADS_USE_INSTEAD: &str = "http://www.loc.gov/mads/rdf/v1#useInstead";
+const RDF_TYPE: &str = "http://www.w3.org/1999/02/22-rdf-syntax-ns#type";
+
+/// P31 of places looked up by name: city, big city, human settlement
+const PLACE_TYPES: &[&str] = &["Q515", "Q1549591", "Q486972"];
+/// P31 of place qualifiers that restrict the lookup to a country (P17): country, sovereign state
+const COUNTRY_TYPES: &[&str] = &["Q6256", "Q3624078"];
 
 pub struct LOC {
     id: String,
     graph: FastGraph,
 }
 
-const HTTP_USER_AGENT : &str = "Mozilla/5.0 (iPad; U; CPU OS 3_2_1 like Mac OS X; en-us) AppleWebKit/531.21.10 (KHTML, like Gecko) Mobile/7B405";
-
-unsafe impl Send for LOC {}
-unsafe impl Sync for LOC {}
+lazy_static! {
+    static ref RE_LOC_NAME_URI: Regex =
+        Regex::new(r"^https?://id\.loc\.gov/authorities/names/([a-z]+\d+)$").expect("Regexp error");
+    static ref RE_LOC_HEADING_URI: Regex = Regex::new(
+        r"^https?://id\.loc\.gov/authorities/(?:subjects|names|genreForms)/([a-z]+\d+)$"
+    )
+    .expect("Regexp error");
+    static ref RE_PLACE_QUALIFIER: Regex =
+        Regex::new(r"^\s*(.+?)\s*\(\s*(.*?)\s*\)\s*$").expect("Regexp error");
+    static ref RE_COMPACT_DATE: Regex =
+        Regex::new(r"^(\d{4})(\d{2})(\d{2})$").expect("Regexp error");
+    static ref RE_LABEL_DATES: Regex =
+        Regex::new(r"^(?:born\s+|died\s+|-)?(?:approximately\s+)?\d").expect("Regexp error");
+}
 
 #[async_trait]
 impl ExternalImporter for LOC {
@@ -41,9 +73,27 @@ impl ExternalImporter for LOC {
         self.transform_label_last_first_name(s)
     }
 
+    async fn fetch(&mut self) -> Result<()> {
+        self.parse(&Self::fetch_rdf(&self.id).await?)?;
+        // Deprecated records name their successor; import that one instead
+        if let IdValidity::Replaced(current) = self.record_validity()? {
+            self.id = current.id().to_string();
+            self.parse(&Self::fetch_rdf(&self.id).await?)?;
+        }
+        Ok(())
+    }
+
+    fn parse(&mut self, text: &str) -> Result<()> {
+        self.graph = Utility::parse_rdf_xml(text)?;
+        Ok(())
+    }
+
     async fn run(&self) -> Result<MetaItem> {
         let mut ret = MetaItem::new();
         self.add_the_usual(&mut ret).await?;
+        self.add_dates(&mut ret)?;
+        self.add_occupations(&mut ret).await?;
+        self.add_places(&mut ret).await?;
         self.try_rescue_prop_text(&mut ret).await?;
         ret.cleanup();
         Ok(ret)
@@ -51,25 +101,271 @@ impl ExternalImporter for LOC {
 }
 
 impl LOC {
+    pub fn unfetched(id: &str) -> Self {
+        Self {
+            id: id.to_string(),
+            graph: FastGraph::new(),
+        }
+    }
+
     pub async fn new(id: &str) -> Result<Self> {
+        let mut ret = Self::unfetched(id);
+        ret.fetch().await?;
+        Ok(ret)
+    }
+
+    pub async fn fetch_rdf(id: &str) -> Result<String> {
         let rdf_url = format!("https://id.loc.gov/authorities/names/{id}.rdf");
-        let client = reqwest::ClientBuilder::new()
-            .redirect(reqwest::redirect::Policy::limited(10))
-            .user_agent(HTTP_USER_AGENT)
-            .build()?;
-        let resp = client.get(&rdf_url).send().await?.text().await?;
-        let mut graph: FastGraph = FastGraph::new();
-        let _ = xml::parser::parse_str(&resp).add_to_graph(&mut graph)?;
-        Ok(Self {
-            id: id.to_string(),
-            graph,
-        })
+        Utility::get_url(&rdf_url).await
+    }
+
+    /// Occupations (P106) and fields of activity (P101); anything that can not be resolved is kept as text
+    async fn add_occupations(&self, ret: &mut MetaItem) -> Result<()> {
+        let fields = [(MADS_OCCUPATION, 106), (MADS_FIELD_OF_ACTIVITY, 101)];
+        for (property, item, label) in self.linked_headings(&fields).await? {
+            let _ = match (item, label) {
+                (Some(item), _) => ret.add_claim(self.new_statement_item(property, &item)),
+                (None, Some(label)) => ret.add_prop_text(ExternalId::new(property, &label)),
+                (None, None) => None,
+            };
+        }
+        Ok(())
+    }
+
+    /// Place of birth (P19) and associated places (P937), where they resolve to exactly one item;
+    /// others are kept as text
+    async fn add_places(&self, ret: &mut MetaItem) -> Result<()> {
+        let fields = [(MADS_BIRTH_PLACE, 19), (MADS_ASSOCIATED_LOCALE, 937)];
+        for (property, item, label) in self.linked_headings(&fields).await? {
+            let item = match (item, &label) {
+                (Some(item), _) => Some(item),
+                (None, Some(label)) => Self::lookup_place(label).await,
+                (None, None) => None,
+            };
+            let _ = match (item, label) {
+                (Some(item), _) => ret.add_claim(self.new_statement_item(property, &item)),
+                (None, Some(label)) => ret.add_prop_text(ExternalId::new(property, &label)),
+                (None, None) => None,
+            };
+        }
+        Ok(())
+    }
+
+    /// The values of MADS fields as (property, item, label); linked headings are resolved via their P244
+    async fn linked_headings(
+        &self,
+        fields: &[(&str, usize)],
+    ) -> Result<Vec<(usize, Option<String>, Option<String>)>> {
+        let mut components = vec![];
+        for (predicate, property) in fields {
+            for (iri, label) in self.mads_components(predicate)? {
+                let ext_id = iri
+                    .as_deref()
+                    .and_then(|iri| RE_LOC_HEADING_URI.captures(iri))
+                    .map(|caps| ExternalId::new(244, &caps[1]));
+                components.push((*property, ext_id, label.or(iri)));
+            }
+        }
+        let ext_ids: Vec<ExternalId> = components.iter().filter_map(|c| c.1.to_owned()).collect();
+        let items = ExternalId::get_item_for_external_id_values(&ext_ids).await;
+        Ok(components
+            .into_iter()
+            .map(|(property, ext_id, label)| {
+                let item = ext_id.and_then(|ext_id| items.get(&ext_id).cloned());
+                (property, item, label)
+            })
+            .collect())
+    }
+
+    /// Looks up a place by its name; only an unambiguous match is used.
+    /// If LoC adds a qualifier, like "(France)", it must be a country, and the place must be in it.
+    async fn lookup_place(label: &str) -> Option<String> {
+        let (name, qualifier) = Self::place_name(label);
+        let country = match qualifier {
+            Some(qualifier) => Some(Self::find_unique(COUNTRY_TYPES, qualifier, None).await?),
+            None => None,
+        };
+        Self::find_unique(PLACE_TYPES, name, country.as_deref()).await
+    }
+
+    /// The one item with one of the P31 `types` and `name` as a label or alias, optionally in `country` (P17)
+    async fn find_unique(types: &[&str], name: &str, country: Option<&str>) -> Option<String> {
+        let mut candidates: Vec<LookupCandidate> = vec![];
+        for p31 in types {
+            let mut query = LookupQuery::new(31, p31).with_text(name);
+            if let Some(country) = country {
+                query = query.with_statement(17, country);
+            }
+            match lookup().find_candidates(&query).await {
+                Ok(found) => candidates.extend(found),
+                Err(e) => {
+                    tracing::warn!("Lookup for '{name}' failed: {e}");
+                    return None;
+                }
+            }
+        }
+        candidates.sort_by(|a, b| a.item.cmp(&b.item));
+        candidates.dedup_by(|a, b| a.item == b.item);
+        LookupCandidate::pick(&candidates, name).map(|candidate| candidate.item.to_owned())
+    }
+
+    /// Splits a label like "Shrewsbury (England)" into name and qualifier
+    fn place_name(label: &str) -> (&str, Option<&str>) {
+        match RE_PLACE_QUALIFIER.captures(label) {
+            Some(caps) => match (caps.get(1), caps.get(2)) {
+                (Some(name), qualifier) => (name.as_str(), qualifier.map(|q| q.as_str())),
+                _ => (label.trim(), None),
+            },
+            None => (label.trim(), None),
+        }
+    }
+
+    /// Birth and death dates from the MADS fields or, where those have none, from the authoritative label.
+    /// Records often give the year as well as the full date; only the most precise is used.
+    fn add_dates(&self, ret: &mut MetaItem) -> Result<()> {
+        let (label_birth, label_death) = self
+            .triples_literals(MADS_AUTHORITATIVE_LABEL)?
+            .iter()
+            .map(|label| Self::label_dates(label))
+            .find(|(birth, death)| birth.is_some() || death.is_some())
+            .unwrap_or_default();
+        let fields = [
+            (MADS_BIRTH_DATE, 569, label_birth),
+            (MADS_DEATH_DATE, 570, label_death),
+        ];
+        for (predicate, property, from_label) in fields {
+            let mut dates = vec![];
+            let values = self.mads_components(predicate)?.into_iter();
+            for s in values.filter_map(|(_, label)| label) {
+                match Self::parse_mads_date(&s) {
+                    Some(date) => dates.push(date),
+                    None => {
+                        let _ = ret.add_prop_text(ExternalId::new(property, &s));
+                    }
+                }
+            }
+            if dates.is_empty() {
+                dates.extend(from_label);
+            }
+            let best_precision = dates.iter().map(|date| date.precision()).max();
+            for date in dates {
+                if Some(date.precision()) == best_precision {
+                    let _ = ret.add_claim(self.new_statement_fuzzy_date(property, &date));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The values of a MADS field, like an occupation, of any subject, as (IRI, label).
+    /// LoC gives them as literals, as blank nodes with a label, or as links to other headings,
+    /// which may or may not be described in the record.
+    fn mads_components(&self, predicate: &str) -> Result<Vec<(Option<String>, Option<String>)>> {
+        let iri_p = Iri::new(predicate)?;
+        let mut literals = vec![];
+        let mut nodes = vec![];
+        self.graph
+            .triples_matching(Any, [&iri_p], Any)
+            .for_each_triple(|t| match t.o().lexical_form() {
+                Some(literal) => literals.push(literal.to_string()),
+                None => nodes.extend(Self::node_key(t.o())),
+            })?;
+        let mut labels = HashMap::new();
+        for label_predicate in [MADS_AUTHORITATIVE_LABEL, RDFS_LABEL] {
+            let iri_label = Iri::new(label_predicate)?;
+            self.graph
+                .triples_matching(Any, [&iri_label], Any)
+                .for_each_triple(|t| {
+                    if let (Some(key), Some(literal)) =
+                        (Self::node_key(t.s()), t.o().lexical_form())
+                    {
+                        if nodes.contains(&key) {
+                            labels.entry(key).or_insert_with(|| literal.to_string());
+                        }
+                    }
+                })?;
+        }
+        let mut ret: Vec<_> = literals.into_iter().map(|l| (None, Some(l))).collect();
+        for key in nodes {
+            let label = labels.get(&key).cloned();
+            let iri = (!key.starts_with("_:")).then_some(key);
+            ret.push((iri, label));
+        }
+        ret.sort();
+        ret.dedup();
+        Ok(ret)
+    }
+
+    fn node_key<T: Term>(term: T) -> Option<String> {
+        match (term.bnode_id(), term.iri()) {
+            (Some(bnode), _) => Some(format!("_:{}", bnode.as_str())),
+            (None, Some(iri)) => Some(iri.as_str().to_string()),
+            _ => None,
+        }
+    }
+
+    /// Parses a MADS date, which may also be compact, like "18090212", or EDTF, like "1500~"
+    fn parse_mads_date(s: &str) -> Option<FuzzyDate> {
+        let s = RE_COMPACT_DATE.replace(s.trim(), "$1-$2-$3");
+        FuzzyDate::parse(&s)
+    }
+
+    /// The (birth, death) dates at the end of an authoritative label,
+    /// like "Darwin, Charles, 1809-1882", "Smith, John, approximately 1500-1550", or "Doe, Jane, born 1950"
+    fn label_dates(label: &str) -> (Option<FuzzyDate>, Option<FuzzyDate>) {
+        let part = label
+            .rsplit(',')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .trim_end_matches('.');
+        if !RE_LABEL_DATES.is_match(part) {
+            return (None, None);
+        }
+        if let Some(s) = part.strip_prefix("born ") {
+            return (FuzzyDate::parse(s), None);
+        }
+        if let Some(s) = part.strip_prefix("died ") {
+            return (None, FuzzyDate::parse(s));
+        }
+        match part.split_once('-') {
+            Some((birth, death)) => (FuzzyDate::parse(birth), FuzzyDate::parse(death)),
+            None => (None, None),
+        }
+    }
+
+    /// Checks the MADS/RDF record of `id` for `DeprecatedAuthority` and a `useInstead` replacement
+    pub fn validity(id: &str, rdf: &str) -> Result<IdValidity> {
+        let mut loc = Self::unfetched(id);
+        loc.parse(rdf)?;
+        loc.record_validity()
+    }
+
+    /// Only statements about the record itself count; other headings in the graph, like related names,
+    /// may be deprecated on their own
+    fn record_validity(&self) -> Result<IdValidity> {
+        let subject = self.get_id_url();
+        let replacement = self
+            .triples_subject_iris(&subject, MADS_USE_INSTEAD)?
+            .iter()
+            .filter_map(|url| RE_LOC_NAME_URI.captures(url))
+            .map(|caps| ExternalId::new(244, &caps[1]))
+            .find(|ext_id| ext_id.id() != self.id);
+        if let Some(ext_id) = replacement {
+            return Ok(IdValidity::Replaced(ext_id));
+        }
+        let types = self.triples_subject_iris(&subject, RDF_TYPE)?;
+        if types.iter().any(|t| t == MADS_DEPRECATED_AUTHORITY) {
+            return Ok(IdValidity::Invalid);
+        }
+        Ok(IdValidity::Valid)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use wikimisc::wikibase::Value;
 
     const TEST_ID: &str = "n78095637";
 
@@ -77,4 +373,132 @@ mod tests {
     async fn test_new() {
         assert!(LOC::new(TEST_ID).await.is_ok());
     }
+
+    fn mads(body: &str) -> String {
+        format!(
+            r#"<?xml version="1.0"?>
+<rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#" xmlns:madsrdf="http://www.loc.gov/mads/rdf/v1#">
+{body}
+</rdf:RDF>"#
+        )
+    }
+
+    #[test]
+    fn test_validity() {
+        // A related heading that is deprecated, and mentions "useInstead" in a note
+        let rdf = mads(
+            r#"<madsrdf:PersonalName rdf:about="http://id.loc.gov/authorities/names/n78095637">
+  <madsrdf:editorialNote>Not a DeprecatedAuthority; see useInstead of n79000002</madsrdf:editorialNote>
+</madsrdf:PersonalName>
+<madsrdf:DeprecatedAuthority rdf:about="http://id.loc.gov/authorities/names/n79000002">
+  <madsrdf:useInstead rdf:resource="http://id.loc.gov/authorities/names/n79000003"/>
+</madsrdf:DeprecatedAuthority>"#,
+        );
+        assert_eq!(LOC::validity("n78095637", &rdf).unwrap(), IdValidity::Valid);
+        let rdf = mads(
+            r#"<madsrdf:DeprecatedAuthority rdf:about="http://id.loc.gov/authorities/names/n79000001">
+  <madsrdf:useInstead><madsrdf:PersonalName rdf:about="http://id.loc.gov/authorities/names/n78095637"/></madsrdf:useInstead>
+</madsrdf:DeprecatedAuthority>"#,
+        );
+        assert_eq!(
+            LOC::validity("n79000001", &rdf).unwrap(),
+            IdValidity::Replaced(ExternalId::new(244, "n78095637"))
+        );
+        let rdf = mads(
+            r#"<madsrdf:DeprecatedAuthority rdf:about="http://id.loc.gov/authorities/names/n79000001"/>"#,
+        );
+        assert_eq!(
+            LOC::validity("n79000001", &rdf).unwrap(),
+            IdValidity::Invalid
+        );
+    }
+
+    #[test]
+    fn test_label_dates() {
+        let (birth, death) = LOC::label_dates("Darwin, Charles, 1809-1882");
+        assert_eq!(birth.unwrap().precision(), 9);
+        assert!(death.is_some());
+        let (birth, death) = LOC::label_dates("Smith, John, approximately 1500-1550");
+        assert!(birth.unwrap().circa);
+        assert!(!death.unwrap().circa);
+        let (birth, death) = LOC::label_dates("Doe, Jane, born 1950");
+        assert!(birth.is_some() && death.is_none());
+        let (birth, death) = LOC::label_dates("Doe, Jane, 1950-");
+        assert!(birth.is_some() && death.is_none());
+        assert_eq!(LOC::label_dates("Sartre, Jean-Paul"), (None, None));
+        assert_eq!(LOC::label_dates("Homer"), (None, None));
+    }
+
+    #[test]
+    fn test_place_name() {
+        assert_eq!(
+            LOC::place_name("Shrewsbury (England)"),
+            ("Shrewsbury", Some("England"))
+        );
+        assert_eq!(LOC::place_name(" London "), ("London", None));
+    }
+
+    #[test]
+    fn test_mads_components() {
+        let rdf = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#" xmlns:madsrdf="http://www.loc.gov/mads/rdf/v1#">
+  <madsrdf:RWO rdf:about="http://id.loc.gov/rwo/agents/n78095637">
+    <madsrdf:occupation>
+      <madsrdf:Occupation rdf:about="http://id.loc.gov/authorities/subjects/sh85090190">
+        <madsrdf:authoritativeLabel>Naturalists</madsrdf:authoritativeLabel>
+      </madsrdf:Occupation>
+    </madsrdf:occupation>
+    <madsrdf:occupation rdf:resource="http://id.loc.gov/authorities/subjects/sh85053094"/>
+    <madsrdf:occupation>Geologists</madsrdf:occupation>
+  </madsrdf:RWO>
+</rdf:RDF>"#;
+        let mut loc = LOC::unfetched(TEST_ID);
+        loc.parse(rdf).unwrap();
+        assert_eq!(
+            loc.mads_components(MADS_OCCUPATION).unwrap(),
+            vec![
+                (None, Some("Geologists".to_string())),
+                (
+                    Some("http://id.loc.gov/authorities/subjects/sh85053094".to_string()),
+                    None
+                ),
+                (
+                    Some("http://id.loc.gov/authorities/subjects/sh85090190".to_string()),
+                    Some("Naturalists".to_string())
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_add_dates() {
+        let rdf = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#" xmlns:rdfs="http://www.w3.org/2000/01/rdf-schema#" xmlns:madsrdf="http://www.loc.gov/mads/rdf/v1#">
+  <madsrdf:PersonalName rdf:about="http://id.loc.gov/authorities/names/n78095637">
+    <madsrdf:authoritativeLabel>Darwin, Charles, 1809-1882</madsrdf:authoritativeLabel>
+  </madsrdf:PersonalName>
+  <madsrdf:RWO rdf:about="http://id.loc.gov/rwo/agents/n78095637">
+    <madsrdf:birthDate><rdf:Description><rdfs:label>18090212</rdfs:label></rdf:Description></madsrdf:birthDate>
+    <madsrdf:birthDate>1809</madsrdf:birthDate>
+  </madsrdf:RWO>
+</rdf:RDF>"#;
+        let mut loc = LOC::unfetched(TEST_ID);
+        loc.parse(rdf).unwrap();
+        let mut mi = MetaItem::new();
+        loc.add_dates(&mut mi).unwrap();
+        let claims = mi.item.claims();
+        assert_eq!(claims.len(), 2);
+        assert_eq!(claims[0].main_snak().property(), "P569");
+        match claims[0]
+            .main_snak()
+            .data_value()
+            .as_ref()
+            .map(|dv| dv.value())
+        {
+            Some(Value::Time(t)) => assert_eq!(*t.precision(), 11),
+            _ => panic!("Not a time value"),
+        }
+        // The death date is only in the label
+        assert_eq!(claims[1].main_snak().property(), "P570");
+    }
 }
@@ -2,122 +2,450 @@
 extern crate lazy_static;
 extern crate nom_bibtex;
 
+pub mod app_error;
+pub mod batch;
 pub mod bne;
 pub mod bnf;
+pub mod cache;
+pub mod cassette;
+pub mod checkpoint;
+pub mod cli_args;
 pub mod combinator;
+pub mod comparison;
+pub mod config;
+pub mod csv_ingest;
+pub mod disk_cache;
+pub mod edit_log;
+pub mod export;
 pub mod external_id;
 pub mod external_importer;
+pub mod fetch_budget;
+pub mod format_constraint;
+pub mod fuzzy_date;
 pub mod gbif_taxon;
 pub mod gnd;
+pub mod http_cache;
+pub mod i18n;
+pub mod id_format;
 pub mod id_ref;
 pub mod inaturalist;
+pub mod isni;
+pub mod jobs;
 pub mod loc;
+pub mod merge_diff_ext;
 pub mod meta_item;
+pub mod mixnmatch;
 pub mod nb;
 pub mod ncbi_taxonomy;
+pub mod ndl;
 pub mod noraf;
+pub mod occupation;
+pub mod preview;
+pub mod rate_limiter;
+pub mod rdf_dataset;
+pub mod rdf_export;
+pub mod reconcile;
+pub mod rest_patch;
+pub mod search;
 pub mod selibr;
+pub mod singleflight;
 pub mod supported_property;
+pub mod target;
+pub mod templates;
+pub mod ulan;
 pub mod utility;
+pub mod validate;
 pub mod viaf;
+pub mod wikidata_lookup;
 pub mod worldcat;
 
+use app_error::AppError;
+use axum::extract::{Query, Request, State};
+use axum::http::{header, HeaderMap};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
 use axum::Form;
-use axum::{extract::Path, response::Html, routing::get, Json, Router};
+use axum::{
+    extract::Path,
+    response::Html,
+    routing::{delete, get, post},
+    Json, Router,
+};
+use batch::{Batch, BatchInput, BatchOptions};
+use cache::ResponseCache;
+use cli_args::CliArgs;
 use combinator::*;
+use comparison::Comparison;
+use config::Config;
+use csv_ingest::CsvIngest;
+use edit_log::{EditFilter, EditLog};
+use export::{Export, ExportFormat};
 use external_id::*;
 use external_importer::*;
+use fetch_budget::FetchBudget;
+use i18n::I18n;
+use jobs::JobQueue;
+use merge_diff_ext::MergeDiffExt;
 use meta_item::MetaItem;
+use mixnmatch::MixNMatch;
+use preview::Preview;
+use rdf_dataset::{RdfDataset, TRIG_CONTENT_TYPE};
+use rdf_export::RdfFormat;
+use reconcile::Reconciliation;
+use regex::Regex;
+use rest_patch::RestPatch;
+use search::NameSearch;
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
 use std::collections::HashMap;
-use std::fs::File;
-use std::io::{BufRead, BufReader};
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
 use std::{env, fs};
 use supported_property::SUPPORTED_PROPERTIES;
+use templates::Templates;
 use tower_http::cors::{Any, CorsLayer};
 use tower_http::services::ServeDir;
 use tower_http::{compression::CompressionLayer, trace::TraceLayer};
+use tracing::level_filters::LevelFilter;
+use validate::{Validation, Verdict};
 use wikimisc::item_merger::ItemMerger;
-use wikimisc::mediawiki::api::Api;
-use wikimisc::merge_diff::MergeDiff;
 use wikimisc::wikibase::{EntityTrait, ItemEntity, Snak, Statement};
 
-fn wrap_html(html: &str) -> String {
-    let outer: String = fs::read_to_string("./html/wrapper.html").unwrap();
-    outer.replace("$1$", html)
+lazy_static! {
+    static ref RE_QID: Regex = Regex::new(r"^Q\d+$").expect("Regexp error");
 }
 
-async fn root() -> Html<String> {
-    let sources: Vec<String> = SUPPORTED_PROPERTIES.iter().map(|sp| sp.as_li()).collect();
-    let mut html: String = fs::read_to_string("./html/root.html").unwrap();
-    html = html.replace("$1$", &sources.join("\n"));
-    Html(wrap_html(&html))
+/// State shared by all server routes
+#[derive(Clone)]
+struct AppState {
+    jobs: JobQueue,
+    cache: ResponseCache,
+    fetch_budget: FetchBudget,
 }
 
-async fn item(Path((property, id)): Path<(String, String)>) -> Json<serde_json::Value> {
+impl AppState {
+    fn new() -> Self {
+        Self {
+            jobs: JobQueue::new(),
+            cache: ResponseCache::global().clone(),
+            fetch_budget: FetchBudget::global().clone(),
+        }
+    }
+}
+
+/// Rejects requests to routes that fetch upstream data with 503 while the fetch budget is exhausted
+async fn backpressure(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    if state.fetch_budget.is_saturated() {
+        return FetchBudget::overloaded().into_response();
+    }
+    next.run(request).await
+}
+
+/// Turns a template rendering result into a response, showing errors as plain text
+fn render_html(result: anyhow::Result<String>) -> Html<String> {
+    match result {
+        Ok(html) => Html(html),
+        Err(e) => {
+            tracing::error!("Template error: {e}");
+            Html(format!("Template error: {e}"))
+        }
+    }
+}
+
+fn wrap_html(html: &str, i18n: &I18n) -> Html<String> {
+    render_html(Templates::wrap(html, i18n))
+}
+
+/// Determines the interface language from `?uselang=` or the `Accept-Language` header
+fn get_i18n(params: &HashMap<String, String>, headers: &HeaderMap) -> I18n {
+    let accept_language = headers
+        .get(header::ACCEPT_LANGUAGE)
+        .and_then(|v| v.to_str().ok());
+    I18n::from_request(params.get("uselang").map(|s| s.as_str()), accept_language)
+}
+
+async fn root(Query(params): Query<HashMap<String, String>>, headers: HeaderMap) -> Html<String> {
+    let i18n = get_i18n(&params, &headers);
+    let sources: Vec<String> = SUPPORTED_PROPERTIES
+        .iter()
+        .map(|sp| sp.as_li(&i18n))
+        .collect();
+    let ctx = minijinja::context! {sources => sources};
+    render_html(Templates::render("root.html", &i18n, ctx))
+}
+
+/// Parses an external record, using the response cache if possible
+async fn get_meta_item(state: &AppState, property: &str, id: &str) -> Result<MetaItem, AppError> {
+    let prop = ExternalId::prop_numeric(property)
+        .ok_or_else(|| AppError::BadRequest(format!("malformed property: '{property}'")))?;
+    let ext_id = ExternalId::new(prop, id);
+    if let Some(mi) = state.cache.get(&ext_id).await {
+        return Ok(mi);
+    }
     let parser: Box<dyn ExternalImporter + Send + Sync> =
-        match Combinator::get_parser_for_property(&property, &id).await {
-            Ok(parser) => parser,
-            Err(e) => return Json(json!({"status":e.to_string()})),
-        };
-    let mi = match parser.run().await {
-        Ok(mi) => mi,
-        Err(e) => return Json(json!({"status":e.to_string()})),
-    };
+        Combinator::get_parser_for_ext_id(&ext_id).await?;
+    let mi = parser.run().await?;
+    // Redirected IDs are cached under the ID they resolved to
+    let canonical = ExternalId::new(parser.my_property(), &parser.my_id());
+    state.cache.insert(canonical, mi.clone()).await;
+    Ok(mi)
+}
+
+/// JSON, or RDF if the `Accept` header asks for Turtle or N-Triples
+async fn item(
+    State(state): State<AppState>,
+    Path((property, id)): Path<(String, String)>,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    let mi = get_meta_item(&state, &property, &id).await?;
+    let rdf_format = headers
+        .get(header::ACCEPT)
+        .and_then(|v| v.to_str().ok())
+        .and_then(RdfFormat::from_accept);
+    if let Some(format) = rdf_format {
+        let headers = [(header::CONTENT_TYPE, format.content_type())];
+        return Ok((headers, mi.to_rdf(format)).into_response());
+    }
     let mut j = json!(mi)["item"].to_owned();
     j["status"] = json!("OK");
-    Json(j)
+    Ok(Json(j).into_response())
 }
 
-async fn meta_item(Path((property, id)): Path<(String, String)>) -> Json<serde_json::Value> {
-    let parser: Box<dyn ExternalImporter + Send + Sync> =
-        match Combinator::get_parser_for_property(&property, &id).await {
-            Ok(parser) => parser,
-            Err(e) => return Json(json!({"status":e.to_string()})),
-        };
-    let mi = match parser.run().await {
-        Ok(mi) => mi,
-        Err(e) => return Json(json!({"status":e.to_string()})),
-    };
+async fn meta_item(
+    State(state): State<AppState>,
+    Path((property, id)): Path<(String, String)>,
+) -> Result<Json<Value>, AppError> {
+    let mi = get_meta_item(&state, &property, &id).await?;
     let mut j = json!(mi);
     j["status"] = json!("OK");
-    Json(j)
+    Ok(Json(j))
+}
+
+async fn compare(
+    State(state): State<AppState>,
+    Path((property, id, item)): Path<(String, String, String)>,
+) -> Result<Json<Value>, AppError> {
+    let record = get_meta_item(&state, &property, &id).await?;
+    let own_property = format!(
+        "P{}",
+        ExternalId::prop_numeric(&property).unwrap_or_default()
+    );
+    let base_item = MetaItem::from_entity(&item).await?;
+    let comparison = Comparison::new(&base_item, &record, &own_property);
+    let mut j = json!(comparison);
+    j["status"] = json!("OK");
+    j["item"] = json!(item);
+    j["external_id"] = json!(format!("{own_property}:{id}"));
+    j["prop_text"] = json!(record.prop_text);
+    Ok(Json(j))
+}
+
+async fn reconcile(
+    params: &HashMap<String, String>,
+    headers: &HeaderMap,
+) -> Result<Json<Value>, AppError> {
+    match params.get("queries") {
+        Some(queries) => {
+            let queries: Value = serde_json::from_str(queries)
+                .map_err(|e| AppError::BadRequest(format!("Bad queries: {e}")))?;
+            Ok(Json(Reconciliation::reconcile(&queries).await))
+        }
+        None => Ok(Json(Reconciliation::manifest(&base_url(headers)))),
+    }
+}
+
+async fn reconcile_get(
+    headers: HeaderMap,
+    Query(params): Query<HashMap<String, String>>,
+) -> Result<Json<Value>, AppError> {
+    reconcile(&params, &headers).await
+}
+
+async fn reconcile_post(
+    headers: HeaderMap,
+    Form(params): Form<HashMap<String, String>>,
+) -> Result<Json<Value>, AppError> {
+    reconcile(&params, &headers).await
+}
+
+async fn reconcile_preview(
+    State(state): State<AppState>,
+    Path((property, id)): Path<(String, String)>,
+) -> Result<Html<String>, AppError> {
+    let mi = get_meta_item(&state, &property, &id).await?;
+    Ok(Html(Reconciliation::preview_html(&json!(mi)["item"])))
+}
+
+/// The URL this server is reached at, from the `Host` header; `X-Forwarded-Proto` tells if a proxy terminates TLS
+fn base_url(headers: &HeaderMap) -> String {
+    let header = |name: &str| headers.get(name).and_then(|v| v.to_str().ok());
+    let host = header("host").unwrap_or("localhost");
+    let scheme = header("x-forwarded-proto").unwrap_or(if host.starts_with("localhost") {
+        "http"
+    } else {
+        "https"
+    });
+    format!("{scheme}://{host}")
 }
 
-async fn graph(Path((property, id)): Path<(String, String)>) -> String {
+async fn graph(Path((property, id)): Path<(String, String)>) -> Result<String, AppError> {
     let mut parser: Box<dyn ExternalImporter> =
-        match Combinator::get_parser_for_property(&property, &id).await {
-            Ok(parser) => parser,
-            Err(e) => return e.to_string(),
-        };
-    parser.get_graph_text()
+        Combinator::get_parser_for_property(&property, &id).await?;
+    Ok(parser.get_graph_text())
+}
+
+/// The raw graphs of the record and all records found via its external IDs, as TriG
+async fn dataset(Path((property, id)): Path<(String, String)>) -> Result<Response, AppError> {
+    let property = ExternalId::prop_numeric(&property)
+        .ok_or_else(|| AppError::BadRequest(format!("Malformed property: '{property}'")))?;
+    let trig = rdf_dataset(ExternalId::new(property, &id), Combinator::new()).await?;
+    Ok(([(header::CONTENT_TYPE, TRIG_CONTENT_TYPE)], trig).into_response())
+}
+
+async fn rdf_dataset(ext_id: ExternalId, mut combinator: Combinator) -> anyhow::Result<String> {
+    combinator.set_keep_graphs(true);
+    combinator.import(vec![ext_id]).await?;
+    Ok(RdfDataset::new(combinator.graphs().to_vec()).to_trig())
 }
 
-async fn extend(Path(item): Path<String>) -> Json<serde_json::Value> {
-    let mut base_item = match MetaItem::from_entity(&item).await {
-        Ok(base_item) => base_item,
-        Err(e) => return Json(json!({"status":e.to_string()})),
+async fn extend(Path(item): Path<String>) -> Result<Json<Value>, AppError> {
+    let diff = Combinator::extend_item(&item).await?;
+    Ok(Json(diff.to_target_json()))
+}
+
+async fn preview(
+    Path(item): Path<String>,
+    Query(params): Query<HashMap<String, String>>,
+    headers: HeaderMap,
+) -> Html<String> {
+    let i18n = get_i18n(&params, &headers);
+    let diff = match Combinator::extend_item(&item).await {
+        Ok(diff) => diff,
+        Err(e) => {
+            let html = format!(
+                r#"<div class="alert alert-danger">{}</div>"#,
+                utility::Utility::html_escape(&e.to_string())
+            );
+            return wrap_html(&html, &i18n);
+        }
     };
-    let ext_ids: Vec<ExternalId> = base_item
-        .get_external_ids()
-        .iter()
-        .filter(|ext_id| Combinator::has_parser_for_ext_id(ext_id))
-        .cloned()
-        .collect();
-    let mut combinator = Combinator::new();
-    if let Err(e) = combinator.import(ext_ids).await {
-        return Json(json!({"status":e.to_string()}));
+    let mut preview = Preview::new(&item, &diff, i18n.clone());
+    let _ = preview.load_labels().await; // Labels are cosmetic, IDs will be shown instead
+    wrap_html(&preview.as_html(), &i18n)
+}
+
+async fn submit_extend_job(
+    State(state): State<AppState>,
+    Path(item): Path<String>,
+    Query(params): Query<HashMap<String, String>>,
+) -> Result<Json<Value>, AppError> {
+    let callback = params.get("callback").cloned();
+    let job = state.jobs.submit(&item, callback).await?;
+    Ok(Json(json!({"status":"OK","job":job})))
+}
+
+async fn job_status(
+    State(state): State<AppState>,
+    Path(id): Path<usize>,
+) -> Result<Json<Value>, AppError> {
+    match state.jobs.get(id).await {
+        Some(job) => Ok(Json(json!({"status":"OK","job":job}))),
+        None => Err(AppError::NotFound(format!("No such job: {id}"))),
+    }
+}
+
+/// Checks the `Authorization: Bearer` header against `server.admin_token`.
+/// Admin routes are disabled if no token is configured.
+fn check_admin(headers: &HeaderMap) -> Result<(), AppError> {
+    let token = match &Config::get().server.admin_token {
+        Some(token) if !token.is_empty() => token,
+        _ => return Err(AppError::Forbidden("Admin routes are disabled".to_string())),
+    };
+    let given = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+    if given != Some(token.as_str()) {
+        return Err(AppError::Unauthorized(
+            "Invalid or missing admin token".to_string(),
+        ));
     }
-    let mut other = match combinator.combine() {
-        Some(other) => other,
-        None => return Json(json!({"status":"No items to combine"})),
+    Ok(())
+}
+
+async fn admin_cache_stats(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<Value>, AppError> {
+    check_admin(&headers)?;
+    Ok(Json(
+        json!({"status":"OK","cache":state.cache.stats().await}),
+    ))
+}
+
+async fn admin_cache_flush(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<Value>, AppError> {
+    check_admin(&headers)?;
+    let removed = state.cache.flush().await;
+    Ok(Json(json!({"status":"OK","removed":removed})))
+}
+
+async fn admin_cache_invalidate(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path((property, id)): Path<(String, String)>,
+) -> Result<Json<Value>, AppError> {
+    check_admin(&headers)?;
+    let prop = ExternalId::prop_numeric(&property)
+        .ok_or_else(|| AppError::BadRequest(format!("malformed property: '{property}'")))?;
+    let removed = state.cache.invalidate(&ExternalId::new(prop, &id)).await;
+    Ok(Json(json!({"status":"OK","removed":removed})))
+}
+
+/// Recorded edits, newest first, filtered by `item`, `since` (timestamp or date), and `limit` (default 100)
+async fn admin_edits(
+    Query(params): Query<HashMap<String, String>>,
+    headers: HeaderMap,
+) -> Result<Json<Value>, AppError> {
+    check_admin(&headers)?;
+    let log = EditLog::global()
+        .ok_or_else(|| AppError::NotFound("No edit log is configured".to_string()))?;
+    let filter = edit_filter(&params).map_err(AppError::BadRequest)?;
+    let edits = log
+        .edits(&filter)
+        .map_err(|e| AppError::from_error(e.as_ref()))?;
+    let stats = log
+        .stats(filter.since)
+        .map_err(|e| AppError::from_error(e.as_ref()))?;
+    Ok(Json(json!({"status":"OK","edits":edits,"stats":stats})))
+}
+
+fn edit_filter(params: &HashMap<String, String>) -> Result<EditFilter, String> {
+    let since = match params.get("since") {
+        Some(s) => Some(EditFilter::parse_since(s).ok_or(format!("Bad since: '{s}'"))?),
+        None => None,
+    };
+    let limit = match params.get("limit") {
+        Some(s) => s.parse().map_err(|_| format!("Bad limit: '{s}'"))?,
+        None => 100,
     };
-    other.fix_dates();
-    other.fix_images(&base_item);
-    let diff = base_item.merge(&other);
-    Json(json!(diff))
+    Ok(EditFilter {
+        item: params.get("item").cloned(),
+        since,
+        limit: Some(limit),
+    })
+}
+
+async fn admin_jobs(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<Value>, AppError> {
+    check_admin(&headers)?;
+    Ok(Json(
+        json!({"status":"OK","jobs":state.jobs.in_flight().await}),
+    ))
 }
 
 #[derive(Serialize, Deserialize)]
@@ -137,18 +465,21 @@ fn item_from_json_string(s: &str) -> Result<(ItemEntity, bool), String> {
     Ok((item, has_fake_id))
 }
 
-async fn merge(Form(params): Form<MergeForm>) -> Json<serde_json::Value> {
-    let (base_item, base_item_has_fake_id) = match item_from_json_string(&params.base_item) {
-        Ok(item) => item,
-        Err(e) => return Json(json!({"error":e.to_string()})),
-    };
-    let (new_item, _) = match item_from_json_string(&params.new_item) {
-        Ok(item) => item,
-        Err(e) => return Json(json!({"error":e.to_string()})),
-    };
+async fn merge(Form(params): Form<MergeForm>) -> Result<Json<Value>, AppError> {
+    let (base_item, base_item_has_fake_id) =
+        item_from_json_string(&params.base_item).map_err(AppError::BadRequest)?;
+    let (new_item, _) = item_from_json_string(&params.new_item).map_err(AppError::BadRequest)?;
+    Ok(Json(merge_items(
+        base_item,
+        base_item_has_fake_id,
+        &new_item,
+    )))
+}
 
+/// Merges `new_item` into `base_item`, and returns the merged item and the diff
+fn merge_items(base_item: ItemEntity, base_item_has_fake_id: bool, new_item: &ItemEntity) -> Value {
     let mut im = ItemMerger::new(base_item);
-    let diff = im.merge(&new_item);
+    let diff = im.merge(new_item);
 
     let mut j = im.item.to_json();
     if base_item_has_fake_id {
@@ -156,11 +487,14 @@ async fn merge(Form(params): Form<MergeForm>) -> Json<serde_json::Value> {
             jo.remove("id");
         }
     }
-    let j = json!({"item":j,"diff":diff});
-    Json(j)
+    json!({"item":j,"diff":diff})
 }
 
-async fn merge_info() -> Html<String> {
+async fn merge_info(
+    Query(params): Query<HashMap<String, String>>,
+    headers: HeaderMap,
+) -> Html<String> {
+    let i18n = get_i18n(&params, &headers);
     let mut base_item = ItemEntity::new_empty();
     let mut new_item = ItemEntity::new_empty();
     base_item.set_id("Q0".to_string());
@@ -181,46 +515,63 @@ async fn merge_info() -> Html<String> {
     base_item.as_object_mut().unwrap().remove("id");
     new_item.as_object_mut().unwrap().remove("id");
 
-    let mut html: String = fs::read_to_string("./html/merge_info.html").unwrap();
-    html = html.replace("$1$", &serde_json::to_string_pretty(&base_item).unwrap());
-    html = html.replace("$2$", &serde_json::to_string_pretty(&new_item).unwrap());
-    Html(wrap_html(&html))
+    let ctx = minijinja::context! {
+        base_item => serde_json::to_string_pretty(&base_item).unwrap(),
+        new_item => serde_json::to_string_pretty(&new_item).unwrap(),
+    };
+    render_html(Templates::render("merge_info.html", &i18n, ctx))
 }
 
 async fn supported_properties() -> Json<serde_json::Value> {
-    let ret: Vec<String> = Combinator::get_supported_properties()
-        .iter()
-        .map(|prop| format!("P{prop}"))
-        .collect();
+    let ret: Vec<Value> = SUPPORTED_PROPERTIES.iter().map(|sp| sp.as_json()).collect();
     Json(json!(ret))
 }
 
 async fn run_server() -> Result<(), Box<dyn std::error::Error>> {
-    tracing_subscriber::fmt::init();
-
     let cors = CorsLayer::new().allow_origin(Any);
+    let config = &Config::get().server;
 
-    let app = Router::new()
-        .route("/", get(root))
-        .route("/supported_properties", get(supported_properties))
+    let state = AppState::new();
+
+    // Routes that fetch data from upstream sources
+    let upstream = Router::new()
         .route("/item/:prop/:id", get(item))
         .route("/meta_item/:prop/:id", get(meta_item))
         .route("/graph/:prop/:id", get(graph))
+        .route("/dataset/:prop/:id", get(dataset))
+        .route("/compare/:prop/:id/:item", get(compare))
+        .route("/reconcile", get(reconcile_get).post(reconcile_post))
+        .route("/reconcile/preview/:prop/:id", get(reconcile_preview))
         .route("/extend/:item", get(extend))
+        .route("/preview/:item", get(preview))
+        .route("/jobs/extend/:item", post(submit_extend_job))
+        .route_layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            backpressure,
+        ));
+
+    let app = Router::new()
+        .route("/", get(root))
+        .route("/supported_properties", get(supported_properties))
+        .merge(upstream)
         .route("/merge", get(merge_info).post(merge))
-        .nest_service("/images", ServeDir::new("images"))
+        .route("/jobs/:id", get(job_status))
+        .route(
+            "/admin/cache",
+            get(admin_cache_stats).delete(admin_cache_flush),
+        )
+        .route("/admin/cache/:prop/:id", delete(admin_cache_invalidate))
+        .route("/admin/jobs", get(admin_jobs))
+        .route("/admin/edits", get(admin_edits))
+        .nest_service("/images", ServeDir::new(&config.images_dir))
+        .layer(axum::middleware::from_fn(AppError::legacy_errors))
         .layer(TraceLayer::new_for_http())
         .layer(CompressionLayer::new())
-        .layer(cors);
-
-    let port: u16 = match env::var("AC2WD_PORT") {
-        Ok(port) => port.as_str().parse::<u16>().unwrap_or(8000),
-        Err(_) => 8000,
-    };
-
-    let address = [0, 0, 0, 0]; // TODOO env::var("AC2WD_ADDRESS")
+        .layer(cors)
+        .with_state(state);
 
-    let addr = SocketAddr::from((address, port));
+    let address: IpAddr = config.address.parse()?;
+    let addr = SocketAddr::from((address, config.port));
     tracing::debug!("listening on {}", addr);
     let listener = tokio::net::TcpListener::bind(addr)
         .await
@@ -232,89 +583,125 @@ async fn run_server() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
-fn get_extid_from_argv(argv: &[String]) -> Result<ExternalId, Box<dyn std::error::Error>> {
-    let property = argv.get(2).expect("USAGE: combinator PROP ID");
-    let property = ExternalId::prop_numeric(property).expect("malformed property: '{property}'");
-    let id = argv.get(3).expect("USAGE: combinator PROP ID");
-    Ok(ExternalId::new(property, id))
+/// `merge BASE NEW [--format=both|item|diff] [--compact]`
+/// BASE can be a QID or a JSON file, NEW is a JSON file.
+async fn merge_cli(args: &CliArgs) -> Result<String, Box<dyn std::error::Error>> {
+    const USAGE: &str =
+        "USAGE: merge BASE_ITEM_FILE_OR_QID NEW_ITEM_FILE [--format=both|item|diff] [--compact]";
+    let base = args.positional(1).ok_or(USAGE)?;
+    let new = args.positional(2).ok_or(USAGE)?;
+    let (base_item, base_item_has_fake_id) = if RE_QID.is_match(base) {
+        (MetaItem::from_entity(base).await?.item, false)
+    } else {
+        item_from_json_string(&fs::read_to_string(base)?)?
+    };
+    let (new_item, _) = item_from_json_string(&fs::read_to_string(new)?)?;
+    let result = merge_items(base_item, base_item_has_fake_id, &new_item);
+    let output = match args.option("format").unwrap_or("both") {
+        "both" => result,
+        "item" => result["item"].to_owned(),
+        "diff" => result["diff"].to_owned(),
+        other => return Err(format!("Unknown format '{other}'\n{USAGE}").into()),
+    };
+    if args.flag("compact") {
+        Ok(output.to_string())
+    } else {
+        Ok(serde_json::to_string_pretty(&output)?)
+    }
 }
 
-async fn get_extend(item: &str) -> Result<MergeDiff, Box<dyn std::error::Error>> {
-    let mut base_item = MetaItem::from_entity(item).await?;
-    let ext_ids: Vec<ExternalId> = base_item
-        .get_external_ids()
-        .iter()
-        .filter(|ext_id| Combinator::has_parser_for_ext_id(ext_id))
-        .cloned()
-        .collect();
-    let mut combinator = Combinator::new();
-    combinator.import(ext_ids).await?;
-    let mut other = match combinator.combine() {
-        Some(other) => other,
-        None => return Err("No items to combine".into()),
+/// Creates a combinator with the `--sources` and `--exclude` property filters, and the `--max-depth` limit
+fn combinator_from_args(args: &CliArgs) -> Result<Combinator, Box<dyn std::error::Error>> {
+    let properties = |key: &str| -> Result<Vec<usize>, String> {
+        args.option_list(key)
+            .iter()
+            .map(|p| ExternalId::prop_numeric(p).ok_or(format!("malformed property: '{p}'")))
+            .collect()
     };
-    other.fix_dates();
-    other.fix_images(&base_item);
-    Ok(base_item.merge(&other))
-}
-
-async fn apply_diff(
-    item: &str,
-    diff: &MergeDiff,
-    api: &mut Api,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let json_string = json!(diff).to_string();
-    // println!("{item}: {json_string}");
-    if json_string == "{}" {
-        return Ok(());
+    let mut combinator = Combinator::new();
+    if args.option("sources").is_some() {
+        combinator.set_sources(&properties("sources")?);
     }
-    let token = api.get_edit_token().await?;
-    let params: HashMap<String, String> = vec![
-        ("action", "wbeditentity"),
-        ("id", item),
-        ("data", &json_string),
-        ("summary", "AC2WD"),
-        ("token", &token),
-        ("bot", "1"),
-    ]
-    .into_iter()
-    .map(|(k, v)| (k.to_string(), v.to_string()))
-    .collect();
-    let j = api
-        .post_query_api_json(&params)
-        .await
-        .map_err(|e| e.to_string())?;
-    match j["error"].as_object() {
-        Some(o) => {
-            let s = format!("{o:?}");
-            Err(s.into())
-        }
-        None => Ok(()),
+    combinator.set_exclude(&properties("exclude")?);
+    if let Some(max_depth) = args.option("max-depth") {
+        let max_depth = max_depth
+            .parse()
+            .map_err(|_| format!("Bad value for --max-depth: '{max_depth}'"))?;
+        combinator.set_max_depth(Some(max_depth));
     }
+    Ok(combinator)
+}
+
+const USAGE: &str = "USAGE: auth2wd COMMAND [OPTIONS] [--config FILE] [-v|-q]
+Commands:
+  serve [--address ADDRESS] [--port PORT] [--html-dir DIR] [--images-dir DIR]
+  combinator PROP ID
+  parser PROP ID [--from-file FILE]
+  graph PROP ID [--from-file FILE]
+  dataset PROP ID [--sources P,..] [--exclude P,..] [--max-depth N]
+  extend ITEM
+  merge BASE_ITEM NEW_ITEM_FILE
+  list LIST_FILE|-|--sparql QUERY|--mixnmatch CATALOG|--pagepile ID|--petscan PSID
+  mixnmatch CATALOG [--automatic]
+  csv CSV_FILE|- [--format json|qs]
+  validate PROP ID
+  search NAME
+  report [--item ITEM] [--since DATE] [--limit N] [--stats] [--json]";
+
+/// The parser for the ID on the command line; reads the record from `--from-file` if given, instead of downloading it
+async fn parser_from_args(
+    argv: &[String],
+    args: &CliArgs,
+) -> Result<Box<dyn ExternalImporter + Send + Sync>, Box<dyn std::error::Error>> {
+    let ext_id = get_extid_from_argv(argv)?;
+    let parser = match args.option("from-file") {
+        Some(path) => {
+            Combinator::get_parser_for_ext_id_from_text(&ext_id, &fs::read_to_string(path)?)?
+        }
+        None => Combinator::get_parser_for_ext_id(&ext_id).await?,
+    };
+    Ok(parser)
 }
 
-async fn get_wikidata_api(path: &str) -> Result<Api, Box<dyn std::error::Error>> {
-    let file = File::open(path).map_err(|e| format!("{:?}", e))?;
-    let reader = BufReader::new(file);
-    let j: serde_json::Value = serde_json::from_reader(reader).map_err(|e| format!("{:?}", e))?;
-    let oauth2_token = j["oauth2_token"]
-        .as_str()
-        .expect("No oauth2_token in {path}");
-    let mut api = Api::new("https://www.wikidata.org/w/api.php").await?;
-    api.set_oauth2(oauth2_token);
-    Ok(api)
+fn get_extid_from_argv(argv: &[String]) -> Result<ExternalId, Box<dyn std::error::Error>> {
+    let property = argv.get(2).expect("USAGE: combinator PROP ID");
+    let property = ExternalId::prop_numeric(property).expect("malformed property: '{property}'");
+    let id = argv.get(3).expect("USAGE: combinator PROP ID");
+    Ok(ExternalId::new(property, id))
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let argv: Vec<String> = env::args().collect();
+    let mut argv: Vec<String> = env::args().collect();
+    let command = argv.iter().skip(1).find(|arg| !arg.starts_with('-'));
+    let default_level = match command.map(|s| s.as_str()) {
+        Some(
+            "combinator" | "parser" | "graph" | "dataset" | "list" | "extend" | "merge"
+            | "validate" | "search" | "mixnmatch" | "csv" | "report",
+        ) => LevelFilter::WARN,
+        _ => LevelFilter::INFO,
+    };
+    let level = CliArgs::extract_verbosity(&mut argv, default_level);
+    tracing_subscriber::fmt()
+        .with_max_level(level)
+        .with_writer(std::io::stderr)
+        .init();
+    let server_args = match argv.get(1).map(|s| s.as_str()) {
+        Some("serve") => Some(CliArgs::new(&argv[1..], &[])),
+        _ => None,
+    };
+    Config::init(
+        Config::path_from_args(&argv).as_deref(),
+        server_args.as_ref(),
+    )?;
     match argv.get(1).map(|s| s.as_str()) {
         Some("combinator") => {
             // Combinator
+            let args = CliArgs::new(&argv[1..], &[]);
             let mut base_item = MetaItem::from_entity("Q1035").await?;
             //println!("{:?}",&base_item);
             let ext_id = get_extid_from_argv(&argv)?;
-            let mut combinator = Combinator::new();
+            let mut combinator = combinator_from_args(&args)?;
             combinator.import(vec![ext_id]).await?;
             println!(
                 "{} items: {:?}",
@@ -335,52 +722,246 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 diff.altered_statements.len(),
                 diff.added_statements.len()
             );
-            let payload = json!(diff);
+            let payload = diff.to_target_json();
             println!("{}", &serde_json::to_string_pretty(&payload).unwrap());
         }
         Some("parser") => {
             // Single parser
-            let ext_id = get_extid_from_argv(&argv)?;
-            let parser = Combinator::get_parser_for_ext_id(&ext_id).await?;
+            let args = CliArgs::new(&argv[1..], &[]);
+            let parser = parser_from_args(&argv, &args).await?;
             let item = parser.run().await?;
-            println!("{:?}", item);
+            match args.option("format") {
+                Some(format) => {
+                    let format = ExportFormat::from_str(format)?;
+                    println!(
+                        "{}",
+                        Export::from_item(&json!(item)["item"]).render(format)?
+                    );
+                }
+                None => println!("{:?}", item),
+            }
         }
         Some("graph") => {
             // Single graph
-            let ext_id = get_extid_from_argv(&argv)?;
-            let mut parser = Combinator::get_parser_for_ext_id(&ext_id).await?;
+            let args = CliArgs::new(&argv[1..], &[]);
+            let mut parser = parser_from_args(&argv, &args).await?;
             parser.dump_graph();
         }
-        Some("list") => {
-            // List
-            let filename = argv.get(2).expect("USAGE: list LIST_FILE [START_ROW]");
-            let start = match argv.get(3) {
-                Some(s) => s.parse::<usize>().unwrap(),
-                None => 0,
+        Some("dataset") => {
+            // Raw graphs of all sources
+            let args = CliArgs::new(&argv[1..], &[]);
+            let ext_id = get_extid_from_argv(&argv)?;
+            print!(
+                "{}",
+                rdf_dataset(ext_id, combinator_from_args(&args)?).await?
+            );
+        }
+        Some("csv") => {
+            // New items for rows of external IDs, as JSON lines or one QuickStatements batch
+            const CSV_USAGE: &str = "USAGE: csv CSV_FILE|- [--format json|qs]";
+            let args = CliArgs::new(&argv[1..], &[]);
+            let path = args.positional(1).ok_or(CSV_USAGE)?;
+            let rows = match path {
+                "-" => CsvIngest::read_rows(std::io::stdin())?,
+                path => CsvIngest::read_rows(fs::File::open(path)?)?,
             };
-            let file = File::open(filename).unwrap();
-            let reader = BufReader::new(file);
-            let mut api = get_wikidata_api("config.json").await?;
-            for (index, line) in reader.lines().enumerate() {
-                if index >= start {
-                    if let Ok(item) = line {
-                        println!("{index}: {item}");
-                        if let Ok(diff) = get_extend(&item).await {
-                            let _ = apply_diff(&item, &diff, &mut api).await; // Ignore result
-                        }
+            let format = match args.option("format") {
+                Some(format) => ExportFormat::from_str(format)?,
+                None => ExportFormat::Json,
+            };
+            if !matches!(format, ExportFormat::Json | ExportFormat::QuickStatements) {
+                return Err(format!("Only json and qs are supported\n{CSV_USAGE}").into());
+            }
+            for row in rows {
+                let ids: Vec<String> = row.ids.iter().map(|id| id.to_string()).collect();
+                let item = CsvIngest::new_item(&row, combinator_from_args(&args)?).await;
+                match (item, format) {
+                    (Ok(new_item), ExportFormat::QuickStatements)
+                        if !new_item.existing_items.is_empty() =>
+                    {
+                        tracing::warn!(
+                            "Row {}: skipped, the sources link to {}",
+                            row.row,
+                            new_item.existing_items.join(", ")
+                        );
+                    }
+                    (Ok(new_item), ExportFormat::QuickStatements) => {
+                        println!("{}", Export::from_item(&new_item.item).render(format)?);
+                    }
+                    (Ok(new_item), _) if !new_item.existing_items.is_empty() => {
+                        println!(
+                            "{}",
+                            json!({"row": row.row, "ids": ids, "item": new_item.item, "existing_items": new_item.existing_items})
+                        )
+                    }
+                    (Ok(new_item), _) => {
+                        println!(
+                            "{}",
+                            json!({"row": row.row, "ids": ids, "item": new_item.item})
+                        )
                     }
+                    (Err(e), ExportFormat::QuickStatements) => {
+                        tracing::warn!("Row {}: {e}", row.row);
+                    }
+                    (Err(e), _) => {
+                        println!(
+                            "{}",
+                            json!({"row": row.row, "ids": ids, "error": e.to_string()})
+                        );
+                    }
+                }
+            }
+        }
+        Some("validate") => {
+            let ext_id = get_extid_from_argv(&argv)?;
+            let validation = Validation::check(&ext_id).await;
+            println!("{}", json!(validation));
+            if validation.verdict != Verdict::Ok {
+                std::process::exit(1);
+            }
+        }
+        Some("search") => {
+            let args = CliArgs::new(&argv[1..], &["json"]);
+            let query = args
+                .positional(1)
+                .expect("USAGE: search NAME [--birth-year YEAR] [--limit N] [--json]");
+            let limit = args.option_parsed("limit", 10)?;
+            let mut candidates = NameSearch::search(query, limit).await;
+            if let Some(year) = args.option("birth-year") {
+                let year = year
+                    .parse()
+                    .map_err(|_| format!("Bad birth year: '{year}'"))?;
+                NameSearch::filter_by_birth_year(&mut candidates, year);
+            }
+            if args.flag("json") {
+                println!("{}", json!(candidates));
+            } else {
+                for candidate in candidates {
+                    println!("{candidate}");
                 }
             }
         }
+        Some("list") => {
+            let args = CliArgs::new(&argv[1..], &["resume", "automatic"]);
+            let input = BatchInput::from_args(&args).expect(
+                "USAGE: list LIST_FILE|-|--sparql QUERY|--mixnmatch CATALOG [--automatic]|--pagepile ID|--petscan PSID [--start-row N] [--oauth FILE] [--delay SECONDS] [--summary TEXT] [--maxlag SECONDS] [--jobs N] [--checkpoint FILE] [--resume] [-v|-q]",
+            );
+            let mut options = BatchOptions::from_args(&args)?;
+            options.progress = level > LevelFilter::ERROR;
+            let batch = Batch::new(options).await?;
+            let report = batch.run(&input).await?;
+            eprintln!(
+                "{} edited, {} unchanged, {} failed",
+                report.edited, report.unchanged, report.failed
+            );
+        }
         Some("extend") => {
-            let item = argv.get(2).expect("Item argument required");
-            let diff = get_extend(item).await.unwrap();
-            println!("{}", &serde_json::to_string_pretty(&diff).unwrap());
+            let args = CliArgs::new(&argv[1..], &["submit"]);
+            let item = args.positional(1).expect(
+                "USAGE: extend ITEM [--format json|quickstatements|csv|turtle|wikitext|json-patch [--submit] [--oauth FILE]]",
+            );
+            let format = args.option("format").unwrap_or("json");
+            let diff = combinator_from_args(&args)?.extend(item).await?;
+            if format == "json-patch" {
+                // For the Wikibase REST API; optionally sent right away
+                let patch = RestPatch::from_diff(item, &diff.to_target_json()).await?;
+                println!(
+                    "{}",
+                    serde_json::to_string_pretty(&patch.operations_json())?
+                );
+                if args.flag("submit") && !patch.is_empty() {
+                    let options = BatchOptions::default();
+                    let oauth_file = args.option("oauth").unwrap_or(&options.oauth_file);
+                    let token = Batch::read_oauth2_token(oauth_file)?;
+                    patch.submit(&token, &options.summary).await?;
+                }
+            } else {
+                let format = ExportFormat::from_str(format)?;
+                let mut export = Export::from_diff(item, &diff.to_target_json());
+                if format == ExportFormat::Wikitext {
+                    // Shows current values next to the proposed ones
+                    let current = MetaItem::from_entity(item).await?;
+                    export = export.with_current(&json!(current)["item"]);
+                }
+                println!("{}", export.render(format)?);
+            }
+        }
+        Some("mixnmatch") => {
+            // Diffs for the matched items of a catalog, one JSON line each; `list --mixnmatch` applies them
+            let args = CliArgs::new(&argv[1..], &["automatic"]);
+            let catalog = args
+                .positional(1)
+                .and_then(|c| c.parse().ok())
+                .expect("USAGE: mixnmatch CATALOG [--automatic]");
+            let matches = MixNMatch::new(catalog)
+                .await?
+                .matches(args.flag("automatic"))
+                .await?;
+            for m in matches {
+                let result = combinator_from_args(&args)?
+                    .extend_with(&m.item, vec![m.ext_id.to_owned()])
+                    .await;
+                let j = match result {
+                    Ok(diff) => {
+                        json!({"item": m.item, "external_id": m.ext_id.to_string(), "diff": diff.to_target_json()})
+                    }
+                    Err(e) => {
+                        json!({"item": m.item, "external_id": m.ext_id.to_string(), "error": e.to_string()})
+                    }
+                };
+                println!("{j}");
+            }
+        }
+        Some("report") => {
+            // Recorded edits, as tab-separated lines (usable as a rollback list) or JSON
+            let args = CliArgs::new(&argv[1..], &["stats", "json"]);
+            let log = EditLog::global().ok_or("No edit log is configured (edit_log.path)")?;
+            let params: HashMap<String, String> = ["item", "since", "limit"]
+                .iter()
+                .filter_map(|key| Some((key.to_string(), args.option(key)?.to_string())))
+                .collect();
+            let mut filter = edit_filter(&params)?;
+            if !params.contains_key("limit") {
+                filter.limit = None;
+            }
+            if args.flag("stats") {
+                let stats = log.stats(filter.since)?;
+                match args.flag("json") {
+                    true => println!("{}", json!(stats)),
+                    false => println!(
+                        "{} edits to {} items, {} statements added, {} altered",
+                        stats.edits, stats.items, stats.added_statements, stats.altered_statements
+                    ),
+                }
+            } else {
+                for edit in log.edits(&filter)? {
+                    match args.flag("json") {
+                        true => println!("{}", json!(edit)),
+                        false => println!(
+                            "{}\t{}\t{}\t{}\t{}",
+                            edit.item,
+                            edit.revision.map(|r| r.to_string()).unwrap_or_default(),
+                            edit.timestamp,
+                            edit.summary,
+                            edit.sources.join(",")
+                        ),
+                    }
+                }
+            }
         }
         Some("merge") => {
-            todo!();
+            let args = CliArgs::new(&argv[1..], &["compact"]);
+            let output = merge_cli(&args).await?;
+            println!("{output}");
+        }
+        Some("serve") => {
+            tracing::info!("Effective configuration:\n{}", Config::get().effective());
+            run_server().await?
+        }
+        _ => {
+            eprintln!("{USAGE}");
+            std::process::exit(2);
         }
-        _ => run_server().await?,
     }
     Ok(())
 }
@@ -390,8 +971,6 @@ cargo run -- combinator P950 XX990809
 
 TODO:
 P244	Library of Congress authority ID
-P213	ISNI
-P349    NDL Authority ID (Japan)
 P7545	askArt person ID (has JSON in HTML source)
 
 https://vokabular.bs.no/bibbi/nb/page/22778
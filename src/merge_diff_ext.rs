@@ -0,0 +1,226 @@
+use crate::target::{Direction, Target};
+use crate::utility::Utility;
+use serde_json::{json, Map, Value};
+use wikimisc::merge_diff::MergeDiff;
+
+/// Exports a `MergeDiff` in the formats the Wikidata API accepts
+pub trait MergeDiffExt {
+    /// The serialized diff, with IDs mapped to the configured target Wikibase
+    fn to_target_json(&self) -> Value;
+
+    /// The `data` parameter for `action=wbeditentity`
+    fn to_wbeditentity_json(&self) -> Value;
+
+    /// Like `to_wbeditentity_json`, but also removes the statements with the given IDs
+    fn to_wbeditentity_json_removing(&self, statement_ids: &[String]) -> Value;
+}
+
+impl MergeDiffExt for MergeDiff {
+    fn to_target_json(&self) -> Value {
+        Target::map_json(&json!(self), Direction::ToTarget)
+    }
+
+    fn to_wbeditentity_json(&self) -> Value {
+        WbEditEntity::data(&self.to_target_json(), &[])
+    }
+
+    fn to_wbeditentity_json_removing(&self, statement_ids: &[String]) -> Value {
+        WbEditEntity::data(&self.to_target_json(), statement_ids)
+    }
+}
+
+/// Builds `wbeditentity` data from the serialized form of a diff
+pub struct WbEditEntity {}
+
+impl WbEditEntity {
+    /// Statements with an `id` modify that statement, those without are created.
+    /// Labels, descriptions, and aliases are keyed by language, sitelinks by site; empty sections are left out.
+    pub fn data(diff: &Value, remove_statements: &[String]) -> Value {
+        let mut ret = Map::new();
+        let mut claims = Utility::json_values(&diff["claims"]);
+        claims.extend(
+            remove_statements
+                .iter()
+                .map(|id| json!({"id": id, "remove": ""})),
+        );
+        if !claims.is_empty() {
+            ret.insert("claims".to_string(), Value::Array(claims));
+        }
+        for key in ["labels", "descriptions"] {
+            let mut terms = Map::new();
+            for term in Utility::json_values(&diff[key]) {
+                if let Some(language) = term["language"].as_str() {
+                    terms.insert(language.to_string(), term.to_owned());
+                }
+            }
+            if !terms.is_empty() {
+                ret.insert(key.to_string(), Value::Object(terms));
+            }
+        }
+        let mut aliases = Map::new();
+        for alias in Utility::json_values(&diff["aliases"]) {
+            if let Some(language) = alias["language"].as_str() {
+                let entry = aliases
+                    .entry(language.to_string())
+                    .or_insert_with(|| json!([]));
+                if let Some(list) = entry.as_array_mut() {
+                    if !list.contains(&alias) {
+                        list.push(alias);
+                    }
+                }
+            }
+        }
+        if !aliases.is_empty() {
+            ret.insert("aliases".to_string(), Value::Object(aliases));
+        }
+        let mut sitelinks = Map::new();
+        for sitelink in Utility::json_values(&diff["sitelinks"]) {
+            if let Some(site) = sitelink["site"].as_str() {
+                sitelinks.insert(site.to_string(), sitelink.to_owned());
+            }
+        }
+        if !sitelinks.is_empty() {
+            ret.insert("sitelinks".to_string(), Value::Object(sitelinks));
+        }
+        Value::Object(ret)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::meta_item::MetaItem;
+    use wikimisc::wikibase::{EntityTrait, ItemEntity, LocaleString, Reference, Snak, Statement};
+
+    /// Statements as they come from a merge of Q1035 (Charles Darwin)
+    fn fixture_diff() -> Value {
+        json!({
+            "claims": {
+                "P1015": [{
+                    "type": "statement",
+                    "mainsnak": {"snaktype":"value","property":"P1015","datatype":"external-id","datavalue":{"type":"string","value":"90053126"}},
+                    "rank": "normal"
+                }],
+                "P214": [{
+                    "id": "Q1035$7A3B1C2D-0000-4000-8000-000000000001",
+                    "type": "statement",
+                    "mainsnak": {"snaktype":"value","property":"P214","datatype":"external-id","datavalue":{"type":"string","value":"27063124"}},
+                    "rank": "normal",
+                    "references": [{"snaks":{"P248":[{"snaktype":"value","property":"P248","datavalue":{"type":"wikibase-entityid","value":{"entity-type":"item","id":"Q54919"}}}]}}]
+                }]
+            },
+            "labels": [{"language":"de","value":"Charles Darwin"}],
+            "aliases": [
+                {"language":"en","value":"Darwin"},
+                {"language":"en","value":"Charles Robert Darwin"},
+                {"language":"en","value":"Darwin"}
+            ],
+            "descriptions": {"fr": {"language":"fr","value":"naturaliste britannique"}}
+        })
+    }
+
+    #[test]
+    fn test_data() {
+        let data = WbEditEntity::data(&fixture_diff(), &[]);
+        let claims = data["claims"].as_array().unwrap();
+        assert_eq!(claims.len(), 2);
+        let modified: Vec<&Value> = claims.iter().filter(|c| c.get("id").is_some()).collect();
+        assert_eq!(modified.len(), 1);
+        assert_eq!(modified[0]["mainsnak"]["property"], "P214");
+        assert_eq!(
+            data["labels"],
+            json!({"de":{"language":"de","value":"Charles Darwin"}})
+        );
+        assert_eq!(
+            data["aliases"],
+            json!({"en":[{"language":"en","value":"Darwin"},{"language":"en","value":"Charles Robert Darwin"}]})
+        );
+        assert_eq!(
+            data["descriptions"],
+            json!({"fr":{"language":"fr","value":"naturaliste britannique"}})
+        );
+        assert!(data.get("sitelinks").is_none());
+    }
+
+    #[test]
+    fn test_data_from_merge() {
+        // Q1035 as the Wikidata API returns it, with a VIAF statement that has no reference yet
+        let mut base = MetaItem::new();
+        base.item = ItemEntity::new_from_json(&json!({
+            "type": "item",
+            "id": "Q1035",
+            "labels": {"en": {"language":"en","value":"Charles Darwin"}},
+            "descriptions": {},
+            "aliases": {},
+            "sitelinks": {},
+            "claims": {"P214": [{
+                "id": "Q1035$7A3B1C2D-0000-4000-8000-000000000001",
+                "type": "statement",
+                "mainsnak": {"snaktype":"value","property":"P214","datatype":"external-id","datavalue":{"type":"string","value":"27063124"}},
+                "rank": "normal"
+            }]}
+        }))
+        .unwrap();
+        let mut record = MetaItem::new();
+        record
+            .item
+            .labels_mut()
+            .push(LocaleString::new("de", "Charles Darwin"));
+        record
+            .item
+            .aliases_mut()
+            .push(LocaleString::new("en", "Charles Robert Darwin"));
+        record.item.add_claim(Statement::new_normal(
+            Snak::new_external_id("P214", "27063124"),
+            vec![],
+            vec![Reference::new(vec![Snak::new_item("P248", "Q54919")])],
+        ));
+        record.item.add_claim(Statement::new_normal(
+            Snak::new_external_id("P1015", "90053126"),
+            vec![],
+            vec![],
+        ));
+
+        let data = base.merge(&record).to_wbeditentity_json();
+        let claims = data["claims"].as_array().unwrap();
+        assert_eq!(claims.len(), 2);
+        let modified: Vec<&Value> = claims.iter().filter(|c| c.get("id").is_some()).collect();
+        assert_eq!(modified.len(), 1);
+        assert_eq!(
+            modified[0]["id"],
+            "Q1035$7A3B1C2D-0000-4000-8000-000000000001"
+        );
+        assert_eq!(modified[0]["mainsnak"]["property"], "P214");
+        assert_eq!(
+            modified[0]["references"][0]["snaks"]["P248"][0]["datavalue"]["value"]["id"],
+            "Q54919"
+        );
+        let created: Vec<&Value> = claims.iter().filter(|c| c.get("id").is_none()).collect();
+        assert_eq!(created[0]["mainsnak"]["property"], "P1015");
+        assert_eq!(created[0]["mainsnak"]["datavalue"]["value"], "90053126");
+        assert_eq!(
+            data["labels"],
+            json!({"de":{"language":"de","value":"Charles Darwin"}})
+        );
+        assert_eq!(
+            data["aliases"],
+            json!({"en":[{"language":"en","value":"Charles Robert Darwin"}]})
+        );
+        assert!(data.get("descriptions").is_none());
+        assert!(data.get("sitelinks").is_none());
+    }
+
+    #[test]
+    fn test_data_remove() {
+        let id = "Q1035$7A3B1C2D-0000-4000-8000-000000000002".to_string();
+        let data = WbEditEntity::data(&json!({}), &[id.to_owned()]);
+        assert_eq!(data, json!({"claims":[{"id":id,"remove":""}]}));
+    }
+
+    #[test]
+    fn test_data_empty() {
+        assert_eq!(WbEditEntity::data(&json!({}), &[]), json!({}));
+        let diff = MetaItem::new().merge(&MetaItem::new());
+        assert_eq!(diff.to_wbeditentity_json(), json!({}));
+    }
+}
@@ -1,4 +1,9 @@
+use crate::app_error::AppError;
 use crate::external_id::*;
+use crate::rdf_export::{RdfExport, RdfFormat};
+use crate::target::{Direction, Target};
+use crate::utility::Utility;
+use regex::Regex;
 use serde::ser::{Serialize, SerializeStruct, Serializer};
 use serde_json::json;
 use std::vec::Vec;
@@ -6,10 +11,36 @@ use wikimisc::item_merger::ItemMerger;
 use wikimisc::merge_diff::MergeDiff;
 use wikimisc::wikibase::*;
 
+const REASON_FOR_DEPRECATED_RANK: &str = "P2241";
+const REDIRECT: &str = "Q45403344";
+
+lazy_static! {
+    static ref RE_ITEM_ID: Regex = Regex::new(r"^Q[1-9]\d*$").expect("Regexp error");
+}
+
+/// A text value that matched several Wikidata items, none of them unambiguously
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, serde::Serialize)]
+pub struct PropTextCandidates {
+    pub ext_id: ExternalId,
+    pub items: Vec<String>,
+}
+
+/// A text value for the user to confirm, like a title that might be a notable work;
+/// `count` is how many sources give it
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, serde::Serialize)]
+pub struct PropTextSuggestion {
+    pub ext_id: ExternalId,
+    pub count: usize,
+}
+
 #[derive(Debug, Clone)]
 pub struct MetaItem {
     pub item: ItemEntity,
     pub prop_text: Vec<ExternalId>,
+    pub prop_text_candidates: Vec<PropTextCandidates>,
+    pub prop_text_suggestions: Vec<PropTextSuggestion>,
+    /// Wikidata items the sources link the record to, like the WKP member of a VIAF cluster
+    pub linked_items: Vec<String>,
 }
 
 impl Serialize for MetaItem {
@@ -17,11 +48,14 @@ impl Serialize for MetaItem {
     where
         S: Serializer,
     {
-        let mut state = serializer.serialize_struct("MetaItem", 2)?;
+        let mut state = serializer.serialize_struct("MetaItem", 5)?;
         let mut item = self.item.to_json();
         item["type"] = json!("item");
         state.serialize_field("item", &item)?;
         state.serialize_field("prop_text", &self.prop_text)?;
+        state.serialize_field("prop_text_candidates", &self.prop_text_candidates)?;
+        state.serialize_field("prop_text_suggestions", &self.prop_text_suggestions)?;
+        state.serialize_field("linked_items", &self.linked_items)?;
         state.end()
     }
 }
@@ -31,6 +65,9 @@ impl Default for MetaItem {
         Self {
             item: ItemEntity::new_empty(),
             prop_text: vec![],
+            prop_text_candidates: vec![],
+            prop_text_suggestions: vec![],
+            linked_items: vec![],
         }
     }
 }
@@ -47,18 +84,49 @@ impl MetaItem {
         }
     }
 
+    /// Loads an item from the target wiki; fails with `AppError::BadRequest` for a malformed ID,
+    /// and `AppError::NotFound` if there is no such item
     pub async fn from_entity(id: &str) -> Result<Self, Box<dyn std::error::Error>> {
-        let api = mediawiki::api::Api::new("https://www.wikidata.org/w/api.php").await?;
+        if !RE_ITEM_ID.is_match(id) {
+            return Err(AppError::BadRequest(format!("Not an item ID: '{id}'")).into());
+        }
+        let mut api = mediawiki::api::Api::new(Target::api()).await?;
+        api.set_user_agent(Utility::user_agent());
         let entity_container = entity_container::EntityContainer::new();
-        let entity = entity_container.load_entity(&api, id).await?;
-        let item = match entity {
+        let entity = match entity_container.load_entity(&api, id).await {
+            Ok(entity) => entity,
+            Err(_) if Self::is_missing(&api, id).await => {
+                return Err(AppError::NotFound(format!("No such item: '{id}'")).into())
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let mut item = match entity {
             Entity::Item(item) => item,
-            _ => return Err(format!("Not an item: '{id}'").into()),
+            _ => return Err(AppError::BadRequest(format!("Not an item: '{id}'")).into()),
         };
-        Ok(Self {
-            item,
-            prop_text: vec![],
-        })
+        if Target::is_mapped() {
+            // Records from the importers use Wikidata IDs
+            let j = Target::map_json(&json!(item), Direction::FromTarget);
+            item = ItemEntity::new_from_json(&j).map_err(|e| e.to_string())?;
+        }
+        Ok(Self::new_from_item(item))
+    }
+
+    /// True if the wiki says that the entity does not exist; false if that could not be determined
+    async fn is_missing(api: &mediawiki::api::Api, id: &str) -> bool {
+        let params =
+            api.params_into(&[("action", "wbgetentities"), ("ids", id), ("props", "info")]);
+        match api.get_query_api_json(&params).await {
+            Ok(j) => {
+                j["entities"][id].get("missing").is_some() || j["error"]["code"] == "no-such-entity"
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// The item in the Wikidata RDF dump format, eg for loading into a triple store
+    pub fn to_rdf(&self, format: RdfFormat) -> String {
+        RdfExport::from_item(&self.item.to_json()).render(format)
     }
 
     /// Parses a date string and returns a tuple with the time and precision.
@@ -170,6 +238,58 @@ impl MetaItem {
         None
     }
 
+    /// Adds a text value, with the Wikidata items it might refer to
+    pub fn add_prop_text_candidates(&mut self, ext_id: ExternalId, items: Vec<String>) {
+        self.prop_text_candidates
+            .push(PropTextCandidates { ext_id, items });
+    }
+
+    pub fn add_prop_text_suggestion(&mut self, ext_id: ExternalId, count: usize) {
+        self.prop_text_suggestions
+            .push(PropTextSuggestion { ext_id, count });
+    }
+
+    pub fn add_linked_item(&mut self, item: &str) {
+        if !self.linked_items.iter().any(|i| i == item) {
+            self.linked_items.push(item.to_string());
+        }
+    }
+
+    /// Most frequent first
+    fn sort_prop_text_suggestions(&mut self) {
+        self.prop_text_suggestions
+            .sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.ext_id.cmp(&b.ext_id)));
+        self.prop_text_suggestions.dedup();
+    }
+
+    /// Adds an external ID the source has redirected, with deprecated rank and "redirect" as reason
+    pub fn add_redirected_id(&mut self, ext_id: &ExternalId) -> Option<Statement> {
+        let reason = Snak::new(
+            SnakDataType::WikibaseItem,
+            REASON_FOR_DEPRECATED_RANK,
+            SnakType::Value,
+            Some(DataValue::new(
+                DataValueType::EntityId,
+                Value::Entity(EntityValue::new(EntityType::Item, REDIRECT)),
+            )),
+        );
+        self.add_claim(Statement::new(
+            "statement",
+            StatementRank::Deprecated,
+            Snak::new(
+                SnakDataType::ExternalId,
+                format!("P{}", ext_id.property()),
+                SnakType::Value,
+                Some(DataValue::new(
+                    DataValueType::StringType,
+                    Value::StringValue(ext_id.wikidata_value()),
+                )),
+            ),
+            vec![reason],
+            vec![],
+        ))
+    }
+
     pub fn get_external_ids(&self) -> Vec<ExternalId> {
         self.item
             .claims()
@@ -181,6 +301,11 @@ impl MetaItem {
     pub fn cleanup(&mut self) {
         self.prop_text.sort();
         self.prop_text.dedup();
+        self.prop_text_candidates.sort();
+        self.prop_text_candidates.dedup();
+        self.sort_prop_text_suggestions();
+        self.linked_items.sort();
+        self.linked_items.dedup();
     }
 
     pub fn fix_images(&mut self, base_item: &MetaItem) {
@@ -243,6 +368,19 @@ impl MetaItem {
         }
     }
 
+    /// Removes all statements for properties that `other` already has
+    pub fn remove_properties_of(&mut self, other: &MetaItem) {
+        let properties: Vec<String> = other
+            .item
+            .claims()
+            .iter()
+            .map(|c| c.property().to_string())
+            .collect();
+        self.item
+            .claims_mut()
+            .retain(|c| !properties.contains(&c.property().to_string()));
+    }
+
     pub fn merge(&mut self, other: &MetaItem) -> MergeDiff {
         let mut im = ItemMerger::new(self.item.to_owned());
         im.set_properties_ignore_qualifier_match(vec!["P225".to_string()]);
@@ -250,6 +388,16 @@ impl MetaItem {
         self.prop_text.append(&mut other.prop_text.clone());
         self.prop_text.sort();
         self.prop_text.dedup();
+        self.prop_text_candidates
+            .append(&mut other.prop_text_candidates.clone());
+        self.prop_text_candidates.sort();
+        self.prop_text_candidates.dedup();
+        self.prop_text_suggestions
+            .append(&mut other.prop_text_suggestions.clone());
+        self.sort_prop_text_suggestions();
+        self.linked_items.append(&mut other.linked_items.clone());
+        self.linked_items.sort();
+        self.linked_items.dedup();
         diff
     }
 }
@@ -258,6 +406,15 @@ impl MetaItem {
 mod tests {
     use super::*;
 
+    #[tokio::test]
+    async fn test_from_entity_malformed_id() {
+        let e = MetaItem::from_entity("Q42x").await.unwrap_err();
+        assert_eq!(
+            AppError::from(e),
+            AppError::BadRequest("Not an item ID: 'Q42x'".to_string())
+        );
+    }
+
     #[test]
     fn test_parse_date() {
         let mi = MetaItem::new();
@@ -279,6 +436,17 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_add_redirected_id() {
+        let mut mi = MetaItem::new();
+        let ext_id = ExternalId::new(227, "118523813");
+        assert!(mi.add_redirected_id(&ext_id).is_some());
+        let claim = &mi.item.claims()[0];
+        assert_eq!(*claim.rank(), StatementRank::Deprecated);
+        assert_eq!(claim.qualifiers()[0].property(), REASON_FOR_DEPRECATED_RANK);
+        assert_eq!(mi.get_external_ids(), vec![ext_id]);
+    }
+
     #[tokio::test]
     async fn test_add_prop_text() {
         let mut mi = MetaItem::new();
@@ -299,6 +467,28 @@ mod tests {
         assert_eq!(mi.prop_text, vec![ext_id2, ext_id1]);
     }
 
+    #[test]
+    fn test_prop_text_suggestions() {
+        let mut mi = MetaItem::new();
+        mi.add_prop_text_suggestion(ExternalId::new(800, "Minor work"), 1);
+        mi.add_prop_text_suggestion(ExternalId::new(800, "Major work"), 5);
+        mi.add_prop_text_suggestion(ExternalId::new(800, "Minor work"), 1);
+        mi.cleanup();
+        assert_eq!(
+            mi.prop_text_suggestions,
+            vec![
+                PropTextSuggestion {
+                    ext_id: ExternalId::new(800, "Major work"),
+                    count: 5
+                },
+                PropTextSuggestion {
+                    ext_id: ExternalId::new(800, "Minor work"),
+                    count: 1
+                },
+            ]
+        );
+    }
+
     #[test]
     fn test_fix_dates() {
         let mut mi = MetaItem::new();
@@ -326,4 +516,28 @@ mod tests {
         assert_eq!(*mi.item.claims()[1].rank(), StatementRank::Normal);
         assert_eq!(*mi.item.claims()[2].rank(), StatementRank::Deprecated);
     }
+
+    #[test]
+    fn test_remove_properties_of() {
+        let mut mi = MetaItem::new();
+        mi.item.add_claim(Statement::new_normal(
+            Snak::new_item("P31", "Q5"),
+            vec![],
+            vec![],
+        ));
+        mi.item.add_claim(Statement::new_normal(
+            Snak::new_item("P27", "Q145"),
+            vec![],
+            vec![],
+        ));
+        let mut other = MetaItem::new();
+        other.item.add_claim(Statement::new_normal(
+            Snak::new_item("P31", "Q6581097"),
+            vec![],
+            vec![],
+        ));
+        mi.remove_properties_of(&other);
+        assert_eq!(mi.item.claims().len(), 1);
+        assert_eq!(mi.item.claims()[0].property(), "P27");
+    }
 }
@@ -0,0 +1,165 @@
+use crate::combinator::Combinator;
+use crate::external_id::ExternalId;
+use crate::utility::Utility;
+use anyhow::{anyhow, Result};
+use serde_json::Value;
+
+const MIXNMATCH_API: &str = "https://mix-n-match.toolforge.org/api.php";
+const ENTRIES_PER_PAGE: usize = 500;
+
+/// An entry of a Mix'n'match catalog that is matched to a Wikidata item
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CatalogMatch {
+    pub item: String,
+    pub ext_id: ExternalId,
+    /// Matched by a user, not by the automatic matcher
+    pub confirmed: bool,
+}
+
+/// Reads the matched entries of a Mix'n'match catalog, to import their records into the matched items
+pub struct MixNMatch {
+    catalog: usize,
+    property: usize,
+}
+
+impl MixNMatch {
+    /// Loads the catalog details, to find the property of its IDs
+    pub async fn new(catalog: usize) -> Result<Self> {
+        let url = reqwest::Url::parse_with_params(
+            MIXNMATCH_API,
+            &[
+                ("query", "get_catalog_info"),
+                ("catalog", &catalog.to_string()),
+            ],
+        )?;
+        let j: Value = serde_json::from_str(&Utility::get_url(url.as_str()).await?)?;
+        let property = Self::property_from_catalog_info(&j)
+            .ok_or_else(|| anyhow!("Mix'n'match catalog {catalog} has no Wikidata property"))?;
+        Ok(Self { catalog, property })
+    }
+
+    pub fn property(&self) -> usize {
+        self.property
+    }
+
+    /// All matched entries, page by page; with `include_automatic`, also those that are only matched automatically.
+    /// Entries without an importer for the catalog property are of no use, so this fails early for those catalogs.
+    pub async fn matches(&self, include_automatic: bool) -> Result<Vec<CatalogMatch>> {
+        if !Combinator::has_parser_for_ext_id(&ExternalId::new(self.property, "")) {
+            return Err(anyhow!("Property P{} is not supported", self.property));
+        }
+        let mut ret = vec![];
+        for offset in (0..).step_by(ENTRIES_PER_PAGE) {
+            let url = reqwest::Url::parse_with_params(
+                MIXNMATCH_API,
+                &[
+                    ("query", "catalog"),
+                    ("catalog", &self.catalog.to_string()),
+                    ("offset", &offset.to_string()),
+                    ("per_page", &ENTRIES_PER_PAGE.to_string()),
+                    ("show_userq", "1"),
+                    ("show_autoq", if include_automatic { "1" } else { "0" }),
+                    ("show_noq", "0"),
+                    ("show_na", "0"),
+                    ("show_nowd", "0"),
+                    ("show_multiple", "0"),
+                ],
+            )?;
+            let j: Value = serde_json::from_str(&Utility::get_url(url.as_str()).await?)?;
+            let (page, entries) = Self::parse_entries(&j, self.property);
+            ret.extend(page);
+            if entries < ENTRIES_PER_PAGE {
+                break;
+            }
+        }
+        ret.sort_by(|a, b| a.item.cmp(&b.item).then(a.ext_id.cmp(&b.ext_id)));
+        ret.dedup();
+        Ok(ret)
+    }
+
+    fn property_from_catalog_info(j: &Value) -> Option<usize> {
+        let property = match &j["data"][0]["wd_prop"] {
+            Value::String(s) => ExternalId::prop_numeric(s)?,
+            Value::Number(n) => n.as_u64()? as usize,
+            _ => return None,
+        };
+        (property > 0).then_some(property)
+    }
+
+    /// Returns the matched entries, and the number of entries on the page.
+    /// Entries that are not matched to an item (`q` is missing, 0, or -1 for "not applicable") are skipped.
+    fn parse_entries(j: &Value, property: usize) -> (Vec<CatalogMatch>, usize) {
+        let entries: Vec<&Value> = match &j["data"]["entries"] {
+            Value::Object(o) => o.values().collect(),
+            Value::Array(a) => a.iter().collect(),
+            _ => vec![],
+        };
+        let matches = entries
+            .iter()
+            .filter_map(|entry| {
+                let q = match &entry["q"] {
+                    Value::String(s) => s.trim_start_matches('Q').parse::<i64>().ok()?,
+                    v => v.as_i64()?,
+                };
+                if q <= 0 {
+                    return None;
+                }
+                let ext_id = entry["ext_id"].as_str()?.trim();
+                if ext_id.is_empty() {
+                    return None;
+                }
+                let user = match &entry["user"] {
+                    Value::String(s) => s.parse::<i64>().unwrap_or(0),
+                    v => v.as_i64().unwrap_or(0),
+                };
+                Some(CatalogMatch {
+                    item: format!("Q{q}"),
+                    ext_id: ExternalId::new(property, ext_id),
+                    confirmed: user > 0,
+                })
+            })
+            .collect();
+        (matches, entries.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_property_from_catalog_info() {
+        let j = json!({"data":[{"id":"2050","name":"GND","wd_prop":"227","wd_qual":null}]});
+        assert_eq!(MixNMatch::property_from_catalog_info(&j), Some(227));
+        let j = json!({"data":[{"id":"1","wd_prop":null}]});
+        assert_eq!(MixNMatch::property_from_catalog_info(&j), None);
+    }
+
+    #[test]
+    fn test_parse_entries() {
+        let j = json!({"status":"OK","data":{"entries":{
+            "101":{"id":"101","ext_id":"118523813","q":"1035","user":"4"},
+            "102":{"id":"102","ext_id":"118529579","q":"937","user":"0"},
+            "103":{"id":"103","ext_id":"118540238","q":"-1","user":"4"},
+            "104":{"id":"104","ext_id":"118575449","q":null,"user":null}
+        }}});
+        let (matches, entries) = MixNMatch::parse_entries(&j, 227);
+        assert_eq!(entries, 4);
+        assert_eq!(
+            matches,
+            vec![
+                CatalogMatch {
+                    item: "Q1035".to_string(),
+                    ext_id: ExternalId::new(227, "118523813"),
+                    confirmed: true,
+                },
+                CatalogMatch {
+                    item: "Q937".to_string(),
+                    ext_id: ExternalId::new(227, "118529579"),
+                    confirmed: false,
+                },
+            ]
+        );
+    }
+}
@@ -1,6 +1,7 @@
 use crate::external_importer::*;
 use crate::meta_item::*;
-use crate::ExternalId;
+use crate::utility::Utility;
+use crate::wikidata_lookup::{lookup, LookupQuery};
 use anyhow::{anyhow, Result};
 use axum::async_trait;
 use quickxml_to_serde::xml_string_to_json;
@@ -14,9 +15,6 @@ pub struct NCBItaxonomy {
     json: Value,
 }
 
-unsafe impl Send for NCBItaxonomy {}
-unsafe impl Sync for NCBItaxonomy {}
-
 #[async_trait]
 impl ExternalImporter for NCBItaxonomy {
     fn my_property(&self) -> usize {
@@ -38,6 +36,27 @@ impl ExternalImporter for NCBItaxonomy {
         self.id.to_owned()
     }
 
+    async fn fetch(&mut self) -> Result<()> {
+        let url = format!(
+            "https://eutils.ncbi.nlm.nih.gov/entrez/eutils/efetch.fcgi?db=taxonomy&id={}&format=xml",
+            self.id
+        );
+        let resp = Utility::get_url(&url).await?;
+        self.parse(&resp)
+    }
+
+    fn parse(&mut self, text: &str) -> Result<()> {
+        let conf = quickxml_to_serde::Config::new_with_defaults();
+        let json = xml_string_to_json(text.to_owned(), &conf)?;
+        self.json = json
+            .get("TaxaSet")
+            .ok_or_else(|| anyhow!("Invalid JSON"))?
+            .get("Taxon")
+            .ok_or_else(|| anyhow!("Invalid JSON"))?
+            .to_owned();
+        Ok(())
+    }
+
     async fn run(&self) -> Result<MetaItem> {
         let mut ret = MetaItem::new();
         self.add_own_id(&mut ret)?;
@@ -51,27 +70,23 @@ impl ExternalImporter for NCBItaxonomy {
 }
 
 impl NCBItaxonomy {
-    pub async fn new(id: &str) -> Result<Self> {
-        let url = format!("https://eutils.ncbi.nlm.nih.gov/entrez/eutils/efetch.fcgi?db=taxonomy&id={id}&format=xml");
-        let resp = reqwest::get(&url).await?.text().await?;
-        let conf = quickxml_to_serde::Config::new_with_defaults();
-        let json = xml_string_to_json(resp.to_owned(), &conf)?;
-        let json = json
-            .get("TaxaSet")
-            .ok_or_else(|| anyhow!("Invalid JSON"))?
-            .get("Taxon")
-            .ok_or_else(|| anyhow!("Invalid JSON"))?
-            .to_owned();
-        Ok(Self {
+    pub fn unfetched(id: &str) -> Self {
+        Self {
             id: id.to_string(),
-            json,
-        })
+            json: Value::Null,
+        }
+    }
+
+    pub async fn new(id: &str) -> Result<Self> {
+        let mut ret = Self::unfetched(id);
+        ret.fetch().await?;
+        Ok(ret)
     }
 
     async fn add_parent_taxon(&self, ret: &mut MetaItem) -> Option<()> {
         let parent_id = self.json.get("ParentTaxId")?.as_i64()?;
-        let query = format!("haswbstatement:P685={parent_id} haswbstatement:P31=Q16521");
-        let item = ExternalId::search_wikidata_single_item(&query).await?;
+        let query = LookupQuery::new(685, &parent_id.to_string()).with_statement(31, "Q16521");
+        let item = lookup().find_single_item(&query).await?;
         ret.add_claim(self.new_statement_item(171, &item));
         Some(())
     }
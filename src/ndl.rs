@@ -0,0 +1,291 @@
+use crate::external_id::*;
+use crate::external_importer::*;
+use crate::fuzzy_date::FuzzyDate;
+use crate::meta_item::*;
+use crate::occupation::Occupation;
+use crate::utility::Utility;
+use anyhow::Result;
+use axum::async_trait;
+use sophia::inmem::graph::FastGraph;
+
+/// RDA Group 2 elements describe the person; the namespace turns up in both spellings
+const RDA: &[&str] = &[
+    "http://RDVocab.info/ElementsGr2/",
+    "http://rdvocab.info/ElementsGr2/",
+];
+
+const SKOS_EXACT_MATCH: &str = "http://www.w3.org/2004/02/skos/core#exactMatch";
+
+/// RDA elements for occupations and fields of activity, and their properties
+const OCCUPATIONS: &[(&str, usize)] = &[
+    ("professionOrOccupation", 106),
+    ("fieldOfActivityOfThePerson", 101),
+];
+
+pub struct NDL {
+    id: String,
+    graph: FastGraph,
+}
+
+#[async_trait]
+impl ExternalImporter for NDL {
+    fn my_property(&self) -> usize {
+        349
+    }
+    fn my_stated_in(&self) -> &str {
+        "Q477675"
+    }
+    fn primary_language(&self) -> String {
+        "ja".to_string()
+    }
+    fn get_key_url(&self, _key: &str) -> String {
+        format!("http://id.ndl.go.jp/auth/entity/{}", self.id)
+    }
+    fn my_id(&self) -> String {
+        self.id.to_owned()
+    }
+    fn graph(&self) -> &FastGraph {
+        &self.graph
+    }
+
+    /// Japanese names are family name first, without a space, like "夏目, 漱石" => "夏目漱石";
+    /// romanized names are turned around
+    fn transform_label(&self, s: &str) -> String {
+        match s.split_once(", ") {
+            Some((family, given))
+                if !given.contains(", ") && !s.chars().any(|c| c.is_ascii_alphabetic()) =>
+            {
+                format!("{family}{given}")
+            }
+            _ => self.transform_label_last_first_name(s),
+        }
+    }
+
+    async fn fetch(&mut self) -> Result<()> {
+        let rdf_url = format!("https://id.ndl.go.jp/auth/ndlna/{}.rdf", self.id);
+        self.graph = Utility::get_rdf_graph(&rdf_url).await?;
+        Ok(())
+    }
+
+    fn parse(&mut self, text: &str) -> Result<()> {
+        self.graph = Utility::parse_rdf_xml(text)?;
+        Ok(())
+    }
+
+    /// The exactMatch links to VIAF, LoC and others are on the authority record (`ndlna`), not on the person
+    async fn add_same_as(&self, ret: &mut MetaItem) -> Result<()> {
+        let mut urls = vec![];
+        for subject in [self.get_id_url(), self.authority_url()] {
+            urls.extend(self.triples_subject_iris(&subject, SKOS_EXACT_MATCH)?);
+        }
+        self.add_same_as_urls(ret, urls).await
+    }
+
+    async fn run(&self) -> Result<MetaItem> {
+        let mut ret = MetaItem::new();
+        self.add_the_usual(&mut ret).await?;
+        self.add_dates(&mut ret)?;
+        self.add_occupations(&mut ret)?;
+        self.try_rescue_prop_text(&mut ret).await?;
+        ret.cleanup();
+        Ok(ret)
+    }
+}
+
+impl NDL {
+    pub fn unfetched(id: &str) -> Self {
+        Self {
+            id: id.to_string(),
+            graph: FastGraph::new(),
+        }
+    }
+
+    pub async fn new(id: &str) -> Result<Self> {
+        let mut ret = Self::unfetched(id);
+        ret.fetch().await?;
+        Ok(ret)
+    }
+
+    /// The authority record, which describes the person at `get_id_url`
+    fn authority_url(&self) -> String {
+        format!("http://id.ndl.go.jp/auth/ndlna/{}", self.id)
+    }
+
+    /// The values of an RDA element of the person
+    fn rda_literals(&self, element: &str) -> Result<Vec<String>> {
+        let mut ret = vec![];
+        for namespace in RDA {
+            let predicate = format!("{namespace}{element}");
+            ret.extend(self.triples_subject_literals(&self.get_id_url(), &predicate)?);
+        }
+        Ok(ret)
+    }
+
+    fn life_dates(&self) -> Result<Vec<(usize, FuzzyDate)>> {
+        let mut ret = vec![];
+        for (element, property) in [("dateOfBirth", 569), ("dateOfDeath", 570)] {
+            for s in self.rda_literals(element)? {
+                if let Some(date) = FuzzyDate::parse(&s) {
+                    ret.push((property, date));
+                }
+            }
+        }
+        Ok(ret)
+    }
+
+    fn add_dates(&self, ret: &mut MetaItem) -> Result<()> {
+        for (property, date) in self.life_dates()? {
+            ret.add_claim(self.new_statement_fuzzy_date(property, &date));
+        }
+        Ok(())
+    }
+
+    /// Occupation and field of activity terms, with their properties
+    fn occupation_terms(&self) -> Result<Vec<(usize, String)>> {
+        let mut ret = vec![];
+        for (element, property) in OCCUPATIONS {
+            for s in self.rda_literals(element)? {
+                ret.extend(
+                    Occupation::split_terms(&s)
+                        .into_iter()
+                        .map(|term| (*property, term)),
+                );
+            }
+        }
+        Ok(ret)
+    }
+
+    /// Occupations (P106) for known Japanese terms; fields of activity (P101) and other terms are kept as text.
+    /// The biographical information is prose, so only terms from the occupation table are taken from it.
+    fn add_occupations(&self, ret: &mut MetaItem) -> Result<()> {
+        for (property, term) in self.occupation_terms()? {
+            let _ = match Occupation::from_japanese(&term) {
+                Some(item) if property == 106 => ret.add_claim(self.new_statement_item(106, item)),
+                _ => ret.add_prop_text(ExternalId::new(property, &term)),
+            };
+        }
+        for note in self.rda_literals("biographicalInformation")? {
+            for term in Occupation::split_terms(&note) {
+                if let Some(item) = Occupation::from_japanese(&term) {
+                    ret.add_claim(self.new_statement_item(106, item));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wikimisc::wikibase::EntityTrait;
+
+    const TEST_ID: &str = "00054222";
+
+    /// A record for `TEST_ID`, with the given elements on the authority record and on the person
+    fn ndl(authority: &str, person: &str) -> NDL {
+        let rdf = format!(
+            r#"<?xml version="1.0"?>
+<rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#"
+    xmlns:skos="http://www.w3.org/2004/02/skos/core#"
+    xmlns:foaf="http://xmlns.com/foaf/0.1/"
+    xmlns:rda="http://RDVocab.info/ElementsGr2/"
+    xmlns:rdalc="http://rdvocab.info/ElementsGr2/">
+  <rdf:Description rdf:about="http://id.ndl.go.jp/auth/ndlna/{TEST_ID}">
+    <foaf:primaryTopic rdf:resource="http://id.ndl.go.jp/auth/entity/{TEST_ID}"/>
+    {authority}
+  </rdf:Description>
+  <foaf:Person rdf:about="http://id.ndl.go.jp/auth/entity/{TEST_ID}">
+    <foaf:name xml:lang="ja">夏目, 漱石</foaf:name>
+    {person}
+  </foaf:Person>
+</rdf:RDF>"#
+        );
+        NDL::unfetched(TEST_ID).with_record(&rdf).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_new() {
+        assert!(NDL::new(TEST_ID).await.is_ok());
+    }
+
+    #[test]
+    fn test_my_property() {
+        assert_eq!(NDL::unfetched(TEST_ID).my_property(), 349);
+    }
+
+    #[test]
+    fn test_transform_label() {
+        let ndl = NDL::unfetched(TEST_ID);
+        assert_eq!(ndl.transform_label("夏目, 漱石"), "夏目漱石");
+        assert_eq!(ndl.transform_label("Natsume, Sōseki"), "Sōseki Natsume");
+        assert_eq!(
+            ndl.transform_label("夏目, 漱石, 1867-1916"),
+            "夏目, 漱石, 1867-1916"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_add_same_as() {
+        let ndl = ndl(
+            r#"<skos:exactMatch rdf:resource="http://viaf.org/viaf/22148223"/>
+    <skos:exactMatch rdf:resource="http://id.loc.gov/authorities/names/n79022880"/>"#,
+            "",
+        );
+        let mut mi = MetaItem::new();
+        ndl.add_same_as(&mut mi).await.unwrap();
+        let mut properties: Vec<&str> = mi
+            .item
+            .claims()
+            .iter()
+            .map(|c| c.main_snak().property())
+            .collect();
+        properties.sort();
+        assert_eq!(properties, vec!["P214", "P244"]);
+    }
+
+    #[test]
+    fn test_life_dates() {
+        // Both spellings of the RDA namespace
+        let ndl = ndl(
+            "",
+            "<rda:dateOfBirth>1867</rda:dateOfBirth><rdalc:dateOfDeath>1916-12-09</rdalc:dateOfDeath>",
+        );
+        let dates: Vec<(usize, u64)> = ndl
+            .life_dates()
+            .unwrap()
+            .into_iter()
+            .map(|(property, date)| (property, date.precision()))
+            .collect();
+        assert_eq!(dates, vec![(569, 9), (570, 11)]);
+    }
+
+    #[test]
+    fn test_occupations() {
+        let ndl = ndl(
+            "",
+            r#"<rda:professionOrOccupation>小説家、錬金術師</rda:professionOrOccupation>
+    <rda:fieldOfActivityOfThePerson>英文学</rda:fieldOfActivityOfThePerson>
+    <rda:biographicalInformation>東京帝国大学講師。英文学者、詩人</rda:biographicalInformation>"#,
+        );
+        assert_eq!(
+            ndl.occupation_terms().unwrap(),
+            vec![
+                (106, "小説家".to_string()),
+                (106, "錬金術師".to_string()),
+                (101, "英文学".to_string())
+            ]
+        );
+        let mut mi = MetaItem::new();
+        ndl.add_occupations(&mut mi).unwrap();
+        // 小説家 from the occupations, 詩人 from the biographical information
+        assert_eq!(mi.item.claims().len(), 2);
+        assert_eq!(
+            mi.prop_text,
+            vec![
+                ExternalId::new(106, "錬金術師"),
+                ExternalId::new(101, "英文学")
+            ]
+        );
+    }
+}
@@ -1,5 +1,8 @@
+use crate::external_id::*;
 use crate::external_importer::*;
 use crate::meta_item::*;
+use crate::occupation::Occupation;
+use crate::utility::Utility;
 use anyhow::Result;
 use axum::async_trait;
 use regex::Regex;
@@ -9,14 +12,27 @@ use wikimisc::wikibase::{EntityTrait, LocaleString, SnakDataType};
 
 // Was: Bibsys
 
+/// Nationality adjectives of 386 $a, and their countries
+const NATIONALITIES: &[(&str, &str)] = &[
+    ("amerikansk", "Q30"),
+    ("dansk", "Q35"),
+    ("finsk", "Q33"),
+    ("fransk", "Q142"),
+    ("islandsk", "Q189"),
+    ("italiensk", "Q38"),
+    ("nederlandsk", "Q55"),
+    ("norsk", "Q20"),
+    ("russisk", "Q159"),
+    ("spansk", "Q29"),
+    ("svensk", "Q34"),
+    ("tysk", "Q183"),
+];
+
 pub struct NORAF {
     id: String,
     j: Value,
 }
 
-unsafe impl Send for NORAF {}
-unsafe impl Sync for NORAF {}
-
 #[async_trait]
 impl ExternalImporter for NORAF {
     fn my_property(&self) -> usize {
@@ -49,14 +65,37 @@ impl ExternalImporter for NORAF {
         )
     }
 
+    /// Only names of persons are inverted
     fn transform_label(&self, s: &str) -> String {
-        self.transform_label_last_first_name(s)
+        if self.is_corporate() {
+            s.to_string()
+        } else {
+            self.transform_label_last_first_name(s)
+        }
+    }
+
+    async fn fetch(&mut self) -> Result<()> {
+        let rdf_url = format!(
+            "https://authority.bibsys.no/authority/rest/authorities/v2/{}",
+            self.id
+        );
+        let resp = Utility::get_url(&rdf_url).await?;
+        self.parse(&resp)
+    }
+
+    fn parse(&mut self, text: &str) -> Result<()> {
+        self.j = serde_json::from_str(text)?;
+        Ok(())
     }
 
     async fn run(&self) -> Result<MetaItem> {
         let mut ret = MetaItem::new();
         self.add_own_id(&mut ret)?;
+        if self.is_corporate() {
+            ret.add_claim(self.new_statement_item(31, "Q43229"));
+        }
         self.add_marcdata(&mut ret);
+        self.add_countries(&mut ret).await;
         self.parse_identifiers(&mut ret);
         self.try_rescue_prop_text(&mut ret).await?;
         ret.cleanup();
@@ -65,14 +104,17 @@ impl ExternalImporter for NORAF {
 }
 
 impl NORAF {
-    pub async fn new(id: &str) -> Result<Self> {
-        let rdf_url = format!("https://authority.bibsys.no/authority/rest/authorities/v2/{id}");
-        let resp = reqwest::get(&rdf_url).await?.text().await?;
-        let j: Value = serde_json::from_str(&resp)?;
-        Ok(Self {
+    pub fn unfetched(id: &str) -> Self {
+        Self {
             id: id.to_string(),
-            j,
-        })
+            j: Value::Null,
+        }
+    }
+
+    pub async fn new(id: &str) -> Result<Self> {
+        let mut ret = Self::unfetched(id);
+        ret.fetch().await?;
+        Ok(ret)
     }
 
     fn add_marcdata(&self, ret: &mut MetaItem) {
@@ -88,22 +130,121 @@ impl NORAF {
     fn add_field(&self, field: &serde_json::Map<String, Value>, ret: &mut MetaItem) -> Option<()> {
         let tag = field.get("tag")?.as_str()?;
         let subfields = field.get("subfields")?.as_array()?;
-        match tag {
-            "100" => {
-                subfields.iter().for_each(|sf| {
-                    match (sf["subcode"].as_str(), sf["value"].as_str()) {
-                        (Some("a"), Some(name)) => self.add_name(name, ret),
-                        (Some("d"), Some(date)) => self.add_dates(date, ret),
-                        _ => {}
-                    }
-                });
-            }
-            "386" => {}
-            _ => {}
+        if tag == "110" || tag == "410" {
+            self.add_corporate_name(tag, subfields, ret);
         }
+        subfields.iter().for_each(|sf| {
+            match (tag, sf["subcode"].as_str(), sf["value"].as_str()) {
+                ("100", Some("a"), Some(name)) => self.add_name(name, ret),
+                // Locations, like "(Oslo)"; try_rescue_prop_text looks for the city
+                ("110", Some("c"), Some(location)) => {
+                    let location = location
+                        .trim()
+                        .trim_start_matches('(')
+                        .trim_end_matches(')');
+                    let _ = ret.add_prop_text(ExternalId::new(131, location.trim()));
+                }
+                ("100", Some("d"), Some(date)) => self.add_dates(date, ret),
+                ("374", Some("a"), Some(occupation)) => {
+                    let _ = match Occupation::from_norwegian(occupation) {
+                        Some(item) => ret.add_claim(self.new_statement_item(106, item)),
+                        None => ret.add_prop_text(ExternalId::new(106, occupation.trim())),
+                    };
+                }
+                ("375", Some("a"), Some(gender)) => self.add_gender_term(gender, ret),
+                ("386", Some("a"), Some(nationality)) => {
+                    let _ = match Self::nationality_country(nationality) {
+                        Some(item) => ret.add_claim(self.new_statement_item(27, item)),
+                        None => ret.add_prop_text(ExternalId::new(27, nationality.trim())),
+                    };
+                }
+                _ => {}
+            }
+        });
         Some(())
     }
 
+    /// Corporate authorities have a 110 heading, persons a 100 heading
+    fn is_corporate(&self) -> bool {
+        !self.subfield_values("110", "a").is_empty()
+    }
+
+    /// The name, and subordinate units ($b), like "Universitetet i Oslo. Det humanistiske fakultet".
+    /// Variant names (410) become aliases.
+    fn add_corporate_name(&self, tag: &str, subfields: &[Value], ret: &mut MetaItem) {
+        let parts: Vec<&str> = subfields
+            .iter()
+            .filter(|sf| matches!(sf["subcode"].as_str(), Some("a") | Some("b")))
+            .filter_map(|sf| sf["value"].as_str())
+            .map(|part| part.trim().trim_end_matches('.').trim())
+            .filter(|part| !part.is_empty())
+            .collect();
+        if parts.is_empty() {
+            return;
+        }
+        let name = LocaleString::new(self.primary_language(), parts.join(". "));
+        if tag == "110" && ret.item.label_in_locale(name.language()).is_none() {
+            ret.item.labels_mut().push(name);
+        } else if !ret.item.aliases().contains(&name) {
+            ret.item.aliases_mut().push(name);
+        }
+    }
+
+    /// Values of a subfield, in all fields with the tag
+    fn subfield_values(&self, tag: &str, subcode: &str) -> Vec<&str> {
+        self.j["marcdata"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter(|field| field["tag"].as_str() == Some(tag))
+            .filter_map(|field| field["subfields"].as_array())
+            .flatten()
+            .filter(|sf| sf["subcode"].as_str() == Some(subcode))
+            .filter_map(|sf| sf["value"].as_str())
+            .collect()
+    }
+
+    fn add_gender_term(&self, gender: &str, ret: &mut MetaItem) {
+        let _ = match gender.trim().to_lowercase().as_str() {
+            "male" | "mann" | "menn" => ret.add_claim(self.new_statement_item(21, "Q6581097")),
+            "female" | "kvinne" | "kvinner" => {
+                ret.add_claim(self.new_statement_item(21, "Q6581072"))
+            }
+            _ => ret.add_prop_text(ExternalId::new(21, gender.trim())),
+        };
+    }
+
+    /// The country for a nationality like "Norsk"
+    fn nationality_country(nationality: &str) -> Option<&'static str> {
+        let nationality = nationality.trim().trim_end_matches('.').to_lowercase();
+        NATIONALITIES
+            .iter()
+            .find(|(adjective, _)| *adjective == nationality)
+            .map(|(_, item)| *item)
+    }
+
+    /// Countries from the ISO 3166 codes in the geographic area codes (043 $c), via P297
+    fn country_codes(&self) -> Vec<ExternalId> {
+        self.subfield_values("043", "c")
+            .into_iter()
+            .map(|code| code.trim().to_uppercase())
+            .filter(|code| code.len() == 2 && code.chars().all(|c| c.is_ascii_alphabetic()))
+            .map(|code| ExternalId::new(297, &code))
+            .collect()
+    }
+
+    /// Countries (P27) for the geographic area codes; codes without an item are left for `try_rescue_prop_text`
+    async fn add_countries(&self, ret: &mut MetaItem) {
+        let codes = self.country_codes();
+        let items = ExternalId::get_item_for_external_id_values(&codes).await;
+        for code in codes {
+            let _ = match items.get(&code) {
+                Some(item) => ret.add_claim(self.new_statement_item(27, item)),
+                None => ret.add_prop_text(code),
+            };
+        }
+    }
+
     fn add_dates(&self, date: &str, ret: &mut MetaItem) {
         lazy_static! {
             static ref RE_BORN_DIED: Regex = Regex::new(r#"^(.*)-(.*)$"#).expect("Regexp error");
@@ -139,7 +280,7 @@ impl NORAF {
                 .filter_map(|field| field.as_str())
                 .filter_map(|s| self.url2external_id(s))
                 .for_each(|ext_id| {
-                    let mut statement = self.new_statement_string(ext_id.property(), ext_id.id());
+                    let mut statement = self.new_statement_external_id(&ext_id);
                     statement.set_datatype(SnakDataType::ExternalId);
                     ret.item.add_claim(statement);
                 });
@@ -151,6 +292,106 @@ impl NORAF {
 mod tests {
     use super::*;
 
+    const TEST_RECORD: &str = r#"{"marcdata": [
+        {"tag": "043", "subfields": [{"subcode": "c", "value": "no"}]},
+        {"tag": "100", "subfields": [
+            {"subcode": "a", "value": "Undset, Sigrid"},
+            {"subcode": "d", "value": "1882-1949"}
+        ]},
+        {"tag": "374", "subfields": [{"subcode": "a", "value": "Forfatter"}]},
+        {"tag": "375", "subfields": [{"subcode": "a", "value": "kvinne"}]},
+        {"tag": "386", "subfields": [{"subcode": "a", "value": "Norsk"}]}
+    ]}"#;
+
+    #[test]
+    fn test_add_marcdata() {
+        let noraf = NORAF::unfetched("90053126")
+            .with_record(TEST_RECORD)
+            .unwrap();
+        let mut ret = MetaItem::new();
+        noraf.add_marcdata(&mut ret);
+        assert_eq!(
+            *ret.item.labels(),
+            vec![LocaleString::new("no", "Sigrid Undset")]
+        );
+        let properties: Vec<&str> = ret
+            .item
+            .claims()
+            .iter()
+            .map(|c| c.main_snak().property())
+            .collect();
+        assert_eq!(properties, vec!["P569", "P570", "P106", "P21", "P27"]);
+        assert!(ret.prop_text.is_empty());
+    }
+
+    #[test]
+    fn test_corporate() {
+        let noraf = NORAF::unfetched("90049986")
+            .with_record(
+                r#"{"marcdata": [
+                    {"tag": "110", "subfields": [
+                        {"subcode": "a", "value": "Universitetet i Oslo."},
+                        {"subcode": "b", "value": "Det humanistiske fakultet"},
+                        {"subcode": "c", "value": "(Oslo)"}
+                    ]},
+                    {"tag": "410", "subfields": [{"subcode": "a", "value": "UiO, HF"}]}
+                ]}"#,
+            )
+            .unwrap();
+        assert!(noraf.is_corporate());
+        assert_eq!(noraf.transform_label("UiO, HF"), "UiO, HF");
+        let mut ret = MetaItem::new();
+        noraf.add_marcdata(&mut ret);
+        assert_eq!(
+            *ret.item.labels(),
+            vec![LocaleString::new(
+                "no",
+                "Universitetet i Oslo. Det humanistiske fakultet"
+            )]
+        );
+        assert_eq!(
+            *ret.item.aliases(),
+            vec![LocaleString::new("no", "UiO, HF")]
+        );
+        assert_eq!(ret.prop_text, vec![ExternalId::new(131, "Oslo")]);
+        assert!(!NORAF::unfetched("90053126")
+            .with_record(TEST_RECORD)
+            .unwrap()
+            .is_corporate());
+    }
+
+    #[test]
+    fn test_country_codes() {
+        assert_eq!(
+            NORAF::unfetched("90053126")
+                .with_record(TEST_RECORD)
+                .unwrap()
+                .country_codes(),
+            vec![ExternalId::new(297, "NO")]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_add_countries() {
+        let record = r#"{"marcdata": [{"tag": "043", "subfields": [
+            {"subcode": "c", "value": "no"},
+            {"subcode": "c", "value": "xq"}
+        ]}]}"#;
+        let noraf = NORAF::unfetched("90053126").with_record(record).unwrap();
+        let mut ret = MetaItem::new();
+        noraf.add_countries(&mut ret).await;
+        assert_eq!(ret.item.claims().len(), 1);
+        assert_eq!(ret.item.claims()[0].main_snak().property(), "P27");
+        assert_eq!(ret.prop_text, vec![ExternalId::new(297, "XQ")]);
+    }
+
+    #[test]
+    fn test_nationality_country() {
+        assert_eq!(NORAF::nationality_country("Norsk"), Some("Q20"));
+        assert_eq!(NORAF::nationality_country("svensk."), Some("Q34"));
+        assert_eq!(NORAF::nationality_country("Samisk"), None);
+    }
+
     #[tokio::test]
     async fn test_add_dates() {
         let noraf = NORAF::new("123").await.unwrap();
@@ -0,0 +1,215 @@
+/// French occupation terms, as data.bnf.fr uses them, and their items; both grammatical genders where they differ
+const FRENCH: &[(&str, &str)] = &[
+    ("acteur", "Q33999"),
+    ("actrice", "Q33999"),
+    ("architecte", "Q42973"),
+    ("avocat", "Q40348"),
+    ("avocate", "Q40348"),
+    ("chimiste", "Q593644"),
+    ("compositeur", "Q36834"),
+    ("compositrice", "Q36834"),
+    ("écrivain", "Q36180"),
+    ("écrivaine", "Q36180"),
+    ("femme politique", "Q82955"),
+    ("géologue", "Q520549"),
+    ("historien", "Q201788"),
+    ("historienne", "Q201788"),
+    ("homme politique", "Q82955"),
+    ("journaliste", "Q1930187"),
+    ("mathématicien", "Q170790"),
+    ("mathématicienne", "Q170790"),
+    ("médecin", "Q39631"),
+    ("musicien", "Q639669"),
+    ("musicienne", "Q639669"),
+    ("naturaliste", "Q18805"),
+    ("peintre", "Q1028181"),
+    ("philosophe", "Q4964182"),
+    ("photographe", "Q33231"),
+    ("physicien", "Q169470"),
+    ("physicienne", "Q169470"),
+    ("poète", "Q49757"),
+    ("poétesse", "Q49757"),
+    ("romancier", "Q6625963"),
+    ("romancière", "Q6625963"),
+    ("sculpteur", "Q1281618"),
+    ("sculptrice", "Q1281618"),
+    ("traducteur", "Q333634"),
+    ("traductrice", "Q333634"),
+];
+
+/// Swedish occupation terms, as Libris uses them, and their items
+const SWEDISH: &[(&str, &str)] = &[
+    ("advokat", "Q40348"),
+    ("arkitekt", "Q42973"),
+    ("filosof", "Q4964182"),
+    ("fotograf", "Q33231"),
+    ("fysiker", "Q169470"),
+    ("författare", "Q36180"),
+    ("historiker", "Q201788"),
+    ("illustratör", "Q644687"),
+    ("journalist", "Q1930187"),
+    ("kemist", "Q593644"),
+    ("kompositör", "Q36834"),
+    ("konstnär", "Q483501"),
+    ("läkare", "Q39631"),
+    ("lärare", "Q37226"),
+    ("matematiker", "Q170790"),
+    ("musiker", "Q639669"),
+    ("målare", "Q1028181"),
+    ("naturforskare", "Q18805"),
+    ("poet", "Q49757"),
+    ("politiker", "Q82955"),
+    ("präst", "Q42603"),
+    ("romanförfattare", "Q6625963"),
+    ("sångare", "Q177220"),
+    ("skulptör", "Q1281618"),
+    ("skådespelare", "Q33999"),
+    ("tonsättare", "Q36834"),
+    ("översättare", "Q333634"),
+];
+
+/// Norwegian occupation terms, as NORAF uses them, and their items
+const NORWEGIAN: &[(&str, &str)] = &[
+    ("advokat", "Q40348"),
+    ("arkitekt", "Q42973"),
+    ("billedhugger", "Q1281618"),
+    ("dikter", "Q49757"),
+    ("filosof", "Q4964182"),
+    ("forfatter", "Q36180"),
+    ("fotograf", "Q33231"),
+    ("fysiker", "Q169470"),
+    ("historiker", "Q201788"),
+    ("illustratør", "Q644687"),
+    ("journalist", "Q1930187"),
+    ("kjemiker", "Q593644"),
+    ("komponist", "Q36834"),
+    ("kunstner", "Q483501"),
+    ("lege", "Q39631"),
+    ("lyriker", "Q49757"),
+    ("lærer", "Q37226"),
+    ("maler", "Q1028181"),
+    ("matematiker", "Q170790"),
+    ("musiker", "Q639669"),
+    ("oversetter", "Q333634"),
+    ("politiker", "Q82955"),
+    ("prest", "Q42603"),
+    ("romanforfatter", "Q6625963"),
+    ("sanger", "Q177220"),
+    ("skuespiller", "Q33999"),
+];
+
+/// Japanese occupation terms, as NDL uses them in biographical information, and their items
+const JAPANESE: &[(&str, &str)] = &[
+    ("医師", "Q39631"),
+    ("歌手", "Q177220"),
+    ("画家", "Q1028181"),
+    ("化学者", "Q593644"),
+    ("建築家", "Q42973"),
+    ("作家", "Q36180"),
+    ("作曲家", "Q36834"),
+    ("詩人", "Q49757"),
+    ("実業家", "Q43845"),
+    ("写真家", "Q33231"),
+    ("小説家", "Q6625963"),
+    ("政治家", "Q82955"),
+    ("彫刻家", "Q1281618"),
+    ("哲学者", "Q4964182"),
+    ("俳優", "Q33999"),
+    ("物理学者", "Q169470"),
+    ("弁護士", "Q40348"),
+    ("翻訳家", "Q333634"),
+    ("漫画家", "Q191633"),
+    ("歴史学者", "Q201788"),
+    ("ジャーナリスト", "Q1930187"),
+];
+
+/// Maps occupation terms of the sources to items
+pub struct Occupation {}
+
+impl Occupation {
+    /// The item for a French term like "Écrivain"
+    pub fn from_french(term: &str) -> Option<&'static str> {
+        Self::lookup(FRENCH, term)
+    }
+
+    /// The item for a Swedish term like "Författare"
+    pub fn from_swedish(term: &str) -> Option<&'static str> {
+        Self::lookup(SWEDISH, term)
+    }
+
+    /// The item for a Norwegian term like "Forfatter"
+    pub fn from_norwegian(term: &str) -> Option<&'static str> {
+        Self::lookup(NORWEGIAN, term)
+    }
+
+    /// The item for a Japanese term like "小説家"
+    pub fn from_japanese(term: &str) -> Option<&'static str> {
+        Self::lookup(JAPANESE, term)
+    }
+
+    fn lookup(table: &[(&str, &'static str)], term: &str) -> Option<&'static str> {
+        let term = term.trim().trim_end_matches('.').trim().to_lowercase();
+        table
+            .iter()
+            .find(|(name, _)| *name == term)
+            .map(|(_, item)| *item)
+    }
+
+    /// Splits a list like "Naturaliste. - Géologue ; Explorateur" or "小説家、英文学者" into its terms; hyphens within
+    /// words are kept
+    pub fn split_terms(s: &str) -> Vec<String> {
+        s.split([';', '；', '、', '，'])
+            .flat_map(|part| part.split(" - "))
+            .map(|term| term.trim().trim_end_matches('.').trim())
+            .filter(|term| !term.is_empty())
+            .map(|term| term.to_string())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_french() {
+        assert_eq!(Occupation::from_french("Écrivain"), Some("Q36180"));
+        assert_eq!(
+            Occupation::from_french(" Homme politique. "),
+            Some("Q82955")
+        );
+        assert_eq!(Occupation::from_french("Alchimiste"), None);
+    }
+
+    #[test]
+    fn test_from_swedish() {
+        assert_eq!(Occupation::from_swedish("Författare"), Some("Q36180"));
+        assert_eq!(Occupation::from_swedish("tonsättare"), Some("Q36834"));
+        assert_eq!(Occupation::from_swedish("Alkemist"), None);
+    }
+
+    #[test]
+    fn test_from_norwegian() {
+        assert_eq!(Occupation::from_norwegian("Forfatter"), Some("Q36180"));
+        assert_eq!(Occupation::from_norwegian("billedhugger"), Some("Q1281618"));
+        assert_eq!(Occupation::from_norwegian("Alkymist"), None);
+    }
+
+    #[test]
+    fn test_from_japanese() {
+        assert_eq!(Occupation::from_japanese("小説家"), Some("Q6625963"));
+        assert_eq!(Occupation::from_japanese("錬金術師"), None);
+    }
+
+    #[test]
+    fn test_split_terms() {
+        assert_eq!(
+            Occupation::split_terms("Naturaliste. - Géologue ; Saint-simonien"),
+            vec!["Naturaliste", "Géologue", "Saint-simonien"]
+        );
+        assert_eq!(
+            Occupation::split_terms("小説家、英文学者；詩人"),
+            vec!["小説家", "英文学者", "詩人"]
+        );
+    }
+}
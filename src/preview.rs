@@ -0,0 +1,374 @@
+use crate::i18n::I18n;
+use crate::merge_diff_ext::MergeDiffExt;
+use crate::target::Target;
+use crate::utility::Utility;
+use anyhow::Result;
+use serde_json::Value;
+use std::collections::HashMap;
+use wikimisc::merge_diff::MergeDiff;
+
+const LABELS_PER_REQUEST: usize = 50;
+
+/// Renders a `MergeDiff` as a human-readable HTML table
+pub struct Preview {
+    item: String,
+    diff: Value,
+    i18n: I18n,
+    labels: HashMap<String, String>,
+}
+
+impl Preview {
+    pub fn new(item: &str, diff: &MergeDiff, i18n: I18n) -> Self {
+        Self {
+            item: item.to_string(),
+            diff: diff.to_target_json(),
+            i18n,
+            labels: HashMap::new(),
+        }
+    }
+
+    /// Loads labels for all properties and items used in the diff, in the interface language.
+    pub async fn load_labels(&mut self) -> Result<()> {
+        let mut ids: Vec<String> = self.get_entity_ids();
+        ids.sort();
+        ids.dedup();
+        let languages = format!("{}|en", self.i18n.language());
+        for chunk in ids.chunks(LABELS_PER_REQUEST) {
+            let url = format!(
+                "{}?action=wbgetentities&ids={}&props=labels&languages={languages}&languagefallback=1&format=json",
+                Target::api(),
+                chunk.join("|")
+            );
+            let text = Utility::get_url(&url).await?;
+            let j: Value = serde_json::from_str(&text)?;
+            let entities = match j["entities"].as_object() {
+                Some(entities) => entities,
+                None => continue,
+            };
+            for (id, entity) in entities {
+                let label = entity["labels"][self.i18n.language()]["value"]
+                    .as_str()
+                    .or_else(|| entity["labels"]["en"]["value"].as_str());
+                if let Some(label) = label {
+                    self.labels.insert(id.to_owned(), label.to_string());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn as_html(&self) -> String {
+        let mut html = format!(
+            "<h1>{}</h1>",
+            self.i18n
+                .message_with_params("preview-title", &[self.entity_link(&self.item).as_str()])
+        );
+        let statements = self.get_statements();
+        let terms = self.get_terms();
+        if statements.is_empty() && terms.is_empty() {
+            html += &format!("<p>{}</p>", self.i18n.message("preview-no-changes"));
+            return html;
+        }
+        if !statements.is_empty() {
+            html += &self.statements_as_html(&statements);
+        }
+        if !terms.is_empty() {
+            html += &self.terms_as_html(&terms);
+        }
+        html
+    }
+
+    fn statements_as_html(&self, statements: &[Value]) -> String {
+        let mut html = r#"<table class="table table-sm table-striped"><thead><tr>"#.to_string();
+        for key in [
+            "preview-property",
+            "preview-value",
+            "preview-change",
+            "preview-references",
+        ] {
+            html += &format!("<th>{}</th>", self.i18n.message(key));
+        }
+        html += "</tr></thead><tbody>";
+        for statement in statements {
+            let snak = &statement["mainsnak"];
+            let property = snak["property"].as_str().unwrap_or_default();
+            let change = match statement.get("id") {
+                Some(_) => self.i18n.message("preview-references-added"),
+                None => self.i18n.message("preview-added"),
+            };
+            let references: Vec<String> = statement["references"]
+                .as_array()
+                .map(|refs| refs.iter().map(|r| self.reference_as_html(r)).collect())
+                .unwrap_or_default();
+            html += &format!(
+                "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                self.entity_link(property),
+                self.snak_value_as_html(snak),
+                change,
+                references.join("<hr/>")
+            );
+        }
+        html += "</tbody></table>";
+        html
+    }
+
+    fn terms_as_html(&self, terms: &[(String, String, String)]) -> String {
+        let mut html = format!(
+            r#"<h2>{}</h2><table class="table table-sm table-striped"><thead><tr>"#,
+            self.i18n.message("preview-terms")
+        );
+        for key in ["preview-type", "preview-language", "preview-value"] {
+            html += &format!("<th>{}</th>", self.i18n.message(key));
+        }
+        html += "</tr></thead><tbody>";
+        for (term_type, language, value) in terms {
+            html += &format!(
+                "<tr><td>{}</td><td>{}</td><td>{}</td></tr>",
+                self.i18n.message(&format!("preview-{term_type}")),
+                Utility::html_escape(language),
+                Utility::html_escape(value)
+            );
+        }
+        html += "</tbody></table>";
+        html
+    }
+
+    fn reference_as_html(&self, reference: &Value) -> String {
+        Self::snaks(&reference["snaks"])
+            .iter()
+            .map(|snak| {
+                let property = snak["property"].as_str().unwrap_or_default();
+                format!(
+                    "{}: {}",
+                    self.entity_link(property),
+                    self.snak_value_as_html(snak)
+                )
+            })
+            .collect::<Vec<String>>()
+            .join("<br/>")
+    }
+
+    fn snak_value_as_html(&self, snak: &Value) -> String {
+        match snak["snaktype"].as_str() {
+            Some("somevalue") => {
+                return format!("<i>{}</i>", self.i18n.message("preview-somevalue"))
+            }
+            Some("novalue") => return format!("<i>{}</i>", self.i18n.message("preview-novalue")),
+            _ => {}
+        }
+        let datavalue = &snak["datavalue"];
+        let value = &datavalue["value"];
+        match datavalue["type"].as_str().unwrap_or_default() {
+            "wikibase-entityid" => self.entity_link(value["id"].as_str().unwrap_or_default()),
+            "string" => {
+                let s = value.as_str().unwrap_or_default();
+                match snak["datatype"].as_str().unwrap_or_default() {
+                    "url" if Self::is_web_url(s) => format!(
+                        r#"<a href="{}" class="external">{}</a>"#,
+                        Utility::html_escape(s),
+                        Utility::html_escape(s)
+                    ),
+                    "commonsMedia" => format!(
+                        r#"<a href="https://commons.wikimedia.org/wiki/File:{}">{}</a>"#,
+                        Utility::html_escape(&s.replace(' ', "_")),
+                        Utility::html_escape(s)
+                    ),
+                    "external-id" => format!("<tt>{}</tt>", Utility::html_escape(s)),
+                    _ => Utility::html_escape(s),
+                }
+            }
+            "time" => Utility::html_escape(&Self::format_time(value)),
+            "monolingualtext" => format!(
+                "{} <small>[{}]</small>",
+                Utility::html_escape(value["text"].as_str().unwrap_or_default()),
+                Utility::html_escape(value["language"].as_str().unwrap_or_default())
+            ),
+            "quantity" => Utility::html_escape(value["amount"].as_str().unwrap_or_default()),
+            "globecoordinate" => format!(
+                "{}, {}",
+                value["latitude"].as_f64().unwrap_or_default(),
+                value["longitude"].as_f64().unwrap_or_default()
+            ),
+            _ => Utility::html_escape(&value.to_string()),
+        }
+    }
+
+    /// Only http(s) URLs are linked; anything else (eg `javascript:`) is shown as text
+    fn is_web_url(s: &str) -> bool {
+        let s = s.trim_start().to_ascii_lowercase();
+        s.starts_with("http://") || s.starts_with("https://")
+    }
+
+    /// Shortens a Wikibase time value to its precision, eg "+1809-02-12T00:00:00Z"/9 => "1809"
+    fn format_time(value: &Value) -> String {
+        let time = value["time"].as_str().unwrap_or_default();
+        let time = time.trim_start_matches('+');
+        let len = match value["precision"].as_u64() {
+            Some(9) => 4,
+            Some(10) => 7,
+            Some(11) => 10,
+            _ => time.len(),
+        };
+        time.get(..len).unwrap_or(time).to_string()
+    }
+
+    fn entity_link(&self, id: &str) -> String {
+        let id = Utility::html_escape(id);
+        let page = if id.starts_with('P') {
+            format!("Property:{id}")
+        } else {
+            id.to_owned()
+        };
+        match self.labels.get(&id) {
+            Some(label) => format!(
+                r#"<a href="{}/{page}">{}</a> <small>({id})</small>"#,
+                Target::wiki(),
+                Utility::html_escape(label)
+            ),
+            None => format!(r#"<a href="{}/{page}">{id}</a>"#, Target::wiki()),
+        }
+    }
+
+    /// Returns all statements from the diff; supports both list and property-map forms.
+    fn get_statements(&self) -> Vec<Value> {
+        match &self.diff["claims"] {
+            Value::Array(a) => a.to_owned(),
+            Value::Object(o) => o
+                .values()
+                .filter_map(|v| v.as_array())
+                .flatten()
+                .cloned()
+                .collect(),
+            _ => vec![],
+        }
+    }
+
+    /// Returns (type, language, value) for all labels, descriptions, and aliases in the diff.
+    fn get_terms(&self) -> Vec<(String, String, String)> {
+        let mut ret = vec![];
+        for key in ["labels", "descriptions", "aliases"] {
+            let values: Vec<&Value> = match &self.diff[key] {
+                Value::Array(a) => a.iter().collect(),
+                Value::Object(o) => o
+                    .values()
+                    .flat_map(|v| match v {
+                        Value::Array(a) => a.iter().collect(),
+                        v => vec![v],
+                    })
+                    .collect(),
+                _ => vec![],
+            };
+            for v in values {
+                if let (Some(language), Some(value)) = (v["language"].as_str(), v["value"].as_str())
+                {
+                    ret.push((key.to_string(), language.to_string(), value.to_string()));
+                }
+            }
+        }
+        ret
+    }
+
+    /// Returns all snaks in a snak list; supports both list and property-map forms.
+    fn snaks(j: &Value) -> Vec<Value> {
+        match j {
+            Value::Array(a) => a.to_owned(),
+            Value::Object(o) => o
+                .values()
+                .filter_map(|v| v.as_array())
+                .flatten()
+                .cloned()
+                .collect(),
+            _ => vec![],
+        }
+    }
+
+    fn get_entity_ids(&self) -> Vec<String> {
+        let mut ret = vec![self.item.to_owned()];
+        for statement in self.get_statements() {
+            let mut snaks = vec![statement["mainsnak"].to_owned()];
+            if let Some(refs) = statement["references"].as_array() {
+                for r in refs {
+                    snaks.append(&mut Self::snaks(&r["snaks"]));
+                }
+            }
+            for snak in snaks {
+                if let Some(property) = snak["property"].as_str() {
+                    ret.push(property.to_string());
+                }
+                if let Some(id) = snak["datavalue"]["value"]["id"].as_str() {
+                    ret.push(id.to_string());
+                }
+            }
+        }
+        ret
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn test_preview(diff: Value) -> Preview {
+        Preview {
+            item: "Q1035".to_string(),
+            diff,
+            i18n: I18n::new("en"),
+            labels: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn test_format_time() {
+        let v = json!({"time":"+1809-02-12T00:00:00Z","precision":11});
+        assert_eq!(Preview::format_time(&v), "1809-02-12");
+        let v = json!({"time":"+1809-02-12T00:00:00Z","precision":9});
+        assert_eq!(Preview::format_time(&v), "1809");
+    }
+
+    #[test]
+    fn test_get_entity_ids() {
+        let preview = test_preview(json!({"claims":[{
+            "mainsnak":{"snaktype":"value","property":"P27","datavalue":{"type":"wikibase-entityid","value":{"id":"Q145"}}},
+            "references":[{"snaks":{"P248":[{"snaktype":"value","property":"P248","datavalue":{"type":"wikibase-entityid","value":{"id":"Q36578"}}}]}}]
+        }]}));
+        let mut ids = preview.get_entity_ids();
+        ids.sort();
+        assert_eq!(ids, vec!["P248", "P27", "Q1035", "Q145", "Q36578"]);
+    }
+
+    #[test]
+    fn test_as_html() {
+        let preview = test_preview(json!({}));
+        assert!(preview.as_html().contains("No changes proposed."));
+        let preview = test_preview(json!({"claims":[{
+            "mainsnak":{"snaktype":"value","property":"P1015","datatype":"external-id","datavalue":{"type":"string","value":"90053126"}}
+        }],"labels":{"de":{"language":"de","value":"Charles Darwin"}}}));
+        let html = preview.as_html();
+        assert!(html.contains("<tt>90053126</tt>"));
+        assert!(html.contains("new statement"));
+        assert!(html.contains("<td>label</td><td>de</td><td>Charles Darwin</td>"));
+    }
+
+    #[test]
+    fn test_snak_value_as_html() {
+        let preview = test_preview(json!({}));
+        let url = |s: &str| json!({"snaktype":"value","property":"P973","datatype":"url","datavalue":{"type":"string","value":s}});
+        assert_eq!(
+            preview.snak_value_as_html(&url("https://example.org/?a=1&b=2")),
+            r#"<a href="https://example.org/?a=1&amp;b=2" class="external">https://example.org/?a=1&amp;b=2</a>"#
+        );
+        assert_eq!(
+            preview.snak_value_as_html(&url("javascript:alert(1)")),
+            "javascript:alert(1)"
+        );
+        let preview = Preview {
+            i18n: I18n::new("de"),
+            ..test_preview(json!({}))
+        };
+        assert_eq!(
+            preview.snak_value_as_html(&json!({"snaktype":"somevalue","property":"P569"})),
+            "<i>unbekannter Wert</i>"
+        );
+    }
+}
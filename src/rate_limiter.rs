@@ -0,0 +1,154 @@
+use crate::app_error::AppError;
+use crate::config::Config;
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tokio::time::Instant;
+
+/// Longest a request waits for its turn; beyond that the host is considered overloaded
+const MAX_WAIT: Duration = Duration::from_secs(30);
+
+lazy_static! {
+    static ref RATE_LIMITER: RateLimiter = RateLimiter::new(&Config::get().http.rate_limits);
+}
+
+/// Spaces out requests to the same host, so batch runs stay within the limits of authority providers.
+/// Shared by all fetches, including the concurrent ones of the `Combinator`.
+#[derive(Debug)]
+pub struct RateLimiter {
+    /// Minimum time between two requests, per host
+    intervals: HashMap<String, Duration>,
+    /// The earliest time the next request to a host may start
+    next_slot: Mutex<HashMap<String, Instant>>,
+}
+
+impl RateLimiter {
+    /// `limits` are requests per second, per host; a host also covers its subdomains, unless a subdomain has its own
+    /// limit
+    pub fn new(limits: &HashMap<String, f64>) -> Self {
+        let intervals = limits
+            .iter()
+            .filter(|(_, per_second)| **per_second > 0.0)
+            .map(|(host, per_second)| {
+                (
+                    host.to_lowercase(),
+                    Duration::from_secs_f64(1.0 / per_second),
+                )
+            })
+            .collect();
+        Self {
+            intervals,
+            next_slot: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The limiter configured via `http.rate_limits`
+    pub fn global() -> &'static Self {
+        &RATE_LIMITER
+    }
+
+    /// Waits until a request to the host of `url` is allowed.
+    /// Fails with `AppError::Overloaded` without taking a turn if that would take longer than `MAX_WAIT`.
+    pub async fn wait(&self, url: &reqwest::Url) -> Result<(), AppError> {
+        let (host, interval) = match url.host_str().and_then(|host| self.limit_for(host)) {
+            Some(limit) => limit,
+            None => return Ok(()),
+        };
+        let slot = {
+            let mut next_slot = self.next_slot.lock().await;
+            let now = Instant::now();
+            let slot = next_slot.get(&host).map_or(now, |next| (*next).max(now));
+            if slot - now > MAX_WAIT {
+                return Err(AppError::Overloaded(format!(
+                    "Too many requests to {host}, please try again later"
+                )));
+            }
+            next_slot.insert(host, slot + interval);
+            slot
+        };
+        tokio::time::sleep_until(slot).await;
+        Ok(())
+    }
+
+    /// The most specific limit for `host`, the one with the longest matching host name
+    fn limit_for(&self, host: &str) -> Option<(String, Duration)> {
+        let host = host.to_lowercase();
+        self.intervals
+            .iter()
+            .filter(|(limited, _)| host == **limited || host.ends_with(&format!(".{limited}")))
+            .max_by_key(|(limited, _)| limited.len())
+            .map(|(limited, interval)| (limited.to_owned(), *interval))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter() -> RateLimiter {
+        RateLimiter::new(&HashMap::from([("viaf.org".to_string(), 5.0)]))
+    }
+
+    #[test]
+    fn test_limit_for() {
+        let limiter = limiter();
+        assert_eq!(
+            limiter.limit_for("www.VIAF.org"),
+            Some(("viaf.org".to_string(), Duration::from_millis(200)))
+        );
+        assert_eq!(limiter.limit_for("notviaf.org"), None);
+        assert_eq!(limiter.limit_for("d-nb.info"), None);
+
+        let limiter = RateLimiter::new(&HashMap::from([
+            ("ncbi.nlm.nih.gov".to_string(), 10.0),
+            ("eutils.ncbi.nlm.nih.gov".to_string(), 3.0),
+            ("nih.gov".to_string(), 20.0),
+        ]));
+        for _ in 0..10 {
+            assert_eq!(
+                limiter.limit_for("eutils.ncbi.nlm.nih.gov"),
+                Some((
+                    "eutils.ncbi.nlm.nih.gov".to_string(),
+                    Duration::from_secs_f64(1.0 / 3.0)
+                ))
+            );
+        }
+        assert_eq!(
+            limiter.limit_for("www.ncbi.nlm.nih.gov"),
+            Some(("ncbi.nlm.nih.gov".to_string(), Duration::from_millis(100)))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_wait() {
+        let limiter = RateLimiter::new(&HashMap::from([("viaf.org".to_string(), 50.0)]));
+        let url = reqwest::Url::parse("https://viaf.org/viaf/30701597").unwrap();
+        let start = Instant::now();
+        for _ in 0..3 {
+            limiter.wait(&url).await.unwrap();
+        }
+        assert!(start.elapsed() >= Duration::from_millis(40));
+        let other = reqwest::Url::parse("https://d-nb.info/gnd/118523813").unwrap();
+        let start = Instant::now();
+        limiter.wait(&other).await.unwrap();
+        assert!(start.elapsed() < Duration::from_millis(20));
+    }
+
+    #[tokio::test]
+    async fn test_wait_capped() {
+        // One request every 20 seconds, so the third one would have to wait 40 seconds
+        let limiter = RateLimiter::new(&HashMap::from([("viaf.org".to_string(), 0.05)]));
+        let url = reqwest::Url::parse("https://viaf.org/viaf/30701597").unwrap();
+        limiter.wait(&url).await.unwrap();
+        let start = Instant::now();
+        // Takes the second turn, then sleeps
+        let second = limiter.wait(&url);
+        tokio::pin!(second);
+        assert!(futures::poll!(second.as_mut()).is_pending());
+        assert!(matches!(
+            limiter.wait(&url).await,
+            Err(AppError::Overloaded(_))
+        ));
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+}
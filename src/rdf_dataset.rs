@@ -0,0 +1,119 @@
+use crate::external_id::ExternalId;
+use crate::external_importer::ExternalImporter;
+use sophia::api::prelude::*;
+use sophia::turtle::serializer::nt::NtSerializer;
+
+pub const TRIG_CONTENT_TYPE: &str = "application/trig";
+
+/// The raw RDF graph of one source record, as N-Triples
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceGraph {
+    pub ext_id: ExternalId,
+    /// The graph IRI; the record URL, if it is a valid IRI
+    pub name: String,
+    pub ntriples: String,
+}
+
+impl SourceGraph {
+    /// `None` for importers that do not read RDF, or an empty graph
+    pub fn from_parser(parser: &dyn ExternalImporter) -> Option<Self> {
+        let ntriples = NtSerializer::new_stringifier()
+            .serialize_graph(parser.graph())
+            .ok()?
+            .to_string();
+        if ntriples.trim().is_empty() {
+            return None;
+        }
+        let ext_id = ExternalId::new(parser.my_property(), &parser.my_id());
+        let name = match reqwest::Url::parse(&parser.get_id_url()) {
+            Ok(url) => url.to_string(),
+            Err(_) => format!("urn:auth2wd:{ext_id}"),
+        };
+        Some(Self {
+            ext_id,
+            name,
+            ntriples,
+        })
+    }
+}
+
+/// The graphs of all records imported for an entity, as one dataset with a named graph per source.
+/// This is what the authorities asserted, before any mapping to Wikidata statements.
+#[derive(Debug, Clone, Default)]
+pub struct RdfDataset {
+    graphs: Vec<SourceGraph>,
+}
+
+impl RdfDataset {
+    pub fn new(graphs: Vec<SourceGraph>) -> Self {
+        Self { graphs }
+    }
+
+    pub fn to_trig(&self) -> String {
+        let mut ret = String::new();
+        for (num, graph) in self.graphs.iter().enumerate() {
+            ret += &format!("# {}\n<{}> {{\n", graph.ext_id, graph.name);
+            for line in graph.ntriples.lines().filter(|l| !l.trim().is_empty()) {
+                ret += &format!("    {}\n", Self::relabel_blank_nodes(line, num));
+            }
+            ret += "}\n\n";
+        }
+        ret
+    }
+
+    /// Blank node labels are scoped to the whole TriG document, but every source was parsed separately,
+    /// so the labels of each graph get their own prefix. In N-Triples, blank nodes can only be the whole
+    /// subject or object, so literals are never touched.
+    fn relabel_blank_nodes(line: &str, graph_num: usize) -> String {
+        let relabel = |term: &str| match term.strip_prefix("_:") {
+            Some(label) => format!("_:g{graph_num}_{label}"),
+            None => term.to_string(),
+        };
+        let line = line.trim();
+        let mut parts = line.splitn(3, ' ');
+        let (subject, predicate, rest) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(s), Some(p), Some(rest)) => (s, p, rest),
+            _ => return line.to_string(),
+        };
+        let object = rest.strip_suffix(" .").unwrap_or(rest);
+        format!("{} {predicate} {} .", relabel(subject), relabel(object))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn source_graph(property: usize, id: &str, ntriples: &str) -> SourceGraph {
+        SourceGraph {
+            ext_id: ExternalId::new(property, id),
+            name: format!("https://example.org/{id}"),
+            ntriples: ntriples.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_relabel_blank_nodes() {
+        assert_eq!(
+            RdfDataset::relabel_blank_nodes("_:n1 <http://schema.org/name> \"_:not a node\" .", 2),
+            "_:g2_n1 <http://schema.org/name> \"_:not a node\" ."
+        );
+        assert_eq!(
+            RdfDataset::relabel_blank_nodes("<https://a> <https://b> _:n1 .", 0),
+            "<https://a> <https://b> _:g0_n1 ."
+        );
+    }
+
+    #[test]
+    fn test_to_trig() {
+        let dataset = RdfDataset::new(vec![
+            source_graph(227, "118523813", "_:b0 <https://p> \"x\"@de .\n"),
+            source_graph(214, "30701597", "<https://s> <https://p> _:b0 .\n"),
+        ]);
+        assert_eq!(
+            dataset.to_trig(),
+            "# P227:118523813\n<https://example.org/118523813> {\n    _:g0_b0 <https://p> \"x\"@de .\n}\n\n\
+             # P214:30701597\n<https://example.org/30701597> {\n    <https://s> <https://p> _:g1_b0 .\n}\n\n"
+        );
+    }
+}
@@ -0,0 +1,538 @@
+use crate::utility::Utility;
+use anyhow::{anyhow, Result};
+use serde_json::Value;
+use std::str::FromStr;
+
+/// Namespaces of the Wikidata RDF dump format, in the order they are written as Turtle prefixes
+const PREFIXES: &[(&str, &str)] = &[
+    ("wd", "http://www.wikidata.org/entity/"),
+    ("wds", "http://www.wikidata.org/entity/statement/"),
+    ("wdref", "http://www.wikidata.org/reference/"),
+    ("wdt", "http://www.wikidata.org/prop/direct/"),
+    ("wdno", "http://www.wikidata.org/prop/novalue/"),
+    ("p", "http://www.wikidata.org/prop/"),
+    ("ps", "http://www.wikidata.org/prop/statement/"),
+    ("pq", "http://www.wikidata.org/prop/qualifier/"),
+    ("pr", "http://www.wikidata.org/prop/reference/"),
+    ("wikibase", "http://wikiba.se/ontology#"),
+    ("prov", "http://www.w3.org/ns/prov#"),
+    ("rdf", "http://www.w3.org/1999/02/22-rdf-syntax-ns#"),
+    ("rdfs", "http://www.w3.org/2000/01/rdf-schema#"),
+    ("schema", "http://schema.org/"),
+    ("skos", "http://www.w3.org/2004/02/skos/core#"),
+    ("xsd", "http://www.w3.org/2001/XMLSchema#"),
+    ("geo", "http://www.opengis.net/ont/geosparql#"),
+];
+const COMMONS_FILE_PATH: &str = "http://commons.wikimedia.org/wiki/Special:FilePath/";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RdfFormat {
+    Turtle,
+    NTriples,
+}
+
+impl RdfFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Self::Turtle => "text/turtle; charset=utf-8",
+            Self::NTriples => "application/n-triples",
+        }
+    }
+
+    /// The first RDF format in an `Accept` header, if any
+    pub fn from_accept(accept: &str) -> Option<Self> {
+        accept
+            .split(',')
+            .filter_map(|part| part.split(';').next())
+            .find_map(|mime| Self::from_str(mime.trim()).ok())
+    }
+}
+
+impl FromStr for RdfFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "text/turtle" | "application/x-turtle" | "turtle" | "ttl" => Ok(Self::Turtle),
+            "application/n-triples" | "ntriples" | "nt" => Ok(Self::NTriples),
+            other => Err(anyhow!("Unknown RDF format '{other}'")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Term {
+    Iri(String),
+    Blank(String),
+    Literal {
+        value: String,
+        datatype: Option<String>,
+        language: Option<String>,
+    },
+}
+
+impl Term {
+    /// An IRI in one of the `PREFIXES` namespaces
+    fn prefixed(prefix: &str, local: &str) -> Self {
+        Self::Iri(Self::expand(prefix, local))
+    }
+
+    fn expand(prefix: &str, local: &str) -> String {
+        let namespace = PREFIXES
+            .iter()
+            .find(|(p, _)| *p == prefix)
+            .map(|(_, ns)| *ns)
+            .unwrap_or_default();
+        format!("{namespace}{local}")
+    }
+
+    fn string(value: &str) -> Self {
+        Self::Literal {
+            value: value.to_string(),
+            datatype: None,
+            language: None,
+        }
+    }
+
+    fn typed(value: &str, prefix: &str, local: &str) -> Self {
+        Self::Literal {
+            value: value.to_string(),
+            datatype: Some(Self::expand(prefix, local)),
+            language: None,
+        }
+    }
+
+    fn language(value: &str, language: &str) -> Self {
+        Self::Literal {
+            value: value.to_string(),
+            datatype: None,
+            language: Some(language.to_string()),
+        }
+    }
+
+    fn to_ntriples(&self) -> String {
+        match self {
+            Self::Iri(iri) => format!("<{}>", Self::escape_iri(iri)),
+            Self::Blank(id) => format!("_:{id}"),
+            Self::Literal {
+                value,
+                datatype,
+                language,
+            } => {
+                let value = Self::escape_literal(value);
+                match (datatype, language) {
+                    (_, Some(language)) => format!("{value}@{language}"),
+                    (Some(datatype), None) => format!("{value}^^<{datatype}>"),
+                    (None, None) => value,
+                }
+            }
+        }
+    }
+
+    fn to_turtle(&self) -> String {
+        match self {
+            Self::Iri(iri) => Self::compact(iri).unwrap_or_else(|| self.to_ntriples()),
+            Self::Literal {
+                value,
+                datatype: Some(datatype),
+                language: None,
+            } => match Self::compact(datatype) {
+                Some(datatype) => format!("{}^^{datatype}", Self::escape_literal(value)),
+                None => self.to_ntriples(),
+            },
+            _ => self.to_ntriples(),
+        }
+    }
+
+    /// The prefixed name for an IRI, if the local part can be written as one
+    fn compact(iri: &str) -> Option<String> {
+        PREFIXES.iter().find_map(|(prefix, namespace)| {
+            let local = iri.strip_prefix(namespace)?;
+            let simple = !local.is_empty()
+                && local
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+                && !local.starts_with('-');
+            simple.then(|| format!("{prefix}:{local}"))
+        })
+    }
+
+    fn escape_literal(s: &str) -> String {
+        let mut ret = String::with_capacity(s.len() + 2);
+        ret.push('"');
+        for c in s.chars() {
+            match c {
+                '\\' => ret.push_str("\\\\"),
+                '"' => ret.push_str("\\\""),
+                '\n' => ret.push_str("\\n"),
+                '\r' => ret.push_str("\\r"),
+                '\t' => ret.push_str("\\t"),
+                c => ret.push(c),
+            }
+        }
+        ret.push('"');
+        ret
+    }
+
+    fn escape_iri(iri: &str) -> String {
+        iri.chars()
+            .map(|c| match c {
+                '<' | '>' | '"' | '{' | '}' | '|' | '^' | '`' | '\\' | ' ' => {
+                    format!("%{:02X}", c as u32)
+                }
+                c => c.to_string(),
+            })
+            .collect()
+    }
+}
+
+/// Converts Wikibase item JSON into RDF, following the mapping of the Wikidata RDF dumps:
+/// truthy `wdt:` values for best-rank statements, and full `p:`/`ps:`/`pq:` statement nodes
+/// with `prov:wasDerivedFrom` references.
+pub struct RdfExport {
+    triples: Vec<(Term, Term, Term)>,
+    blank_nodes: usize,
+}
+
+impl RdfExport {
+    /// A constructed item without an ID becomes the blank node `_:item`
+    pub fn from_item(j: &Value) -> Self {
+        let mut ret = Self {
+            triples: vec![],
+            blank_nodes: 0,
+        };
+        ret.add_item(j);
+        ret
+    }
+
+    pub fn render(&self, format: RdfFormat) -> String {
+        match format {
+            RdfFormat::NTriples => self.as_ntriples(),
+            RdfFormat::Turtle => self.as_turtle(),
+        }
+    }
+
+    fn as_ntriples(&self) -> String {
+        self.triples
+            .iter()
+            .map(|(s, p, o)| {
+                format!(
+                    "{} {} {} .\n",
+                    s.to_ntriples(),
+                    p.to_ntriples(),
+                    o.to_ntriples()
+                )
+            })
+            .collect()
+    }
+
+    fn as_turtle(&self) -> String {
+        let mut ret: String = PREFIXES
+            .iter()
+            .map(|(prefix, namespace)| format!("@prefix {prefix}: <{namespace}> .\n"))
+            .collect();
+        let mut last_subject = None;
+        for (s, p, o) in &self.triples {
+            if last_subject == Some(s) {
+                ret.pop(); // " .\n" becomes " ;\n"
+                ret.pop();
+                ret.push_str(";\n");
+                ret.push_str(&format!("    {} {} .\n", p.to_turtle(), o.to_turtle()));
+            } else {
+                ret.push('\n');
+                ret.push_str(&format!(
+                    "{} {} {} .\n",
+                    s.to_turtle(),
+                    p.to_turtle(),
+                    o.to_turtle()
+                ));
+            }
+            last_subject = Some(s);
+        }
+        ret
+    }
+
+    fn add(&mut self, subject: &Term, predicate: Term, object: Term) {
+        self.triples.push((subject.to_owned(), predicate, object));
+    }
+
+    fn new_blank_node(&mut self, name: &str) -> Term {
+        self.blank_nodes += 1;
+        Term::Blank(format!("{name}{}", self.blank_nodes))
+    }
+
+    fn add_item(&mut self, j: &Value) {
+        let subject = match j["id"].as_str().filter(|id| !id.is_empty() && *id != "Q0") {
+            Some(id) => Term::prefixed("wd", id),
+            None => Term::Blank("item".to_string()),
+        };
+        self.add(
+            &subject,
+            Term::prefixed("rdf", "type"),
+            Term::prefixed("wikibase", "Item"),
+        );
+        for label in Utility::json_values(&j["labels"]) {
+            if let Some(term) = Self::term(&label) {
+                for (prefix, local) in
+                    [("rdfs", "label"), ("skos", "prefLabel"), ("schema", "name")]
+                {
+                    self.add(&subject, Term::prefixed(prefix, local), term.to_owned());
+                }
+            }
+        }
+        for description in Utility::json_values(&j["descriptions"]) {
+            if let Some(term) = Self::term(&description) {
+                self.add(&subject, Term::prefixed("schema", "description"), term);
+            }
+        }
+        for alias in Utility::json_values(&j["aliases"]) {
+            if let Some(term) = Self::term(&alias) {
+                self.add(&subject, Term::prefixed("skos", "altLabel"), term);
+            }
+        }
+        let statements = Utility::json_values(&j["claims"]);
+        for statement in &statements {
+            let property = match statement["mainsnak"]["property"].as_str() {
+                Some(property) => property,
+                None => continue,
+            };
+            let best_rank = Self::best_rank(&statements, property);
+            self.add_statement(&subject, property, statement, best_rank);
+        }
+    }
+
+    /// "preferred" if the property has a preferred statement, otherwise "normal"
+    fn best_rank(statements: &[Value], property: &str) -> &'static str {
+        let has_preferred = statements.iter().any(|s| {
+            s["mainsnak"]["property"].as_str() == Some(property)
+                && s["rank"].as_str() == Some("preferred")
+        });
+        match has_preferred {
+            true => "preferred",
+            false => "normal",
+        }
+    }
+
+    fn add_statement(
+        &mut self,
+        subject: &Term,
+        property: &str,
+        statement: &Value,
+        best_rank: &str,
+    ) {
+        let node = match statement["id"].as_str() {
+            Some(id) => Term::prefixed("wds", &id.replace('$', "-")),
+            None => self.new_blank_node("statement"),
+        };
+        let rank = statement["rank"].as_str().unwrap_or("normal");
+        self.add(subject, Term::prefixed("p", property), node.to_owned());
+        self.add(
+            &node,
+            Term::prefixed("rdf", "type"),
+            Term::prefixed("wikibase", "Statement"),
+        );
+        let snak = &statement["mainsnak"];
+        let value = Self::snak_value(snak);
+        if let Some(value) = &value {
+            self.add(&node, Term::prefixed("ps", property), value.to_owned());
+        }
+        if snak["snaktype"] == "novalue" {
+            self.add(
+                &node,
+                Term::prefixed("rdf", "type"),
+                Term::prefixed("wdno", property),
+            );
+        }
+        let rank_local = match rank {
+            "preferred" => "PreferredRank",
+            "deprecated" => "DeprecatedRank",
+            _ => "NormalRank",
+        };
+        self.add(
+            &node,
+            Term::prefixed("wikibase", "rank"),
+            Term::prefixed("wikibase", rank_local),
+        );
+        if rank == best_rank {
+            self.add(
+                &node,
+                Term::prefixed("rdf", "type"),
+                Term::prefixed("wikibase", "BestRank"),
+            );
+            if let Some(value) = value {
+                self.add(subject, Term::prefixed("wdt", property), value);
+            }
+        }
+        for qualifier in Utility::json_values(&statement["qualifiers"]) {
+            if let (Some(property), Some(value)) =
+                (qualifier["property"].as_str(), Self::snak_value(&qualifier))
+            {
+                self.add(&node, Term::prefixed("pq", property), value);
+            }
+        }
+        for reference in Utility::json_values(&statement["references"]) {
+            let reference_node = match reference["hash"].as_str() {
+                Some(hash) => Term::prefixed("wdref", hash),
+                None => self.new_blank_node("reference"),
+            };
+            self.add(
+                &node,
+                Term::prefixed("prov", "wasDerivedFrom"),
+                reference_node.to_owned(),
+            );
+            self.add(
+                &reference_node,
+                Term::prefixed("rdf", "type"),
+                Term::prefixed("wikibase", "Reference"),
+            );
+            for snak in Utility::json_values(&reference["snaks"]) {
+                if let (Some(property), Some(value)) =
+                    (snak["property"].as_str(), Self::snak_value(&snak))
+                {
+                    self.add(&reference_node, Term::prefixed("pr", property), value);
+                }
+            }
+        }
+    }
+
+    /// A label, description, or alias as a language-tagged literal
+    fn term(j: &Value) -> Option<Term> {
+        Some(Term::language(
+            j["value"].as_str()?,
+            j["language"].as_str()?,
+        ))
+    }
+
+    /// The simple value of a snak; `None` for "somevalue" and "novalue"
+    fn snak_value(snak: &Value) -> Option<Term> {
+        if snak["snaktype"].as_str().unwrap_or("value") != "value" {
+            return None;
+        }
+        let datavalue = &snak["datavalue"];
+        let value = &datavalue["value"];
+        Some(match datavalue["type"].as_str()? {
+            "wikibase-entityid" => Term::prefixed("wd", value["id"].as_str()?),
+            "string" => match snak["datatype"].as_str() {
+                Some("commonsMedia") => Term::Iri(format!(
+                    "{COMMONS_FILE_PATH}{}",
+                    Utility::encode_path_segment(&value.as_str()?.replace(' ', "_"))
+                )),
+                Some("url") => Term::Iri(value.as_str()?.to_string()),
+                _ => Term::string(value.as_str()?),
+            },
+            "time" => Term::typed(&Self::date_time(value["time"].as_str()?), "xsd", "dateTime"),
+            "monolingualtext" => {
+                Term::language(value["text"].as_str()?, value["language"].as_str()?)
+            }
+            "quantity" => Term::typed(
+                value["amount"].as_str()?.trim_start_matches('+'),
+                "xsd",
+                "decimal",
+            ),
+            "globecoordinate" => Term::typed(
+                &format!(
+                    "Point({} {})",
+                    value["longitude"].as_f64()?,
+                    value["latitude"].as_f64()?
+                ),
+                "geo",
+                "wktLiteral",
+            ),
+            _ => return None,
+        })
+    }
+
+    /// Like the dumps, replaces unknown months and days (for years or months precision) with the first one
+    pub(crate) fn date_time(time: &str) -> String {
+        let time = time.trim_start_matches('+');
+        match time.split_once('T') {
+            Some((date, rest)) => {
+                let date = date.replace("-00-00", "-01-01");
+                let date = match date.strip_suffix("-00") {
+                    Some(month) => format!("{month}-01"),
+                    None => date,
+                };
+                format!("{date}T{rest}")
+            }
+            None => time.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn test_item() -> Value {
+        json!({
+            "id": "Q1035",
+            "labels": {"en": {"language": "en", "value": "Charles Darwin"}},
+            "claims": {
+                "P569": [
+                    {"id": "Q1035$A1", "rank": "normal",
+                     "mainsnak": {"snaktype": "value", "property": "P569", "datavalue": {"type": "time", "value": {"time": "+1809-02-12T00:00:00Z", "precision": 11}}},
+                     "references": [{"hash": "abc123", "snaks": {"P227": [{"snaktype": "value", "property": "P227", "datavalue": {"type": "string", "value": "118523813"}}]}}]},
+                    {"id": "Q1035$A2", "rank": "deprecated",
+                     "mainsnak": {"snaktype": "value", "property": "P569", "datavalue": {"type": "time", "value": {"time": "+1808-00-00T00:00:00Z", "precision": 9}}}}
+                ],
+                "P18": [{"rank": "normal",
+                     "mainsnak": {"snaktype": "value", "property": "P18", "datatype": "commonsMedia", "datavalue": {"type": "string", "value": "Charles Darwin.jpg"}}}]
+            }
+        })
+    }
+
+    #[test]
+    fn test_ntriples() {
+        let nt = RdfExport::from_item(&test_item()).render(RdfFormat::NTriples);
+        let lines: Vec<&str> = nt.lines().collect();
+        assert!(lines.contains(&r#"<http://www.wikidata.org/entity/Q1035> <http://www.w3.org/2000/01/rdf-schema#label> "Charles Darwin"@en ."#));
+        assert!(lines.contains(&r#"<http://www.wikidata.org/entity/Q1035> <http://www.wikidata.org/prop/direct/P569> "1809-02-12T00:00:00Z"^^<http://www.w3.org/2001/XMLSchema#dateTime> ."#));
+        assert!(lines.contains(&r#"<http://www.wikidata.org/entity/statement/Q1035-A1> <http://www.w3.org/ns/prov#wasDerivedFrom> <http://www.wikidata.org/reference/abc123> ."#));
+        assert!(lines.contains(&r#"<http://www.wikidata.org/reference/abc123> <http://www.wikidata.org/prop/reference/P227> "118523813" ."#));
+        assert!(lines.contains(&r#"<http://www.wikidata.org/entity/Q1035> <http://www.wikidata.org/prop/direct/P18> <http://commons.wikimedia.org/wiki/Special:FilePath/Charles_Darwin.jpg> ."#));
+        assert!(lines.contains(&"<http://www.wikidata.org/entity/Q1035> <http://www.wikidata.org/prop/P18> _:statement1 ."));
+        // Deprecated statements are not truthy
+        assert_eq!(
+            lines
+                .iter()
+                .filter(|l| l.contains("/prop/direct/P569>"))
+                .count(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_turtle() {
+        let ttl = RdfExport::from_item(&test_item()).render(RdfFormat::Turtle);
+        assert!(ttl.starts_with("@prefix wd: <http://www.wikidata.org/entity/> .\n"));
+        assert!(ttl.contains("wds:Q1035-A2 rdf:type wikibase:Statement ;\n    ps:P569 \"1808-01-01T00:00:00Z\"^^xsd:dateTime ;\n    wikibase:rank wikibase:DeprecatedRank .\n"));
+        let ttl = RdfExport::from_item(&json!({})).render(RdfFormat::Turtle);
+        assert!(ttl.ends_with("\n_:item rdf:type wikibase:Item .\n"));
+    }
+
+    #[test]
+    fn test_date_time() {
+        assert_eq!(
+            RdfExport::date_time("+1809-02-00T00:00:00Z"),
+            "1809-02-01T00:00:00Z"
+        );
+        assert_eq!(
+            RdfExport::date_time("-0500-00-00T00:00:00Z"),
+            "-0500-01-01T00:00:00Z"
+        );
+    }
+
+    #[test]
+    fn test_format_from_accept() {
+        assert_eq!(
+            RdfFormat::from_accept("text/html, text/turtle;q=0.9"),
+            Some(RdfFormat::Turtle)
+        );
+        assert_eq!(
+            RdfFormat::from_accept("application/n-triples"),
+            Some(RdfFormat::NTriples)
+        );
+        assert_eq!(RdfFormat::from_accept("application/json"), None);
+    }
+}
@@ -0,0 +1,327 @@
+use crate::search::{NameSearch, SearchCandidate};
+use crate::utility::Utility;
+use crate::wikidata_lookup;
+use futures::{stream, StreamExt};
+use regex::Regex;
+use serde_json::{json, Value};
+
+lazy_static! {
+    static ref RE_ITEM: Regex = Regex::new(r"^Q[1-9]\d*$").expect("Regexp error");
+    static ref RE_ISO_COUNTRY: Regex = Regex::new(r"^[A-Z]{2}$").expect("Regexp error");
+    /// GND geographic area codes, like "XA-GB" or "XA-DE-BY"; the second part is the ISO 3166-1 code
+    static ref RE_AREA_CODE: Regex =
+        Regex::new(r"^X[A-E]-([A-Z]{2})(?:-|$)").expect("Regexp error");
+}
+
+const DEFAULT_LIMIT: usize = 10;
+const AUTO_MATCH_SCORE: f64 = 90.0;
+/// Queries of a batch that run at the same time
+const MAX_CONCURRENT_QUERIES: usize = 4;
+
+/// Implements the W3C reconciliation service API (as used by OpenRefine) on top of `NameSearch`
+pub struct Reconciliation {}
+
+impl Reconciliation {
+    /// The service manifest, returned when no queries are given. Clients resolve the URLs on their own, so they are
+    /// absolute, under `base_url` (eg "https://auth2wd.toolforge.org").
+    /// Results are authority records, not Wikidata items; their IDs (eg "P227/118523813") are paths under `/item/`.
+    pub fn manifest(base_url: &str) -> Value {
+        json!({
+            "versions": ["0.2"],
+            "name": "auth2wd authority reconciliation",
+            "identifierSpace": format!("{base_url}/item/"),
+            "schemaSpace": "http://www.wikidata.org/entity/",
+            "defaultTypes": [{"id":"Q5","name":"human"}],
+            "view": {"url": format!("{base_url}/item/{{{{id}}}}")},
+            "preview": {"url": format!("{base_url}/reconcile/preview/{{{{id}}}}"), "width": 400, "height": 300},
+        })
+    }
+
+    /// Runs a batch of queries concurrently, eg `{"q0":{"query":"Charles Darwin","properties":[{"pid":"P569","v":"1809"}]}}`
+    pub async fn reconcile(queries: &Value) -> Value {
+        let queries = match queries.as_object() {
+            Some(queries) => queries,
+            None => return json!({}),
+        };
+        let results: Vec<(String, Vec<Value>)> = stream::iter(queries)
+            .map(|(key, query)| async move { (key.to_owned(), Self::reconcile_query(query).await) })
+            .buffer_unordered(MAX_CONCURRENT_QUERIES)
+            .collect()
+            .await;
+        let mut ret = json!({});
+        for (key, result) in results {
+            ret[key] = json!({ "result": result });
+        }
+        ret
+    }
+
+    /// A short HTML summary of a record, for the preview pane of the client: name, description, and life dates
+    pub fn preview_html(item: &Value) -> String {
+        let first = |key: &str| -> String {
+            let terms = &item[key];
+            terms["en"]["value"]
+                .as_str()
+                .or_else(|| {
+                    terms
+                        .as_object()?
+                        .values()
+                        .find_map(|t| t["value"].as_str())
+                })
+                .map(Utility::html_escape)
+                .unwrap_or_default()
+        };
+        let year = |property: &str| -> Option<String> {
+            let time =
+                item["claims"][property][0]["mainsnak"]["datavalue"]["value"]["time"].as_str()?;
+            let (sign, rest) = time.split_at(1);
+            let year = rest.split('-').next()?.trim_start_matches('0');
+            Some(format!("{}{year}", if sign == "-" { "-" } else { "" }))
+        };
+        let years = match (year("P569"), year("P570")) {
+            (None, None) => String::new(),
+            (birth, death) => format!(
+                "<p>{}–{}</p>",
+                birth.unwrap_or_default(),
+                death.unwrap_or_default()
+            ),
+        };
+        format!(
+            "<html><body><h3>{}</h3><p>{}</p>{years}</body></html>",
+            first("labels"),
+            first("descriptions")
+        )
+    }
+
+    async fn reconcile_query(query: &Value) -> Vec<Value> {
+        let name = match query["query"].as_str() {
+            Some(name) => name,
+            None => return vec![],
+        };
+        let limit = query["limit"]
+            .as_u64()
+            .map(|l| l as usize)
+            .unwrap_or(DEFAULT_LIMIT);
+        let constraints = Self::resolve_constraints(Self::get_constraints(query)).await;
+        let mut scored: Vec<(f64, SearchCandidate)> = NameSearch::search(name, limit)
+            .await
+            .into_iter()
+            .map(|c| (Self::score(name, &constraints, &c), c))
+            .collect();
+        scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+        scored.truncate(limit);
+        let single_match = scored.len() < 2 || scored[1].0 < scored[0].0;
+        scored
+            .iter()
+            .enumerate()
+            .map(|(num, (score, candidate))| {
+                // The ID doubles as the /item/{prop}/{id} path
+                let id = format!("P{}/{}", candidate.ext_id.property(), candidate.ext_id.id());
+                json!({
+                    "id": id,
+                    "name": candidate.name,
+                    "description": candidate.description,
+                    "score": score,
+                    "match": num == 0 && single_match && *score >= AUTO_MATCH_SCORE,
+                    "type": [{"id":"Q5","name":"human"}],
+                })
+            })
+            .collect()
+    }
+
+    /// Returns (property, value) pairs from the query properties
+    fn get_constraints(query: &Value) -> Vec<(String, String)> {
+        query["properties"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|p| {
+                let pid = p["pid"].as_str()?.to_uppercase();
+                let v = match &p["v"] {
+                    Value::String(s) => s.to_owned(),
+                    Value::Object(o) => o.get("id")?.as_str()?.to_string(),
+                    v => v.to_string(),
+                };
+                Some((pid, v))
+            })
+            .collect()
+    }
+
+    /// Turns countries (P27) into ISO 3166-1 codes, to compare with the area codes of candidates.
+    /// Countries that can not be mapped are dropped, so they neither help nor penalise a candidate.
+    async fn resolve_constraints(constraints: Vec<(String, String)>) -> Vec<(String, String)> {
+        let mut ret = vec![];
+        for (pid, v) in constraints {
+            if pid != "P27" {
+                ret.push((pid, v));
+            } else if let Some(code) = Self::country_code(v.trim()).await {
+                ret.push((pid, code));
+            }
+        }
+        ret
+    }
+
+    /// The ISO 3166-1 code for a country item (via P297), an ISO code, or an area code like "XA-GB"
+    async fn country_code(v: &str) -> Option<String> {
+        if let Some(code) = Self::area_country(v) {
+            return Some(code.to_string());
+        }
+        if RE_ISO_COUNTRY.is_match(v) {
+            return Some(v.to_string());
+        }
+        if !RE_ITEM.is_match(v) {
+            return None;
+        }
+        match wikidata_lookup::item_strings(v, 297).await {
+            Ok(codes) => codes.into_iter().next(),
+            Err(e) => {
+                tracing::warn!("No country code for {v}: {e}");
+                None
+            }
+        }
+    }
+
+    /// The ISO 3166-1 code in a GND area code
+    fn area_country(code: &str) -> Option<&str> {
+        Some(RE_AREA_CODE.captures(code)?.get(1)?.as_str())
+    }
+
+    /// Scores a candidate between 0 and 100; matching or conflicting life years and countries adjust the name score
+    fn score(name: &str, constraints: &[(String, String)], candidate: &SearchCandidate) -> f64 {
+        let mut score = NameSearch::name_score(name, &candidate.name) * 100.0;
+        for (pid, v) in constraints {
+            let matches = match pid.as_str() {
+                "P569" => Self::year_matches(v, candidate.birth_year),
+                "P570" => Self::year_matches(v, candidate.death_year),
+                "P27" => {
+                    let codes: Vec<&str> = candidate
+                        .countries
+                        .iter()
+                        .filter_map(|c| Self::area_country(c))
+                        .collect();
+                    match codes.is_empty() {
+                        true => None,
+                        false => Some(codes.contains(&v.as_str())),
+                    }
+                }
+                _ => None,
+            };
+            match matches {
+                Some(true) => score += 10.0,
+                Some(false) => score -= 30.0,
+                None => {}
+            }
+        }
+        score.clamp(0.0, 100.0)
+    }
+
+    fn year_matches(v: &str, year: Option<i32>) -> Option<bool> {
+        Some(NameSearch::parse_year(v.trim_start_matches('+'))? == year?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::external_id::ExternalId;
+
+    fn candidate() -> SearchCandidate {
+        SearchCandidate {
+            ext_id: ExternalId::new(227, "118523813"),
+            name: "Darwin, Charles".to_string(),
+            description: String::new(),
+            birth_year: Some(1809),
+            death_year: Some(1882),
+            countries: vec!["XA-GB".to_string()],
+        }
+    }
+
+    #[test]
+    fn test_get_constraints() {
+        let query = json!({"query":"Charles Darwin","properties":[{"pid":"p569","v":"1809"},{"pid":"P27","v":{"id":"XA-GB"}}]});
+        assert_eq!(
+            Reconciliation::get_constraints(&query),
+            vec![
+                ("P569".to_string(), "1809".to_string()),
+                ("P27".to_string(), "XA-GB".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_manifest() {
+        let manifest = Reconciliation::manifest("https://auth2wd.toolforge.org");
+        assert_eq!(
+            manifest["identifierSpace"],
+            "https://auth2wd.toolforge.org/item/"
+        );
+        assert_eq!(
+            manifest["preview"]["url"],
+            "https://auth2wd.toolforge.org/reconcile/preview/{{id}}"
+        );
+        assert_eq!(
+            manifest["view"]["url"],
+            "https://auth2wd.toolforge.org/item/{{id}}"
+        );
+    }
+
+    #[test]
+    fn test_preview_html() {
+        let item = json!({
+            "labels":{"de":{"language":"de","value":"Darwin, Charles"},"en":{"language":"en","value":"Charles <Darwin>"}},
+            "claims":{"P569":[{"mainsnak":{"datavalue":{"value":{"time":"+1809-02-12T00:00:00Z"}}}}]}
+        });
+        assert_eq!(
+            Reconciliation::preview_html(&item),
+            "<html><body><h3>Charles &lt;Darwin&gt;</h3><p></p><p>1809–</p></body></html>"
+        );
+    }
+
+    #[test]
+    fn test_score() {
+        let c = candidate();
+        assert_eq!(Reconciliation::score("Charles Darwin", &[], &c), 100.0);
+        let wrong_year = vec![("P569".to_string(), "1810".to_string())];
+        assert_eq!(
+            Reconciliation::score("Charles Darwin", &wrong_year, &c),
+            70.0
+        );
+        let bce = vec![("P569".to_string(), "-1809".to_string())];
+        assert_eq!(Reconciliation::score("Charles Darwin", &bce, &c), 70.0);
+        let unknown = vec![("P21".to_string(), "Q6581097".to_string())];
+        assert_eq!(Reconciliation::score("Charles Darwin", &unknown, &c), 100.0);
+        let country = vec![("P27".to_string(), "GB".to_string())];
+        assert_eq!(Reconciliation::score("Charles Darwin", &country, &c), 100.0);
+        let wrong_country = vec![("P27".to_string(), "G".to_string())];
+        assert_eq!(
+            Reconciliation::score("Charles Darwin", &wrong_country, &c),
+            70.0
+        );
+    }
+
+    #[test]
+    fn test_area_country() {
+        assert_eq!(Reconciliation::area_country("XA-GB"), Some("GB"));
+        assert_eq!(Reconciliation::area_country("XA-DE-BY"), Some("DE"));
+        assert_eq!(Reconciliation::area_country("XB-JP"), Some("JP"));
+        assert_eq!(Reconciliation::area_country("Großbritannien"), None);
+    }
+
+    #[tokio::test]
+    async fn test_resolve_constraints() {
+        let constraints = vec![
+            ("P27".to_string(), "Q145".to_string()),
+            ("P27".to_string(), "XA-FR".to_string()),
+            ("P27".to_string(), "United Kingdom".to_string()),
+            ("P27".to_string(), String::new()),
+            ("P569".to_string(), "1809".to_string()),
+        ];
+        assert_eq!(
+            Reconciliation::resolve_constraints(constraints).await,
+            vec![
+                ("P27".to_string(), "GB".to_string()),
+                ("P27".to_string(), "FR".to_string()),
+                ("P569".to_string(), "1809".to_string())
+            ]
+        );
+    }
+}
@@ -0,0 +1,273 @@
+use crate::target::Target;
+use crate::utility::Utility;
+use anyhow::{anyhow, Result};
+use serde_json::{json, Value};
+
+/// A diff as RFC 6902 JSON Patch operations against an item as served by the Wikibase REST API
+/// (`/entities/items/{id}`), which can be sent with `PATCH` instead of using `wbeditentity`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RestPatch {
+    item: String,
+    operations: Vec<Value>,
+}
+
+impl RestPatch {
+    /// Loads the current item from the REST API, and builds the patch for the diff against it
+    pub async fn from_diff(item: &str, diff: &Value) -> Result<Self> {
+        let current: Value = serde_json::from_str(&Utility::get_url(&Self::item_url(item)).await?)?;
+        Ok(Self {
+            item: item.to_string(),
+            operations: Self::operations(&current, diff),
+        })
+    }
+
+    pub fn operations_json(&self) -> Value {
+        json!(self.operations)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.operations.is_empty()
+    }
+
+    /// Sends the patch. Not retried, so a statement can not be added twice.
+    pub async fn submit(&self, oauth2_token: &str, comment: &str) -> Result<Value> {
+        let body = json!({"patch": self.operations, "comment": comment, "bot": true});
+        let resp = Utility::http_client()
+            .patch(Self::item_url(&self.item))
+            .bearer_auth(oauth2_token)
+            .json(&body)
+            .send()
+            .await?;
+        let status = resp.status();
+        let j: Value = resp.json().await.unwrap_or_default();
+        if !status.is_success() {
+            return Err(anyhow!(
+                "PATCH of {} failed with {status}: {}",
+                self.item,
+                j["message"].as_str().unwrap_or_default()
+            ));
+        }
+        Ok(j)
+    }
+
+    fn item_url(item: &str) -> String {
+        format!(
+            "{}/entities/items/{}",
+            Target::rest_api(),
+            Utility::encode_path_segment(item)
+        )
+    }
+
+    /// New statements are appended; statements with an `id` (eg with new references) replace the current one.
+    /// Terms are added, or replace the current ones.
+    fn operations(current: &Value, diff: &Value) -> Vec<Value> {
+        let mut ret = vec![];
+        for term_type in ["labels", "descriptions"] {
+            for term in Utility::json_values(&diff[term_type]) {
+                if let (Some(language), Some(value)) =
+                    (term["language"].as_str(), term["value"].as_str())
+                {
+                    ret.push(json!({
+                        "op": "add",
+                        "path": format!("/{term_type}/{}", Self::escape(language)),
+                        "value": value
+                    }));
+                }
+            }
+        }
+        let mut new_alias_languages = vec![];
+        for alias in Utility::json_values(&diff["aliases"]) {
+            let (language, value) = match (alias["language"].as_str(), alias["value"].as_str()) {
+                (Some(language), Some(value)) => (language, value),
+                _ => continue,
+            };
+            let path = format!("/aliases/{}", Self::escape(language));
+            if current["aliases"].get(language).is_some() || new_alias_languages.contains(&language)
+            {
+                ret.push(json!({"op": "add", "path": format!("{path}/-"), "value": value}));
+            } else {
+                new_alias_languages.push(language);
+                ret.push(json!({"op": "add", "path": path, "value": [value]}));
+            }
+        }
+        let mut new_properties = vec![];
+        for statement in Utility::json_values(&diff["claims"]) {
+            let property = match statement["mainsnak"]["property"].as_str() {
+                Some(property) => property.to_string(),
+                None => continue,
+            };
+            let rest_statement = match Self::statement(&statement) {
+                Some(s) => s,
+                None => continue,
+            };
+            let path = format!("/statements/{}", Self::escape(&property));
+            let existing = current["statements"][&property].as_array();
+            if let Some(id) = statement["id"].as_str() {
+                let position = existing.and_then(|statements| {
+                    statements.iter().position(|s| s["id"].as_str() == Some(id))
+                });
+                if let Some(position) = position {
+                    ret.push(json!({
+                        "op": "replace",
+                        "path": format!("{path}/{position}"),
+                        "value": rest_statement
+                    }));
+                }
+                continue;
+            }
+            if existing.is_some() || new_properties.contains(&property) {
+                ret.push(
+                    json!({"op": "add", "path": format!("{path}/-"), "value": rest_statement}),
+                );
+            } else {
+                new_properties.push(property);
+                ret.push(json!({"op": "add", "path": path, "value": [rest_statement]}));
+            }
+        }
+        ret
+    }
+
+    /// Converts an action API statement to the REST API form
+    fn statement(j: &Value) -> Option<Value> {
+        let mut ret = Self::property_value(&j["mainsnak"])?;
+        ret["rank"] = json!(j["rank"].as_str().unwrap_or("normal"));
+        ret["qualifiers"] = json!(Self::snaks(&j["qualifiers"]));
+        let references: Vec<Value> = Utility::json_values(&j["references"])
+            .iter()
+            .map(|r| json!({"parts": Self::snaks(&r["snaks"])}))
+            .collect();
+        ret["references"] = json!(references);
+        if let Some(id) = j["id"].as_str() {
+            ret["id"] = json!(id);
+        }
+        Some(ret)
+    }
+
+    fn snaks(j: &Value) -> Vec<Value> {
+        Utility::json_values(j)
+            .iter()
+            .filter_map(Self::property_value)
+            .collect()
+    }
+
+    /// A snak as a REST API property/value pair
+    fn property_value(snak: &Value) -> Option<Value> {
+        let property = snak["property"].as_str()?;
+        let value = match snak["snaktype"].as_str().unwrap_or("value") {
+            "somevalue" => json!({"type": "somevalue"}),
+            "novalue" => json!({"type": "novalue"}),
+            _ => json!({"type": "value", "content": Self::content(&snak["datavalue"])?}),
+        };
+        Some(json!({"property": {"id": property}, "value": value}))
+    }
+
+    fn content(datavalue: &Value) -> Option<Value> {
+        let value = &datavalue["value"];
+        Some(match datavalue["type"].as_str()? {
+            "wikibase-entityid" => json!(value["id"].as_str()?),
+            "string" => json!(value.as_str()?),
+            "time" => json!({
+                "time": value["time"],
+                "precision": value["precision"],
+                "calendarmodel": value.get("calendarmodel").cloned()
+                    .unwrap_or_else(|| json!("http://www.wikidata.org/entity/Q1985727")),
+            }),
+            "monolingualtext" => json!({"text": value["text"], "language": value["language"]}),
+            "quantity" => {
+                json!({"amount": value["amount"], "unit": value.get("unit").cloned().unwrap_or_else(|| json!("1"))})
+            }
+            "globecoordinate" => json!({
+                "latitude": value["latitude"],
+                "longitude": value["longitude"],
+                "precision": value["precision"],
+                "globe": value.get("globe").cloned()
+                    .unwrap_or_else(|| json!("http://www.wikidata.org/entity/Q2")),
+            }),
+            _ => return None,
+        })
+    }
+
+    /// Escapes a JSON Pointer path segment
+    fn escape(s: &str) -> String {
+        s.replace('~', "~0").replace('/', "~1")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn current_item() -> Value {
+        json!({
+            "id": "Q1035",
+            "labels": {"en": "Charles Darwin"},
+            "aliases": {"en": ["Darwin"]},
+            "statements": {
+                "P214": [{
+                    "id": "Q1035$1",
+                    "rank": "normal",
+                    "property": {"id": "P214", "data_type": "external-id"},
+                    "value": {"type": "value", "content": "27063124"},
+                    "qualifiers": [],
+                    "references": []
+                }]
+            }
+        })
+    }
+
+    #[test]
+    fn test_operations() {
+        let diff = json!({
+            "labels": {"de": {"language": "de", "value": "Charles Darwin"}},
+            "aliases": [
+                {"language": "en", "value": "Charles Robert Darwin"},
+                {"language": "de", "value": "Darwin"}
+            ],
+            "claims": [
+                {"mainsnak": {"snaktype": "value", "property": "P31", "datavalue": {"type": "wikibase-entityid", "value": {"id": "Q5"}}}},
+                {"id": "Q1035$1", "rank": "normal",
+                 "mainsnak": {"snaktype": "value", "property": "P214", "datavalue": {"type": "string", "value": "27063124"}},
+                 "references": [{"snaks": {"P248": [{"snaktype": "value", "property": "P248", "datavalue": {"type": "wikibase-entityid", "value": {"id": "Q54919"}}}]}}]}
+            ]
+        });
+        let ops = RestPatch::operations(&current_item(), &diff);
+        assert_eq!(
+            ops[0],
+            json!({"op": "add", "path": "/labels/de", "value": "Charles Darwin"})
+        );
+        assert_eq!(
+            ops[1],
+            json!({"op": "add", "path": "/aliases/en/-", "value": "Charles Robert Darwin"})
+        );
+        assert_eq!(
+            ops[2],
+            json!({"op": "add", "path": "/aliases/de", "value": ["Darwin"]})
+        );
+        assert_eq!(ops[3]["path"], "/statements/P31");
+        assert_eq!(
+            ops[3]["value"][0]["value"],
+            json!({"type": "value", "content": "Q5"})
+        );
+        assert_eq!(ops[4]["op"], "replace");
+        assert_eq!(ops[4]["path"], "/statements/P214/0");
+        assert_eq!(
+            ops[4]["value"]["references"][0]["parts"][0]["property"]["id"],
+            "P248"
+        );
+    }
+
+    #[test]
+    fn test_property_value() {
+        let snak = json!({"snaktype": "somevalue", "property": "P20"});
+        assert_eq!(
+            RestPatch::property_value(&snak),
+            Some(json!({"property": {"id": "P20"}, "value": {"type": "somevalue"}}))
+        );
+        let snak = json!({"snaktype": "value", "property": "P569", "datavalue": {"type": "time", "value": {"time": "+1809-02-12T00:00:00Z", "precision": 11}}});
+        assert_eq!(
+            RestPatch::property_value(&snak).unwrap()["value"]["content"]["calendarmodel"],
+            "http://www.wikidata.org/entity/Q1985727"
+        );
+        assert_eq!(RestPatch::escape("a/b~c"), "a~1b~0c");
+    }
+}
@@ -0,0 +1,397 @@
+use crate::external_id::ExternalId;
+use crate::utility::Utility;
+use anyhow::Result;
+use regex::Regex;
+use serde::Serialize;
+use serde_json::Value;
+use std::fmt;
+
+lazy_static! {
+    static ref RE_YEARS: Regex =
+        Regex::new(r"\b(\d{3,4})\s*-\s*(?:(\d{3,4})\b)?").expect("Regexp error");
+    static ref RE_YEAR: Regex = Regex::new(r"^\s*(-?\d{1,4})").expect("Regexp error");
+}
+
+const VIAF_AUTOSUGGEST: &str = "https://viaf.org/viaf/AutoSuggest";
+const LOBID_GND_SEARCH: &str = "https://lobid.org/gnd/search";
+const IDREF_SOLR: &str = "https://www.idref.fr/Sru/Solr";
+const NOID_CHARS: &str = "0123456789bcdfghjkmnpqrstvwxz";
+
+/// Maps VIAF AutoSuggest source keys to Wikidata properties
+const VIAF_SOURCES: &[(&str, usize)] = &[
+    ("viafid", 214),
+    ("dnb", 227),
+    ("lc", 244),
+    ("bnf", 268),
+    ("sudoc", 269),
+    ("selibr", 906),
+    ("bne", 950),
+];
+
+/// A potential match for a name search, from one of the supported authorities
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SearchCandidate {
+    pub ext_id: ExternalId,
+    pub name: String,
+    pub description: String,
+    pub birth_year: Option<i32>,
+    pub death_year: Option<i32>,
+    pub countries: Vec<String>,
+}
+
+impl SearchCandidate {
+    fn new(ext_id: ExternalId, name: &str) -> Self {
+        Self {
+            ext_id,
+            name: name.to_string(),
+            description: String::new(),
+            birth_year: None,
+            death_year: None,
+            countries: vec![],
+        }
+    }
+}
+
+impl fmt::Display for SearchCandidate {
+    /// One tab-separated line: external ID, name, life years, description
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let year = |y: Option<i32>| y.map(|y| y.to_string()).unwrap_or_default();
+        write!(
+            f,
+            "{}\t{}\t{}-{}\t{}",
+            self.ext_id,
+            self.name,
+            year(self.birth_year),
+            year(self.death_year),
+            self.description
+        )
+    }
+}
+
+/// Searches the supported authorities for a person name
+pub struct NameSearch {}
+
+impl NameSearch {
+    /// Searches VIAF, GND, and IdRef; a failing source is logged and skipped.
+    pub async fn search(query: &str, limit: usize) -> Vec<SearchCandidate> {
+        let (viaf, gnd, idref) = futures::join!(
+            Self::search_viaf(query, limit),
+            Self::search_gnd(query, limit),
+            Self::search_idref(query, limit)
+        );
+        let mut ret = vec![];
+        for (source, result) in [("VIAF", viaf), ("GND", gnd), ("IdRef", idref)] {
+            match result {
+                Ok(candidates) => {
+                    for candidate in candidates {
+                        Self::add_candidate(&mut ret, candidate);
+                    }
+                }
+                Err(e) => tracing::warn!("{source} search for '{query}' failed: {e}"),
+            }
+        }
+        ret
+    }
+
+    /// Drops candidates with a different known birth year, and puts those with a matching one first
+    pub fn filter_by_birth_year(candidates: &mut Vec<SearchCandidate>, birth_year: i32) {
+        candidates.retain(|c| c.birth_year.map_or(true, |year| year == birth_year));
+        candidates.sort_by_key(|c| c.birth_year.is_none());
+    }
+
+    /// Adds a candidate, merging it into an existing one with the same external ID
+    fn add_candidate(candidates: &mut Vec<SearchCandidate>, candidate: SearchCandidate) {
+        match candidates.iter_mut().find(|c| c.ext_id == candidate.ext_id) {
+            Some(existing) => {
+                existing.birth_year = existing.birth_year.or(candidate.birth_year);
+                existing.death_year = existing.death_year.or(candidate.death_year);
+                if existing.description.is_empty() {
+                    existing.description = candidate.description;
+                }
+                existing.countries.extend(candidate.countries);
+                existing.countries.sort();
+                existing.countries.dedup();
+            }
+            None => candidates.push(candidate),
+        }
+    }
+
+    async fn search_viaf(query: &str, limit: usize) -> Result<Vec<SearchCandidate>> {
+        let url = reqwest::Url::parse_with_params(VIAF_AUTOSUGGEST, &[("query", query)])?;
+        let text = Utility::get_url(url.as_str()).await?;
+        let j: Value = serde_json::from_str(&text)?;
+        let mut ret = Self::parse_viaf(&j);
+        ret.truncate(limit * VIAF_SOURCES.len());
+        Ok(ret)
+    }
+
+    async fn search_gnd(query: &str, limit: usize) -> Result<Vec<SearchCandidate>> {
+        let url = reqwest::Url::parse_with_params(
+            LOBID_GND_SEARCH,
+            &[
+                ("q", query),
+                ("filter", "type:Person"),
+                ("format", "json"),
+                ("size", &limit.to_string()),
+            ],
+        )?;
+        let text = Utility::get_url(url.as_str()).await?;
+        let j: Value = serde_json::from_str(&text)?;
+        Ok(Self::parse_lobid(&j))
+    }
+
+    async fn search_idref(query: &str, limit: usize) -> Result<Vec<SearchCandidate>> {
+        let terms: Vec<&str> = query
+            .split(|c: char| !c.is_alphanumeric())
+            .filter(|t| !t.is_empty())
+            .collect();
+        let url = reqwest::Url::parse_with_params(
+            IDREF_SOLR,
+            &[
+                (
+                    "q",
+                    format!("persname_t:({})", terms.join(" AND ")).as_str(),
+                ),
+                ("fl", "ppn_z,affcourt_z"),
+                ("wt", "json"),
+                ("rows", &limit.to_string()),
+            ],
+        )?;
+        let text = Utility::get_url(url.as_str()).await?;
+        let j: Value = serde_json::from_str(&text)?;
+        Ok(Self::parse_idref(&j))
+    }
+
+    /// Parses VIAF AutoSuggest results; each cluster yields candidates for all supported authorities it links to
+    fn parse_viaf(j: &Value) -> Vec<SearchCandidate> {
+        let mut ret = vec![];
+        for result in j["result"].as_array().into_iter().flatten() {
+            if result["nametype"].as_str() != Some("personal") {
+                continue;
+            }
+            let display = match result["displayForm"].as_str() {
+                Some(display) => display,
+                None => continue,
+            };
+            let (birth_year, death_year) = Self::years_from_heading(display);
+            let name = RE_YEARS.replace(display, "");
+            let name = name.trim().trim_end_matches(',').trim();
+            for (key, property) in VIAF_SOURCES {
+                let id = match result[*key].as_str() {
+                    Some(id) => id,
+                    None => continue,
+                };
+                let id = match property {
+                    268 => Self::bnf_id(id),
+                    _ => id.to_string(),
+                };
+                let mut candidate = SearchCandidate::new(ExternalId::new(*property, &id), name);
+                candidate.description = display.to_string();
+                candidate.birth_year = birth_year;
+                candidate.death_year = death_year;
+                ret.push(candidate);
+            }
+        }
+        ret
+    }
+
+    /// Parses lobid.org GND search results
+    fn parse_lobid(j: &Value) -> Vec<SearchCandidate> {
+        let mut ret = vec![];
+        for member in j["member"].as_array().into_iter().flatten() {
+            let (id, name) = match (
+                member["gndIdentifier"].as_str(),
+                member["preferredName"].as_str(),
+            ) {
+                (Some(id), Some(name)) => (id, name),
+                _ => continue,
+            };
+            let mut candidate = SearchCandidate::new(ExternalId::new(227, id), name);
+            candidate.birth_year = member["dateOfBirth"][0].as_str().and_then(Self::parse_year);
+            candidate.death_year = member["dateOfDeath"][0].as_str().and_then(Self::parse_year);
+            candidate.description = member["biographicalOrHistoricalInformation"][0]
+                .as_str()
+                .unwrap_or_default()
+                .to_string();
+            candidate.countries = member["geographicAreaCode"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .flat_map(|area| {
+                    let code = area["id"].as_str().and_then(|id| id.split('#').last());
+                    [code, area["label"].as_str()]
+                })
+                .flatten()
+                .map(|s| s.to_string())
+                .collect();
+            ret.push(candidate);
+        }
+        ret
+    }
+
+    /// Parses IdRef Solr results, where the heading looks like "Darwin, Charles (1809-1882)"
+    fn parse_idref(j: &Value) -> Vec<SearchCandidate> {
+        let mut ret = vec![];
+        for doc in j["response"]["docs"].as_array().into_iter().flatten() {
+            let (id, heading) = match (doc["ppn_z"].as_str(), doc["affcourt_z"].as_str()) {
+                (Some(id), Some(heading)) => (id, heading),
+                _ => continue,
+            };
+            let (birth_year, death_year) = Self::years_from_heading(heading);
+            let name = RE_YEARS.replace(heading, "");
+            let name = name.replace("()", "");
+            let mut candidate = SearchCandidate::new(ExternalId::new(269, id), name.trim());
+            candidate.description = heading.to_string();
+            candidate.birth_year = birth_year;
+            candidate.death_year = death_year;
+            ret.push(candidate);
+        }
+        ret
+    }
+
+    /// Extracts life years from a heading like "Darwin, Charles, 1809-1882"
+    fn years_from_heading(heading: &str) -> (Option<i32>, Option<i32>) {
+        match RE_YEARS.captures(heading) {
+            Some(caps) => (
+                caps.get(1).and_then(|m| m.as_str().parse().ok()),
+                caps.get(2).and_then(|m| m.as_str().parse().ok()),
+            ),
+            None => (None, None),
+        }
+    }
+
+    /// The year at the start of a date, like "1809-02-12"; negative for BCE, like "-0500"
+    pub fn parse_year(s: &str) -> Option<i32> {
+        RE_YEAR.captures(s)?.get(1)?.as_str().parse().ok()
+    }
+
+    /// VIAF reports BnF IDs without the NOID check character that P268 requires
+    fn bnf_id(id: &str) -> String {
+        if id.len() != 8 {
+            return id.to_string();
+        }
+        let check = format!("cb{id}")
+            .chars()
+            .enumerate()
+            .map(|(pos, c)| (pos + 1) * NOID_CHARS.find(c).unwrap_or(0))
+            .sum::<usize>()
+            % NOID_CHARS.len();
+        format!("{id}{}", &NOID_CHARS[check..check + 1])
+    }
+
+    /// Compares two names, ignoring order, case, punctuation, and years; returns a score between 0 and 1.
+    pub fn name_score(a: &str, b: &str) -> f64 {
+        let tokens = |s: &str| -> Vec<String> {
+            let mut ret: Vec<String> = s
+                .to_lowercase()
+                .split(|c: char| !c.is_alphabetic())
+                .filter(|t| !t.is_empty())
+                .map(|t| t.to_string())
+                .collect();
+            ret.sort();
+            ret.dedup();
+            ret
+        };
+        let (a, b) = (tokens(a), tokens(b));
+        if a.is_empty() || b.is_empty() {
+            return 0.0;
+        }
+        let common = a.iter().filter(|t| b.contains(t)).count();
+        (2 * common) as f64 / (a.len() + b.len()) as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_name_score() {
+        assert_eq!(
+            NameSearch::name_score("Darwin, Charles", "Charles Darwin"),
+            1.0
+        );
+        assert_eq!(
+            NameSearch::name_score("Charles Darwin", "Erasmus Darwin"),
+            0.5
+        );
+        assert_eq!(NameSearch::name_score("", "Charles Darwin"), 0.0);
+    }
+
+    #[test]
+    fn test_years() {
+        assert_eq!(
+            NameSearch::years_from_heading("Darwin, Charles, 1809-1882"),
+            (Some(1809), Some(1882))
+        );
+        assert_eq!(
+            NameSearch::years_from_heading("Attenborough, David, 1926-"),
+            (Some(1926), None)
+        );
+        assert_eq!(NameSearch::parse_year("1809-02-12"), Some(1809));
+        assert_eq!(NameSearch::parse_year("-0500"), Some(-500));
+    }
+
+    #[test]
+    fn test_bnf_id() {
+        assert_eq!(NameSearch::bnf_id("11899426"), "11899426h");
+        assert_eq!(NameSearch::bnf_id("11899426h"), "11899426h");
+    }
+
+    #[test]
+    fn test_parse_viaf() {
+        let j = json!({"result":[
+            {"displayForm":"Darwin, Charles, 1809-1882","nametype":"personal","viafid":"27063124","dnb":"118523813"},
+            {"displayForm":"Darwin College","nametype":"corporate","viafid":"1"}
+        ]});
+        let candidates = NameSearch::parse_viaf(&j);
+        assert_eq!(candidates.len(), 2);
+        assert_eq!(candidates[0].ext_id, ExternalId::new(214, "27063124"));
+        assert_eq!(candidates[1].ext_id, ExternalId::new(227, "118523813"));
+        assert_eq!(candidates[0].name, "Darwin, Charles");
+        assert_eq!(candidates[0].birth_year, Some(1809));
+        assert_eq!(candidates[0].death_year, Some(1882));
+    }
+
+    #[test]
+    fn test_parse_idref() {
+        let j = json!({"response":{"docs":[
+            {"ppn_z":"026812304","affcourt_z":"Darwin, Charles (1809-1882)"},
+            {"ppn_z":"123"}
+        ]}});
+        let candidates = NameSearch::parse_idref(&j);
+        assert_eq!(candidates.len(), 1);
+        assert_eq!(candidates[0].ext_id, ExternalId::new(269, "026812304"));
+        assert_eq!(candidates[0].name, "Darwin, Charles");
+        assert_eq!(candidates[0].death_year, Some(1882));
+    }
+
+    #[test]
+    fn test_filter_by_birth_year() {
+        let mut a = SearchCandidate::new(ExternalId::new(227, "1"), "A");
+        a.birth_year = Some(1900);
+        let b = SearchCandidate::new(ExternalId::new(227, "2"), "B");
+        let mut c = SearchCandidate::new(ExternalId::new(227, "3"), "C");
+        c.birth_year = Some(1809);
+        let mut candidates = vec![b, a, c];
+        NameSearch::filter_by_birth_year(&mut candidates, 1809);
+        let ids: Vec<&str> = candidates.iter().map(|c| c.ext_id.id()).collect();
+        assert_eq!(ids, vec!["3", "2"]);
+    }
+
+    #[test]
+    fn test_parse_lobid() {
+        let j = json!({"member":[{
+            "gndIdentifier":"118523813",
+            "preferredName":"Darwin, Charles",
+            "dateOfBirth":["1809-02-12"],
+            "geographicAreaCode":[{"id":"https://d-nb.info/standards/vocab/gnd/geographic-area-code#XA-GB","label":"Großbritannien"}]
+        }]});
+        let candidates = NameSearch::parse_lobid(&j);
+        assert_eq!(candidates.len(), 1);
+        assert_eq!(candidates[0].birth_year, Some(1809));
+        assert_eq!(candidates[0].death_year, None);
+        assert_eq!(candidates[0].countries, vec!["XA-GB", "Großbritannien"]);
+    }
+}
@@ -1,11 +1,14 @@
 use crate::external_id::*;
 use crate::external_importer::*;
+use crate::fuzzy_date::FuzzyDate;
 use crate::meta_item::*;
+use crate::occupation::Occupation;
+use crate::utility::Utility;
 use anyhow::{anyhow, Result};
 use axum::async_trait;
-use sophia::api::prelude::*;
 use sophia::inmem::graph::FastGraph;
-use sophia::xml;
+
+const KBV: &str = "https://id.kb.se/vocab/";
 
 pub struct SELIBR {
     id: String,
@@ -13,9 +16,6 @@ pub struct SELIBR {
     graph: FastGraph,
 }
 
-unsafe impl Send for SELIBR {}
-unsafe impl Sync for SELIBR {}
-
 #[async_trait]
 impl ExternalImporter for SELIBR {
     fn my_property(&self) -> usize {
@@ -31,6 +31,13 @@ impl ExternalImporter for SELIBR {
         format!("{}#it", self.key)
     }
 
+    /// Merged records list the old IDs as `sameAs`, but only the current one as control number
+    fn resolve_canonical_id(&self) -> Result<Option<String>> {
+        let ids =
+            self.triples_subject_literals(&self.key, "https://id.kb.se/vocab/controlNumber")?;
+        Ok(canonical_id(ids, &self.id))
+    }
+
     fn my_id(&self) -> String {
         self.id.to_owned()
     }
@@ -41,13 +48,46 @@ impl ExternalImporter for SELIBR {
         self.transform_label_last_first_name(s)
     }
 
+    async fn fetch(&mut self) -> Result<()> {
+        let id = self.id.to_owned();
+        let rdf_url = format!("http://libris.kb.se/resource/auth/{}/data.rdf", id);
+        let request = Utility::http_client()
+            .get(&rdf_url)
+            .header(reqwest::header::ACCEPT, "application/rdf+xml");
+        let resp = Utility::get_text(request).await?;
+        self.parse(&resp)
+    }
+
+    fn parse(&mut self, text: &str) -> Result<()> {
+        let id = self.id.to_owned();
+        self.graph = Utility::parse_rdf_xml(text)?;
+
+        let ids = self.triples_property_object_iris(
+            "https://id.kb.se/vocab/sameAs",
+            &format!("http://libris.kb.se/auth/{id}"),
+        )?;
+        match ids.first() {
+            Some(key) => self.key = key.to_owned(),
+            None => return Err(anyhow!("could not find main key for '{id}'")),
+        }
+        if let Some(id) = self.resolve_canonical_id()? {
+            self.id = id;
+        }
+        Ok(())
+    }
+
     async fn run(&self) -> Result<MetaItem> {
         let mut ret = MetaItem::new();
         self.add_the_usual(&mut ret).await?;
+        if let Some(libris_uri) = self.libris_uri() {
+            ret.add_claim(self.new_statement_external_id(&ExternalId::new(5587, libris_uri)));
+        }
 
         for url in self.triples_iris("https://id.kb.se/vocab/nationality")? {
             ret.add_prop_text(ExternalId::new(27, &url));
         }
+        self.add_dates(&mut ret)?;
+        self.add_occupations(&mut ret)?;
 
         self.try_rescue_prop_text(&mut ret).await?;
         ret.cleanup();
@@ -56,37 +96,82 @@ impl ExternalImporter for SELIBR {
 }
 
 impl SELIBR {
-    pub async fn new(id: &str) -> Result<Self> {
-        let rdf_url = format!("http://libris.kb.se/resource/auth/{}/data.rdf", id);
-        let client = reqwest::ClientBuilder::new()
-            .redirect(reqwest::redirect::Policy::limited(10))
-            .build()?;
-        let resp = client
-            .get(&rdf_url)
-            .header(reqwest::header::ACCEPT, "application/rdf+xml")
-            .send()
-            .await?
-            .text()
-            .await?;
-        let mut graph: FastGraph = FastGraph::new();
-        let _ = xml::parser::parse_str(&resp).add_to_graph(&mut graph)?;
-        let mut ret = Self {
+    pub fn unfetched(id: &str) -> Self {
+        Self {
             id: id.to_string(),
             key: String::new(),
-            graph,
-        };
+            graph: FastGraph::new(),
+        }
+    }
 
-        let ids = ret.triples_property_object_iris(
-            "https://id.kb.se/vocab/sameAs",
-            &format!("http://libris.kb.se/auth/{id}"),
-        )?;
-        match ids.first() {
-            Some(id) => ret.key = id.to_owned(),
-            None => return Err(anyhow!("could not find main key for '{id}'")),
+    pub async fn new(id: &str) -> Result<Self> {
+        let mut ret = Self::unfetched(id);
+        ret.fetch().await?;
+        Ok(ret)
+    }
+
+    /// The ID of the record in Libris XL, the last part of its key, like "pm135sp73dmxqcf"
+    fn libris_uri(&self) -> Option<&str> {
+        self.key
+            .strip_prefix("https://libris.kb.se/")
+            .filter(|id| !id.is_empty() && id.chars().all(|c| c.is_ascii_alphanumeric()))
+    }
+
+    /// Birth and death years where given, otherwise the life span, like "1809-1882"
+    fn life_dates(&self) -> Result<Vec<(usize, FuzzyDate)>> {
+        let mut ret = vec![];
+        for (key, property) in [("birthYear", 569), ("deathYear", 570)] {
+            for s in self.triples_literals(&format!("{KBV}{key}"))? {
+                if let Some(date) = FuzzyDate::parse(&s) {
+                    ret.push((property, date));
+                }
+            }
+        }
+        for s in self.triples_literals(&format!("{KBV}lifeSpan"))? {
+            let (birth, death) = FuzzyDate::parse_life_span(&s);
+            for (property, date) in [(569, birth), (570, death)] {
+                let date = match date {
+                    Some(date) => date,
+                    None => continue,
+                };
+                if !ret.iter().any(|(p, _)| *p == property) {
+                    ret.push((property, date));
+                }
+            }
         }
+        Ok(ret)
+    }
 
+    fn add_dates(&self, ret: &mut MetaItem) -> Result<()> {
+        for (property, date) in self.life_dates()? {
+            ret.add_claim(self.new_statement_fuzzy_date(property, &date));
+        }
+        Ok(())
+    }
+
+    /// Occupation terms, given as text or as a linked term with a label
+    fn occupations(&self) -> Result<Vec<String>> {
+        let predicate = format!("{KBV}hasOccupation");
+        let mut ret = self.triples_literals(&predicate)?;
+        for url in self.triples_iris(&predicate)? {
+            let labels = self.triples_subject_literals(&url, &format!("{KBV}prefLabel"))?;
+            match labels.into_iter().next() {
+                Some(label) => ret.push(label),
+                None => ret.push(url),
+            }
+        }
         Ok(ret)
     }
+
+    fn add_occupations(&self, ret: &mut MetaItem) -> Result<()> {
+        for term in self.occupations()? {
+            let _ = match Occupation::from_swedish(&term) {
+                Some(item) => ret.add_claim(self.new_statement_item(106, item)),
+                None => ret.add_prop_text(ExternalId::new(106, &term)),
+            };
+        }
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -95,11 +180,69 @@ mod tests {
 
     const TEST_ID: &str = "231727";
 
+    fn parsed(person: &str) -> SELIBR {
+        let text = format!(
+            r#"<?xml version="1.0"?>
+<rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#" xmlns:kbv="https://id.kb.se/vocab/">
+  <rdf:Description rdf:about="https://libris.kb.se/pm135sp73dmxqcf">
+    <kbv:sameAs rdf:resource="http://libris.kb.se/auth/231727"/>
+  </rdf:Description>
+  <rdf:Description rdf:about="https://libris.kb.se/pm135sp73dmxqcf#it">{person}</rdf:Description>
+  <rdf:Description rdf:about="https://id.kb.se/term/sao/F%C3%B6rfattare">
+    <kbv:prefLabel>Författare</kbv:prefLabel>
+  </rdf:Description>
+</rdf:RDF>"#
+        );
+        let mut selibr = SELIBR::unfetched(TEST_ID);
+        selibr.parse(&text).unwrap();
+        selibr
+    }
+
     #[tokio::test]
     async fn test_new() {
         assert!(SELIBR::new(TEST_ID).await.is_ok());
     }
 
+    #[test]
+    fn test_libris_uri() {
+        assert_eq!(parsed("").libris_uri(), Some("pm135sp73dmxqcf"));
+        assert_eq!(SELIBR::unfetched(TEST_ID).libris_uri(), None);
+    }
+
+    #[test]
+    fn test_life_dates() {
+        let selibr = parsed("<kbv:lifeSpan>1809-1882</kbv:lifeSpan>");
+        let dates: Vec<(usize, u64)> = selibr
+            .life_dates()
+            .unwrap()
+            .into_iter()
+            .map(|(property, date)| (property, date.precision()))
+            .collect();
+        assert_eq!(dates, vec![(569, 9), (570, 9)]);
+
+        let selibr = parsed(
+            "<kbv:birthYear>1809-02-12</kbv:birthYear><kbv:lifeSpan>1809-1882</kbv:lifeSpan>",
+        );
+        let dates: Vec<(usize, u64)> = selibr
+            .life_dates()
+            .unwrap()
+            .into_iter()
+            .map(|(property, date)| (property, date.precision()))
+            .collect();
+        assert_eq!(dates, vec![(569, 11), (570, 9)]);
+    }
+
+    #[test]
+    fn test_occupations() {
+        let selibr = parsed(
+            r#"<kbv:hasOccupation rdf:resource="https://id.kb.se/term/sao/F%C3%B6rfattare"/>
+            <kbv:hasOccupation>Alkemist</kbv:hasOccupation>"#,
+        );
+        let occupations = selibr.occupations().unwrap();
+        assert_eq!(occupations, vec!["Alkemist", "Författare"]);
+        assert_eq!(Occupation::from_swedish(&occupations[1]), Some("Q36180"));
+    }
+
     #[tokio::test]
     async fn test_my_property() {
         let selibr = SELIBR::new(TEST_ID).await.unwrap();
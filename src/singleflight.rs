@@ -0,0 +1,109 @@
+use crate::app_error::AppError;
+use anyhow::Result;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use tokio::sync::OnceCell;
+
+/// Lets concurrent calls with the same key share one run, eg two `/extend` requests that need the same record.
+/// Only calls that overlap are merged; once a run has finished, the next call starts a new one.
+#[derive(Debug)]
+pub struct SingleFlight<T> {
+    in_flight: Mutex<HashMap<String, Arc<OnceCell<Result<T, AppError>>>>>,
+}
+
+impl<T> Default for SingleFlight<T> {
+    fn default() -> Self {
+        Self {
+            in_flight: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl<T: Clone> SingleFlight<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Runs `f`, unless a run for `key` is already underway; then waits for that one and returns its result.
+    /// The caller that ran `f` gets its original error; the others get it as an `AppError`.
+    /// If the running caller is cancelled, one of the waiting ones takes over.
+    pub async fn run<F, Fut>(&self, key: &str, f: F) -> Result<T>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let cell = match self.in_flight.lock() {
+            Ok(mut in_flight) => in_flight.entry(key.to_string()).or_default().clone(),
+            Err(_) => return f().await, // Poisoned; do not share
+        };
+        let mut own_error = None;
+        let own_error_ref = &mut own_error;
+        let result = cell
+            .get_or_init(|| async move {
+                f().await.map_err(|e| {
+                    let shared = AppError::from_error(e.as_ref());
+                    *own_error_ref = Some(e);
+                    shared
+                })
+            })
+            .await
+            .to_owned();
+        if let Ok(mut in_flight) = self.in_flight.lock() {
+            if in_flight.get(key).is_some_and(|c| Arc::ptr_eq(c, &cell)) {
+                in_flight.remove(key);
+            }
+        }
+        match own_error {
+            Some(e) => Err(e),
+            None => result.map_err(|e| e.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_concurrent_calls_share_one_run() {
+        let flight = SingleFlight::new();
+        let runs = AtomicUsize::new(0);
+        let call = || {
+            flight.run("https://d-nb.info/gnd/118523813/about/lds.rdf", || async {
+                runs.fetch_add(1, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                Ok("<rdf/>".to_string())
+            })
+        };
+        let (a, b) = tokio::join!(call(), call());
+        assert_eq!(a.unwrap(), "<rdf/>");
+        assert_eq!(b.unwrap(), "<rdf/>");
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+
+        // Finished runs are not reused
+        call().await.unwrap();
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_shared_error() {
+        let flight: SingleFlight<String> = SingleFlight::new();
+        let call = || {
+            flight.run("key", || async {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                Err(AppError::NotFound("gone".to_string()).into())
+            })
+        };
+        let (a, b) = tokio::join!(call(), call());
+        for result in [a, b] {
+            let e = result.unwrap_err();
+            assert_eq!(
+                e.downcast_ref::<AppError>(),
+                Some(&AppError::NotFound("gone".to_string()))
+            );
+        }
+    }
+}
@@ -1,77 +1,130 @@
 use crate::external_importer::*;
+use crate::i18n::I18n;
 use anyhow::{anyhow, Result};
+use regex::Regex;
+use serde_json::{json, Value};
 
 lazy_static! {
     /// Examples of all supported properties
     pub static ref SUPPORTED_PROPERTIES: Vec<SupportedProperty> = {
         vec![
+            SupportedProperty::new(
+                213,
+                "ISNI",
+                "International Standard Name Identifier",
+                "000000012146438X",
+                None,
+            )
+            .with_id_format(r"\d{15}[\dX]")
+            .with_language("mul"),
             SupportedProperty::new(
                 214,
                 "VIAF",
                 "Virtual International Authority File",
                 "27063124",
                 None,
-            ),
-            SupportedProperty::new(227, "GND", "Deutsche Nationalbibliothek", "118523813", None),
-            SupportedProperty::new(244, "LoC", "Library of Congress", "n78095637", None),
+            )
+            .with_id_format(r"[1-9]\d(\d{0,7}|\d{17,20})")
+            .with_language("mul"),
+            SupportedProperty::new(227, "GND", "Deutsche Nationalbibliothek", "118523813", None)
+                .with_id_format(r"1[012]?\d{7}[0-9X]|[47]\d{6}-\d|[1-9]\d{0,7}-[0-9X]|3\d{7}[0-9X]")
+                .with_language("de"),
+            SupportedProperty::new(244, "LoC", "Library of Congress", "n78095637", None)
+                .with_id_format(r"(gf|n|nb|nr|no|ns|sh|sj)([4-9][0-9]|00|20[0-2][0-9])[0-9]{6}")
+                .with_language("en"),
+            SupportedProperty::new(
+                245,
+                "ULAN",
+                "Getty Union List of Artist Names",
+                "500115493",
+                None,
+            )
+            .with_id_format(r"500\d{6}")
+            .with_language("en"),
             SupportedProperty::new(
                 268,
                 "BnF",
                 "Bibliothèque nationale de France",
                 "11898689q",
                 None,
-            ),
-            SupportedProperty::new(269, "IdRef", "IdRef/SUDOC", "026812304", None),
-            SupportedProperty::new(906, "SELIBR", "National Library of Sweden", "231727", None),
+            )
+            .with_id_format(r"\d{8}[0-9bcdfghjkmnpqrstvwxz]")
+            .with_language("fr"),
+            SupportedProperty::new(269, "IdRef", "IdRef/SUDOC", "026812304", None)
+                .with_id_format(r"\d{8}[\dX]")
+                .with_language("fr"),
+            SupportedProperty::new(349, "NDL", "National Diet Library", "00054222", None)
+                .with_id_format(r"0?0?\d{8}|a1\d{8}|s\d{8}")
+                .with_language("ja"),
+            SupportedProperty::new(906, "SELIBR", "National Library of Sweden", "231727", None)
+                .with_id_format(r"[1-9]\d{4,5}|[0-9bcdfghjklmnpqrstvwxz]{15}")
+                .with_language("sv"),
             SupportedProperty::new(
                 950,
                 "BNE",
                 "Biblioteca Nacional de España",
                 "XX990809",
                 None,
-            ),
+            )
+            .with_id_format(r"(XX|FF|a)\d{4,7}|(bima|bimo|bica|bis[eo]|bivi|Mise|Mimo|Mima)\d{10}")
+            .with_language("es"),
             SupportedProperty::new(
                 1015,
                 "NORAF",
                 "Norwegian Authority File",
                 "90053126",
                 Some("Rainer Maria Rilke".into()),
-            ),
+            )
+            .with_id_format(r"[1-9]\d*")
+            .with_language("nb"),
             SupportedProperty::new(
                 1006,
                 "NB",
                 "Nationale Thesaurus voor Auteurs ID",
                 "068364229",
                 None,
-            ),
+            )
+            .with_id_format(r"\d{8}[\dX]")
+            .with_language("nl"),
             SupportedProperty::new(
                 10832,
                 "WorldCat",
                 "WorldCat Identities",
                 "E39PBJd87VvgDDTV6RxBYm6qcP",
                 None,
-            ),
+            )
+            .with_id_format(r"E39P[0-9A-Za-z]+")
+            .with_language("en"),
             SupportedProperty::new(
                 3151,
                 "INaturalist",
                 "INaturalist taxon ID",
                 "890",
                 Some("Ruffed Grouse".to_string()),
-            ),
+            )
+            .with_id_format(r"[1-9]\d*")
+            .with_language("en")
+            .without_recursion(),
             SupportedProperty::new(
                 685,
                 "NCBI taxonomy",
                 "NCBI taxon ID",
                 "1747344",
                 Some("Priocnessus nuperus".to_string()),
-            ),
+            )
+            .with_id_format(r"[1-9]\d{0,6}")
+            .with_language("mul")
+            .without_recursion(),
             SupportedProperty::new(
                 846,
                 "GBIF taxon",
                 "GBIF taxon ID",
                 "5141342",
                 Some("Battus philenor".to_string()),
-            ),
+            )
+            .with_id_format(r"[1-9]\d{0,8}")
+            .with_language("mul")
+            .without_recursion(),
         ]
     };
 }
@@ -82,11 +135,11 @@ pub struct SupportedProperty {
     source: String,
     demo_id: String,
     demo_name: String,
+    id_format: Option<Regex>,
+    language: String,
+    recursive: bool,
 }
 
-unsafe impl Send for SupportedProperty {}
-unsafe impl Sync for SupportedProperty {}
-
 impl SupportedProperty {
     fn new(
         property: usize,
@@ -101,39 +154,104 @@ impl SupportedProperty {
             source: source.into(),
             demo_id: demo_id.into(),
             demo_name: demo_name.unwrap_or("Charles Darwin".into()),
+            id_format: None,
+            language: "mul".into(),
+            recursive: true,
         }
     }
 
-    pub async fn generator(&self, id: &str) -> Result<Box<dyn ExternalImporter + Send + Sync>> {
+    /// Sets the ID format, as in the "format as a regular expression" (P1793) of the property
+    fn with_id_format(mut self, id_format: &str) -> Self {
+        self.id_format =
+            Some(Regex::new(&format!("^(?:{id_format})$")).expect("Bad ID format regexp"));
+        self
+    }
+
+    /// Sets the primary language of the source data
+    fn with_language(mut self, language: &str) -> Self {
+        self.language = language.into();
+        self
+    }
+
+    /// Marks sources that do not link to other supported external IDs
+    fn without_recursion(mut self) -> Self {
+        self.recursive = false;
+        self
+    }
+
+    /// Checks if `id` matches the ID format of the property; always true if the format is unknown
+    pub fn is_valid_id(&self, id: &str) -> bool {
+        self.id_format.as_ref().map_or(true, |re| re.is_match(id))
+    }
+
+    /// Describes the property for API clients
+    pub fn as_json(&self) -> Value {
+        let id_format = self.id_format.as_ref().map(|re| {
+            re.as_str()
+                .trim_start_matches("^(?:")
+                .trim_end_matches(")$")
+        });
+        json!({
+            "property": format!("P{}", self.property),
+            "numeric_id": self.property,
+            "name": self.name,
+            "source": self.source,
+            "example_id": self.demo_id,
+            "example_name": self.demo_name,
+            "id_format": id_format,
+            "language": self.language,
+            "recursive": self.recursive,
+        })
+    }
+
+    /// An importer for `id` that has not downloaded the record yet
+    pub fn importer(&self, id: &str) -> Result<Box<dyn ExternalImporter + Send + Sync>> {
         let ret: Box<dyn ExternalImporter + Send + Sync> = match self.property {
-            214 => Box::new(crate::viaf::VIAF::new(id).await?),
-            227 => Box::new(crate::gnd::GND::new(id).await?),
-            244 => Box::new(crate::loc::LOC::new(id).await?),
-            268 => Box::new(crate::bnf::BNF::new(id).await?),
-            269 => Box::new(crate::id_ref::IdRef::new(id).await?),
-            685 => Box::new(crate::ncbi_taxonomy::NCBItaxonomy::new(id).await?),
-            846 => Box::new(crate::gbif_taxon::GBIFtaxon::new(id).await?),
-            906 => Box::new(crate::selibr::SELIBR::new(id).await?),
-            950 => Box::new(crate::bne::BNE::new(id).await?),
-            1006 => Box::new(crate::nb::NB::new(id).await?),
-            1015 => Box::new(crate::noraf::NORAF::new(id).await?),
-            3151 => Box::new(crate::inaturalist::INaturalist::new(id).await?),
-            10832 => Box::new(crate::worldcat::WorldCat::new(id).await?),
+            213 => Box::new(crate::isni::ISNI::unfetched(id)),
+            214 => Box::new(crate::viaf::VIAF::unfetched(id)),
+            227 => Box::new(crate::gnd::GND::unfetched(id)),
+            244 => Box::new(crate::loc::LOC::unfetched(id)),
+            245 => Box::new(crate::ulan::ULAN::unfetched(id)),
+            268 => Box::new(crate::bnf::BNF::unfetched(id)),
+            269 => Box::new(crate::id_ref::IdRef::unfetched(id)),
+            349 => Box::new(crate::ndl::NDL::unfetched(id)),
+            685 => Box::new(crate::ncbi_taxonomy::NCBItaxonomy::unfetched(id)),
+            846 => Box::new(crate::gbif_taxon::GBIFtaxon::unfetched(id)),
+            906 => Box::new(crate::selibr::SELIBR::unfetched(id)),
+            950 => Box::new(crate::bne::BNE::unfetched(id)),
+            1006 => Box::new(crate::nb::NB::unfetched(id)),
+            1015 => Box::new(crate::noraf::NORAF::unfetched(id)),
+            3151 => Box::new(crate::inaturalist::INaturalist::unfetched(id)),
+            10832 => Box::new(crate::worldcat::WorldCat::unfetched(id)),
             _ => return Err(anyhow!("no generator for property: 'P{}'", self.property)),
         };
         Ok(ret)
     }
 
-    pub fn as_li(&self) -> String {
+    /// An importer for `id`, with the record downloaded
+    pub async fn generator(&self, id: &str) -> Result<Box<dyn ExternalImporter + Send + Sync>> {
+        let mut ret = self.importer(id)?;
+        ret.fetch().await?;
+        Ok(ret)
+    }
+
+    /// Returns the localized source description, falling back to the built-in English one.
+    pub fn source(&self, i18n: &I18n) -> String {
+        let key = format!("source-P{}", self.property);
+        match i18n.message(&key) {
+            s if s == key => self.source.to_owned(),
+            s => s,
+        }
+    }
+
+    pub fn as_li(&self, i18n: &I18n) -> String {
+        let example = i18n.message_with_params(
+            "source-example",
+            &[self.demo_name.as_str(), &self.source(i18n)],
+        );
         format!(
-            r#"<li><a href="/item/P{}/{}">{}</a> ("{}" from {}) <small>[[<a href="https://www.wikidata.org/wiki/Property:P{}">P{}</a>]]</small></li>"#,
-            self.property,
-            &self.demo_id,
-            &self.name,
-            &self.demo_name,
-            &self.source,
-            &self.property,
-            &self.property
+            r#"<li><a href="/item/P{}/{}">{}</a> ({}) <small>[[<a href="https://www.wikidata.org/wiki/Property:P{}">P{}</a>]]</small></li>"#,
+            self.property, &self.demo_id, &self.name, &example, &self.property, &self.property
         )
     }
 
@@ -141,3 +259,52 @@ impl SupportedProperty {
         self.property
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Importers are shared between tasks; this fails to compile if one of them is not thread-safe
+    #[test]
+    fn test_importers_are_send_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<SupportedProperty>();
+        assert_send_sync::<crate::bne::BNE>();
+        assert_send_sync::<crate::bnf::BNF>();
+        assert_send_sync::<crate::gbif_taxon::GBIFtaxon>();
+        assert_send_sync::<crate::gnd::GND>();
+        assert_send_sync::<crate::id_ref::IdRef>();
+        assert_send_sync::<crate::inaturalist::INaturalist>();
+        assert_send_sync::<crate::isni::ISNI>();
+        assert_send_sync::<crate::loc::LOC>();
+        assert_send_sync::<crate::nb::NB>();
+        assert_send_sync::<crate::ncbi_taxonomy::NCBItaxonomy>();
+        assert_send_sync::<crate::ndl::NDL>();
+        assert_send_sync::<crate::noraf::NORAF>();
+        assert_send_sync::<crate::selibr::SELIBR>();
+        assert_send_sync::<crate::ulan::ULAN>();
+        assert_send_sync::<crate::viaf::VIAF>();
+        assert_send_sync::<crate::worldcat::WorldCat>();
+    }
+
+    #[test]
+    fn test_demo_ids_are_valid() {
+        for sp in SUPPORTED_PROPERTIES.iter() {
+            assert!(sp.is_valid_id(&sp.demo_id), "P{}", sp.property);
+        }
+    }
+
+    #[test]
+    fn test_as_json() {
+        let sp = SUPPORTED_PROPERTIES
+            .iter()
+            .find(|sp| sp.property() == 268)
+            .unwrap();
+        let j = sp.as_json();
+        assert_eq!(j["property"], json!("P268"));
+        assert_eq!(j["id_format"], json!(r"\d{8}[0-9bcdfghjkmnpqrstvwxz]"));
+        assert_eq!(j["language"], json!("fr"));
+        assert_eq!(j["recursive"], json!(true));
+        assert!(!sp.is_valid_id("1189868"));
+    }
+}
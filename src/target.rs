@@ -0,0 +1,317 @@
+use crate::config::{Config, TargetConfig};
+use regex::Regex;
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+
+lazy_static! {
+    static ref RE_PROPERTY: Regex = Regex::new(r"^P\d+$").expect("Regexp error");
+    static ref RE_ITEM: Regex = Regex::new(r"^Q\d+$").expect("Regexp error");
+    static ref REVERSE_PROPERTIES: HashMap<String, String> =
+        Target::reversed(&Config::get().target.properties);
+    static ref REVERSE_ITEMS: HashMap<String, String> =
+        Target::reversed(&Config::get().target.items);
+}
+
+/// Which way IDs are mapped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// From the Wikidata IDs used internally, to those of the target
+    ToTarget,
+    /// From the target to Wikidata IDs
+    FromTarget,
+}
+
+/// The Wikibase installation configured in `[target]`.
+/// Importers always use Wikidata property and item IDs; they are mapped where data is loaded from, or sent to, the target.
+pub struct Target {}
+
+impl Target {
+    pub fn api() -> &'static str {
+        &Config::get().target.api
+    }
+
+    pub fn rest_api() -> &'static str {
+        &Config::get().target.rest_api
+    }
+
+    pub fn sparql() -> &'static str {
+        &Config::get().target.sparql
+    }
+
+    pub fn entity_prefix() -> &'static str {
+        &Config::get().target.entity_prefix
+    }
+
+    pub fn wiki() -> &'static str {
+        &Config::get().target.wiki
+    }
+
+    /// `false` for Wikidata itself
+    pub fn is_mapped() -> bool {
+        let config = &Config::get().target;
+        !config.properties.is_empty() || !config.items.is_empty()
+    }
+
+    /// The target property for a Wikidata property, like "P227"; `None` if the target does not have it
+    pub fn property(property: &str, direction: Direction) -> Option<String> {
+        let (properties, reverse) = (&Config::get().target.properties, &*REVERSE_PROPERTIES);
+        Self::map_property(property, direction, properties, reverse)
+    }
+
+    /// Like `property`, for numeric property IDs
+    pub fn property_numeric(property: usize, direction: Direction) -> Option<usize> {
+        Self::property(&format!("P{property}"), direction)?
+            .trim_start_matches('P')
+            .parse()
+            .ok()
+    }
+
+    /// The target item for a Wikidata item; items without a mapping, like the target's own items, are passed through
+    pub fn item(item: &str, direction: Direction) -> String {
+        let (items, reverse) = (&Config::get().target.items, &*REVERSE_ITEMS);
+        Self::map_item(item, direction, items, reverse).unwrap_or_else(|| item.to_string())
+    }
+
+    /// Maps all property and item IDs in item, statement, or diff JSON.
+    /// Statements, qualifiers, and reference snaks with an unmapped property, or an unmapped item or property value,
+    /// are removed.
+    pub fn map_json(j: &Value, direction: Direction) -> Value {
+        if !Self::is_mapped() {
+            return j.to_owned();
+        }
+        let mapper = IdMapper {
+            direction,
+            properties: &Config::get().target.properties,
+            reverse_properties: &REVERSE_PROPERTIES,
+            items: &Config::get().target.items,
+            reverse_items: &REVERSE_ITEMS,
+        };
+        mapper.map(j).unwrap_or(Value::Null)
+    }
+
+    /// Like `map_json`, for a target other than the configured one
+    pub fn map_json_for(target: &TargetConfig, j: &Value, direction: Direction) -> Value {
+        let mapper = IdMapper {
+            direction,
+            properties: &target.properties,
+            reverse_properties: &Self::reversed(&target.properties),
+            items: &target.items,
+            reverse_items: &Self::reversed(&target.items),
+        };
+        mapper.map(j).unwrap_or(Value::Null)
+    }
+
+    fn map_property(
+        property: &str,
+        direction: Direction,
+        properties: &HashMap<String, String>,
+        reverse: &HashMap<String, String>,
+    ) -> Option<String> {
+        if properties.is_empty() {
+            return Some(property.to_string());
+        }
+        let map = match direction {
+            Direction::ToTarget => properties,
+            Direction::FromTarget => reverse,
+        };
+        map.get(property).cloned()
+    }
+
+    fn map_item(
+        item: &str,
+        direction: Direction,
+        items: &HashMap<String, String>,
+        reverse: &HashMap<String, String>,
+    ) -> Option<String> {
+        if items.is_empty() {
+            return Some(item.to_string());
+        }
+        let map = match direction {
+            Direction::ToTarget => items,
+            Direction::FromTarget => reverse,
+        };
+        map.get(item).cloned()
+    }
+
+    fn reversed(map: &HashMap<String, String>) -> HashMap<String, String> {
+        map.iter()
+            .map(|(k, v)| (v.to_owned(), k.to_owned()))
+            .collect()
+    }
+}
+
+/// Walks Wikibase JSON, mapping IDs with the given tables
+struct IdMapper<'a> {
+    direction: Direction,
+    properties: &'a HashMap<String, String>,
+    reverse_properties: &'a HashMap<String, String>,
+    items: &'a HashMap<String, String>,
+    reverse_items: &'a HashMap<String, String>,
+}
+
+impl IdMapper<'_> {
+    fn property(&self, property: &str) -> Option<String> {
+        Target::map_property(
+            property,
+            self.direction,
+            self.properties,
+            self.reverse_properties,
+        )
+    }
+
+    fn item(&self, item: &str) -> Option<String> {
+        Target::map_item(item, self.direction, self.items, self.reverse_items)
+    }
+
+    /// `None` if the value should be removed, because its property or value has no mapping
+    fn map(&self, j: &Value) -> Option<Value> {
+        match j {
+            Value::Array(a) => Some(Value::Array(a.iter().filter_map(|v| self.map(v)).collect())),
+            Value::Object(o) => self.map_object(o),
+            v => Some(v.to_owned()),
+        }
+    }
+
+    fn map_object(&self, o: &Map<String, Value>) -> Option<Value> {
+        let mut ret = Map::new();
+        for (key, value) in o {
+            let is_property = RE_PROPERTY.is_match(key);
+            let key = match is_property {
+                true => match self.property(key) {
+                    Some(key) => key,
+                    None => continue,
+                },
+                false => key.to_owned(),
+            };
+            let value = match (key.as_str(), value) {
+                ("property", Value::String(property)) => Value::String(self.property(property)?),
+                ("snaks-order" | "qualifiers-order", Value::Array(a)) => Value::Array(
+                    a.iter()
+                        .filter_map(|p| p.as_str())
+                        .filter_map(|p| self.property(p))
+                        .map(Value::String)
+                        .collect(),
+                ),
+                ("mainsnak", v) => self.map(v)?,
+                ("datavalue", v) if v["type"] == "wikibase-entityid" => self.map_entity_id(v)?,
+                (_, v) => self.map(v).unwrap_or(Value::Null),
+            };
+            // All snaks of this property were removed
+            if is_property && value.as_array().is_some_and(|a| a.is_empty()) {
+                continue;
+            }
+            ret.insert(key, value);
+        }
+        Self::retain_order(&mut ret, "snaks-order", "snaks");
+        Self::retain_order(&mut ret, "qualifiers-order", "qualifiers");
+        Some(Value::Object(ret))
+    }
+
+    /// Removes properties from an order list that no longer have snaks
+    fn retain_order(o: &mut Map<String, Value>, order_key: &str, snaks_key: &str) {
+        let snaks = o.get(snaks_key).cloned().unwrap_or(Value::Null);
+        if let Some(Value::Array(order)) = o.get_mut(order_key) {
+            order.retain(|p| p.as_str().is_some_and(|p| snaks.get(p).is_some()));
+        }
+    }
+
+    /// `None` if the item or property value has no mapping on the target
+    fn map_entity_id(&self, datavalue: &Value) -> Option<Value> {
+        let mut ret = datavalue.to_owned();
+        let id = match datavalue["value"]["id"].as_str() {
+            Some(id) => id,
+            None => return Some(ret),
+        };
+        let new_id = if RE_PROPERTY.is_match(id) {
+            self.property(id)?
+        } else if RE_ITEM.is_match(id) {
+            self.item(id)?
+        } else {
+            return Some(ret);
+        };
+        if let Ok(numeric) = new_id[1..].parse::<u64>() {
+            if ret["value"].get("numeric-id").is_some() {
+                ret["value"]["numeric-id"] = Value::from(numeric);
+            }
+        }
+        ret["value"]["id"] = Value::String(new_id);
+        Some(ret)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn mapper(direction: Direction) -> IdMapper<'static> {
+        lazy_static! {
+            static ref PROPERTIES: HashMap<String, String> = [("P227", "P12"), ("P31", "P1")]
+                .into_iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect();
+            static ref REVERSE: HashMap<String, String> = Target::reversed(&PROPERTIES);
+            static ref ITEMS: HashMap<String, String> =
+                [("Q5".to_string(), "Q3".to_string())].into_iter().collect();
+            static ref REVERSE_ITEMS: HashMap<String, String> = Target::reversed(&ITEMS);
+        }
+        IdMapper {
+            direction,
+            properties: &PROPERTIES,
+            reverse_properties: &REVERSE,
+            items: &ITEMS,
+            reverse_items: &REVERSE_ITEMS,
+        }
+    }
+
+    #[test]
+    fn test_map_json() {
+        let diff = json!({"claims":{
+            "P31":[{"mainsnak":{"snaktype":"value","property":"P31","datavalue":{"type":"wikibase-entityid","value":{"entity-type":"item","numeric-id":5,"id":"Q5"}}},
+                "references":[{"snaks":{"P227":[{"snaktype":"value","property":"P227","datavalue":{"type":"string","value":"118523813"}}],
+                    "P813":[{"snaktype":"value","property":"P813"}]},"snaks-order":["P227","P813"]}]}],
+            "P214":[{"mainsnak":{"snaktype":"value","property":"P214","datavalue":{"type":"string","value":"1"}}}]
+        }});
+        let mapped = mapper(Direction::ToTarget).map(&diff).unwrap();
+        assert_eq!(
+            mapped,
+            json!({"claims":{
+                "P1":[{"mainsnak":{"snaktype":"value","property":"P1","datavalue":{"type":"wikibase-entityid","value":{"entity-type":"item","numeric-id":3,"id":"Q3"}}},
+                    "references":[{"snaks":{"P12":[{"snaktype":"value","property":"P12","datavalue":{"type":"string","value":"118523813"}}]},"snaks-order":["P12"]}]}]
+            }})
+        );
+        assert_eq!(
+            mapper(Direction::FromTarget).map(&mapped).unwrap()["claims"]["P31"][0]["mainsnak"]
+                ["datavalue"]["value"]["id"],
+            "Q5"
+        );
+    }
+
+    #[test]
+    fn test_unmapped() {
+        let empty = HashMap::new();
+        assert_eq!(
+            Target::map_property("P214", Direction::ToTarget, &empty, &empty),
+            Some("P214".to_string())
+        );
+        assert_eq!(
+            Target::map_item("Q42", Direction::FromTarget, &empty, &empty),
+            Some("Q42".to_string())
+        );
+    }
+
+    #[test]
+    fn test_unmapped_item_value() {
+        let item_value = |id: &str| json!({"snaktype":"value","property":"P31","datavalue":{"type":"wikibase-entityid","value":{"entity-type":"item","id":id}}});
+        let diff = json!({"claims":{"P31":[
+            {"mainsnak":item_value("Q5")},
+            {"mainsnak":item_value("Q42")},
+            {"mainsnak":item_value("Q5"),"qualifiers":{"P31":[item_value("Q42")]},"qualifiers-order":["P31"]}
+        ]}});
+        let mapped = mapper(Direction::ToTarget).map(&diff).unwrap();
+        let statements = mapped["claims"]["P1"].as_array().unwrap();
+        assert_eq!(statements.len(), 2);
+        assert_eq!(statements[1]["qualifiers"], json!({}));
+        assert_eq!(statements[1]["qualifiers-order"], json!([]));
+    }
+}
@@ -0,0 +1,106 @@
+use crate::config::Config;
+use crate::i18n::I18n;
+use anyhow::Result;
+use minijinja::{context, Environment, Value};
+use std::{fs, path::Path};
+
+lazy_static! {
+    static ref TEMPLATES: Environment<'static> = Templates::environment();
+}
+
+/// Templates compiled into the binary; each can be overridden by a file of the same name in `server.template_dir`.
+const EMBEDDED_TEMPLATES: &[(&str, &str)] = &[
+    ("wrapper.html", include_str!("../html/wrapper.html")),
+    ("root.html", include_str!("../html/root.html")),
+    ("merge_info.html", include_str!("../html/merge_info.html")),
+];
+
+/// Renders the HTML frontend pages
+pub struct Templates {}
+
+impl Templates {
+    fn environment() -> Environment<'static> {
+        Self::environment_with_overrides(Config::get().server.template_dir.as_deref())
+    }
+
+    /// A template override that does not compile is logged, and the embedded template is used instead
+    fn environment_with_overrides(override_dir: Option<&str>) -> Environment<'static> {
+        let mut environment = Environment::new();
+        for (name, source) in EMBEDDED_TEMPLATES {
+            let custom = override_dir
+                .map(|dir| Path::new(dir).join(name))
+                .and_then(|path| fs::read_to_string(path).ok());
+            if let Some(custom) = custom {
+                match environment.add_template_owned(*name, custom) {
+                    Ok(()) => continue,
+                    Err(e) => {
+                        tracing::error!("Bad template override {name}, using the embedded one: {e}")
+                    }
+                }
+            }
+            environment
+                .add_template(*name, *source)
+                .unwrap_or_else(|e| panic!("Bad embedded template {name}: {e}"));
+        }
+        environment
+    }
+
+    /// Renders template `name`; `ctx` is extended with the interface language and a `msg(key)` function.
+    pub fn render(name: &str, i18n: &I18n, ctx: Value) -> Result<String> {
+        let i18n = i18n.to_owned();
+        let ctx = context! {
+            lang => i18n.language(),
+            msg => Value::from_function(move |key: &str| Value::from_safe_string(i18n.message(key))),
+            ..ctx
+        };
+        Ok(TEMPLATES.get_template(name)?.render(ctx)?)
+    }
+
+    /// Renders pre-generated HTML inside the page wrapper
+    pub fn wrap(html: &str, i18n: &I18n) -> Result<String> {
+        Self::render("wrapper.html", i18n, context! {content => html})
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wrap() {
+        let html = Templates::wrap("<p>Test</p>", &I18n::new("nl")).unwrap();
+        assert!(html.contains(r#"<html lang="nl">"#));
+        assert!(html.contains("<p>Test</p>"));
+        assert!(html.contains("Help"));
+    }
+
+    #[test]
+    fn test_render_root() {
+        let ctx = context! {sources => vec!["<li>source</li>"]};
+        let html = Templates::render("root.html", &I18n::new("de"), ctx).unwrap();
+        assert!(html.contains("<li>source</li>"));
+        assert!(html.contains(r#"<html lang="de">"#));
+        assert!(html.contains("<tt>wbeditentity</tt>"));
+    }
+
+    #[test]
+    fn test_bad_override() {
+        let dir = std::env::temp_dir().join("auth2wd_test_templates");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("root.html"), "{% if %}").unwrap();
+        fs::write(dir.join("merge_info.html"), "custom").unwrap();
+        let environment = Templates::environment_with_overrides(dir.to_str());
+        let root = environment.get_template("root.html").unwrap();
+        assert_eq!(root.source(), include_str!("../html/root.html"));
+        let merge_info = environment.get_template("merge_info.html").unwrap();
+        assert_eq!(merge_info.source(), "custom");
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_render_escapes() {
+        let ctx = context! {base_item => "<b>", new_item => ""};
+        let html = Templates::render("merge_info.html", &I18n::default(), ctx).unwrap();
+        assert!(html.contains("&lt;b&gt;"));
+    }
+}
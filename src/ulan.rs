@@ -0,0 +1,550 @@
+use crate::external_id::*;
+use crate::external_importer::*;
+use crate::fuzzy_date::FuzzyDate;
+use crate::meta_item::*;
+use crate::utility::Utility;
+use crate::wikidata_lookup::items_of_class;
+use anyhow::Result;
+use axum::async_trait;
+use chrono::{Datelike, Utc};
+use sophia::inmem::graph::FastGraph;
+use wikimisc::wikibase::{EntityTrait, LocaleString};
+
+const GVP: &str = "http://vocab.getty.edu/ontology#";
+const SKOS: &str = "http://www.w3.org/2004/02/skos/core#";
+const FOAF_FOCUS: &str = "http://xmlns.com/foaf/0.1/focus";
+const SCHEMA_DESCRIPTION: &str = "http://schema.org/description";
+const SCHEMA_GENDER: &str = "http://schema.org/gender";
+const RDF_TYPE: &str = "http://www.w3.org/1999/02/22-rdf-syntax-ns#type";
+/// Country; nationalities only become citizenship if they resolve to an instance of it, or of a subclass
+const COUNTRY: &str = "Q6256";
+
+/// Associative relations to other ULAN records, and their properties
+const RELATIONS: &[(&str, usize)] = &[
+    ("ulan1101_teacher_of", 802),
+    ("ulan1102_student_of", 1066),
+    ("ulan1105_apprentice_of", 1066),
+    ("ulan1106_apprentice_was", 802),
+    ("ulan1305_patron_was", 859),
+    ("ulan2101_parent_of", 40),
+];
+
+/// AAT concepts that are mapped without a lookup
+const AAT_ITEMS: &[(&str, &str)] = &[
+    ("300189557", "Q6581072"), // female
+    ("300189559", "Q6581097"), // male
+];
+
+/// What the record is about
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum UlanKind {
+    Person,
+    /// Corporate bodies, like workshops, firms, and families of artists
+    Group,
+    /// Constructs for anonymous artists, like "anonymous Flemish"; not a single person
+    Unknown,
+}
+
+impl UlanKind {
+    fn from_type(url: &str) -> Option<Self> {
+        match url.strip_prefix(GVP)? {
+            "PersonConcept" => Some(Self::Person),
+            "GroupConcept" => Some(Self::Group),
+            "UnknownPersonConcept" => Some(Self::Unknown),
+            _ => None,
+        }
+    }
+
+    fn instance_of(&self) -> Option<&'static str> {
+        match self {
+            Self::Person => Some("Q5"),
+            Self::Group => Some("Q43229"),
+            Self::Unknown => None,
+        }
+    }
+
+    /// Properties for the estimated start and end of the biography
+    fn date_properties(&self) -> Option<(usize, usize)> {
+        match self {
+            Self::Person => Some((569, 570)),
+            Self::Group => Some((571, 576)),
+            Self::Unknown => None,
+        }
+    }
+}
+
+pub struct ULAN {
+    id: String,
+    graph: FastGraph,
+    kind: UlanKind,
+}
+
+#[async_trait]
+impl ExternalImporter for ULAN {
+    fn my_property(&self) -> usize {
+        245
+    }
+
+    fn my_id(&self) -> String {
+        self.id.to_owned()
+    }
+
+    fn my_stated_in(&self) -> &str {
+        "Q2494649"
+    }
+
+    fn graph(&self) -> &FastGraph {
+        &self.graph
+    }
+
+    fn primary_language(&self) -> String {
+        "en".to_string()
+    }
+
+    /// The subject of the record is the concept; the artist it stands for is its "-agent"
+    fn get_key_url(&self, _key: &str) -> String {
+        format!("http://vocab.getty.edu/ulan/{}", self.id)
+    }
+
+    /// Only names of persons are inverted
+    fn transform_label(&self, s: &str) -> String {
+        match self.kind {
+            UlanKind::Person => self.transform_label_last_first_name(s),
+            _ => s.to_string(),
+        }
+    }
+
+    async fn fetch(&mut self) -> Result<()> {
+        let rdf_url = format!("https://vocab.getty.edu/ulan/{}.rdf", self.id);
+        self.graph = Utility::get_rdf_graph(&rdf_url).await?;
+        self.kind = self.detect_kind()?;
+        Ok(())
+    }
+
+    fn parse(&mut self, text: &str) -> Result<()> {
+        self.graph = Utility::parse_rdf_xml(text)?;
+        self.kind = self.detect_kind()?;
+        Ok(())
+    }
+
+    async fn add_instance_of(&self, ret: &mut MetaItem) -> Result<()> {
+        if let Some(item) = self.kind.instance_of() {
+            ret.add_claim(self.new_statement_item(31, item));
+        }
+        Ok(())
+    }
+
+    async fn run(&self) -> Result<MetaItem> {
+        let mut ret = MetaItem::new();
+        self.add_own_id(&mut ret)?;
+        self.add_instance_of(&mut ret).await?;
+        self.add_labels(&mut ret)?;
+        self.add_biography(&mut ret)?;
+        self.add_dates(&mut ret)?;
+        let mut related = match self.kind {
+            UlanKind::Person => self.place_ids()?,
+            _ => vec![],
+        };
+        related.extend(self.relations()?);
+        self.add_related(&mut ret, related).await;
+        self.add_aat_links(&mut ret).await?;
+        self.try_rescue_prop_text(&mut ret).await?;
+        ret.cleanup();
+        Ok(ret)
+    }
+}
+
+impl ULAN {
+    pub fn unfetched(id: &str) -> Self {
+        Self {
+            id: id.to_string(),
+            graph: FastGraph::new(),
+            kind: UlanKind::Person,
+        }
+    }
+
+    pub async fn new(id: &str) -> Result<Self> {
+        let mut ret = Self::unfetched(id);
+        ret.fetch().await?;
+        Ok(ret)
+    }
+
+    /// Records without a known type are taken to be about a person
+    fn detect_kind(&self) -> Result<UlanKind> {
+        Ok(self
+            .triples_iris(RDF_TYPE)?
+            .iter()
+            .find_map(|url| UlanKind::from_type(url))
+            .unwrap_or(UlanKind::Person))
+    }
+
+    fn agent_urls(&self) -> Result<Vec<String>> {
+        self.triples_iris(FOAF_FOCUS)
+    }
+
+    /// The preferred biographies; ULAN gives a biography per contributor, and marks one as preferred
+    fn bio_urls(&self) -> Result<Vec<String>> {
+        let mut ret = vec![];
+        for agent in self.agent_urls()? {
+            ret.extend(self.triples_subject_iris(&agent, &format!("{GVP}biographyPreferred"))?);
+        }
+        Ok(ret)
+    }
+
+    /// The preferred name is inverted, like "Gogh, Vincent van"
+    fn add_labels(&self, ret: &mut MetaItem) -> Result<()> {
+        let language = self.primary_language();
+        let id_url = self.get_id_url();
+        for (key, is_label) in [("prefLabel", true), ("altLabel", false)] {
+            for s in self.triples_subject_literals(&id_url, &format!("{SKOS}{key}"))? {
+                let s = self.limit_string_length(&self.transform_label(&s));
+                if is_label && ret.item.label_in_locale(&language).is_none() {
+                    ret.item.labels_mut().push(LocaleString::new(&language, &s));
+                } else if ret.item.label_in_locale(&language) != Some(s.as_str()) {
+                    ret.item
+                        .aliases_mut()
+                        .push(LocaleString::new(&language, &s));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The preferred biography is a short description, like "Dutch painter and draftsman, 1853-1890"
+    fn add_biography(&self, ret: &mut MetaItem) -> Result<()> {
+        let language = self.primary_language();
+        for bio in self.bio_urls()? {
+            for s in self.triples_subject_literals(&bio, SCHEMA_DESCRIPTION)? {
+                if ret.item.description_in_locale(&language).is_none() {
+                    let s = self.limit_string_length(&s);
+                    ret.item
+                        .descriptions_mut()
+                        .push(LocaleString::new(&language, &s));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Birth and death dates, or inception and dissolution, from the estimated start and end of the preferred biography.
+    /// The dates of anonymous artists are those of the style.
+    fn life_dates(&self) -> Result<Vec<(usize, String)>> {
+        let mut ret = vec![];
+        let (start, end) = match self.kind.date_properties() {
+            Some(properties) => properties,
+            None => return Ok(ret),
+        };
+        for bio in self.bio_urls()? {
+            for (key, property) in [("estStart", start), ("estEnd", end)] {
+                for s in self.triples_subject_literals(&bio, &format!("{GVP}{key}"))? {
+                    ret.push((property, s));
+                }
+            }
+        }
+        Ok(ret)
+    }
+
+    fn add_dates(&self, ret: &mut MetaItem) -> Result<()> {
+        for (property, s) in self.life_dates()? {
+            let _ = match FuzzyDate::parse(&s) {
+                // Living artists, and existing groups, are given an end date far in the future
+                Some(_) if [570, 576].contains(&property) && Self::is_future_year(&s) => None,
+                Some(date) => ret.add_claim(self.new_statement_fuzzy_date(property, &date)),
+                None => ret.add_prop_text(ExternalId::new(property, &s)),
+            };
+        }
+        Ok(())
+    }
+
+    fn is_future_year(s: &str) -> bool {
+        let year = s.get(..4).and_then(|y| y.parse::<i32>().ok());
+        year.is_some_and(|year| year > Utc::now().year())
+    }
+
+    /// Birth and death places, as TGN (P1667) IDs
+    fn place_ids(&self) -> Result<Vec<(usize, String, Option<ExternalId>)>> {
+        let mut ret = vec![];
+        for bio in self.bio_urls()? {
+            for (key, property) in [("birthPlace", 19), ("deathPlace", 20)] {
+                for url in self.triples_subject_iris(&bio, &format!("{GVP}{key}"))? {
+                    let ext_id = self
+                        .url2external_id(&url)
+                        .filter(|ext_id| ext_id.property() == 1667);
+                    ret.push((property, url, ext_id));
+                }
+            }
+        }
+        Ok(ret)
+    }
+
+    /// Teachers, students, patrons, and children, as ULAN IDs
+    fn relations(&self) -> Result<Vec<(usize, String, Option<ExternalId>)>> {
+        let mut ret = vec![];
+        for (key, property) in RELATIONS {
+            for url in self.triples_iris(&format!("{GVP}{key}"))? {
+                let ext_id = self
+                    .url2external_id(&url)
+                    .filter(|ext_id| ext_id.property() == 245);
+                ret.push((*property, url, ext_id));
+            }
+        }
+        Ok(ret)
+    }
+
+    /// Links to AAT concepts: nationalities, roles, and the gender of persons; the types of groups, like "workshops"
+    fn aat_links(&self) -> Result<Vec<(usize, String)>> {
+        let mut ret = vec![];
+        let role_property = match self.kind {
+            UlanKind::Person => 106,
+            UlanKind::Group => 31,
+            UlanKind::Unknown => return Ok(ret),
+        };
+        // Roles, like "painters (artists)", are given for the concept, or for the agent
+        let subjects = std::iter::once(self.get_id_url()).chain(self.agent_urls()?);
+        for subject in subjects {
+            for url in self.triples_subject_iris(&subject, &format!("{GVP}agentTypeAAT"))? {
+                ret.push((role_property, url));
+            }
+        }
+        if self.kind == UlanKind::Group {
+            return Ok(ret);
+        }
+        for agent in self.agent_urls()? {
+            // Only the preferred nationality; the others are often former or mixed-up ones
+            for url in self.triples_subject_iris(&agent, &format!("{GVP}nationalityPreferred"))? {
+                ret.push((27, url));
+            }
+        }
+        for bio in self.bio_urls()? {
+            for url in self.triples_subject_iris(&bio, SCHEMA_GENDER)? {
+                ret.push((21, url));
+            }
+        }
+        Ok(ret)
+    }
+
+    fn aat_id(&self, url: &str) -> Option<ExternalId> {
+        self.url2external_id(url)
+            .filter(|ext_id| ext_id.property() == 1014)
+    }
+
+    /// Resolves the AAT concepts to items via AAT ID (P1014), all in one batch.
+    /// AAT nationalities like "Dutch" often resolve to a people or a style rather than a country, so they only become
+    /// citizenship (P27) if the item is a country; otherwise they are kept as text.
+    async fn add_aat_links(&self, ret: &mut MetaItem) -> Result<()> {
+        let links: Vec<(usize, String, Option<ExternalId>)> = self
+            .aat_links()?
+            .into_iter()
+            .map(|(property, url)| {
+                let ext_id = self.aat_id(&url);
+                (property, url, ext_id)
+            })
+            .collect();
+        let ext_ids: Vec<ExternalId> = links
+            .iter()
+            .filter_map(|link| link.2.to_owned())
+            .filter(|ext_id| Self::fixed_aat_item(ext_id).is_none())
+            .collect();
+        let items = ExternalId::get_item_for_external_id_values(&ext_ids).await;
+        let links: Vec<(usize, String, Option<String>)> = links
+            .into_iter()
+            .map(|(property, url, ext_id)| {
+                let item = ext_id.and_then(|ext_id| {
+                    Self::fixed_aat_item(&ext_id)
+                        .map(|item| item.to_string())
+                        .or_else(|| items.get(&ext_id).cloned())
+                });
+                (property, url, item)
+            })
+            .collect();
+        let nationalities: Vec<String> = links
+            .iter()
+            .filter(|(property, _, _)| *property == 27)
+            .filter_map(|(_, _, item)| item.to_owned())
+            .collect();
+        let countries = items_of_class(&nationalities, COUNTRY)
+            .await
+            .unwrap_or_else(|e| {
+                tracing::warn!("Could not check ULAN nationalities for countries: {e}");
+                vec![]
+            });
+        for (property, url, item) in links {
+            let _ = match item {
+                Some(item) if property != 27 || countries.contains(&item) => {
+                    ret.add_claim(self.new_statement_item(property, &item))
+                }
+                _ => ret.add_prop_text(ExternalId::new(property, &url)),
+            };
+        }
+        Ok(())
+    }
+
+    fn fixed_aat_item(ext_id: &ExternalId) -> Option<&'static str> {
+        AAT_ITEMS
+            .iter()
+            .find(|(id, _)| *id == ext_id.id())
+            .map(|(_, item)| *item)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEST_ID: &str = "500115493";
+
+    const TEST_RECORD: &str = r#"<?xml version="1.0"?>
+<rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#"
+    xmlns:gvp="http://vocab.getty.edu/ontology#"
+    xmlns:skos="http://www.w3.org/2004/02/skos/core#"
+    xmlns:foaf="http://xmlns.com/foaf/0.1/"
+    xmlns:schema="http://schema.org/">
+  <gvp:PersonConcept rdf:about="http://vocab.getty.edu/ulan/500115493">
+    <skos:prefLabel xml:lang="en">Gogh, Vincent van</skos:prefLabel>
+    <skos:altLabel xml:lang="en">Gogh, Vincent Willem van</skos:altLabel>
+    <foaf:focus rdf:resource="http://vocab.getty.edu/ulan/500115493-agent"/>
+    <gvp:agentTypeAAT rdf:resource="http://vocab.getty.edu/aat/300025136"/>
+    <gvp:ulan1102_student_of rdf:resource="http://vocab.getty.edu/ulan/500009367"/>
+    <gvp:ulan1101_teacher_of rdf:resource="http://vocab.getty.edu/ulan/500018641"/>
+    <gvp:ulan1305_patron_was rdf:resource="http://vocab.getty.edu/ulan/500356558"/>
+  </gvp:PersonConcept>
+  <rdf:Description rdf:about="http://vocab.getty.edu/ulan/500115493-agent">
+    <gvp:biographyPreferred rdf:resource="http://vocab.getty.edu/ulan/bio/4000061466"/>
+    <gvp:nationalityPreferred rdf:resource="http://vocab.getty.edu/aat/300111175"/>
+    <gvp:nationalityNonPreferred rdf:resource="http://vocab.getty.edu/aat/300111188"/>
+  </rdf:Description>
+  <rdf:Description rdf:about="http://vocab.getty.edu/ulan/bio/4000061466">
+    <schema:description>Dutch painter and draftsman, 1853-1890</schema:description>
+    <schema:gender rdf:resource="http://vocab.getty.edu/aat/300189559"/>
+    <gvp:estStart>1853</gvp:estStart>
+    <gvp:estEnd>1890</gvp:estEnd>
+    <gvp:birthPlace rdf:resource="http://vocab.getty.edu/tgn/7016845-place"/>
+    <gvp:deathPlace rdf:resource="http://vocab.getty.edu/tgn/7008809-place"/>
+  </rdf:Description>
+</rdf:RDF>"#;
+
+    #[tokio::test]
+    async fn test_new() {
+        assert!(ULAN::new(TEST_ID).await.is_ok());
+    }
+
+    #[test]
+    fn test_life_dates() {
+        assert_eq!(
+            ULAN::unfetched(TEST_ID)
+                .with_record(TEST_RECORD)
+                .unwrap()
+                .life_dates()
+                .unwrap(),
+            vec![(569, "1853".to_string()), (570, "1890".to_string())]
+        );
+        assert!(ULAN::is_future_year("2500"));
+        assert!(!ULAN::is_future_year("1890"));
+    }
+
+    #[test]
+    fn test_place_ids() {
+        let places = ULAN::unfetched(TEST_ID)
+            .with_record(TEST_RECORD)
+            .unwrap()
+            .place_ids()
+            .unwrap();
+        assert_eq!(places.len(), 2);
+        assert_eq!(places[0].0, 19);
+        assert_eq!(places[0].2, Some(ExternalId::new(1667, "7016845")));
+        assert_eq!(places[1].2, Some(ExternalId::new(1667, "7008809")));
+    }
+
+    #[test]
+    fn test_relations() {
+        let relations: Vec<(usize, Option<ExternalId>)> = ULAN::unfetched(TEST_ID)
+            .with_record(TEST_RECORD)
+            .unwrap()
+            .relations()
+            .unwrap()
+            .into_iter()
+            .map(|(property, _, ext_id)| (property, ext_id))
+            .collect();
+        assert_eq!(
+            relations,
+            vec![
+                (802, Some(ExternalId::new(245, "500018641"))),
+                (1066, Some(ExternalId::new(245, "500009367"))),
+                (859, Some(ExternalId::new(245, "500356558"))),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_aat_links() {
+        let ulan = ULAN::unfetched(TEST_ID).with_record(TEST_RECORD).unwrap();
+        let links = ulan.aat_links().unwrap();
+        assert_eq!(
+            links,
+            vec![
+                (106, "http://vocab.getty.edu/aat/300025136".to_string()),
+                (27, "http://vocab.getty.edu/aat/300111175".to_string()),
+                (21, "http://vocab.getty.edu/aat/300189559".to_string()),
+            ]
+        );
+        let gender = ulan.aat_id(&links[2].1).unwrap();
+        assert_eq!(ULAN::fixed_aat_item(&gender), Some("Q6581097"));
+        let nationality = ulan.aat_id(&links[1].1).unwrap();
+        assert_eq!(ULAN::fixed_aat_item(&nationality), None);
+    }
+
+    #[test]
+    fn test_group() {
+        let record = r#"<?xml version="1.0"?>
+<rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#"
+    xmlns:gvp="http://vocab.getty.edu/ontology#"
+    xmlns:foaf="http://xmlns.com/foaf/0.1/">
+  <gvp:GroupConcept rdf:about="http://vocab.getty.edu/ulan/500336597">
+    <foaf:focus rdf:resource="http://vocab.getty.edu/ulan/500336597-agent"/>
+    <gvp:agentTypeAAT rdf:resource="http://vocab.getty.edu/aat/300025877"/>
+  </gvp:GroupConcept>
+  <rdf:Description rdf:about="http://vocab.getty.edu/ulan/500336597-agent">
+    <gvp:biographyPreferred rdf:resource="http://vocab.getty.edu/ulan/bio/4000141532"/>
+    <gvp:nationalityPreferred rdf:resource="http://vocab.getty.edu/aat/300111159"/>
+  </rdf:Description>
+  <rdf:Description rdf:about="http://vocab.getty.edu/ulan/bio/4000141532">
+    <gvp:estStart>1861</gvp:estStart>
+    <gvp:estEnd>1940</gvp:estEnd>
+  </rdf:Description>
+</rdf:RDF>"#;
+        let ulan = ULAN::unfetched("500336597").with_record(record).unwrap();
+        assert_eq!(ulan.kind, UlanKind::Group);
+        assert_eq!(
+            ulan.transform_label("Sotheby, Wilkinson & Hodge"),
+            "Sotheby, Wilkinson & Hodge"
+        );
+        assert_eq!(
+            ulan.life_dates().unwrap(),
+            vec![(571, "1861".to_string()), (576, "1940".to_string())]
+        );
+        assert_eq!(
+            ulan.aat_links().unwrap(),
+            vec![(31, "http://vocab.getty.edu/aat/300025877".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_labels() {
+        let mut mi = MetaItem::new();
+        let ulan = ULAN::unfetched(TEST_ID).with_record(TEST_RECORD).unwrap();
+        ulan.add_labels(&mut mi).unwrap();
+        ulan.add_biography(&mut mi).unwrap();
+        assert_eq!(
+            *mi.item.labels(),
+            vec![LocaleString::new("en", "Vincent van Gogh")]
+        );
+        assert_eq!(
+            *mi.item.aliases(),
+            vec![LocaleString::new("en", "Vincent Willem van Gogh")]
+        );
+        assert_eq!(
+            mi.item.description_in_locale("en"),
+            Some("Dutch painter and draftsman, 1853-1890")
+        );
+    }
+}
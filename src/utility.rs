@@ -1,18 +1,472 @@
-use anyhow::Result;
+use crate::app_error::AppError;
+use crate::cassette::{Cassette, RecordedResponse};
+use crate::config::CassetteMode;
+use crate::config::Config;
+use crate::fetch_budget::FetchBudget;
+use crate::http_cache::{HttpCache, Validators};
+use crate::rate_limiter::RateLimiter;
+use crate::singleflight::SingleFlight;
+use anyhow::{anyhow, Result};
+use serde_json::Value;
+use sophia::api::prelude::*;
+use sophia::inmem::graph::FastGraph;
+use sophia::xml;
+use std::io::{BufReader, Read};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::time::Duration;
+use tokio::sync::{mpsc, OwnedSemaphorePermit};
+
+static FETCH_COUNT: AtomicUsize = AtomicUsize::new(0);
+/// Number of body chunks buffered between download and RDF parser
+const RDF_STREAM_CHUNKS: usize = 16;
+/// Upper limit for a `Retry-After` delay, so a misbehaving server can not stall a request indefinitely
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(60);
+
+lazy_static! {
+    /// Shared by all fetches, so connections are pooled across importers and concurrent batch items
+    static ref HTTP_CLIENT: reqwest::Client = {
+        let config = &Config::get().http;
+        reqwest::ClientBuilder::new()
+            .timeout(Duration::from_secs(config.timeout_secs))
+            .redirect(reqwest::redirect::Policy::limited(config.max_redirects))
+            .user_agent(Utility::user_agent())
+            .build()
+            .expect("Could not build HTTP client")
+    };
+    /// Concurrent fetches of the same URL share one download (and, for RDF, one parse)
+    static ref URL_FETCHES: SingleFlight<String> = SingleFlight::new();
+    static ref RDF_FETCHES: SingleFlight<FastGraph> = SingleFlight::new();
+}
+
+/// A response, and the fetch budget slot it holds until its body has been read
+type BudgetedResponse = (reqwest::Response, OwnedSemaphorePermit);
+
+/// A failed fetch, with what is needed to decide on a retry
+enum RetryableError {
+    Request(reqwest::Error),
+    Other(anyhow::Error),
+}
+
+impl From<RetryableError> for anyhow::Error {
+    fn from(e: RetryableError) -> Self {
+        match e {
+            RetryableError::Request(e) => e.into(),
+            RetryableError::Other(e) => e,
+        }
+    }
+}
+
+/// Feeds body chunks from the download task to the (blocking) RDF parser
+struct ChunkReader {
+    rx: mpsc::Receiver<Vec<u8>>,
+    chunk: Vec<u8>,
+    pos: usize,
+}
+
+impl Read for ChunkReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.pos >= self.chunk.len() {
+            match self.rx.blocking_recv() {
+                Some(chunk) => {
+                    self.chunk = chunk;
+                    self.pos = 0;
+                }
+                None => return Ok(0),
+            }
+        }
+        let len = buf.len().min(self.chunk.len() - self.pos);
+        buf[..len].copy_from_slice(&self.chunk[self.pos..self.pos + len]);
+        self.pos += len;
+        Ok(len)
+    }
+}
 
 pub struct Utility {}
 
 impl Utility {
+    /// Identifies the tool to upstream services, as most API policies require
+    pub fn user_agent() -> &'static str {
+        &Config::get().http.user_agent
+    }
+
+    /// The HTTP client for all upstream requests, configured in the `[http]` section
+    pub fn http_client() -> &'static reqwest::Client {
+        &HTTP_CLIENT
+    }
+
+    /// Fetches a URL, using the HTTP cache if one is configured.
+    /// Expired entries are revalidated with `If-None-Match`/`If-Modified-Since` if the server sent validators.
     pub async fn get_url(url: &str) -> Result<String> {
-        let resp = reqwest::ClientBuilder::new()
-            .timeout(Duration::from_secs(60))
-            .build()?
-            .get(url)
-            .send()
-            .await?
-            .text()
-            .await?;
-        Ok(resp)
+        URL_FETCHES
+            .run(url, || Self::get_url_uncoalesced(url))
+            .await
+    }
+
+    async fn get_url_uncoalesced(url: &str) -> Result<String> {
+        let cache = match HttpCache::global() {
+            Some(cache) if Cassette::global().is_none() => cache,
+            _ => return Self::get_text(HTTP_CLIENT.get(url)).await,
+        };
+        if let Some(body) = cache.get(url) {
+            return Ok(body);
+        }
+        let stale = cache.get_stale(url);
+        let request = match &stale {
+            Some(stale) => stale.validators.apply(HTTP_CLIENT.get(url)),
+            None => HTTP_CLIENT.get(url),
+        };
+        let (resp, _permit) = Self::get_response(request).await?;
+        if let Some(stale) = stale.filter(|_| resp.status() == reqwest::StatusCode::NOT_MODIFIED) {
+            if let Err(e) = cache.refresh(url) {
+                tracing::warn!("Could not write to HTTP cache: {e}");
+            }
+            return Ok(stale.body);
+        }
+        let validators = Validators::from_headers(resp.headers());
+        let body = Self::read_body(resp).await?;
+        if let Err(e) = cache.set(url, &body, validators) {
+            tracing::warn!("Could not write to HTTP cache: {e}");
+        }
+        Ok(body)
+    }
+
+    /// Sends a request within the server-wide upstream fetch budget, and returns the response text.
+    /// Retries on 429/5xx responses and connection errors, as configured in the `[http]` section.
+    /// With a cassette, responses are recorded or replayed with their status and headers, so a replayed
+    /// error (eg 404) fails just like the live one.
+    pub async fn get_text(request: reqwest::RequestBuilder) -> Result<String> {
+        let cassette = match Cassette::global() {
+            Some(cassette) => cassette,
+            None => {
+                let (resp, _permit) = Self::get_response(request).await?;
+                return Self::read_body(resp).await;
+            }
+        };
+        let key = Cassette::request_key(&request)?;
+        let recorded = match cassette.mode() {
+            CassetteMode::Replay => cassette.load(&key)?,
+            _ => {
+                let (resp, _permit) = Self::get_response_unchecked(request).await?;
+                let recorded = RecordedResponse {
+                    status: resp.status().as_u16(),
+                    headers: RecordedResponse::headers_from(resp.headers()),
+                    body: Self::read_body(resp).await?,
+                };
+                cassette.save(&key, &recorded)?;
+                recorded
+            }
+        };
+        Self::read_body(recorded.into_response()?.error_for_status()?).await
+    }
+
+    /// Fetches and parses an RDF/XML record. Without HTTP cache or cassette, the body is parsed while it downloads,
+    /// so it is never held in memory as a whole.
+    pub async fn get_rdf_graph(url: &str) -> Result<FastGraph> {
+        RDF_FETCHES
+            .run(url, || Self::get_rdf_graph_uncoalesced(url))
+            .await
+    }
+
+    async fn get_rdf_graph_uncoalesced(url: &str) -> Result<FastGraph> {
+        if HttpCache::global().is_some() || Cassette::global().is_some() {
+            return Self::parse_rdf_xml(&Self::get_url(url).await?);
+        }
+        // The slot is held until the parser is done, as downloading and parsing are the expensive part
+        let (mut resp, _permit) = Self::get_response(HTTP_CLIENT.get(url)).await?;
+        Self::check_size(resp.content_length().unwrap_or(0) as usize)?;
+        let (tx, rx) = mpsc::channel(RDF_STREAM_CHUNKS);
+        let parser = tokio::task::spawn_blocking(move || {
+            let reader = BufReader::new(ChunkReader {
+                rx,
+                chunk: vec![],
+                pos: 0,
+            });
+            let mut graph = FastGraph::new();
+            let _ = xml::parser::parse_bufread(reader).add_to_graph(&mut graph)?;
+            Ok::<_, anyhow::Error>(graph)
+        });
+        let mut size = 0;
+        while let Some(chunk) = resp.chunk().await? {
+            size += chunk.len();
+            // Returning drops the sender, which ends the parser
+            Self::check_size(size)?;
+            if tx.send(chunk.to_vec()).await.is_err() {
+                break; // Parser stopped early, its error is returned below
+            }
+        }
+        drop(tx);
+        parser.await?
+    }
+
+    /// Parses an RDF/XML document into a graph
+    pub fn parse_rdf_xml(text: &str) -> Result<FastGraph> {
+        let mut graph = FastGraph::new();
+        let _ = xml::parser::parse_str(text).add_to_graph(&mut graph)?;
+        Ok(graph)
+    }
+
+    /// Reads a response body, up to `http.max_response_bytes`
+    async fn read_body(mut resp: reqwest::Response) -> Result<String> {
+        Self::check_size(resp.content_length().unwrap_or(0) as usize)?;
+        let mut body = vec![];
+        while let Some(chunk) = resp.chunk().await? {
+            Self::check_size(body.len() + chunk.len())?;
+            body.extend_from_slice(&chunk);
+        }
+        Ok(String::from_utf8_lossy(&body).into_owned())
+    }
+
+    fn check_size(size: usize) -> Result<()> {
+        let max = Config::get().http.max_response_bytes;
+        if size > max {
+            return Err(anyhow!("Response exceeds the maximum size of {max} bytes"));
+        }
+        Ok(())
+    }
+
+    async fn get_response(request: reqwest::RequestBuilder) -> Result<BudgetedResponse> {
+        let (resp, permit) = Self::get_response_unchecked(request).await?;
+        Ok((resp.error_for_status()?, permit))
+    }
+
+    /// Like `get_response`, but returns the final response even if it has an error status
+    async fn get_response_unchecked(request: reqwest::RequestBuilder) -> Result<BudgetedResponse> {
+        let config = &Config::get().http;
+        let mut attempt = 0;
+        loop {
+            let retry = match request.try_clone() {
+                Some(retry) if attempt < config.retries => retry,
+                _ => return Ok(Self::send(request).await?),
+            };
+            let delay = match Self::send(retry).await {
+                Ok((resp, permit)) => match Self::status_retry_delay(&resp) {
+                    Some(delay) => delay,
+                    None => return Ok((resp, permit)),
+                },
+                Err(e) => match Self::retry_delay(&e) {
+                    Some(delay) => delay,
+                    None => return Err(e.into()),
+                },
+            };
+            let delay =
+                delay.unwrap_or_else(|| Self::backoff_delay(config.retry_delay_ms, attempt));
+            tracing::debug!("Retrying upstream fetch in {delay:?}");
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+
+    /// Sends a request once a fetch budget slot is free; the caller keeps the slot while it reads the body
+    async fn send(request: reqwest::RequestBuilder) -> Result<BudgetedResponse, RetryableError> {
+        let (client, request) = request.build_split();
+        let request = request.map_err(RetryableError::Request)?;
+        RateLimiter::global()
+            .wait(request.url())
+            .await
+            .map_err(|e| RetryableError::Other(e.into()))?;
+        let permit = FetchBudget::global()
+            .acquire()
+            .await
+            .map_err(|e| RetryableError::Other(e.into()))?;
+        FETCH_COUNT.fetch_add(1, Ordering::Relaxed);
+        let resp = client
+            .execute(request)
+            .await
+            .map_err(RetryableError::Request)?;
+        Ok((resp, permit))
+    }
+
+    /// `None` if the response is not worth a retry, otherwise the delay requested by the server, if any
+    fn status_retry_delay(resp: &reqwest::Response) -> Option<Option<Duration>> {
+        let status = resp.status();
+        (status == reqwest::StatusCode::TOO_MANY_REQUESTS || status.is_server_error())
+            .then(|| Self::retry_after(resp.headers()))
+    }
+
+    /// `None` if the error is not worth a retry
+    fn retry_delay(e: &RetryableError) -> Option<Option<Duration>> {
+        match e {
+            RetryableError::Request(e) => (e.is_connect() || e.is_timeout()).then_some(None),
+            RetryableError::Other(_) => None,
+        }
+    }
+
+    fn backoff_delay(base_ms: u64, attempt: u32) -> Duration {
+        Duration::from_millis(base_ms.saturating_mul(1 << attempt.min(16)))
+    }
+
+    /// Parses a `Retry-After` header given in seconds
+    fn retry_after(headers: &reqwest::header::HeaderMap) -> Option<Duration> {
+        let seconds: u64 = headers
+            .get(reqwest::header::RETRY_AFTER)?
+            .to_str()
+            .ok()?
+            .trim()
+            .parse()
+            .ok()?;
+        Some(Duration::from_secs(seconds).min(MAX_RETRY_AFTER))
+    }
+
+    /// Flattens a list, or an object of lists or values (eg claims by property), into a list
+    pub(crate) fn json_values(j: &Value) -> Vec<Value> {
+        match j {
+            Value::Array(a) => a.to_owned(),
+            Value::Object(o) => o
+                .values()
+                .flat_map(|v| match v {
+                    Value::Array(a) => a.to_owned(),
+                    v => vec![v.to_owned()],
+                })
+                .collect(),
+            _ => vec![],
+        }
+    }
+
+    /// Checks if an error came from an exhausted fetch budget; callers that ignore failed fetches must still pass
+    /// this on, so the request is answered with 503 rather than with a partial result
+    pub fn is_overloaded(e: &anyhow::Error) -> bool {
+        matches!(e.downcast_ref::<AppError>(), Some(AppError::Overloaded(_)))
+    }
+
+    /// Checks if an error from `get_url`/`get_text` was an HTTP 404 or 410
+    pub fn is_not_found(e: &anyhow::Error) -> bool {
+        // Shared with a concurrent identical fetch
+        if let Some(AppError::NotFound(_)) = e.downcast_ref::<AppError>() {
+            return true;
+        }
+        e.downcast_ref::<reqwest::Error>()
+            .and_then(|e| e.status())
+            .is_some_and(|status| {
+                status == reqwest::StatusCode::NOT_FOUND || status == reqwest::StatusCode::GONE
+            })
+    }
+
+    /// The number of upstream fetches since the program started
+    pub fn fetch_count() -> usize {
+        FETCH_COUNT.load(Ordering::Relaxed)
+    }
+
+    /// Percent-encodes everything except unreserved characters, for use as one URL path segment
+    pub fn encode_path_segment(s: &str) -> String {
+        s.bytes()
+            .map(|b| match b {
+                b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                    (b as char).to_string()
+                }
+                b => format!("%{b:02X}"),
+            })
+            .collect()
+    }
+
+    /// Escapes a string for safe use in HTML text and attribute values
+    pub fn html_escape(s: &str) -> String {
+        s.replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;")
+            .replace('"', "&quot;")
+            .replace('\'', "&#39;")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_html_escape() {
+        assert_eq!(
+            Utility::html_escape(r#"<a href="x">Tom & Jerry's</a>"#),
+            "&lt;a href=&quot;x&quot;&gt;Tom &amp; Jerry&#39;s&lt;/a&gt;"
+        );
+    }
+
+    #[test]
+    fn test_parse_rdf_xml() {
+        let graph = Utility::parse_rdf_xml(
+            r#"<rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#" xmlns:owl="http://www.w3.org/2002/07/owl#">
+            <rdf:Description rdf:about="https://d-nb.info/gnd/118523813"><owl:sameAs rdf:resource="http://viaf.org/viaf/30701597"/></rdf:Description>
+            </rdf:RDF>"#,
+        )
+        .unwrap();
+        assert_eq!(graph.triples().count(), 1);
+        assert!(Utility::parse_rdf_xml("<rdf:RDF").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_chunk_reader() {
+        let (tx, rx) = mpsc::channel(2);
+        let reader = tokio::task::spawn_blocking(move || {
+            let mut text = String::new();
+            ChunkReader {
+                rx,
+                chunk: vec![],
+                pos: 0,
+            }
+            .read_to_string(&mut text)
+            .map(|_| text)
+        });
+        for chunk in ["<rdf:", "", "RDF/>"] {
+            tx.send(chunk.as_bytes().to_vec()).await.unwrap();
+        }
+        drop(tx);
+        assert_eq!(reader.await.unwrap().unwrap(), "<rdf:RDF/>");
+    }
+
+    #[test]
+    fn test_backoff_delay() {
+        assert_eq!(Utility::backoff_delay(500, 0), Duration::from_millis(500));
+        assert_eq!(Utility::backoff_delay(500, 2), Duration::from_millis(2000));
+        assert_eq!(
+            Utility::backoff_delay(u64::MAX, 40),
+            Duration::from_millis(u64::MAX)
+        );
+    }
+
+    #[test]
+    fn test_retry_after() {
+        let mut headers = reqwest::header::HeaderMap::new();
+        assert_eq!(Utility::retry_after(&headers), None);
+        headers.insert(reqwest::header::RETRY_AFTER, "5".parse().unwrap());
+        assert_eq!(Utility::retry_after(&headers), Some(Duration::from_secs(5)));
+        headers.insert(reqwest::header::RETRY_AFTER, "86400".parse().unwrap());
+        assert_eq!(Utility::retry_after(&headers), Some(MAX_RETRY_AFTER));
+        headers.insert(
+            reqwest::header::RETRY_AFTER,
+            "Wed, 21 Oct 2015 07:28:00 GMT".parse().unwrap(),
+        );
+        assert_eq!(Utility::retry_after(&headers), None);
+    }
+
+    #[test]
+    fn test_is_overloaded() {
+        let e: anyhow::Error = FetchBudget::overloaded().into();
+        assert!(Utility::is_overloaded(&e));
+        let e: anyhow::Error = AppError::NotFound("gone".to_string()).into();
+        assert!(!Utility::is_overloaded(&e));
+        assert!(!Utility::is_overloaded(&anyhow!("boom")));
+    }
+
+    #[test]
+    fn test_json_values() {
+        use serde_json::json;
+        assert_eq!(
+            Utility::json_values(&json!({"P31":[1,2],"P21":[3]})),
+            vec![json!(3), json!(1), json!(2)]
+        );
+        assert_eq!(
+            Utility::json_values(&json!({"en":{"value":"x"}})),
+            vec![json!({"value":"x"})]
+        );
+        assert_eq!(Utility::json_values(&json!([1])), vec![json!(1)]);
+        assert!(Utility::json_values(&Value::Null).is_empty());
+    }
+
+    #[test]
+    fn test_encode_path_segment() {
+        assert_eq!(Utility::encode_path_segment("118523813"), "118523813");
+        assert_eq!(
+            Utility::encode_path_segment("a b/c&d+é"),
+            "a%20b%2Fc%26d%2B%C3%A9"
+        );
     }
 }
@@ -0,0 +1,182 @@
+use crate::external_id::{ExternalId, IdValidity};
+use crate::external_importer::ExternalImporter;
+use crate::supported_property::SUPPORTED_PROPERTIES;
+use crate::utility::Utility;
+use anyhow::Result;
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Verdict {
+    /// The ID is well-formed, exists, and is not used on Wikidata yet
+    Ok,
+    UnsupportedProperty,
+    BadFormat,
+    NotFound,
+    /// The source has merged or deprecated the ID; see `redirect` and `deprecated`
+    Redirect,
+    /// The ID is valid, but already on one or more Wikidata items
+    InUse,
+    /// The source or Wikidata could not be checked, eg because it was unreachable; see `error`
+    Unknown,
+}
+
+/// The result of checking an external ID before importing it
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Validation {
+    pub property: String,
+    pub id: String,
+    pub verdict: Verdict,
+    pub format_ok: bool,
+    /// `None` if the source could not be checked
+    pub exists: Option<bool>,
+    pub redirect: Option<String>,
+    pub deprecated: bool,
+    pub wikidata_items: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl Validation {
+    /// Checks the ID format, whether the record exists at the source (and is not redirected),
+    /// and whether the ID is already used on Wikidata.
+    pub async fn check(ext_id: &ExternalId) -> Self {
+        let mut ret = Self {
+            property: format!("P{}", ext_id.property()),
+            id: ext_id.id().to_string(),
+            verdict: Verdict::Ok,
+            format_ok: false,
+            exists: None,
+            redirect: None,
+            deprecated: false,
+            wikidata_items: vec![],
+            error: None,
+        };
+        let sp = match SUPPORTED_PROPERTIES
+            .iter()
+            .find(|sp| sp.property() == ext_id.property())
+        {
+            Some(sp) => sp,
+            None => {
+                ret.verdict = Verdict::UnsupportedProperty;
+                return ret;
+            }
+        };
+        ret.format_ok = sp.is_valid_id(ext_id.id());
+        if ret.format_ok {
+            ret.check_source(sp.generator(ext_id.id()).await, ext_id)
+                .await;
+            match ext_id.get_items_for_external_id_value().await {
+                Ok(items) => ret.wikidata_items = items,
+                Err(e) => ret.error = Some(e.to_string()),
+            }
+        }
+        ret.verdict = ret.verdict();
+        ret
+    }
+
+    async fn check_source(
+        &mut self,
+        parser: Result<Box<dyn ExternalImporter + Send + Sync>>,
+        ext_id: &ExternalId,
+    ) {
+        let parser = match parser {
+            Ok(parser) => parser,
+            Err(e) => {
+                if Utility::is_not_found(&e) {
+                    self.exists = Some(false);
+                } else {
+                    self.error = Some(e.to_string());
+                }
+                return;
+            }
+        };
+        self.exists = Some(true);
+        if parser.my_id() != ext_id.id() {
+            self.redirect = Some(parser.my_id());
+            return;
+        }
+        match ext_id.check_validity().await {
+            Ok(IdValidity::Replaced(current)) => {
+                self.redirect = Some(current.id().to_string());
+                return;
+            }
+            Ok(IdValidity::Invalid) => self.deprecated = true,
+            Ok(IdValidity::Valid) => {}
+            Err(e) => self.error = Some(e.to_string()),
+        }
+        // A record that only names a different ID of its own property has usually been merged into that one
+        if let Ok(item) = parser.run().await {
+            let own_ids: Vec<ExternalId> = item
+                .get_external_ids()
+                .into_iter()
+                .filter(|other| other.property() == ext_id.property())
+                .collect();
+            if !own_ids.contains(ext_id) {
+                self.redirect = own_ids.first().map(|other| other.id().to_string());
+            }
+        }
+    }
+
+    fn verdict(&self) -> Verdict {
+        if !self.format_ok {
+            Verdict::BadFormat
+        } else if self.exists == Some(false) {
+            Verdict::NotFound
+        } else if self.redirect.is_some() || self.deprecated {
+            Verdict::Redirect
+        } else if self.exists.is_none() || self.error.is_some() {
+            Verdict::Unknown
+        } else if !self.wikidata_items.is_empty() {
+            Verdict::InUse
+        } else {
+            Verdict::Ok
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_verdict() {
+        let mut v = Validation {
+            property: "P227".to_string(),
+            id: "118523813".to_string(),
+            verdict: Verdict::Ok,
+            format_ok: true,
+            exists: Some(true),
+            redirect: None,
+            deprecated: false,
+            wikidata_items: vec![],
+            error: None,
+        };
+        assert_eq!(v.verdict(), Verdict::Ok);
+        v.wikidata_items = vec!["Q1035".to_string()];
+        assert_eq!(v.verdict(), Verdict::InUse);
+        v.error = Some("Wikidata lookup failed".to_string());
+        assert_eq!(v.verdict(), Verdict::Unknown);
+        v.exists = None;
+        v.error = None;
+        assert_eq!(v.verdict(), Verdict::Unknown);
+        v.exists = Some(true);
+        v.redirect = Some("118523814".to_string());
+        assert_eq!(v.verdict(), Verdict::Redirect);
+        v.exists = Some(false);
+        assert_eq!(v.verdict(), Verdict::NotFound);
+        v.format_ok = false;
+        assert_eq!(v.verdict(), Verdict::BadFormat);
+        assert_eq!(json!(Verdict::InUse), json!("in_use"));
+    }
+
+    #[tokio::test]
+    async fn test_check_bad_format() {
+        let v = Validation::check(&ExternalId::new(227, "not-an-id")).await;
+        assert_eq!(v.verdict, Verdict::BadFormat);
+        assert_eq!(v.exists, None);
+        let v = Validation::check(&ExternalId::new(1, "x")).await;
+        assert_eq!(v.verdict, Verdict::UnsupportedProperty);
+    }
+}
@@ -1,20 +1,69 @@
+use crate::external_id::ExternalId;
 use crate::external_importer::*;
+use crate::fuzzy_date::FuzzyDate;
 use crate::meta_item::*;
+use crate::utility::Utility;
 use anyhow::Result;
 use axum::async_trait;
-use sophia::api::prelude::*;
+use regex::Regex;
+use serde_json::Value;
 use sophia::inmem::graph::FastGraph;
-use sophia::xml;
+
+/// How many of the titles associated with the person are suggested as notable works
+const MAX_WORKS: usize = 10;
+
+lazy_static! {
+    static ref RE_WIKIDATA_ITEM: Regex =
+        Regex::new(r"^https?://www\.wikidata\.org/entity/(Q\d+)$").expect("Regexp error");
+    static ref RE_FLOURISHED: Regex =
+        Regex::new(r"(?i)^\s*(?:fl\.|flourished|active)\s*(.+)$").expect("Regexp error");
+}
+
+/// Cluster types by RDF type, which tells meetings apart from other corporate bodies
+const RDF_TYPES: &[(&str, ViafKind)] = &[
+    ("http://schema.org/Person", ViafKind::Person),
+    ("http://schema.org/Event", ViafKind::Meeting),
+    ("http://schema.org/Organization", ViafKind::CorporateBody),
+    ("http://schema.org/Place", ViafKind::Place),
+];
+
+/// Cluster types by the `nameType` of the cluster record
+const NAME_TYPES: &[(&str, ViafKind)] = &[
+    ("Personal", ViafKind::Person),
+    ("Corporate", ViafKind::CorporateBody),
+    ("Geographic", ViafKind::Place),
+];
+
+/// What a VIAF cluster describes; the statements to import depend on it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ViafKind {
+    Person,
+    CorporateBody,
+    Meeting,
+    Place,
+}
+
+impl ViafKind {
+    /// P31 for all but persons, whose P31 comes from the RDF
+    fn instance_of(&self) -> Option<&'static str> {
+        match self {
+            ViafKind::Person => None,
+            ViafKind::CorporateBody => Some("Q43229"),
+            ViafKind::Meeting => Some("Q2761147"),
+            ViafKind::Place => Some("Q2221906"),
+        }
+    }
+}
 
 #[derive(Clone)]
 pub struct VIAF {
     id: String,
     graph: FastGraph,
+    /// The cluster record, with the fields that the RDF lacks
+    cluster: Value,
+    kind: ViafKind,
 }
 
-unsafe impl Send for VIAF {}
-unsafe impl Sync for VIAF {}
-
 #[async_trait]
 impl ExternalImporter for VIAF {
     fn my_property(&self) -> usize {
@@ -37,12 +86,58 @@ impl ExternalImporter for VIAF {
         &self.graph
     }
     fn transform_label(&self, s: &str) -> String {
-        self.transform_label_last_first_name(s)
+        match self.kind {
+            ViafKind::Person => self.transform_label_last_first_name(s),
+            _ => s.to_string(),
+        }
+    }
+
+    async fn add_instance_of(&self, ret: &mut MetaItem) -> Result<()> {
+        let _ = match self.kind.instance_of() {
+            Some(item) => ret.add_claim(self.new_statement_item(31, item)),
+            None => ret.add_claim(self.new_statement_item(31, "Q5")),
+        };
+        Ok(())
+    }
+
+    async fn fetch(&mut self) -> Result<()> {
+        let rdf_url = format!("https://viaf.org/viaf/{}/rdf.xml", self.id);
+        self.graph = Utility::get_rdf_graph(&rdf_url).await?;
+        let cluster_url = format!("https://viaf.org/viaf/{}/viaf.json", self.id);
+        let cluster = match Utility::get_url(&cluster_url).await {
+            Ok(text) => self.parse_cluster(&text),
+            Err(e) => Err(e),
+        };
+        match cluster {
+            Err(e) if Utility::is_overloaded(&e) => return Err(e),
+            Err(e) => tracing::warn!("Could not load VIAF cluster {}: {e}", self.id),
+            Ok(_) => {}
+        }
+        self.kind = self.detect_kind()?;
+        Ok(())
+    }
+
+    fn parse(&mut self, text: &str) -> Result<()> {
+        self.graph = Utility::parse_rdf_xml(text)?;
+        self.kind = self.detect_kind()?;
+        Ok(())
     }
 
     async fn run(&self) -> Result<MetaItem> {
         let mut ret = MetaItem::new();
         self.add_the_usual(&mut ret).await?;
+        for item in self.wkp_items()? {
+            ret.add_linked_item(&item);
+        }
+        match self.kind {
+            ViafKind::Person => {
+                self.add_dates(&mut ret)?;
+                self.add_countries(&mut ret, 27).await;
+                self.add_person_fields(&mut ret);
+                self.add_works(&mut ret);
+            }
+            _ => self.add_countries(&mut ret, 17).await,
+        }
         self.try_rescue_prop_text(&mut ret).await?;
         ret.cleanup();
         Ok(ret)
@@ -50,22 +145,214 @@ impl ExternalImporter for VIAF {
 }
 
 impl VIAF {
-    pub async fn new(id: &str) -> Result<Self> {
-        let rdf_url = format!("https://viaf.org/viaf/{}/rdf.xml", id);
-        // let resp = ureq::get(&rdf_url).call()?.into_string()?;
-        let resp = reqwest::get(&rdf_url).await?.text().await?;
-        let mut graph: FastGraph = FastGraph::new();
-        let _ = xml::parser::parse_str(&resp).add_to_graph(&mut graph)?;
-        Ok(Self {
+    pub fn unfetched(id: &str) -> Self {
+        Self {
             id: id.to_string(),
-            graph,
-        })
+            graph: FastGraph::new(),
+            cluster: Value::Null,
+            kind: ViafKind::Person,
+        }
+    }
+
+    pub async fn new(id: &str) -> Result<Self> {
+        let mut ret = Self::unfetched(id);
+        ret.fetch().await?;
+        Ok(ret)
+    }
+
+    pub fn parse_cluster(&mut self, text: &str) -> Result<()> {
+        let j: Value = serde_json::from_str(text)?;
+        self.cluster = match Self::field(&j, "VIAFCluster") {
+            Value::Null => j,
+            cluster => cluster.to_owned(),
+        };
+        self.kind = self.detect_kind()?;
+        Ok(())
+    }
+
+    /// The RDF types take precedence, as the `nameType` of meetings is "Corporate"
+    fn detect_kind(&self) -> Result<ViafKind> {
+        let types = self.triples_iris("http://www.w3.org/1999/02/22-rdf-syntax-ns#type")?;
+        let from_rdf = RDF_TYPES
+            .iter()
+            .find(|(rdf_type, _)| types.iter().any(|t| t == rdf_type));
+        let name_type = Self::field(&self.cluster, "nameType").as_str();
+        let from_name_type = NAME_TYPES.iter().find(|(nt, _)| Some(*nt) == name_type);
+        Ok(from_rdf
+            .or(from_name_type)
+            .map_or(ViafKind::Person, |(_, kind)| *kind))
+    }
+
+    /// A field of the cluster record, which has the "ns1:" prefix in some serializations
+    fn field<'a>(j: &'a Value, name: &str) -> &'a Value {
+        match &j[name] {
+            Value::Null => &j[format!("ns1:{name}")],
+            v => v,
+        }
+    }
+
+    /// Fields with one value are objects, those with more are arrays
+    fn as_list(j: &Value) -> Vec<&Value> {
+        match j {
+            Value::Array(values) => values.iter().collect(),
+            Value::Null => vec![],
+            v => vec![v],
+        }
+    }
+
+    /// The texts of a cluster field like `<occupation><data><text>`
+    fn data_texts(&self, name: &str) -> Vec<String> {
+        let mut ret: Vec<String> =
+            Self::as_list(Self::field(Self::field(&self.cluster, name), "data"))
+                .into_iter()
+                .filter_map(|data| Self::field(data, "text").as_str())
+                .map(|s| s.trim().to_string())
+                .filter(|s| !s.is_empty())
+                .collect();
+        ret.sort();
+        ret.dedup();
+        ret
+    }
+
+    /// ISO 3166 codes are upper case; MARC country codes, lower case, are in P4801 as "countries/xx"
+    fn country_external_id(code: &str) -> Option<ExternalId> {
+        let code = code.trim();
+        if !code.chars().all(|c| c.is_ascii_alphabetic()) || code.eq_ignore_ascii_case("xx") {
+            return None;
+        }
+        match code.len() {
+            2 if code.chars().all(|c| c.is_ascii_uppercase()) => Some(ExternalId::new(297, code)),
+            2 | 3 if code.chars().all(|c| c.is_ascii_lowercase()) => {
+                Some(ExternalId::new(4801, &format!("countries/{code}")))
+            }
+            _ => None,
+        }
+    }
+
+    /// The Wikidata items of the cluster's WKP member, from the RDF and the sources of the cluster record
+    fn wkp_items(&self) -> Result<Vec<String>> {
+        let mut ret = vec![];
+        for predicate in [
+            "http://schema.org/sameAs",
+            "http://www.w3.org/2002/07/owl#sameAs",
+        ] {
+            for url in self.triples_iris(predicate)? {
+                if let Some(caps) = RE_WIKIDATA_ITEM.captures(&url) {
+                    ret.push(caps[1].to_string());
+                }
+            }
+        }
+        let sources = Self::field(Self::field(&self.cluster, "sources"), "source");
+        for source in Self::as_list(sources) {
+            let text = source.as_str().or_else(|| source["#text"].as_str());
+            if let Some(item) = text.and_then(|text| text.strip_prefix("WKP|")) {
+                if item.starts_with('Q') && item[1..].chars().all(|c| c.is_ascii_digit()) {
+                    ret.push(item.to_string());
+                }
+            }
+        }
+        ret.sort();
+        ret.dedup();
+        Ok(ret)
+    }
+
+    /// Countries from the nationality codes of the cluster record, as `property`
+    async fn add_countries(&self, ret: &mut MetaItem, property: usize) {
+        let codes = self.data_texts("nationalityOfEntity");
+        let countries: Vec<(String, Option<ExternalId>)> = codes
+            .into_iter()
+            .map(|code| {
+                let ext_id = Self::country_external_id(&code);
+                (code, ext_id)
+            })
+            .collect();
+        let ext_ids: Vec<ExternalId> = countries.iter().filter_map(|c| c.1.to_owned()).collect();
+        let items = ExternalId::get_item_for_external_id_values(&ext_ids).await;
+        for (code, ext_id) in countries {
+            let _ = match ext_id.and_then(|ext_id| items.get(&ext_id)) {
+                Some(item) => ret.add_claim(self.new_statement_item(property, item)),
+                None if code.eq_ignore_ascii_case("xx") => None,
+                None => ret.add_prop_text(ExternalId::new(property, &code)),
+            };
+        }
+    }
+
+    /// Occupations (P106) and gender (P21) from the cluster record
+    fn add_person_fields(&self, ret: &mut MetaItem) {
+        for occupation in self.data_texts("occupation") {
+            let _ = ret.add_prop_text(ExternalId::new(106, &occupation));
+        }
+
+        let gender = Self::field(Self::field(&self.cluster, "fixed"), "gender");
+        let _ = match gender.as_str() {
+            Some("a") => ret.add_claim(self.new_statement_item(21, "Q6581072")),
+            Some("b") => ret.add_claim(self.new_statement_item(21, "Q6581097")),
+            _ => None,
+        };
+    }
+
+    /// Uniform titles of the cluster, with the number of member records that give them, most frequent first
+    fn work_titles(&self) -> Vec<(String, usize)> {
+        let titles = Self::field(Self::field(&self.cluster, "titles"), "work");
+        let mut ret: Vec<(String, usize)> = Self::as_list(titles)
+            .into_iter()
+            .filter_map(|work| {
+                let title = Self::field(work, "title").as_str()?;
+                let title = title.trim().trim_end_matches(['.', ',', ';', ' ']);
+                let sources = Self::as_list(Self::field(Self::field(work, "sources"), "s")).len();
+                Some((title.to_string(), sources.max(1)))
+            })
+            .filter(|(title, _)| !title.is_empty())
+            .collect();
+        ret.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        ret.dedup_by(|a, b| a.0 == b.0);
+        ret
+    }
+
+    /// The most frequent titles, as notable work (P800) suggestions
+    fn add_works(&self, ret: &mut MetaItem) {
+        for (title, count) in self.work_titles().into_iter().take(MAX_WORKS) {
+            ret.add_prop_text_suggestion(ExternalId::new(800, &title), count);
+        }
+    }
+
+    /// Birth and death dates, as aggregated from the cluster members; dates of activity, like "fl. 1500",
+    /// become floruit (P1317). The most precise dates go first, so `MetaItem::add_claim` deprecates the others.
+    fn add_dates(&self, ret: &mut MetaItem) -> Result<()> {
+        let fields = [
+            ("http://schema.org/birthDate", 569),
+            ("http://schema.org/deathDate", 570),
+        ];
+        let mut dates = vec![];
+        for (predicate, property) in fields {
+            for s in self.triples_literals(predicate)? {
+                // Unknown dates are sometimes given as "0"
+                if !s.chars().any(|c| ('1'..='9').contains(&c)) {
+                    continue;
+                }
+                let (property, s) = match RE_FLOURISHED.captures(&s) {
+                    Some(caps) => (1317, caps[1].to_string()),
+                    None => (property, s),
+                };
+                match FuzzyDate::parse(&s) {
+                    Some(date) => dates.push((property, date)),
+                    None => {
+                        let _ = ret.add_prop_text(ExternalId::new(property, &s));
+                    }
+                }
+            }
+        }
+        dates.sort_by_key(|(_, date)| std::cmp::Reverse(date.precision()));
+        for (property, date) in dates {
+            let _ = ret.add_claim(self.new_statement_fuzzy_date(property, &date));
+        }
+        Ok(())
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use wikimisc::wikibase::{EntityTrait, LocaleString};
+    use wikimisc::wikibase::{EntityTrait, LocaleString, StatementRank};
 
     use super::*;
 
@@ -114,6 +401,110 @@ mod tests {
         assert_eq!(viaf.transform_label("Magnus Manske"), "Magnus Manske");
     }
 
+    #[test]
+    fn test_add_dates() {
+        let rdf = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#" xmlns:schema="http://schema.org/">
+  <rdf:Description rdf:about="http://viaf.org/viaf/30701597">
+    <schema:birthDate>1809</schema:birthDate>
+    <schema:birthDate>1809-02-12</schema:birthDate>
+    <schema:deathDate>fl. 1850</schema:deathDate>
+    <schema:deathDate>0</schema:deathDate>
+  </rdf:Description>
+</rdf:RDF>"#;
+        let mut viaf = VIAF::unfetched(TEST_ID);
+        viaf.parse(rdf).unwrap();
+        let mut mi = MetaItem::new();
+        viaf.add_dates(&mut mi).unwrap();
+        let claims = mi.item.claims();
+        assert_eq!(claims.len(), 3);
+        assert_eq!(claims[0].property(), "P569");
+        assert_eq!(*claims[0].rank(), StatementRank::Normal);
+        assert_eq!(claims[1].property(), "P569");
+        assert_eq!(*claims[1].rank(), StatementRank::Deprecated);
+        assert_eq!(claims[2].property(), "P1317");
+    }
+
+    #[test]
+    fn test_cluster_fields() {
+        let mut viaf = VIAF::unfetched(TEST_ID);
+        viaf.parse_cluster(
+            r#"{"ns1:VIAFCluster":{
+                "ns1:nationalityOfEntity":{"ns1:data":[{"ns1:text":"DE"},{"ns1:text":"gw"},{"ns1:text":"DE"}]},
+                "ns1:occupation":{"ns1:data":{"ns1:text":"Computer scientists"}},
+                "ns1:fixed":{"ns1:gender":"b"}}}"#,
+        )
+        .unwrap();
+        assert_eq!(viaf.data_texts("nationalityOfEntity"), vec!["DE", "gw"]);
+        assert_eq!(viaf.data_texts("occupation"), vec!["Computer scientists"]);
+        assert_eq!(viaf.data_texts("titles"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_detect_kind() {
+        let mut viaf = VIAF::unfetched(TEST_ID);
+        viaf.parse_cluster(r#"{"nameType":"Corporate"}"#).unwrap();
+        assert_eq!(viaf.kind, ViafKind::CorporateBody);
+        assert_eq!(
+            viaf.transform_label("Royal Society, London"),
+            "Royal Society, London"
+        );
+
+        let rdf = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+  <rdf:Description rdf:about="http://viaf.org/viaf/30701597">
+    <rdf:type rdf:resource="http://schema.org/Event"/>
+  </rdf:Description>
+</rdf:RDF>"#;
+        viaf.parse(rdf).unwrap();
+        assert_eq!(viaf.kind, ViafKind::Meeting);
+
+        let mut viaf = VIAF::unfetched(TEST_ID);
+        viaf.parse_cluster(r#"{"nameType":"Geographic"}"#).unwrap();
+        assert_eq!(viaf.kind, ViafKind::Place);
+        viaf.parse_cluster("{}").unwrap();
+        assert_eq!(viaf.kind, ViafKind::Person);
+    }
+
+    #[test]
+    fn test_work_titles() {
+        let mut viaf = VIAF::unfetched(TEST_ID);
+        viaf.parse_cluster(
+            r#"{"titles":{"work":[
+                {"title":"Minor work.","sources":{"s":"LC"}},
+                {"title":"Major work","sources":{"s":["LC","DNB","BNF"]}}]}}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            viaf.work_titles(),
+            vec![("Major work".to_string(), 3), ("Minor work".to_string(), 1)]
+        );
+    }
+
+    #[test]
+    fn test_wkp_items() {
+        let mut viaf = VIAF::unfetched(TEST_ID);
+        viaf.parse_cluster(
+            r##"{"sources":{"source":[{"#text":"DNB|118523813"},{"#text":"WKP|Q1035"},"WKP|Q1035"]}}"##,
+        )
+        .unwrap();
+        assert_eq!(viaf.wkp_items().unwrap(), vec!["Q1035"]);
+    }
+
+    #[test]
+    fn test_country_external_id() {
+        assert_eq!(
+            VIAF::country_external_id("DE"),
+            Some(ExternalId::new(297, "DE"))
+        );
+        assert_eq!(
+            VIAF::country_external_id("xxk"),
+            Some(ExternalId::new(4801, "countries/xxk"))
+        );
+        assert_eq!(VIAF::country_external_id("XX"), None);
+        assert_eq!(VIAF::country_external_id("De"), None);
+    }
+
     #[tokio::test]
     async fn test_run() {
         let viaf = VIAF::new(TEST_ID).await.unwrap();
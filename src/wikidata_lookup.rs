@@ -0,0 +1,558 @@
+use crate::config::{Config, LookupMode};
+use crate::target::{Direction, Target};
+use crate::utility::Utility;
+use anyhow::Result;
+use axum::async_trait;
+use serde_json::Value;
+use std::collections::HashMap;
+
+const MAX_RESULTS: usize = 50;
+const ENTITIES_PER_REQUEST: usize = 50;
+const VALUES_PER_QUERY: usize = 200;
+
+lazy_static! {
+    static ref SEARCH_LOOKUP: SearchLookup = SearchLookup {};
+    static ref SPARQL_LOOKUP: SparqlLookup = SparqlLookup {};
+}
+
+/// Conditions for finding Wikidata items: all statements must match, and if given, a label or alias must contain `text`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LookupQuery {
+    pub statements: Vec<(usize, String)>,
+    pub text: Option<String>,
+}
+
+impl LookupQuery {
+    pub fn new(property: usize, value: &str) -> Self {
+        Self {
+            statements: vec![(property, value.to_string())],
+            text: None,
+        }
+    }
+
+    pub fn with_statement(mut self, property: usize, value: &str) -> Self {
+        self.statements.push((property, value.to_string()));
+        self
+    }
+
+    pub fn with_text(mut self, text: &str) -> Self {
+        self.text = Some(text.to_string());
+        self
+    }
+
+    /// With property and item IDs of the target wiki; `None` if it lacks one of the properties
+    fn to_target(&self) -> Option<Self> {
+        let statements = self
+            .statements
+            .iter()
+            .map(|(property, value)| {
+                let property = Target::property_numeric(*property, Direction::ToTarget)?;
+                Some((property, Target::item(value, Direction::ToTarget)))
+            })
+            .collect::<Option<Vec<_>>>()?;
+        Some(Self {
+            statements,
+            text: self.text.to_owned(),
+        })
+    }
+}
+
+/// A matching item, with its labels and aliases in all languages
+#[derive(Debug, Clone, PartialEq)]
+pub struct LookupCandidate {
+    pub item: String,
+    pub labels: Vec<String>,
+}
+
+impl LookupCandidate {
+    /// Picks the only candidate, or the only one with a label or alias equal to `text` (ignoring case)
+    pub fn pick<'a>(candidates: &'a [LookupCandidate], text: &str) -> Option<&'a LookupCandidate> {
+        if candidates.len() == 1 {
+            return candidates.first();
+        }
+        let text = text.trim().to_lowercase();
+        let mut matching = candidates
+            .iter()
+            .filter(|c| c.labels.iter().any(|label| label.to_lowercase() == text));
+        match (matching.next(), matching.next()) {
+            (Some(candidate), None) => Some(candidate),
+            _ => None,
+        }
+    }
+
+    /// Loads labels and aliases for `items` via `wbgetentities`
+    async fn load(items: Vec<String>) -> Result<Vec<Self>> {
+        let mut ret = vec![];
+        for chunk in items.chunks(ENTITIES_PER_REQUEST) {
+            let ids: Vec<String> = chunk
+                .iter()
+                .map(|item| Target::item(item, Direction::ToTarget))
+                .collect();
+            let url = reqwest::Url::parse_with_params(
+                Target::api(),
+                &[
+                    ("action", "wbgetentities"),
+                    ("ids", &ids.join("|")),
+                    ("props", "labels|aliases"),
+                    ("format", "json"),
+                ],
+            )?;
+            let j: Value = serde_json::from_str(&Utility::get_url(url.as_str()).await?)?;
+            ret.extend(Self::from_entities(&j, chunk));
+        }
+        Ok(ret)
+    }
+
+    fn from_entities(j: &Value, items: &[String]) -> Vec<Self> {
+        items
+            .iter()
+            .map(|item| {
+                let entity = &j["entities"][Target::item(item, Direction::ToTarget)];
+                let labels = entity["labels"]
+                    .as_object()
+                    .into_iter()
+                    .flat_map(|o| o.values());
+                let aliases = entity["aliases"]
+                    .as_object()
+                    .into_iter()
+                    .flat_map(|o| o.values())
+                    .filter_map(|v| v.as_array())
+                    .flatten();
+                let mut labels: Vec<String> = labels
+                    .chain(aliases)
+                    .filter_map(|v| v["value"].as_str())
+                    .map(|s| s.to_string())
+                    .collect();
+                labels.sort();
+                labels.dedup();
+                Self {
+                    item: item.to_owned(),
+                    labels,
+                }
+            })
+            .collect()
+    }
+}
+
+/// Finds Wikidata items by their statements
+#[async_trait]
+pub trait WikidataLookup: Send + Sync {
+    async fn find_items(&self, query: &LookupQuery) -> Result<Vec<String>>;
+
+    /// Returns all matching items, with their labels and aliases
+    async fn find_candidates(&self, query: &LookupQuery) -> Result<Vec<LookupCandidate>> {
+        LookupCandidate::load(self.find_items(query).await?).await
+    }
+
+    /// Returns the item if exactly one matches
+    async fn find_single_item(&self, query: &LookupQuery) -> Option<String> {
+        match self.find_items(query).await {
+            Ok(items) if items.len() == 1 => items.into_iter().next(),
+            Ok(_) => None,
+            Err(e) => {
+                tracing::warn!("Wikidata lookup failed: {e}");
+                None
+            }
+        }
+    }
+}
+
+/// The lookup selected by `wikidata.lookup` in the configuration
+pub fn lookup() -> &'static dyn WikidataLookup {
+    match Config::get().wikidata.lookup {
+        LookupMode::Search => &*SEARCH_LOOKUP,
+        LookupMode::Sparql => &*SPARQL_LOOKUP,
+    }
+}
+
+/// Finds the items for many values of one property, with one SPARQL `VALUES` query per chunk of values.
+/// If a query fails, its values are looked up one by one; values whose lookup failed are missing from the result.
+pub async fn find_items_for_values(
+    property: usize,
+    values: &[String],
+) -> HashMap<String, Vec<String>> {
+    let mut ret = HashMap::new();
+    for chunk in values.chunks(VALUES_PER_QUERY) {
+        match SparqlLookup::query_values(property, chunk).await {
+            Ok(mut items) => {
+                for value in chunk {
+                    let found = items.remove(value).unwrap_or_default();
+                    ret.insert(value.to_owned(), found);
+                }
+            }
+            Err(e) => {
+                tracing::warn!("Batched SPARQL lookup failed, looking up one by one: {e}");
+                for value in chunk {
+                    match lookup()
+                        .find_items(&LookupQuery::new(property, value))
+                        .await
+                    {
+                        Ok(items) => {
+                            ret.insert(value.to_owned(), items);
+                        }
+                        Err(e) => {
+                            tracing::warn!("Wikidata lookup for P{property}:{value} failed: {e}")
+                        }
+                    }
+                }
+            }
+        }
+    }
+    ret
+}
+
+/// The `items` that are instances (P31) of `class`, or of one of its subclasses (P279)
+pub async fn items_of_class(items: &[String], class: &str) -> Result<Vec<String>> {
+    let (p31, p279) = match (
+        Target::property_numeric(31, Direction::ToTarget),
+        Target::property_numeric(279, Direction::ToTarget),
+    ) {
+        (Some(p31), Some(p279)) => (p31, p279),
+        _ => return Ok(vec![]),
+    };
+    if items.is_empty() {
+        return Ok(vec![]);
+    }
+    let values: Vec<String> = items
+        .iter()
+        .map(|item| SparqlLookup::sparql_value(&Target::item(item, Direction::ToTarget)))
+        .collect();
+    let class = SparqlLookup::sparql_value(&Target::item(class, Direction::ToTarget));
+    let sparql = format!(
+        "SELECT DISTINCT ?q {{ VALUES ?q {{ {} }} ?q wdt:P{p31}/wdt:P{p279}* {class} }}",
+        values.join(" ")
+    );
+    Ok(SparqlLookup::bindings(&sparql)
+        .await?
+        .iter()
+        .filter_map(|binding| SparqlLookup::binding_item(binding, "q"))
+        .map(|item| Target::item(&item, Direction::FromTarget))
+        .collect())
+}
+
+/// The string values of `property` on `item`, like the ISO 3166-1 code (P297) of a country
+pub async fn item_strings(item: &str, property: usize) -> Result<Vec<String>> {
+    let property = match Target::property_numeric(property, Direction::ToTarget) {
+        Some(property) => property,
+        None => return Ok(vec![]),
+    };
+    let item = SparqlLookup::sparql_value(&Target::item(item, Direction::ToTarget));
+    let sparql = format!("SELECT DISTINCT ?value {{ {item} wdt:P{property} ?value }}");
+    Ok(SparqlLookup::bindings(&sparql)
+        .await?
+        .iter()
+        .filter_map(|binding| binding["value"]["value"].as_str())
+        .map(|value| value.to_string())
+        .collect())
+}
+
+/// Uses `haswbstatement` in the Wikidata search index; fast, but lags behind recent edits
+#[derive(Debug, Clone, Default)]
+pub struct SearchLookup {}
+
+impl SearchLookup {
+    fn search_string(query: &LookupQuery) -> String {
+        let mut parts: Vec<String> = query
+            .text
+            .iter()
+            .map(|text| Self::sanitize_text(text))
+            .filter(|text| !text.is_empty())
+            .collect();
+        for (property, value) in &query.statements {
+            parts.push(Self::haswbstatement(*property, value));
+        }
+        parts.join(" ")
+    }
+
+    /// A quoted `haswbstatement` keyword; quotes and backslashes in the value are escaped
+    fn haswbstatement(property: usize, value: &str) -> String {
+        let value = value.replace('\\', "\\\\").replace('"', "\\\"");
+        format!("haswbstatement:\"P{property}={value}\"")
+    }
+
+    /// Reduces free text to plain search terms, so it cannot add keywords (`intitle:`), negations, or phrases
+    fn sanitize_text(text: &str) -> String {
+        text.split_whitespace()
+            .map(|term| {
+                term.chars()
+                    .filter(|c| !matches!(c, '"' | ':' | '\\' | '*' | '?' | '~'))
+                    .collect::<String>()
+                    .trim_start_matches(['-', '!'])
+                    .to_string()
+            })
+            .filter(|term| !term.is_empty())
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+#[async_trait]
+impl WikidataLookup for SearchLookup {
+    async fn find_items(&self, query: &LookupQuery) -> Result<Vec<String>> {
+        let query = match query.to_target() {
+            Some(query) => query,
+            None => return Ok(vec![]),
+        };
+        let url = reqwest::Url::parse_with_params(
+            Target::api(),
+            &[
+                ("action", "query"),
+                ("list", "search"),
+                ("srnamespace", "0"),
+                ("srlimit", &MAX_RESULTS.to_string()),
+                ("format", "json"),
+                ("srsearch", &Self::search_string(&query)),
+            ],
+        )?;
+        let j: Value = serde_json::from_str(&Utility::get_url(url.as_str()).await?)?;
+        Ok(j["query"]["search"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|result| result["title"].as_str())
+            .map(|title| Target::item(title, Direction::FromTarget))
+            .collect())
+    }
+}
+
+/// Uses the Wikidata Query Service, which is exact and more current; falls back to the search index on failure
+#[derive(Debug, Clone, Default)]
+pub struct SparqlLookup {}
+
+impl SparqlLookup {
+    fn sparql(query: &LookupQuery) -> String {
+        let mut patterns: Vec<String> = query
+            .statements
+            .iter()
+            .map(|(property, value)| format!("?q wdt:P{property} {}", Self::sparql_value(value)))
+            .collect();
+        if let Some(text) = &query.text {
+            patterns.push("?q rdfs:label|skos:altLabel ?label".to_string());
+            patterns.push(format!(
+                "FILTER(CONTAINS(LCASE(STR(?label)), LCASE({})))",
+                Self::sparql_string(text)
+            ));
+        }
+        format!(
+            "SELECT DISTINCT ?q {{ {} }} LIMIT {MAX_RESULTS}",
+            patterns.join(" . ")
+        )
+    }
+
+    /// Item IDs become entity references; everything else a string literal
+    fn sparql_value(value: &str) -> String {
+        let is_item = value.len() > 1
+            && value.starts_with('Q')
+            && value[1..].chars().all(|c| c.is_ascii_digit());
+        if is_item {
+            format!("wd:{value}")
+        } else {
+            Self::sparql_string(value)
+        }
+    }
+
+    fn sparql_string(s: &str) -> String {
+        format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+    }
+
+    /// All items with any of `values` for `property`, and the value each has
+    fn values_sparql(property: usize, values: &[String]) -> String {
+        let values: Vec<String> = values.iter().map(|v| Self::sparql_value(v)).collect();
+        format!(
+            "SELECT DISTINCT ?q ?value {{ VALUES ?value {{ {} }} ?q wdt:P{property} ?value }}",
+            values.join(" ")
+        )
+    }
+
+    async fn bindings(sparql: &str) -> Result<Vec<Value>> {
+        let url = reqwest::Url::parse_with_params(
+            Target::sparql(),
+            &[("query", sparql), ("format", "json")],
+        )?;
+        let mut j: Value = serde_json::from_str(&Utility::get_url(url.as_str()).await?)?;
+        match j["results"]["bindings"].take() {
+            Value::Array(bindings) => Ok(bindings),
+            _ => Ok(vec![]),
+        }
+    }
+
+    fn binding_item(binding: &Value, name: &str) -> Option<String> {
+        binding[name]["value"]
+            .as_str()?
+            .strip_prefix(Target::entity_prefix())
+            .map(|q| q.to_string())
+    }
+
+    async fn query(query: &LookupQuery) -> Result<Vec<String>> {
+        let query = match query.to_target() {
+            Some(query) => query,
+            None => return Ok(vec![]),
+        };
+        Ok(Self::bindings(&Self::sparql(&query))
+            .await?
+            .iter()
+            .filter_map(|binding| Self::binding_item(binding, "q"))
+            .map(|item| Target::item(&item, Direction::FromTarget))
+            .collect())
+    }
+
+    async fn query_values(
+        property: usize,
+        values: &[String],
+    ) -> Result<HashMap<String, Vec<String>>> {
+        let property = match Target::property_numeric(property, Direction::ToTarget) {
+            Some(property) => property,
+            None => return Ok(HashMap::new()),
+        };
+        let target_values: Vec<String> = values
+            .iter()
+            .map(|value| Target::item(value, Direction::ToTarget))
+            .collect();
+        let bindings = Self::bindings(&Self::values_sparql(property, &target_values)).await?;
+        Ok(Self::items_per_value(&bindings)
+            .into_iter()
+            .map(|(value, items)| {
+                let items = items
+                    .iter()
+                    .map(|item| Target::item(item, Direction::FromTarget))
+                    .collect();
+                (Target::item(&value, Direction::FromTarget), items)
+            })
+            .collect())
+    }
+
+    /// Item values come back as entity URIs, and are turned back into item IDs
+    fn items_per_value(bindings: &[Value]) -> HashMap<String, Vec<String>> {
+        let mut ret: HashMap<String, Vec<String>> = HashMap::new();
+        for binding in bindings {
+            let item = match Self::binding_item(binding, "q") {
+                Some(item) => item,
+                None => continue,
+            };
+            let value = match Self::binding_item(binding, "value") {
+                Some(value) => value,
+                None => match binding["value"]["value"].as_str() {
+                    Some(value) => value.to_string(),
+                    None => continue,
+                },
+            };
+            ret.entry(value).or_default().push(item);
+        }
+        ret
+    }
+}
+
+#[async_trait]
+impl WikidataLookup for SparqlLookup {
+    async fn find_items(&self, query: &LookupQuery) -> Result<Vec<String>> {
+        match Self::query(query).await {
+            Ok(items) => Ok(items),
+            Err(e) => {
+                tracing::warn!("SPARQL lookup failed, falling back to search: {e}");
+                SEARCH_LOOKUP.find_items(query).await
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json::json;
+
+    fn candidate(item: &str, labels: &[&str]) -> LookupCandidate {
+        LookupCandidate {
+            item: item.to_string(),
+            labels: labels.iter().map(|s| s.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn test_pick() {
+        let candidates = vec![
+            candidate("Q64", &["Berlin", "Berlin, Germany"]),
+            candidate("Q821244", &["Berlin, New Hampshire"]),
+        ];
+        assert_eq!(
+            LookupCandidate::pick(&candidates, " berlin").map(|c| c.item.as_str()),
+            Some("Q64")
+        );
+        assert_eq!(LookupCandidate::pick(&candidates, "Paris"), None);
+        assert_eq!(
+            LookupCandidate::pick(&candidates[1..], "Paris").map(|c| c.item.as_str()),
+            Some("Q821244")
+        );
+    }
+
+    #[test]
+    fn test_from_entities() {
+        let j = json!({"entities":{"Q64":{
+            "labels":{"de":{"language":"de","value":"Berlin"},"en":{"language":"en","value":"Berlin"}},
+            "aliases":{"en":[{"language":"en","value":"Berlin, Germany"}]}
+        }}});
+        let candidates = LookupCandidate::from_entities(&j, &["Q64".to_string()]);
+        assert_eq!(
+            candidates,
+            vec![candidate("Q64", &["Berlin", "Berlin, Germany"])]
+        );
+    }
+
+    #[test]
+    fn test_search_string_sanitized() {
+        let query = LookupQuery::new(214, r#"a "b"\c"#).with_text("-foo intitle:bar \"baz\"");
+        assert_eq!(
+            SearchLookup::search_string(&query),
+            r#"foo intitlebar baz haswbstatement:"P214=a \"b\"\\c""#
+        );
+        let query = LookupQuery::new(227, "1&2+3 4");
+        assert_eq!(
+            SearchLookup::search_string(&query),
+            r#"haswbstatement:"P227=1&2+3 4""#
+        );
+    }
+
+    #[test]
+    fn test_search_string() {
+        let query = LookupQuery::new(685, "9606")
+            .with_statement(31, "Q16521")
+            .with_text("Homo");
+        assert_eq!(
+            SearchLookup::search_string(&query),
+            r#"Homo haswbstatement:"P685=9606" haswbstatement:"P31=Q16521""#
+        );
+    }
+
+    #[test]
+    fn test_sparql() {
+        let query = LookupQuery::new(214, "30701597").with_statement(31, "Q5");
+        assert_eq!(
+            SparqlLookup::sparql(&query),
+            r#"SELECT DISTINCT ?q { ?q wdt:P214 "30701597" . ?q wdt:P31 wd:Q5 } LIMIT 50"#
+        );
+        let query = LookupQuery::new(214, "1").with_text("Say \"hi\"");
+        assert!(SparqlLookup::sparql(&query)
+            .contains(r#"FILTER(CONTAINS(LCASE(STR(?label)), LCASE("Say \"hi\"")))"#));
+    }
+
+    #[test]
+    fn test_values_sparql() {
+        let values = vec!["118523813".to_string(), "Q5".to_string()];
+        assert_eq!(
+            SparqlLookup::values_sparql(227, &values),
+            r#"SELECT DISTINCT ?q ?value { VALUES ?value { "118523813" wd:Q5 } ?q wdt:P227 ?value }"#
+        );
+    }
+
+    #[test]
+    fn test_items_per_value() {
+        let bindings = json!([
+            {"q":{"value":"http://www.wikidata.org/entity/Q1"},"value":{"value":"118523813"}},
+            {"q":{"value":"http://www.wikidata.org/entity/Q2"},"value":{"value":"118523813"}},
+            {"q":{"value":"http://www.wikidata.org/entity/Q3"},"value":{"value":"http://www.wikidata.org/entity/Q5"}}
+        ]);
+        let items = SparqlLookup::items_per_value(bindings.as_array().unwrap());
+        assert_eq!(items["118523813"], vec!["Q1", "Q2"]);
+        assert_eq!(items["Q5"], vec!["Q3"]);
+    }
+}
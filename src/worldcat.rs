@@ -1,5 +1,6 @@
 use crate::external_importer::*;
 use crate::meta_item::*;
+use crate::utility::Utility;
 use crate::ExternalId;
 use anyhow::Result;
 use axum::async_trait;
@@ -7,15 +8,27 @@ use serde_json::Value;
 use wikimisc::wikibase::EntityTrait;
 use wikimisc::wikibase::LocaleString;
 
+/// JSON-LD keys for linked entities like occupations and places, and the properties they map to
+const LINKED_ENTITIES: &[(&str, usize)] = &[
+    ("hasOccupation", 106),
+    ("occupation", 106),
+    ("fieldOfWork", 101),
+    ("fieldOfActivity", 101),
+    ("placeOfBirth", 19),
+    ("placeOfDeath", 20),
+    ("nationality", 27),
+];
+
+/// Keys of a linked entity that hold URLs of the same concept elsewhere; its own ID comes last,
+/// as vocabularies like LCSH and FAST are more likely to be on Wikidata
+const ENTITY_URL_KEYS: &[&str] = &["sameAs", "exactMatch", "closeMatch", "id", "@id"];
+
 #[derive(Clone)]
 pub struct WorldCat {
     id: String,
     json: Value,
 }
 
-unsafe impl Send for WorldCat {}
-unsafe impl Sync for WorldCat {}
-
 #[async_trait]
 impl ExternalImporter for WorldCat {
     fn my_property(&self) -> usize {
@@ -34,6 +47,17 @@ impl ExternalImporter for WorldCat {
         self.id.to_owned()
     }
 
+    async fn fetch(&mut self) -> Result<()> {
+        let url = format!("https://id.oclc.org/worldcat/entity/{}.jsonld", self.id);
+        let resp = Utility::get_url(&url).await?;
+        self.parse(&resp)
+    }
+
+    fn parse(&mut self, text: &str) -> Result<()> {
+        self.json = serde_json::from_str(text)?;
+        Ok(())
+    }
+
     async fn run(&self) -> Result<MetaItem> {
         let mut ret = MetaItem::new();
         self.add_own_id(&mut ret)?;
@@ -43,20 +67,25 @@ impl ExternalImporter for WorldCat {
         let _ = self.add_labels(&mut ret);
         let _ = self.add_aliases(&mut ret);
         let _ = self.add_descriptions(&mut ret);
+        self.add_linked_entities(&mut ret).await;
+        self.add_same_as_urls(&mut ret, self.same_as_urls()).await?;
         ret.cleanup();
         Ok(ret)
     }
 }
 
 impl WorldCat {
-    pub async fn new(id: &str) -> Result<Self> {
-        let url = format!("https://id.oclc.org/worldcat/entity/{id}.jsonld");
-        let resp = reqwest::get(&url).await?.text().await?;
-        let j = serde_json::from_str(&resp)?;
-        Ok(Self {
+    pub fn unfetched(id: &str) -> Self {
+        Self {
             id: id.to_string(),
-            json: j,
-        })
+            json: Value::Null,
+        }
+    }
+
+    pub async fn new(id: &str) -> Result<Self> {
+        let mut ret = Self::unfetched(id);
+        ret.fetch().await?;
+        Ok(ret)
     }
 
     fn add_p31(&self, ret: &mut MetaItem) -> Option<()> {
@@ -145,6 +174,72 @@ impl WorldCat {
     fn dt2part(j: &Value, key: &str) -> Option<String> {
         Some(j.get(key)?.get("@value")?.as_str()?.to_string())
     }
+
+    /// The values of `key`; a single value counts as a list of one
+    fn values<'a>(j: &'a Value, key: &str) -> Vec<&'a Value> {
+        match j.get(key) {
+            Some(Value::Array(values)) => values.iter().collect(),
+            Some(Value::Null) | None => vec![],
+            Some(value) => vec![value],
+        }
+    }
+
+    /// The URLs of a linked entity, which may be just a URL
+    fn entity_urls(entity: &Value) -> Vec<String> {
+        if let Some(url) = entity.as_str() {
+            return vec![url.to_string()];
+        }
+        ENTITY_URL_KEYS
+            .iter()
+            .flat_map(|key| Self::values(entity, key))
+            .filter_map(|url| url.as_str())
+            .map(|url| url.to_string())
+            .collect()
+    }
+
+    /// The label of a linked entity, preferably in English
+    fn entity_label(entity: &Value) -> Option<String> {
+        let labels = entity.get("label").or_else(|| entity.get("prefLabel"))?;
+        if let Some(label) = labels.as_str() {
+            return Some(label.to_string());
+        }
+        let labels = labels.as_object()?;
+        let label = labels.get("en").or_else(|| labels.values().next())?;
+        Some(label.as_str()?.to_string())
+    }
+
+    /// The entities linked under `key`, as (property, label or URL, the first of their URLs that is an external ID)
+    fn linked_entities(
+        &self,
+        key: &str,
+        property: usize,
+    ) -> Vec<(usize, String, Option<ExternalId>)> {
+        Self::values(&self.json, key)
+            .into_iter()
+            .filter_map(|entity| {
+                let urls = Self::entity_urls(entity);
+                let ext_id = urls.iter().find_map(|url| self.url2external_id(url));
+                let text = Self::entity_label(entity).or_else(|| urls.first().cloned())?;
+                Some((property, text, ext_id))
+            })
+            .collect()
+    }
+
+    fn same_as_urls(&self) -> Vec<String> {
+        Self::values(&self.json, "sameAs")
+            .into_iter()
+            .filter_map(|url| url.as_str())
+            .map(|url| url.to_string())
+            .collect()
+    }
+
+    async fn add_linked_entities(&self, ret: &mut MetaItem) {
+        let related = LINKED_ENTITIES
+            .iter()
+            .flat_map(|(key, property)| self.linked_entities(key, *property))
+            .collect();
+        self.add_related(ret, related).await;
+    }
 }
 
 #[cfg(test)]
@@ -152,6 +247,30 @@ mod tests {
     use super::*;
 
     const TEST_ID: &str = "E39PBJrcqvXdm3kkwGr7HVG8md";
+    const TEST_RECORD: &str = r#"{
+        "id": "https://id.oclc.org/worldcat/entity/E39PBJrcqvXdm3kkwGr7HVG8md",
+        "type": ["Person"],
+        "prefLabel": {"en": "Helen Clark"},
+        "sameAs": [
+            "http://viaf.org/viaf/29614845",
+            "http://id.loc.gov/rwo/agents/n82116427",
+            "http://www.wikidata.org/entity/Q152789"
+        ],
+        "hasOccupation": [
+            {
+                "id": "https://id.oclc.org/worldcat/entity/E39QbtfRvQh7864Jh4rDGBFDWc",
+                "label": {"en": "Politician"},
+                "sameAs": ["http://id.loc.gov/authorities/subjects/sh85104200"]
+            },
+            {"label": {"en": "Diplomat"}}
+        ],
+        "fieldOfWork": {"id": "http://id.worldcat.org/fast/1069781"},
+        "placeOfBirth": {
+            "label": {"en": "Hamilton (N.Z.)"},
+            "sameAs": ["http://vocab.getty.edu/tgn/7001989-place", "https://sws.geonames.org/2190324/"]
+        },
+        "nationality": [{"label": {"en": "New Zealanders"}, "sameAs": "https://www.geonames.org/2186224"}]
+    }"#;
 
     #[tokio::test]
     async fn test_new() {
@@ -191,18 +310,111 @@ mod tests {
         assert_eq!(worldcat.my_id(), TEST_ID);
     }
 
+    #[test]
+    fn test_linked_entities() {
+        let worldcat = WorldCat::unfetched(TEST_ID)
+            .with_record(TEST_RECORD)
+            .unwrap();
+        assert_eq!(
+            worldcat.linked_entities("hasOccupation", 106),
+            vec![
+                (
+                    106,
+                    "Politician".to_string(),
+                    Some(ExternalId::new(244, "sh85104200"))
+                ),
+                (106, "Diplomat".to_string(), None),
+            ]
+        );
+        assert_eq!(
+            worldcat.linked_entities("fieldOfWork", 101),
+            vec![(
+                101,
+                "http://id.worldcat.org/fast/1069781".to_string(),
+                Some(ExternalId::new(2163, "1069781"))
+            )]
+        );
+        assert!(worldcat.linked_entities("occupation", 106).is_empty());
+    }
+
+    #[test]
+    fn test_linked_places() {
+        let worldcat = WorldCat::unfetched(TEST_ID)
+            .with_record(TEST_RECORD)
+            .unwrap();
+        assert_eq!(
+            worldcat.linked_entities("placeOfBirth", 19),
+            vec![(
+                19,
+                "Hamilton (N.Z.)".to_string(),
+                Some(ExternalId::new(1667, "7001989"))
+            )]
+        );
+        assert_eq!(
+            worldcat.linked_entities("nationality", 27),
+            vec![(
+                27,
+                "New Zealanders".to_string(),
+                Some(ExternalId::new(1566, "2186224"))
+            )]
+        );
+        assert!(worldcat.linked_entities("placeOfDeath", 20).is_empty());
+    }
+
+    #[test]
+    fn test_same_as_urls() {
+        let worldcat = WorldCat::unfetched(TEST_ID)
+            .with_record(TEST_RECORD)
+            .unwrap();
+        let ext_ids: Vec<_> = worldcat
+            .same_as_urls()
+            .iter()
+            .filter_map(|url| worldcat.url2external_id(url))
+            .collect();
+        assert_eq!(
+            ext_ids,
+            vec![
+                ExternalId::new(214, "29614845"),
+                ExternalId::new(244, "n82116427")
+            ]
+        );
+    }
+
     #[tokio::test]
     async fn test_run() {
-        let worldcat = WorldCat::new(TEST_ID).await.unwrap();
+        // Without linked entities, so the claims do not depend on Wikidata lookups
+        let worldcat = WorldCat::unfetched(TEST_ID)
+            .with_record(
+                r#"{
+                "id": "https://id.oclc.org/worldcat/entity/E39PBJrcqvXdm3kkwGr7HVG8md",
+                "type": ["Person"],
+                "prefLabel": {"en": "Helen Clark"},
+                "altLabel": {"en": ["Helen Elizabeth Clark"]},
+                "dateOfBirth": [{"time:inDateTime": {
+                    "time:year": {"@value": "1950"},
+                    "time:month": {"@value": "--02"},
+                    "time:day": {"@value": "---26"}
+                }}]
+            }"#,
+            )
+            .unwrap();
         let meta_item = worldcat.run().await.unwrap();
-        assert!(meta_item
-            .item
-            .labels()
-            .contains(&LocaleString::new("en", "Helen Clark")));
-        assert!(meta_item
+        assert_eq!(
+            *meta_item.item.labels(),
+            vec![LocaleString::new("en", "Helen Clark")]
+        );
+        assert_eq!(
+            *meta_item.item.aliases(),
+            vec![LocaleString::new("en", "Helen Elizabeth Clark")]
+        );
+        let mut properties: Vec<&str> = meta_item
             .item
-            .aliases()
-            .contains(&LocaleString::new("en", "Helen Elizabeth Clark")));
-        assert_eq!(meta_item.item.claims().len(), 3);
+            .claims()
+            .iter()
+            .map(|c| c.main_snak().property())
+            .collect();
+        properties.sort();
+        assert_eq!(properties, vec!["P10832", "P31", "P569"]);
+        assert!(meta_item.prop_text.is_empty());
     }
 }